@@ -0,0 +1,24 @@
+use criterion::{criterion_group, criterion_main, Criterion};
+use iridium::assembler::Assembler;
+use iridium::tools::corpus;
+use iridium::vm::config::{Dispatch, VMBuilder};
+use iridium::vm::VM;
+use std::hint::black_box;
+
+fn run(program: &[u8], builder: VMBuilder) {
+    let mut vm = builder.build();
+    vm.add_bytes(program.to_vec());
+    black_box(vm.run().unwrap());
+}
+
+fn bench_dispatch(c: &mut Criterion) {
+    let program = Assembler::new().assemble(&corpus::arithmetic_loop(10_000)).unwrap();
+    let mut group = c.benchmark_group("dispatch");
+    group.bench_function("match", |b| b.iter(|| run(&program, VM::builder())));
+    group.bench_function("predecoded", |b| b.iter(|| run(&program, VM::builder().predecode(true))));
+    group.bench_function("threaded", |b| b.iter(|| run(&program, VM::builder().dispatch(Dispatch::Threaded))));
+    group.finish();
+}
+
+criterion_group!(benches, bench_dispatch);
+criterion_main!(benches);
@@ -0,0 +1,47 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use iridium::assembler::Assembler;
+use iridium::tools::corpus;
+use iridium::vm::VM;
+use std::hint::black_box;
+
+fn run(program: &[u8]) -> u64 {
+    let mut vm = VM::new();
+    vm.add_bytes(program.to_vec());
+    vm.run().unwrap().instructions_executed
+}
+
+/// Times whole programs from the corpus, reported as instructions per second
+fn bench_programs(c: &mut Criterion) {
+    let mut group = c.benchmark_group("programs");
+    let programs = [
+        ("arithmetic_loop", corpus::arithmetic_loop(10_000)),
+        ("jump_heavy", corpus::jump_heavy(64, 1_000)),
+    ];
+    for (name, source) in programs {
+        let program = Assembler::new().assemble(&source).unwrap();
+        group.throughput(Throughput::Elements(run(&program)));
+        group.bench_function(name, |b| b.iter(|| run(black_box(&program))));
+    }
+    group.finish();
+}
+
+/// Times assembling generated source files, reported as bytes of source per second. The long sources differ only in
+/// length, so work that grows faster than the source shows up as lower throughput for the longer one.
+fn bench_assembler(c: &mut Criterion) {
+    let mut group = c.benchmark_group("assembler");
+    let sources = [
+        (BenchmarkId::new("large_source", 500), corpus::large_source(500, 1)),
+        (BenchmarkId::new("long_source", 20_000), corpus::long_source(20_000)),
+        (BenchmarkId::new("long_source", 80_000), corpus::long_source(80_000)),
+    ];
+    for (id, source) in sources {
+        group.throughput(Throughput::Bytes(source.len() as u64));
+        group.bench_with_input(id, &source, |b, source| {
+            b.iter(|| Assembler::new().assemble(black_box(source)).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_programs, bench_assembler);
+criterion_main!(benches);
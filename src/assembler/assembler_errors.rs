@@ -1,5 +1,6 @@
 use std::error::Error;
 use std::fmt;
+use std::path::PathBuf;
 
 #[derive(Debug, Clone)]
 pub enum AssemblerError {
@@ -10,6 +11,16 @@ pub enum AssemblerError {
     NonOpcodeInOpcodeField,
     InsufficientSections,
     ParseError { error: String },
+    /// A line that couldn't be parsed. `line` counts from 1.
+    SyntaxError { line: usize, text: String },
+    InvalidOperands { instruction: u32, reason: String },
+    IncludeNotFound { name: String, searched: Vec<PathBuf> },
+    IncludeFailed { name: String, reason: String },
+    UnbalancedConditional { reason: String },
+    /// A label name that breaks the assembler's label rules
+    InvalidLabelName { name: String, reason: String },
+    /// A source or program transform registered on the assembler that refused the program
+    TransformFailed { reason: String },
 }
 
 impl fmt::Display for AssemblerError {
@@ -30,6 +41,29 @@ impl fmt::Display for AssemblerError {
             AssemblerError::NonOpcodeInOpcodeField => f.write_str("An non-opcode was found in an opcode field"),
             AssemblerError::InsufficientSections => f.write_str("Less than two sections/segments were found in the code"),
             AssemblerError::ParseError { ref error } => f.write_str(&format!("There was an error parsing the code: {}", error)),
+            AssemblerError::SyntaxError { line, ref text } => f.write_str(&format!("Syntax error on line {}: {}", line, text)),
+            AssemblerError::InvalidOperands { instruction, ref reason } => {
+                f.write_str(&format!("Invalid operands for instruction. Instruction # was {}: {}", instruction, reason))
+            }
+            AssemblerError::IncludeNotFound { ref name, ref searched } => {
+                let paths: Vec<String> = searched.iter().map(|path| path.display().to_string()).collect();
+                f.write_str(&format!(
+                    "Could not find included file '{}'. Searched these paths: {}",
+                    name,
+                    paths.join(", ")
+                ))
+            }
+            AssemblerError::IncludeFailed { ref name, ref reason } => {
+                f.write_str(&format!("Could not include file '{}': {}", name, reason))
+            }
+            AssemblerError::UnbalancedConditional { ref reason } => {
+                f.write_str(&format!("Invalid conditional assembly block: {}", reason))
+            }
+            AssemblerError::InvalidLabelName { ref name, ref reason } => f.write_str(&format!(
+                "Invalid label name '{}': {}. Write it in backticks, as `{}`, to use it anyway.",
+                name, reason, name
+            )),
+            AssemblerError::TransformFailed { ref reason } => f.write_str(&format!("A transform failed: {}", reason)),
         }
     }
 }
@@ -44,6 +78,13 @@ impl Error for AssemblerError {
             AssemblerError::NonOpcodeInOpcodeField => "A non-opcode was found in an opcode field",
             AssemblerError::InsufficientSections => "Less than two sections/segments were found in the code",
             AssemblerError::ParseError { .. } => "There was an error parsing the code",
+            AssemblerError::SyntaxError { .. } => "A line could not be parsed",
+            AssemblerError::InvalidOperands { .. } => "Invalid operands for instruction",
+            AssemblerError::IncludeNotFound { .. } => "Could not find included file",
+            AssemblerError::IncludeFailed { .. } => "Could not include file",
+            AssemblerError::UnbalancedConditional { .. } => "Invalid conditional assembly block",
+            AssemblerError::InvalidLabelName { .. } => "Invalid label name",
+            AssemblerError::TransformFailed { .. } => "A transform failed",
         }
     }
 }
\ No newline at end of file
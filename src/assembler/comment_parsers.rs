@@ -0,0 +1,31 @@
+use nom::not_line_ending;
+use nom::types::CompleteStr;
+
+// Looks for a comment, which runs from a `;` to the end of the line: `; this is a comment`
+named!(pub comment<CompleteStr, CompleteStr>,
+    ws!(
+        do_parse!(
+            tag!(";") >>
+            content: not_line_ending >>
+            (
+                content
+            )
+        )
+    )
+);
+
+mod tests {
+    #![allow(unused_imports)]
+    use super::*;
+
+    #[test]
+    fn test_parse_comment() {
+        let result = comment(CompleteStr("; this is a test\n"));
+        assert!(result.is_ok());
+        let (rest, content) = result.unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(content, CompleteStr("this is a test"));
+        let result = comment(CompleteStr("this is a test"));
+        assert!(result.is_err());
+    }
+}
@@ -1,4 +1,11 @@
+use crate::assembler::comment_parsers::comment;
+use crate::assembler::instruction_parsers::AssemblerInstruction;
 use crate::assembler::label_parsers::label_declaration;
+use crate::assembler::operand_parsers::operand;
+use crate::assembler::Token;
+
+use nom::alpha1;
+use nom::types::CompleteStr;
 
 named!(directive_declaration<CompleteStr, Token>,
   do_parse!(
@@ -13,11 +20,13 @@ named!(directive_declaration<CompleteStr, Token>,
 named!(directive_combined<CompleteStr, AssemblerInstruction>,
     ws!(
         do_parse!(
+            opt!(comment) >>
             l: opt!(label_declaration) >>
             name: directive_declaration >>
             o1: opt!(operand) >>
             o2: opt!(operand) >>
             o3: opt!(operand) >>
+            opt!(comment) >>
             (
                 AssemblerInstruction{
                     opcode: None,
@@ -32,7 +41,7 @@ named!(directive_combined<CompleteStr, AssemblerInstruction>,
     )
 );
 
-/// Will try to parse out any of the Directive forms
+// Will try to parse out any of the Directive forms
 named!(pub directive<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
@@ -52,7 +61,7 @@ mod tests {
     #[test]
     fn test_string_directive() {
         let result = directive_combined(CompleteStr("test: .asciiz 'Hello'"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (_, directive) = result.unwrap();
 
         // Yes, this is the what the result should be
@@ -61,9 +70,10 @@ mod tests {
                 opcode: None,
                 label: Some(
                     Token::LabelDeclaration {
-                        name: "test".to_string()
+                        name: "test".to_string(),
+                        escaped: false
                     }),
-                directive: Some(d
+                directive: Some(
                     Token::Directive {
                         name: "asciiz".to_string()
                     }),
@@ -1,10 +1,16 @@
-use crate::assembler::opcode_parsers::*;
-use crate::assembler::operand_parsers::{integer_operand, operand};
-use crate::assembler::register_parsers::register;
+use crate::assembler::comment_parsers::comment;
+use crate::assembler::directive_parsers::directive;
 use crate::assembler::label_parsers::label_declaration;
+use crate::assembler::opcode_parsers::*;
+use crate::assembler::operand_parsers::operand;
+use crate::assembler::symbols::SymbolTable;
 use crate::assembler::Token;
+use crate::encoding::{push_code_f64, push_code_i32, push_code_u16};
+use crate::instruction::{
+    Opcode, HLT_WITHOUT_CODE, HLT_WITH_CODE, RAND_BOUNDED, RAND_UNBOUNDED, RECV_POLL, RECV_WAIT, SHIFT_IMMEDIATE, SHIFT_REGISTER,
+};
+use crate::vm::traps::TRAP_COUNT;
 
-use nom::multispace;
 use nom::types::CompleteStr;
 
 #[derive(Debug, PartialEq)]
@@ -18,50 +24,166 @@ pub struct AssemblerInstruction {
 }
 
 impl AssemblerInstruction {
+    /// Encodes the instruction as though it were placed at address 0
     pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+        self.to_bytes_at(symbols, 0)
+    }
+
+    /// Encodes the instruction placed at `address`, which relative jumps need to work out how far away their
+    /// target is
+    pub fn to_bytes_at(&self, symbols: &SymbolTable, address: u32) -> Vec<u8> {
+        if self.is_opcode_of(Opcode::LI) {
+            return self.expand_load_immediate(symbols);
+        }
+
         let mut results: Vec<u8> = vec![];
         if let Some(ref token) = self.opcode {
             match token {
-                Token::Op { code } => match code {
-                    _ => {
-                        let b: u8 = (*code).into();
-                        results.push(b);
-                    }
-                },
+                Token::Op { code } => {
+                    let b: u8 = (*code).into();
+                    results.push(b);
+                }
                 _ => {
                     println!("Non-opcode found in opcode field");
                 }
             }
         }
-        for operand in &[&self.operand1, &self.operand2, &self.operand3] {
-            if let Some(token) = operand {
-                AssemblerInstruction::extract_operand(token, &mut results, symbols);
+
+        // Immediates that don't fit in an instruction are placed in the words directly following it
+        let mut wide_operands = vec![];
+
+        if self.is_shift() {
+            self.extract_shift_operands(&mut results);
+        } else if self.is_memory_access() {
+            self.extract_memory_operands(&mut results);
+        } else if self.is_vector() {
+            self.extract_vector_operands(&mut results);
+        } else if self.is_opcode_of(Opcode::SETTRAP) {
+            self.extract_trap_operands(&mut results, symbols);
+        } else if self.is_opcode_of(Opcode::RAND) {
+            self.extract_random_operands(&mut results);
+        } else if self.is_opcode_of(Opcode::BR) {
+            self.extract_relative_operand(&mut results, symbols, address);
+        } else if self.is_opcode_of(Opcode::HLT) {
+            self.extract_halt_operands(&mut results);
+        } else if self.is_opcode_of(Opcode::RECV) {
+            self.extract_receive_operands(&mut results);
+        } else {
+            for token in [&self.operand1, &self.operand2, &self.operand3].iter().copied().flatten() {
+                match token {
+                    Token::FloatOperand { value } => {
+                        push_code_f64(&mut wide_operands, *value);
+                    }
+                    _ => AssemblerInstruction::extract_operand(token, &mut results, symbols),
+                }
             }
         }
+
         while results.len() < 4 {
             results.push(0);
         }
 
+        results.append(&mut wide_operands);
+        results
+    }
+
+    /// Encodes the instruction for a program using the wide encoding: a 32-bit immediate followed by the usual
+    /// encoding, whose own immediate is cut down to 16 bits. LI isn't needed to load big values in wide programs, so
+    /// it becomes a plain LOAD.
+    pub fn to_wide_bytes_at(&self, symbols: &SymbolTable, address: u32) -> Vec<u8> {
+        let immediate = self.wide_immediate(symbols, address);
+        let mut results = vec![];
+        push_code_i32(&mut results, immediate);
+
+        if self.is_opcode_of(Opcode::LI) {
+            let register = match self.operand1 {
+                Some(Token::Register { reg_num }) => reg_num,
+                _ => 0,
+            };
+            results.extend_from_slice(&[Opcode::LOAD.into(), register]);
+            push_code_u16(&mut results, immediate as u16);
+            return results;
+        }
+
+        results.append(&mut self.to_bytes_at(symbols, address));
         results
     }
 
+    /// The value that goes in the 32-bit immediate of a wide instruction, which is its last integer or label operand.
+    /// For BR, that's the offset to the label from the start of the instruction.
+    fn wide_immediate(&self, symbols: &SymbolTable, address: u32) -> i32 {
+        let operand = [&self.operand3, &self.operand2, &self.operand1]
+            .iter()
+            .copied()
+            .flatten()
+            .find(|token| matches!(token, Token::IntegerOperand { .. } | Token::LabelUsage { .. }));
+
+        match operand {
+            Some(Token::IntegerOperand { value }) => *value,
+            Some(Token::LabelUsage { name }) => {
+                let value = symbols.symbol_value(name).unwrap_or(0);
+                if self.is_opcode_of(Opcode::BR) {
+                    value.wrapping_sub(address) as i32
+                } else {
+                    value as i32
+                }
+            }
+            _ => 0,
+        }
+    }
+
+    /// True if the instruction has an integer operand that doesn't fit in its 16-bit immediate, so the program has to
+    /// use the wide encoding. Only BR sign-extends the immediate, and LI splits its value over LUI and ORI, so for
+    /// every other opcode a negative number doesn't fit: the VM would read -4 back as 65532.
+    pub fn needs_wide_immediate(&self) -> bool {
+        let signed = self.is_opcode_of(Opcode::BR) || self.is_opcode_of(Opcode::LI);
+        let min = if signed { i32::from(i16::MIN) } else { 0 };
+        [&self.operand1, &self.operand2, &self.operand3].iter().copied().flatten().any(|token| match token {
+            Token::IntegerOperand { value } => *value < min || *value > i32::from(u16::MAX),
+            _ => false,
+        })
+    }
+
+    /// Number of bytes this instruction takes up in a program using the wide encoding
+    pub fn wide_byte_length(&self) -> u32 {
+        if !self.is_opcode() {
+            return 0;
+        }
+
+        if self.is_opcode_of(Opcode::LI) {
+            return 8;
+        }
+
+        4 + self.byte_length()
+    }
+
+    /// Number of bytes this instruction takes up once assembled
+    pub fn byte_length(&self) -> u32 {
+        if !self.is_opcode() {
+            return 0;
+        }
+
+        if self.is_opcode_of(Opcode::LI) {
+            return 8;
+        }
+
+        let wide_operands = [&self.operand1, &self.operand2, &self.operand3]
+            .iter()
+            .filter(|operand| matches!(operand, Some(Token::FloatOperand { .. })))
+            .count() as u32;
+
+        4 + wide_operands * 8
+    }
+
     fn extract_operand(t: &Token, results: &mut Vec<u8>, symbols: &SymbolTable) {
         match t {
             Token::Register { reg_num } => {
                 results.push(*reg_num);
             }
-            Token::IntegerOperand { value } => {
-                let mut wtr = vec![];
-                wtr.write_i16::<LittleEndian>(*value as i16).unwrap();
-                results.push(wtr[1]);
-                results.push(wtr[0]);
-            }
+            Token::IntegerOperand { value } => push_code_u16(results, *value as u16),
             Token::LabelUsage { name } => {
                 if let Some(value) = symbols.symbol_value(name) {
-                    let mut wtr = vec![];
-                    wtr.write_u32::<LittleEndian>(value).unwrap();
-                    results.push(wtr[1]);
-                    results.push(wtr[0]);
+                    push_code_u16(results, value as u16);
                 } else {
                     error!("No value found for {:?}", name);
                 }
@@ -71,15 +193,504 @@ impl AssemblerInstruction {
             }
         };
     }
+
+    /// Shifts are encoded as the target register, the amount (an immediate or a register number), and a byte that
+    /// says which of the two forms the amount is in: `shl $0 #4` and `shl $0 $1`
+    fn extract_shift_operands(&self, results: &mut Vec<u8>) {
+        let target = match self.operand1 {
+            Some(Token::Register { reg_num }) => reg_num,
+            _ => {
+                error!("Shift target must be a register: {:?}", self.operand1);
+                return;
+            }
+        };
+
+        match self.operand2 {
+            Some(Token::IntegerOperand { value }) => {
+                results.push(target);
+                results.push(value as u8);
+                results.push(SHIFT_IMMEDIATE);
+            }
+            Some(Token::Register { reg_num }) => {
+                results.push(target);
+                results.push(reg_num);
+                results.push(SHIFT_REGISTER);
+            }
+            _ => {
+                error!("Shift amount must be an integer or a register: {:?}", self.operand2);
+            }
+        }
+    }
+
+    /// Loads and stores are encoded as the data register, the address register and a one byte offset, which is
+    /// zero when it is left out: `lw $0 $1 #4` and `sw $0 $1`
+    fn extract_memory_operands(&self, results: &mut Vec<u8>) {
+        match (&self.operand1, &self.operand2) {
+            (Some(Token::Register { reg_num: data }), Some(Token::Register { reg_num: address })) => {
+                results.push(*data);
+                results.push(*address);
+            }
+            _ => {
+                error!("Memory accesses take a data register and an address register: {:?}", self);
+                return;
+            }
+        }
+
+        match self.operand3 {
+            Some(Token::IntegerOperand { value }) => results.push(value as u8),
+            _ => results.push(0),
+        }
+    }
+
+    /// Vector instructions take the first register of the target range, the first of the source range and how many
+    /// registers are in each: `vadd $0 $4 #4`
+    fn extract_vector_operands(&self, results: &mut Vec<u8>) {
+        match (&self.operand1, &self.operand2, &self.operand3) {
+            (Some(Token::Register { reg_num: target }), Some(Token::Register { reg_num: source }), Some(Token::IntegerOperand { value })) => {
+                results.extend_from_slice(&[*target, *source, *value as u8]);
+            }
+            _ => {
+                error!("Vector instructions take a target register, a source register and a count: {:?}", self);
+            }
+        }
+    }
+
+    /// Expands `li $0 #value` or `li $0 @symbol` into `lui $0 #upper` followed by `ori $0 #lower`, so any 32-bit
+    /// value can be loaded. Symbols are looked up here, once their values are known.
+    fn expand_load_immediate(&self, symbols: &SymbolTable) -> Vec<u8> {
+        let register = match self.operand1 {
+            Some(Token::Register { reg_num }) => reg_num,
+            _ => {
+                error!("LI target must be a register: {:?}", self.operand1);
+                0
+            }
+        };
+
+        let value = match &self.operand2 {
+            Some(Token::IntegerOperand { value }) => *value as u32,
+            Some(Token::LabelUsage { name }) => symbols.symbol_value(name).unwrap_or_else(|| {
+                error!("No value found for {:?}", name);
+                0
+            }),
+            _ => {
+                error!("LI value must be an integer or a symbol: {:?}", self.operand2);
+                0
+            }
+        };
+
+        let mut results = vec![Opcode::LUI.into(), register];
+        push_code_u16(&mut results, (value >> 16) as u16);
+        results.push(Opcode::ORI.into());
+        results.push(register);
+        push_code_u16(&mut results, value as u16);
+        results
+    }
+
+    /// SETTRAP is encoded as a one byte trap number followed by the two byte handler address: `settrap #0 @handler`
+    fn extract_trap_operands(&self, results: &mut Vec<u8>, symbols: &SymbolTable) {
+        match (&self.operand1, &self.operand2) {
+            (Some(Token::IntegerOperand { value }), Some(handler)) => {
+                results.push(*value as u8);
+                AssemblerInstruction::extract_operand(handler, results, symbols);
+            }
+            _ => {
+                error!("SETTRAP takes a trap number and a handler address: {:?}", self);
+            }
+        }
+    }
+
+    /// RAND is encoded as the target register, the bound register and a byte saying whether there is a bound, so
+    /// `rand $0` and `rand $0 $0` can be told apart
+    fn extract_random_operands(&self, results: &mut Vec<u8>) {
+        match (&self.operand1, &self.operand2) {
+            (Some(Token::Register { reg_num: target }), None) => {
+                results.extend_from_slice(&[*target, 0, RAND_UNBOUNDED]);
+            }
+            (Some(Token::Register { reg_num: target }), Some(Token::Register { reg_num: bound })) => {
+                results.extend_from_slice(&[*target, *bound, RAND_BOUNDED]);
+            }
+            _ => {
+                error!("RAND takes a target register and an optional bound register: {:?}", self);
+            }
+        }
+    }
+
+    /// `hlt $0` is encoded as the register holding the exit code, an unused byte and a byte saying there is an exit
+    /// code, so it can be told apart from a plain `hlt`
+    fn extract_halt_operands(&self, results: &mut Vec<u8>) {
+        match self.operand1 {
+            Some(Token::Register { reg_num }) => results.extend_from_slice(&[reg_num, 0, HLT_WITH_CODE]),
+            _ => results.extend_from_slice(&[0, 0, HLT_WITHOUT_CODE]),
+        }
+    }
+
+    /// RECV is encoded as the target register, an unused byte and a byte saying whether to wait for a message, which
+    /// it does unless given `#0`: `recv $0` and `recv $0 #0`
+    fn extract_receive_operands(&self, results: &mut Vec<u8>) {
+        match (&self.operand1, &self.operand2) {
+            (Some(Token::Register { reg_num }), Some(Token::IntegerOperand { value: 0 })) => {
+                results.extend_from_slice(&[*reg_num, 0, RECV_POLL]);
+            }
+            (Some(Token::Register { reg_num }), _) => results.extend_from_slice(&[*reg_num, 0, RECV_WAIT]),
+            _ => {
+                error!("RECV takes a target register and whether to wait: {:?}", self);
+            }
+        }
+    }
+
+    /// BR is encoded as a signed two byte offset from its own address. Labels are turned into the offset to them
+    /// from `address`: `br @loop` and `br #-8`
+    fn extract_relative_operand(&self, results: &mut Vec<u8>, symbols: &SymbolTable, address: u32) {
+        let offset = match &self.operand1 {
+            Some(Token::IntegerOperand { value }) => i64::from(*value),
+            Some(Token::LabelUsage { name }) => match symbols.symbol_value(name) {
+                Some(target) => i64::from(target) - i64::from(address),
+                None => {
+                    error!("No value found for {:?}", name);
+                    return;
+                }
+            },
+            _ => {
+                error!("BR takes an offset or a label: {:?}", self.operand1);
+                return;
+            }
+        };
+
+        if offset < i64::from(i16::MIN) || offset > i64::from(i16::MAX) {
+            error!("Relative jump of {} bytes doesn't fit in 16 bits", offset);
+            return;
+        }
+
+        push_code_u16(results, offset as i16 as u16);
+    }
+
+    /// Checks that the operands are of a form the opcode can be encoded with. Returns a description of the
+    /// problem if they are not.
+    pub fn validate_operands(&self) -> Result<(), String> {
+        if self.is_shift() {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                (Some(Token::Register { .. }), Some(Token::IntegerOperand { value }), None) => {
+                    if *value < 0 || *value > 31 {
+                        return Err(format!("Shift amount must be between 0 and 31, found {}", value));
+                    }
+                }
+                _ => {
+                    return Err("Shifts take a target register and an integer or register amount".to_string());
+                }
+            }
+        }
+
+        if self.is_memory_access() {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), Some(Token::IntegerOperand { value })) => {
+                    if *value < 0 || *value > 255 {
+                        return Err(format!("Memory offset must be between 0 and 255, found {}", value));
+                    }
+                }
+                _ => {
+                    return Err("Memory accesses take a data register, an address register and an optional offset".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::SYSCALL) {
+            match (&self.operand1, &self.operand2) {
+                (Some(Token::IntegerOperand { value }), None) => {
+                    if *value < 0 || *value > i32::from(u16::MAX) {
+                        return Err(format!("Syscall number must be between 0 and {}, found {}", u16::MAX, value));
+                    }
+                }
+                _ => {
+                    return Err("SYSCALL takes the syscall number as an integer, such as syscall #1".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::BR) {
+            match (&self.operand1, &self.operand2) {
+                (Some(Token::IntegerOperand { value }), None) => {
+                    if *value < i32::from(i16::MIN) || *value > i32::from(i16::MAX) {
+                        return Err(format!("Relative jump must be between {} and {} bytes, found {}", i16::MIN, i16::MAX, value));
+                    }
+                }
+                (Some(Token::LabelUsage { .. }), None) => {}
+                _ => {
+                    return Err("BR takes a label or a signed offset, such as br @loop or br #-8".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::CLOOP) {
+            match (&self.operand1, &self.operand2) {
+                (Some(Token::IntegerOperand { value }), None) => {
+                    if *value < 0 || *value > i32::from(u16::MAX) {
+                        return Err(format!("Loop count must be between 0 and {}, found {}", u16::MAX, value));
+                    }
+                }
+                _ => {
+                    return Err("CLOOP takes the number of iterations as an integer, such as cloop #10".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::LOOP) {
+            match (&self.operand1, &self.operand2) {
+                (Some(Token::LabelUsage { .. }), None) | (Some(Token::IntegerOperand { .. }), None) => {}
+                _ => {
+                    return Err("LOOP takes the address to jump back to, such as loop @top".to_string());
+                }
+            }
+        }
+
+        if self.is_vector() {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { reg_num: target }), Some(Token::Register { reg_num: source }), Some(Token::IntegerOperand { value })) => {
+                    if *value < 1 || i32::from((*target).max(*source)) + *value > 32 {
+                        return Err(format!("Vector of {} registers from ${} and ${} doesn't fit in $0-$31", value, target, source));
+                    }
+                }
+                _ => {
+                    return Err("Vector instructions take a target register, a source register and a count, such as vadd $0 $4 #4".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::HLT) {
+            match (&self.operand1, &self.operand2) {
+                (None, None) | (Some(Token::Register { .. }), None) => {}
+                _ => {
+                    return Err("HLT takes an optional register holding the exit code, such as hlt $0".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::RAND) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), None, None) | (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                _ => {
+                    return Err("RAND takes a target register and an optional bound register, such as rand $0 $1".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::SPAWN) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::LabelUsage { .. }), None)
+                | (Some(Token::Register { .. }), Some(Token::IntegerOperand { .. }), None) => {}
+                _ => {
+                    return Err("SPAWN takes a register for the thread's id and where it starts, such as spawn $0 @worker".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::SEND) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                _ => {
+                    return Err("SEND takes a register with the VM to send to and one with the message, such as send $0 $1".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::RECV) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), None, None) => {}
+                (Some(Token::Register { .. }), Some(Token::IntegerOperand { value }), None) => {
+                    if *value != i32::from(RECV_POLL) && *value != i32::from(RECV_WAIT) {
+                        return Err(format!("RECV waits for a message with #1 or only checks for one with #0, found {}", value));
+                    }
+                }
+                _ => {
+                    return Err("RECV takes a target register and whether to wait, such as recv $0 or recv $0 #0".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::RDCYCLE) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), None, None) => {}
+                _ => return Err("RDCYCLE takes the register to read the count into, such as rdcycle $0".to_string()),
+            }
+        }
+
+        if self.is_opcode_of(Opcode::JMPR) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                _ => {
+                    return Err("JMPR takes a base register and an offset register, such as jmpr $1 $2".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::HCALL) {
+            match (&self.operand1, &self.operand2) {
+                (Some(Token::IntegerOperand { value }), None) => {
+                    if *value < 0 || *value > i32::from(u16::MAX) {
+                        return Err(format!("Host function number must be between 0 and {}, found {}", u16::MAX, value));
+                    }
+                }
+                _ => {
+                    return Err("HCALL takes the host function number as an integer, such as hcall #1".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::SETTRAP) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::IntegerOperand { value }), Some(Token::LabelUsage { .. }), None)
+                | (Some(Token::IntegerOperand { value }), Some(Token::IntegerOperand { .. }), None) => {
+                    if *value < 0 || *value as usize >= TRAP_COUNT {
+                        return Err(format!("Trap number must be between 0 and {}, found {}", TRAP_COUNT - 1, value));
+                    }
+                }
+                _ => {
+                    return Err("SETTRAP takes a trap number and a handler, such as settrap #0 @handler".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::LI) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::IntegerOperand { .. }), None)
+                | (Some(Token::Register { .. }), Some(Token::LabelUsage { .. }), None) => {}
+                _ => {
+                    return Err("LI takes a register and an integer or symbol, such as li $0 #100000".to_string());
+                }
+            }
+        }
+
+        // LOAD's immediate is unsigned, so #-4 would load 65532
+        if self.is_opcode_of(Opcode::LOAD) {
+            if let Some(Token::IntegerOperand { value }) = &self.operand2 {
+                if *value < 0 {
+                    return Err(format!("LOAD can't load a negative number, found {}. Use li instead.", value));
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::LUI) || self.is_opcode_of(Opcode::ORI) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::IntegerOperand { value }), None) => {
+                    if *value < 0 || *value > i32::from(u16::MAX) {
+                        return Err(format!("Immediate must be between 0 and {}, found {}", u16::MAX, value));
+                    }
+                }
+                (Some(Token::Register { .. }), Some(Token::LabelUsage { .. }), None) => {}
+                _ => {
+                    return Err("LUI and ORI take a register and a 16-bit immediate".to_string());
+                }
+            }
+        }
+
+        if self.is_opcode_of(Opcode::ALOC) {
+            match (&self.operand1, &self.operand2, &self.operand3) {
+                (Some(Token::Register { .. }), Some(Token::Register { .. }), None) => {}
+                _ => {
+                    return Err("ALOC takes a register with the number of bytes and a register for the address".to_string());
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn is_shift(&self) -> bool {
+        self.is_opcode_of(Opcode::SHL) || self.is_opcode_of(Opcode::SHR)
+    }
+
+    fn is_memory_access(&self) -> bool {
+        self.is_opcode_of(Opcode::LW) || self.is_opcode_of(Opcode::SW)
+    }
+
+    fn is_vector(&self) -> bool {
+        self.is_opcode_of(Opcode::VADD) || self.is_opcode_of(Opcode::VMUL)
+    }
+
+    fn is_opcode_of(&self, opcode: Opcode) -> bool {
+        match self.opcode {
+            Some(Token::Op { code }) => code == opcode,
+            _ => false,
+        }
+    }
+
+    pub fn is_label(&self) -> bool {
+        self.label.is_some()
+    }
+
+    pub fn is_opcode(&self) -> bool {
+        self.opcode.is_some()
+    }
+
+    pub fn is_directive(&self) -> bool {
+        self.directive.is_some()
+    }
+
+    pub fn has_operands(&self) -> bool {
+        self.operand1.is_some() || self.operand2.is_some() || self.operand3.is_some()
+    }
+
+    /// Returns true if any of the operands refers to the symbol with the given name
+    pub fn uses_symbol(&self, symbol: &str) -> bool {
+        [&self.operand1, &self.operand2, &self.operand3].iter().any(|operand| match operand {
+            Some(Token::LabelUsage { name }) => name == symbol,
+            _ => false,
+        })
+    }
+
+    /// Replaces the operands referring to the symbol with the given name with an integer
+    pub fn replace_symbol(&mut self, symbol: &str, value: i32) {
+        for operand in [&mut self.operand1, &mut self.operand2, &mut self.operand3] {
+            if matches!(operand, Some(Token::LabelUsage { name }) if name == symbol) {
+                *operand = Some(Token::IntegerOperand { value });
+            }
+        }
+    }
+
+    pub fn get_label_name(&self) -> Option<String> {
+        match &self.label {
+            Some(Token::LabelDeclaration { name, .. }) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns true if the instruction declares a label written in backticks, which the label rules don't apply to
+    pub fn has_escaped_label(&self) -> bool {
+        matches!(self.label, Some(Token::LabelDeclaration { escaped: true, .. }))
+    }
+
+    pub fn get_directive_name(&self) -> Option<String> {
+        match &self.directive {
+            Some(Token::Directive { name }) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_string_constant(&self) -> Option<String> {
+        match &self.operand1 {
+            Some(Token::IrString { name }) => Some(name.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn get_integer_constant(&self) -> Option<i32> {
+        match &self.operand1 {
+            Some(Token::IntegerOperand { value }) => Some(*value),
+            _ => None,
+        }
+    }
 }
 
 named!(pub instruction_combined<CompleteStr, AssemblerInstruction>,
     do_parse!(
+        opt!(comment) >>
         l: opt!(label_declaration) >>
         o: opcode >>
         o1: opt!(operand) >>
         o2: opt!(operand) >>
         o3: opt!(operand) >>
+        opt!(comment) >>
         (
             AssemblerInstruction{
                 opcode: Some(o),
@@ -93,11 +704,12 @@ named!(pub instruction_combined<CompleteStr, AssemblerInstruction>,
     )
 );
 
-/// Will try to parse out any of the Instruction forms
+// Will try to parse out any of the Instruction forms
 named!(pub instruction<CompleteStr, AssemblerInstruction>,
     do_parse!(
         ins: alt!(
-            instruction_combined
+            instruction_combined |
+            directive
         ) >>
         (
             ins
@@ -108,6 +720,7 @@ named!(pub instruction<CompleteStr, AssemblerInstruction>,
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
     use crate::instruction::Opcode;
 
     #[test]
@@ -224,6 +837,145 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_shift_immediate_to_bytes() {
+        let (_, instruction) = instruction_combined(CompleteStr("shl $0 #4\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![33, 0, 4, SHIFT_IMMEDIATE]);
+    }
+
+    #[test]
+    fn test_shift_register_to_bytes() {
+        let (_, instruction) = instruction_combined(CompleteStr("shr $2 $1\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![34, 2, 1, SHIFT_REGISTER]);
+    }
+
+    #[test]
+    fn test_halt_to_bytes() {
+        let (_, instruction) = instruction_combined(CompleteStr("hlt $3\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![5, 3, 0, HLT_WITH_CODE]);
+        let (_, instruction) = instruction_combined(CompleteStr("hlt\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![5, 0, 0, HLT_WITHOUT_CODE]);
+        let (_, instruction) = instruction_combined(CompleteStr("hlt #3\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_shift_invalid_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("shl #4 $0\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+        let (_, instruction) = instruction_combined(CompleteStr("shl $0 #32\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+        let (_, instruction) = instruction_combined(CompleteStr("shl $0 $1 $2\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_memory_access_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("lw $0 $1 #8\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![49, 0, 1, 8]);
+        let (_, instruction) = instruction_combined(CompleteStr("sw $2 $3\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![50, 2, 3, 0]);
+        let (_, instruction) = instruction_combined(CompleteStr("sw $2 $3 #256\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+        let (_, instruction) = instruction_combined(CompleteStr("lw $2 #3\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_syscall_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("syscall #3\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![51, 0, 3, 0]);
+        let (_, instruction) = instruction_combined(CompleteStr("syscall $3\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_settrap_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("settrap #2 #300\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![53, 2, 1, 44]);
+        let (_, instruction) = instruction_combined(CompleteStr("settrap #5 @handler\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+        let (_, instruction) = instruction_combined(CompleteStr("settrap $0 @handler\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_load_negative_immediate() {
+        let (_, instruction) = instruction_combined(CompleteStr("load $0 #-4\n")).unwrap();
+        assert!(instruction.validate_operands().unwrap_err().contains("Use li instead"));
+        let (_, instruction) = instruction_combined(CompleteStr("load $0 #65535\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+    }
+
+    #[test]
+    fn test_needs_wide_immediate() {
+        let needs_wide = |source: &str| instruction_combined(CompleteStr(source)).unwrap().1.needs_wide_immediate();
+        assert!(!needs_wide("load $0 #65535\n"));
+        assert!(needs_wide("load $0 #65536\n"));
+        assert!(needs_wide("loop #-4\n"));
+        assert!(!needs_wide("br #-8\n"));
+        assert!(!needs_wide("li $0 #-2\n"));
+        assert!(needs_wide("br #-32769\n"));
+    }
+
+    #[test]
+    fn test_li_expands_to_lui_and_ori() {
+        let (_, instruction) = instruction_combined(CompleteStr("li $3 #305419896\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.byte_length(), 8);
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![39, 3, 0x12, 0x34, 60, 3, 0x56, 0x78]);
+
+        let (_, instruction) = instruction_combined(CompleteStr("li $0 #-2\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![39, 0, 255, 255, 60, 0, 255, 254]);
+    }
+
+    #[test]
+    fn test_li_with_symbol() {
+        use crate::assembler::symbols::{Symbol, SymbolType};
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset("big".to_string(), SymbolType::Integer, 70000));
+        let (_, instruction) = instruction_combined(CompleteStr("li $1 @big\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&symbols), vec![39, 1, 0, 1, 60, 1, 17, 112]);
+    }
+
+    #[test]
+    fn test_lui_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("lui $1 #65535\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        let (_, instruction) = instruction_combined(CompleteStr("ori $1 #65536\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_aloc_operands() {
+        let (_, instruction) = instruction_combined(CompleteStr("aloc $0 $1\n")).unwrap();
+        assert!(instruction.validate_operands().is_ok());
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![17, 0, 1, 0]);
+        let (_, instruction) = instruction_combined(CompleteStr("aloc #10 $1\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_loadf64_to_bytes() {
+        let (_, instruction) = instruction_combined(CompleteStr("loadf64 $1 #2.5\n")).unwrap();
+        assert_eq!(instruction.byte_length(), 12);
+        let bytes = instruction.to_bytes(&SymbolTable::new());
+        assert_eq!(bytes.len(), 12);
+        assert_eq!(bytes[0..4], [22, 1, 0, 0]);
+        assert_eq!(bytes[4..12], [64, 4, 0, 0, 0, 0, 0, 0]);
+        let (_, instruction) = instruction_combined(CompleteStr("loadf64 $1 #-1.5\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new())[4..12], (-1.5f64).to_be_bytes());
+    }
+
     #[test]
     fn test_parse_cloop() {
         let result = instruction_combined(CompleteStr("cloop #10\n"));
@@ -243,6 +995,89 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_conditional_jumps() {
+        let jumps = [
+            ("jz $1\n", Opcode::JZ),
+            ("jnz $1\n", Opcode::JNZ),
+            ("jc $1\n", Opcode::JC),
+            ("jo $1\n", Opcode::JO),
+            ("jno $1\n", Opcode::JNO),
+        ];
+        for (source, code) in jumps {
+            let (_, instruction) = instruction_combined(CompleteStr(source)).unwrap();
+            assert_eq!(instruction.opcode, Some(Token::Op { code }));
+            assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![code.into(), 1, 0, 0]);
+        }
+    }
+
+    #[test]
+    fn test_parse_jmpr() {
+        let (_, instruction) = instruction_combined(CompleteStr("jmpr $1 $2\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![61, 1, 2, 0]);
+        assert!(instruction.validate_operands().is_ok());
+
+        let (_, instruction) = instruction_combined(CompleteStr("jmpr $1 #2\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_parse_br() {
+        let (_, instruction) = instruction_combined(CompleteStr("br #-8\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![62, 255, 248, 0]);
+        assert!(instruction.validate_operands().is_ok());
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset("top".to_string(), SymbolType::Label, 100));
+        let (_, instruction) = instruction_combined(CompleteStr("br @top\n")).unwrap();
+        assert_eq!(instruction.to_bytes_at(&symbols, 112), vec![62, 255, 244, 0]);
+        assert_eq!(instruction.to_bytes_at(&symbols, 80), vec![62, 0, 20, 0]);
+
+        let (_, instruction) = instruction_combined(CompleteStr("br $1\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_parse_rand() {
+        let (_, instruction) = instruction_combined(CompleteStr("rand $2\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![65, 2, 0, 0]);
+        let (_, instruction) = instruction_combined(CompleteStr("rand $2 $0\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![65, 2, 0, 1]);
+        assert!(instruction.validate_operands().is_ok());
+
+        let (_, instruction) = instruction_combined(CompleteStr("rand $2 #6\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_parse_vector() {
+        let (_, instruction) = instruction_combined(CompleteStr("vadd $0 $4 #4\n")).unwrap();
+        assert_eq!(instruction.to_bytes(&SymbolTable::new()), vec![67, 0, 4, 4]);
+        assert!(instruction.validate_operands().is_ok());
+
+        let (_, instruction) = instruction_combined(CompleteStr("vmul $28 $0 #8\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+        let (_, instruction) = instruction_combined(CompleteStr("vmul $0 $4\n")).unwrap();
+        assert!(instruction.validate_operands().is_err());
+    }
+
+    #[test]
+    fn test_wide_encoding() {
+        let (_, instruction) = instruction_combined(CompleteStr("load $1 #100000\n")).unwrap();
+        assert!(instruction.needs_wide_immediate());
+        assert_eq!(instruction.wide_byte_length(), 8);
+        assert_eq!(instruction.to_wide_bytes_at(&SymbolTable::new(), 0), vec![0, 1, 134, 160, 0, 1, 134, 160]);
+
+        let (_, instruction) = instruction_combined(CompleteStr("li $1 #-2\n")).unwrap();
+        assert!(!instruction.needs_wide_immediate());
+        assert_eq!(instruction.to_wide_bytes_at(&SymbolTable::new(), 0), vec![255, 255, 255, 254, 0, 1, 255, 254]);
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset("top".to_string(), SymbolType::Label, 64));
+        let (_, instruction) = instruction_combined(CompleteStr("br @top\n")).unwrap();
+        assert_eq!(instruction.to_wide_bytes_at(&symbols, 80), vec![255, 255, 255, 240, 62, 255, 240, 0]);
+    }
+
     #[test]
     fn test_parse_call() {
         let result = instruction_combined(CompleteStr("call @test\n"));
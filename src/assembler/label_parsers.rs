@@ -1,28 +1,46 @@
+use nom::multispace;
 use nom::types::CompleteStr;
-use nom::{alphanumeric, multispace};
 
 use crate::assembler::Token;
 
-/// Looks for a user-defined label, such as `label1:`
+// Names of labels and symbols are made up of letters, digits and underscores, such as `loop_2` or `__FILE__`
+named!(pub identifier<CompleteStr, CompleteStr>,
+    take_while1!(|c: char| c.is_alphanumeric() || c == '_')
+);
+
+// A name in backticks, such as `load`, can use any characters but backticks and is exempt from the label rules, for
+// names generated by other tools
+named!(pub escaped_identifier<CompleteStr, CompleteStr>,
+    delimited!(
+        tag!("`"),
+        take_while1!(|c: char| c != '`' && c != '\n'),
+        tag!("`")
+    )
+);
+
+// Looks for a user-defined label, such as `label1:`
 named!(pub label_declaration<CompleteStr, Token>,
     ws!(
         do_parse!(
-            name: alphanumeric >>
+            name: alt!(
+                escaped_identifier => { |name: CompleteStr| (name.to_string(), true) } |
+                identifier => { |name: CompleteStr| (name.to_string(), false) }
+            ) >>
             tag!(":") >>
             opt!(multispace) >>
             (
-                Token::LabelDeclaration{ name: name.to_string() }
+                Token::LabelDeclaration{ name: name.0, escaped: name.1 }
             )
         )
     )
 );
 
-/// Looks for a user-defined label, such as `label1:`
+// Looks for a user-defined label, such as `label1:`
 named!(pub label_usage<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("@") >>
-            name: alphanumeric >>
+            name: alt!(escaped_identifier | identifier) >>
             opt!(multispace) >>
             (
                 Token::LabelUsage{ name: name.to_string() }
@@ -39,20 +57,26 @@ mod tests {
     #[test]
     fn test_parse_label_declaration() {
         let result = label_declaration(CompleteStr("test:"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
-        assert_eq!(token, Token::LabelDeclaration { name: "test".to_string() });
+        assert_eq!(token, Token::LabelDeclaration { name: "test".to_string(), escaped: false });
         let result = label_declaration(CompleteStr("test"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
+        let result = label_declaration(CompleteStr("`load.1`:"));
+        assert_eq!(result.unwrap().1, Token::LabelDeclaration { name: "load.1".to_string(), escaped: true });
     }
 
     #[test]
     fn test_parse_label_usage() {
         let result = label_usage(CompleteStr("@test"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::LabelUsage { name: "test".to_string() });
         let result = label_usage(CompleteStr("test"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
+        let result = label_usage(CompleteStr("@__FILE__"));
+        assert_eq!(result.unwrap().1, Token::LabelUsage { name: "__FILE__".to_string() });
+        let result = label_usage(CompleteStr("@`load.1`"));
+        assert_eq!(result.unwrap().1, Token::LabelUsage { name: "load.1".to_string() });
     }
 }
\ No newline at end of file
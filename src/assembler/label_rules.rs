@@ -0,0 +1,66 @@
+use crate::instruction::Opcode;
+
+use nom::types::CompleteStr;
+
+/// Longest label name accepted by default
+pub const DEFAULT_MAX_LABEL_LENGTH: usize = 64;
+
+/// What label names the assembler accepts. Names must start with an ASCII letter or underscore and carry on with
+/// ASCII letters, digits and underscores. Names written in backticks, such as `` `load`: ``, are exempt.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LabelRules {
+    /// Longest name allowed, in bytes
+    pub max_length: usize,
+    /// Whether names that are also opcode mnemonics, such as `load`, are refused
+    pub reserve_mnemonics: bool,
+}
+
+impl Default for LabelRules {
+    fn default() -> Self {
+        LabelRules {
+            max_length: DEFAULT_MAX_LABEL_LENGTH,
+            reserve_mnemonics: true,
+        }
+    }
+}
+
+impl LabelRules {
+    /// Returns why a label name breaks the rules, if it does
+    pub fn check(&self, name: &str) -> Result<(), String> {
+        match name.chars().next() {
+            Some(c) if c.is_ascii_alphabetic() || c == '_' => {}
+            _ => return Err("it must start with a letter or an underscore".to_string()),
+        }
+        if let Some(c) = name.chars().find(|c| !c.is_ascii_alphanumeric() && *c != '_') {
+            return Err(format!("'{}' isn't allowed, only ASCII letters, digits and underscores are", c));
+        }
+        if name.len() > self.max_length {
+            return Err(format!("it is {} characters long, the limit is {}", name.len(), self.max_length));
+        }
+        if self.reserve_mnemonics && Opcode::from(CompleteStr(name)) != Opcode::IGL {
+            return Err("it is the name of an opcode".to_string());
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let rules = LabelRules::default();
+        assert!(rules.check("loop_2").is_ok());
+        assert!(rules.check("_start").is_ok());
+        assert!(rules.check("2fast").is_err());
+        assert!(rules.check("café").is_err());
+        assert_eq!(rules.check("LOAD"), Err("it is the name of an opcode".to_string()));
+        assert!(rules.check(&"a".repeat(65)).is_err());
+
+        let rules = LabelRules { max_length: 4, reserve_mnemonics: false };
+        assert!(rules.check("load").is_ok());
+        assert!(rules.check("loads").is_err());
+    }
+}
@@ -5,13 +5,15 @@ use crate::assembler::Token;
 use crate::instruction::Opcode;
 
 named!(pub opcode<CompleteStr, Token>,
-  do_parse!(
-      opcode: alpha1 >>
-      (
-        {
-            Token::Op { code: Opcode::from(opcode) }
-        }
-      )
+  ws!(
+    do_parse!(
+        opcode: alphanumeric1 >>
+        (
+          {
+              Token::Op { code: Opcode::from(opcode) }
+          }
+        )
+    )
   )
 );
 
@@ -22,10 +24,13 @@ mod tests {
     #[test]
     fn test_opcode() {
         let result = opcode(CompleteStr("load"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (rest, token) = result.unwrap();
         assert_eq!(token, Token::Op { code: Opcode::LOAD });
         assert_eq!(rest, CompleteStr(""));
+        let result = opcode(CompleteStr("loadf64"));
+        let (_, token) = result.unwrap();
+        assert_eq!(token, Token::Op { code: Opcode::LOADF64 });
         let result = opcode(CompleteStr("aold"));
         let (_, token) = result.unwrap();
         assert_eq!(token, Token::Op { code: Opcode::IGL });
@@ -1,17 +1,32 @@
 use nom::digit;
 use nom::types::CompleteStr;
+use crate::assembler::label_parsers::label_usage;
 use crate::assembler::register_parsers::register;
 use crate::assembler::Token;
 
-/// Parser for integer numbers, which we preface with `#` in our assembly language:
-/// #100
+// Parser for integer numbers, which we preface with `#` in our assembly language:
+// #100 or #-100
 named!(pub integer_operand<CompleteStr, Token>,
     ws!(
         do_parse!(
             tag!("#") >>
-            reg_num: digit >>
+            value: map_res!(recognize!(pair!(opt!(tag!("-")), digit)), |value: CompleteStr| value.parse::<i32>()) >>
             (
-                Token::IntegerOperand{ value: reg_num.parse::<i32>().unwrap() }
+                Token::IntegerOperand{ value }
+            )
+        )
+    )
+);
+
+// Parser for floating point numbers, which are also prefaced with `#` but always have a decimal point:
+// #3.14 or #-3.14
+named!(pub float_operand<CompleteStr, Token>,
+    ws!(
+        do_parse!(
+            tag!("#") >>
+            value: recognize!(tuple!(opt!(tag!("-")), digit, tag!("."), digit)) >>
+            (
+                Token::FloatOperand{ value: value.parse::<f64>().unwrap() }
             )
         )
     )
@@ -19,8 +34,10 @@ named!(pub integer_operand<CompleteStr, Token>,
 
 named!(pub operand<CompleteStr, Token>,
     alt!(
+        float_operand |
         integer_operand |
         register |
+        label_usage |
         irstring
     )
 );
@@ -44,19 +61,42 @@ mod tests {
     fn test_parse_integer_operand() {
         // Test a valid integer operand
         let result = integer_operand(CompleteStr("#10"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (rest, value) = result.unwrap();
         assert_eq!(rest, CompleteStr(""));
         assert_eq!(value, Token::IntegerOperand { value: 10 });
 
         // Test an invalid one (missing the #)
         let result = integer_operand(CompleteStr("10"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
+
+        // Negative numbers are allowed, numbers that don't fit in an i32 are not
+        let result = integer_operand(CompleteStr("#-42"));
+        assert_eq!(result.unwrap().1, Token::IntegerOperand { value: -42 });
+        let result = integer_operand(CompleteStr("#99999999999"));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_parse_float_operand() {
+        let result = float_operand(CompleteStr("#2.75"));
+        assert!(result.is_ok());
+        let (rest, value) = result.unwrap();
+        assert_eq!(rest, CompleteStr(""));
+        assert_eq!(value, Token::FloatOperand { value: 2.75 });
+        let result = operand(CompleteStr("#-1.5"));
+        assert_eq!(result.unwrap().1, Token::FloatOperand { value: -1.5 });
+
+        // An integer is not a float, but should still be parsed as an operand
+        let result = float_operand(CompleteStr("#10"));
+        assert!(result.is_err());
+        let result = operand(CompleteStr("#10"));
+        assert_eq!(result.unwrap().1, Token::IntegerOperand { value: 10 });
     }
 
     #[test]
     fn test_parse_string_operand() {
         let result = irstring(CompleteStr("'This is a test'"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
     }
 }
@@ -0,0 +1,72 @@
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Integer symbol holding the version of the assembler, as major * 10000 + minor * 100 + patch
+pub const VERSION_SYMBOL: &str = "__IRIDIUM_VERSION__";
+/// String symbol holding the name of the file being assembled
+pub const FILE_SYMBOL: &str = "__FILE__";
+/// Integer symbol holding the number of the source line it's used on, counting from 1
+pub const LINE_SYMBOL: &str = "__LINE__";
+/// String symbol holding the date the program was assembled on, as YYYY-MM-DD
+pub const DATE_SYMBOL: &str = "__DATE__";
+/// Environment variable that pins `__DATE__` to a Unix time in seconds, so a program can be rebuilt byte for byte
+pub const SOURCE_DATE_EPOCH_VAR: &str = "SOURCE_DATE_EPOCH";
+
+/// Returns the crate version as a single number, so 0.1.2 becomes 102
+pub fn version_number() -> u32 {
+    let mut number = 0;
+
+    for part in env!("CARGO_PKG_VERSION").split('.').take(3) {
+        number = number * 100 + part.parse::<u32>().unwrap_or(0);
+    }
+
+    number
+}
+
+/// Returns today's date (UTC) as YYYY-MM-DD, or the date `SOURCE_DATE_EPOCH` gives if it is set
+pub fn build_date() -> String {
+    let seconds = env::var(SOURCE_DATE_EPOCH_VAR)
+        .ok()
+        .and_then(|value| value.trim().parse::<u64>().ok())
+        .unwrap_or_else(|| SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0));
+    format_date(seconds)
+}
+
+/// Formats a Unix time in seconds as a YYYY-MM-DD date (UTC)
+pub fn format_date(seconds: u64) -> String {
+    let (year, month, day) = civil_date((seconds / 86400) as i64);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+/// Converts a number of days since 1970-01-01 to a (year, month, day) in the proleptic Gregorian calendar
+fn civil_date(days: i64) -> (i64, i64, i64) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_version_number() {
+        assert_eq!(version_number(), 100);
+    }
+
+    #[test]
+    fn test_civil_date() {
+        assert_eq!(civil_date(0), (1970, 1, 1));
+        assert_eq!(civil_date(18_321), (2020, 2, 29));
+        assert_eq!(civil_date(-1), (1969, 12, 31));
+        assert_eq!(build_date().len(), 10);
+        assert_eq!(format_date(1_582_934_400), "2020-02-29");
+    }
+}
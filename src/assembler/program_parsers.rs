@@ -1,18 +1,29 @@
-use crate::assembler::instruction_parsers::{instruction_combined, AssemblerInstruction};
+use crate::assembler::assembler_errors::AssemblerError;
+use crate::assembler::instruction_parsers::{instruction, AssemblerInstruction};
+use crate::assembler::predefined_symbols::LINE_SYMBOL;
+use crate::assembler::symbols::SymbolTable;
 
 use nom::types::CompleteStr;
 
 #[derive(Debug, PartialEq)]
 pub struct Program {
-    instructions: Vec<AssemblerInstruction>,
+    pub instructions: Vec<AssemblerInstruction>,
 }
 
 impl Program {
-    pub fn to_bytes(&self) -> Vec<u8> {
+    pub fn to_bytes(&self, symbols: &SymbolTable) -> Vec<u8> {
+        self.to_bytes_at(symbols, 0)
+    }
+
+    /// Encodes the program to be placed at `base`, such as the end of a program it's being added to
+    pub fn to_bytes_at(&self, symbols: &SymbolTable, base: u32) -> Vec<u8> {
         let mut program = vec![];
 
         for instruction in &self.instructions {
-            program.append(&mut instruction.to_bytes());
+            if instruction.is_opcode() {
+                let address = base + program.len() as u32;
+                program.append(&mut instruction.to_bytes_at(symbols, address));
+            }
         }
 
         program
@@ -21,23 +32,63 @@ impl Program {
 
 named!(pub program<CompleteStr, Program>,
     do_parse!(
-        instructions: many1!(instruction_combined) >>
+        instructions: many1!(instruction) >>
         (
             Program {
-                instructions: instructions
+                instructions
             }
         )
     )
 );
 
+/// Parses a program, carrying on at the next line after a line that can't be parsed, so one mistake doesn't hide the
+/// rest. Returns everything that could be parsed, along with an error for each line that couldn't. Uses of
+/// `__LINE__` are replaced with the number of the line they're on.
+pub fn program_with_recovery(source: &str) -> (Program, Vec<AssemblerError>) {
+    let mut instructions = vec![];
+    let mut errors = vec![];
+    let mut rest = CompleteStr(source);
+    // Number of the line `rest` starts on, counting from 1
+    let mut line = 1;
+
+    while !rest.trim().is_empty() {
+        match instruction(rest) {
+            Ok((remaining, mut parsed)) if remaining.len() < rest.len() => {
+                // The instruction starts after any whitespace the parser skipped
+                let skipped = rest.len() - rest.trim_start().len();
+                if parsed.uses_symbol(LINE_SYMBOL) {
+                    parsed.replace_symbol(LINE_SYMBOL, (line + rest[..skipped].matches('\n').count()) as i32);
+                }
+                instructions.push(parsed);
+                line += rest[..rest.len() - remaining.len()].matches('\n').count();
+                rest = remaining;
+            }
+            _ => {
+                let line_end = rest.find('\n').map_or(rest.len(), |index| index + 1);
+                let text = rest[..line_end].trim();
+
+                // Whitespace before the line and comments on their own at the end aren't mistakes
+                if !text.is_empty() && !text.starts_with(';') {
+                    errors.push(AssemblerError::SyntaxError { line, text: text.to_string() });
+                }
+                line += rest[..line_end].matches('\n').count();
+                rest = CompleteStr(&rest[line_end..]);
+            }
+        }
+    }
+
+    (Program { instructions }, errors)
+}
+
 mod tests {
     #![allow(unused_imports)]
     use super::*;
+    use crate::assembler::Token;
 
     #[test]
     fn test_parse_program() {
         let result = program(CompleteStr("load $0 #100\n"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (leftover, p) = result.unwrap();
         assert_eq!(leftover, CompleteStr(""));
         assert_eq!(1, p.instructions.len());
@@ -47,17 +98,41 @@ mod tests {
     #[test]
     fn test_program_to_bytes() {
         let result = program(CompleteStr("load $0 #100\n"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let (_, program) = result.unwrap();
-        let bytecode = program.to_bytes();
+        let bytecode = program.to_bytes(&SymbolTable::new());
         assert_eq!(bytecode.len(), 4);
         println!("{:?}", bytecode);
     }
 
+    #[test]
+    fn test_program_with_recovery() {
+        let source = ".data\n.code\nload $0 #1\n= nope\nadd $0 $0 $0\n$$$\nhlt\n; the end\n";
+        let (program, errors) = program_with_recovery(source);
+        assert_eq!(program.instructions.len(), 5);
+        let lines: Vec<String> = errors.iter().map(|e| e.to_string()).collect();
+        assert_eq!(lines, vec!["Syntax error on line 4: = nope", "Syntax error on line 6: $$$"]);
+
+        let (program, errors) = program_with_recovery("\n\nhlt\n");
+        assert_eq!(program.instructions.len(), 1);
+        assert!(errors.is_empty());
+
+        let (program, _) = program_with_recovery(".data\n.code\n\n  load $0 @__LINE__\n; note\nload $1 @__LINE__\n");
+        assert_eq!(program.instructions[2].operand2, Some(Token::IntegerOperand { value: 4 }));
+        assert_eq!(program.instructions[3].operand2, Some(Token::IntegerOperand { value: 6 }));
+
+        // Lines are counted as the parser goes, so a long source doesn't take time quadratic in its length
+        let mut source = "load $0 #1\n".repeat(100_000);
+        source.push_str("\n; the end\nload $1 @__LINE__\n$$$\n");
+        let (program, errors) = program_with_recovery(&source);
+        assert_eq!(program.instructions[100_000].operand2, Some(Token::IntegerOperand { value: 100_003 }));
+        assert_eq!(errors[0].to_string(), "Syntax error on line 100004: $$$");
+    }
+
     #[test]
     fn test_complete_program() {
         let test_program = CompleteStr(".data\nhello: .asciiz 'Hello everyone!'\n.code\nhlt");
         let result = program(test_program);
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
     }
 }
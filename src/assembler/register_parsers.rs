@@ -24,10 +24,10 @@ mod tests {
     #[test]
     fn test_parse_register() {
         let result = register(CompleteStr("$0"));
-        assert_eq!(result.is_ok(), true);
+        assert!(result.is_ok());
         let result = register(CompleteStr("0"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
         let result = register(CompleteStr("$a"));
-        assert_eq!(result.is_ok(), false);
+        assert!(result.is_err());
     }
 }
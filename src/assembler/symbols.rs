@@ -21,9 +21,21 @@ impl Symbol {
             offset: Some(offset),
         }
     }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn symbol_type(&self) -> &SymbolType {
+        &self.symbol_type
+    }
+
+    pub fn offset(&self) -> Option<u32> {
+        self.offset
+    }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum SymbolType {
     Label,
     Integer,
@@ -55,7 +67,7 @@ impl SymbolTable {
     }
 
     pub fn set_symbol_offset(&mut self, s: &str, offset: u32) -> bool {
-        for symbol in &self.symbols {
+        for symbol in &mut self.symbols {
             if symbol.name == s {
                 symbol.offset = Some(offset);
                 return true;
@@ -65,6 +77,15 @@ impl SymbolTable {
         false
     }
 
+    /// Shifts the offset of every symbol of the given type by `delta` bytes
+    pub fn offset_symbols(&mut self, symbol_type: &SymbolType, delta: u32) {
+        for symbol in &mut self.symbols {
+            if symbol.symbol_type == *symbol_type {
+                symbol.offset = symbol.offset.map(|offset| offset + delta);
+            }
+        }
+    }
+
     pub fn symbol_value(&self, s: &str) -> Option<u32> {
         for symbol in &self.symbols {
             if symbol.name == s {
@@ -0,0 +1,90 @@
+use std::fmt;
+
+/// Register width of the VM the assembler builds for unless told otherwise, and the only one this VM runs
+pub const DEFAULT_REGISTER_WIDTH: u8 = 32;
+
+/// How the heap of the VM a program is built for behaves
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HeapModel {
+    /// The heap grows as the program allocates, up to whatever limit the VM was given
+    Growable,
+    /// The heap is never bigger than this many bytes, such as on a board with a fixed amount of RAM
+    Fixed(u32),
+}
+
+/// The VM configuration a program was assembled for, kept in its header so a VM can refuse a program built for a
+/// configuration it doesn't have. Written `iridium<width>[-wide][-heap<bytes>]`, such as `iridium32`,
+/// `iridium64-wide` or `iridium32-heap4096`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Target {
+    /// Bits in a register, 32 or 64
+    pub register_width: u8,
+    /// Whether every instruction uses the wide encoding, even when its immediates would fit in 16 bits
+    pub wide: bool,
+    pub heap: HeapModel,
+}
+
+impl Default for Target {
+    fn default() -> Self {
+        Target { register_width: DEFAULT_REGISTER_WIDTH, wide: false, heap: HeapModel::Growable }
+    }
+}
+
+impl Target {
+    pub fn parse(text: &str) -> Result<Target, String> {
+        let invalid = || format!("Expected a target such as iridium32, iridium64-wide or iridium32-heap4096, found {}", text);
+
+        let mut parts = text.split('-');
+        let register_width = match parts.next() {
+            Some("iridium32") => 32,
+            Some("iridium64") => 64,
+            _ => return Err(invalid()),
+        };
+
+        let mut target = Target { register_width, ..Target::default() };
+        for part in parts {
+            match part {
+                "wide" if !target.wide => target.wide = true,
+                _ if part.starts_with("heap") && target.heap == HeapModel::Growable => {
+                    match part["heap".len()..].parse::<u32>() {
+                        Ok(size) if size > 0 => target.heap = HeapModel::Fixed(size),
+                        _ => return Err(invalid()),
+                    }
+                }
+                _ => return Err(invalid()),
+            }
+        }
+        Ok(target)
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "iridium{}", self.register_width)?;
+        if self.wide {
+            write!(f, "-wide")?;
+        }
+        if let HeapModel::Fixed(size) = self.heap {
+            write!(f, "-heap{}", size)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_display() {
+        assert_eq!(Target::parse("iridium32"), Ok(Target::default()));
+        let target = Target::parse("iridium64-wide-heap4096").unwrap();
+        assert_eq!(target, Target { register_width: 64, wide: true, heap: HeapModel::Fixed(4096) });
+        assert_eq!(target.to_string(), "iridium64-wide-heap4096");
+
+        assert!(Target::parse("iridium16").is_err());
+        assert!(Target::parse("iridium32-wide-wide").is_err());
+        assert!(Target::parse("iridium32-heap0").is_err());
+        assert!(Target::parse("iridium32-heap").is_err());
+    }
+}
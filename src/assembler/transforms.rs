@@ -0,0 +1,72 @@
+use crate::assembler::program_parsers::Program;
+
+use std::fmt;
+
+/// Rewrites source before the assembler parses it, for preprocessing such as templating or expanding custom
+/// directives into ones the parser knows. Closures taking and returning the source implement it.
+pub trait SourceTransform {
+    /// Returns the rewritten source, or why it couldn't be rewritten
+    fn transform(&self, source: &str) -> Result<String, String>;
+}
+
+impl<F> SourceTransform for F
+where
+    F: Fn(&str) -> Result<String, String>,
+{
+    fn transform(&self, source: &str) -> Result<String, String> {
+        self(source)
+    }
+}
+
+/// Rewrites the parsed program before the assembler lays it out and emits it. It sees the program after `.include`
+/// files have been spliced in and `.if` blocks resolved, so it gets every instruction that will be assembled.
+/// Closures taking and returning the program implement it.
+pub trait ProgramTransform {
+    /// Returns the rewritten program, or why it couldn't be rewritten
+    fn transform(&self, program: Program) -> Result<Program, String>;
+}
+
+impl<F> ProgramTransform for F
+where
+    F: Fn(Program) -> Result<Program, String>,
+{
+    fn transform(&self, program: Program) -> Result<Program, String> {
+        self(program)
+    }
+}
+
+/// The transforms registered on an assembler, each run in the order it was added
+#[derive(Default)]
+pub struct Transforms {
+    source: Vec<Box<dyn SourceTransform>>,
+    program: Vec<Box<dyn ProgramTransform>>,
+}
+
+impl Transforms {
+    pub fn add_source_transform(&mut self, transform: Box<dyn SourceTransform>) {
+        self.source.push(transform);
+    }
+
+    pub fn add_program_transform(&mut self, transform: Box<dyn ProgramTransform>) {
+        self.program.push(transform);
+    }
+
+    /// Runs the source through every source transform, stopping at the first that fails
+    pub fn transform_source(&self, source: &str) -> Result<String, String> {
+        self.source.iter().try_fold(source.to_string(), |source, transform| transform.transform(&source))
+    }
+
+    /// Runs the program through every program transform, stopping at the first that fails
+    pub fn transform_program(&self, program: Program) -> Result<Program, String> {
+        self.program.iter().try_fold(program, |program, transform| transform.transform(program))
+    }
+}
+
+impl fmt::Debug for Transforms {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Transforms")
+            .field("source", &self.source.len())
+            .field("program", &self.program.len())
+            .finish()
+    }
+}
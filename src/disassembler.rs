@@ -0,0 +1,223 @@
+use crate::encoding::{read_code_f64, read_code_u16};
+use crate::formatting::NumberFormat;
+use crate::instruction::{Opcode, HLT_WITH_CODE, RAND_BOUNDED, RECV_WAIT, SHIFT_REGISTER};
+
+
+/// How the operand bytes following an opcode are laid out
+#[derive(Debug, PartialEq)]
+enum OperandLayout {
+    Nothing,
+    Register,
+    TwoRegisters,
+    ThreeRegisters,
+    RegisterInteger,
+    Integer,
+    RegisterFloat,
+    Shift,
+    Memory,
+    Trap,
+    Relative,
+    Random,
+    Vector,
+    Halt,
+    Receive,
+}
+
+fn operand_layout(opcode: Opcode) -> OperandLayout {
+    match opcode {
+        Opcode::NOP | Opcode::RET | Opcode::IRET | Opcode::YIELD | Opcode::FLUSH | Opcode::IGL => {
+            OperandLayout::Nothing
+        }
+        Opcode::HLT => OperandLayout::Halt,
+        Opcode::JMP | Opcode::JMPF | Opcode::JMPB | Opcode::JMPE | Opcode::DJMPE => OperandLayout::Register,
+        Opcode::JZ | Opcode::JNZ | Opcode::JC | Opcode::JO | Opcode::JNO => OperandLayout::Register,
+        Opcode::INC | Opcode::DEC | Opcode::PUSH | Opcode::POP | Opcode::FREE | Opcode::RDCYCLE => {
+            OperandLayout::Register
+        }
+        Opcode::EQ | Opcode::NEQ | Opcode::GTE | Opcode::LTE | Opcode::LT | Opcode::GT => OperandLayout::TwoRegisters,
+        Opcode::EQF64 | Opcode::NEQF64 | Opcode::GTF64 | Opcode::GTEF64 | Opcode::LTF64 | Opcode::LTEF64 => {
+            OperandLayout::TwoRegisters
+        }
+        Opcode::ALOC | Opcode::NOT | Opcode::LOADM | Opcode::SETM | Opcode::JMPR | Opcode::WCODE | Opcode::SEND => {
+            OperandLayout::TwoRegisters
+        }
+        Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV => OperandLayout::ThreeRegisters,
+        Opcode::ADDF64 | Opcode::SUBF64 | Opcode::MULF64 | Opcode::DIVF64 => OperandLayout::ThreeRegisters,
+        Opcode::AND | Opcode::OR | Opcode::XOR | Opcode::CAS | Opcode::XADD => OperandLayout::ThreeRegisters,
+        Opcode::LOAD | Opcode::LUI | Opcode::ORI | Opcode::LI | Opcode::SPAWN => OperandLayout::RegisterInteger,
+        Opcode::PRTS | Opcode::CLOOP | Opcode::LOOP | Opcode::CALL | Opcode::SYSCALL | Opcode::HCALL => {
+            OperandLayout::Integer
+        },
+        Opcode::LOADF64 => OperandLayout::RegisterFloat,
+        Opcode::SHL | Opcode::SHR => OperandLayout::Shift,
+        Opcode::LW | Opcode::SW => OperandLayout::Memory,
+        Opcode::SETTRAP => OperandLayout::Trap,
+        Opcode::BR => OperandLayout::Relative,
+        Opcode::RAND => OperandLayout::Random,
+        Opcode::VADD | Opcode::VMUL => OperandLayout::Vector,
+        Opcode::RECV => OperandLayout::Receive,
+    }
+}
+
+/// Turns the instruction starting at `bytes[0]` back into assembly, showing immediates in the given format. Returns
+/// the text and the number of bytes the instruction took up, or None if there aren't enough bytes left for a whole
+/// instruction.
+pub fn disassemble_instruction(bytes: &[u8], format: NumberFormat) -> Option<(String, usize)> {
+    if bytes.len() < 4 {
+        return None;
+    }
+
+    let opcode = Opcode::from(bytes[0]);
+    let mnemonic = format!("{:?}", opcode).to_lowercase();
+    let integer = format.format_unsigned(u64::from(read_code_u16(&bytes[2..4])), 16);
+    let byte = |index: usize| format.format_unsigned(u64::from(bytes[index]), 8);
+
+    let text = match operand_layout(opcode) {
+        OperandLayout::Nothing => mnemonic,
+        OperandLayout::Register => format!("{} ${}", mnemonic, bytes[1]),
+        OperandLayout::TwoRegisters => format!("{} ${} ${}", mnemonic, bytes[1], bytes[2]),
+        OperandLayout::ThreeRegisters => format!("{} ${} ${} ${}", mnemonic, bytes[1], bytes[2], bytes[3]),
+        OperandLayout::RegisterInteger => format!("{} ${} #{}", mnemonic, bytes[1], integer),
+        OperandLayout::Integer => {
+            let value = format.format_unsigned(u64::from(read_code_u16(&bytes[1..3])), 16);
+            format!("{} #{}", mnemonic, value)
+        }
+        OperandLayout::RegisterFloat => {
+            if bytes.len() < 12 {
+                return None;
+            }
+            let value = read_code_f64(&bytes[4..12]);
+            return Some((format!("{} ${} #{:?}", mnemonic, bytes[1], value), 12));
+        }
+        OperandLayout::Shift => {
+            if bytes[3] == SHIFT_REGISTER {
+                format!("{} ${} ${}", mnemonic, bytes[1], bytes[2])
+            } else {
+                format!("{} ${} #{}", mnemonic, bytes[1], byte(2))
+            }
+        }
+        OperandLayout::Memory => {
+            if bytes[3] == 0 {
+                format!("{} ${} ${}", mnemonic, bytes[1], bytes[2])
+            } else {
+                format!("{} ${} ${} #{}", mnemonic, bytes[1], bytes[2], byte(3))
+            }
+        }
+        OperandLayout::Trap => format!("{} #{} #{}", mnemonic, bytes[1], integer),
+        OperandLayout::Random => {
+            if bytes[3] == RAND_BOUNDED {
+                format!("{} ${} ${}", mnemonic, bytes[1], bytes[2])
+            } else {
+                format!("{} ${}", mnemonic, bytes[1])
+            }
+        }
+        OperandLayout::Halt => {
+            if bytes[3] == HLT_WITH_CODE {
+                format!("{} ${}", mnemonic, bytes[1])
+            } else {
+                mnemonic
+            }
+        }
+        OperandLayout::Receive => {
+            if bytes[3] == RECV_WAIT {
+                format!("{} ${}", mnemonic, bytes[1])
+            } else {
+                format!("{} ${} #{}", mnemonic, bytes[1], byte(3))
+            }
+        }
+        OperandLayout::Vector => format!("{} ${} ${} #{}", mnemonic, bytes[1], bytes[2], byte(3)),
+        OperandLayout::Relative => {
+            let offset = read_code_u16(&bytes[1..3]);
+            let offset = match format {
+                NumberFormat::Decimal => (offset as i16).to_string(),
+                _ => format.format_unsigned(u64::from(offset), 16),
+            };
+            format!("{} #{}", mnemonic, offset)
+        }
+    };
+
+    Some((text, 4))
+}
+
+/// Disassembles a stretch of bytecode into the offset and text of each instruction. `base` is the offset of
+/// `bytes[0]` in the program, so offsets line up with the program counter.
+pub fn instructions(bytes: &[u8], base: usize, format: NumberFormat) -> Vec<(usize, String)> {
+    let mut result = vec![];
+    let mut offset = 0;
+
+    while offset < bytes.len() {
+        match disassemble_instruction(&bytes[offset..], format) {
+            Some((text, length)) => {
+                result.push((base + offset, text));
+                offset += length;
+            }
+            None => {
+                result.push((base + offset, format!("<truncated instruction {:?}>", &bytes[offset..])));
+                break;
+            }
+        }
+    }
+
+    result
+}
+
+/// Disassembles a stretch of bytecode, prefixing each line with the offset of the instruction
+pub fn disassemble(bytes: &[u8], base: usize, format: NumberFormat) -> Vec<String> {
+    instructions(bytes, base, format)
+        .into_iter()
+        .map(|(offset, text)| format!("{:04}: {}", offset, text))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(bytes: &[u8]) -> Option<(String, usize)> {
+        disassemble_instruction(bytes, NumberFormat::Decimal)
+    }
+
+    #[test]
+    fn test_disassemble_instruction() {
+        assert_eq!(decimal(&[0, 1, 1, 244]), Some(("load $1 #500".to_string(), 4)));
+        assert_eq!(decimal(&[1, 0, 1, 2]), Some(("add $0 $1 $2".to_string(), 4)));
+        assert_eq!(decimal(&[17, 0, 1, 0]), Some(("aloc $0 $1".to_string(), 4)));
+        assert_eq!(decimal(&[5, 0, 0, 0]), Some(("hlt".to_string(), 4)));
+        assert_eq!(decimal(&[5, 3, 0, 1]), Some(("hlt $3".to_string(), 4)));
+        assert_eq!(decimal(&[33, 0, 4, 0]), Some(("shl $0 #4".to_string(), 4)));
+        assert_eq!(decimal(&[34, 0, 1, 1]), Some(("shr $0 $1".to_string(), 4)));
+        assert_eq!(decimal(&[49, 0, 1, 0]), Some(("lw $0 $1".to_string(), 4)));
+        assert_eq!(decimal(&[50, 0, 1, 8]), Some(("sw $0 $1 #8".to_string(), 4)));
+        assert_eq!(decimal(&[51, 0, 3, 0]), Some(("syscall #3".to_string(), 4)));
+        assert_eq!(decimal(&[52, 0, 7, 0]), Some(("hcall #7".to_string(), 4)));
+        assert_eq!(decimal(&[53, 1, 0, 80]), Some(("settrap #1 #80".to_string(), 4)));
+        assert_eq!(decimal(&[54, 0, 0, 0]), Some(("iret".to_string(), 4)));
+        assert_eq!(decimal(&[61, 2, 3, 0]), Some(("jmpr $2 $3".to_string(), 4)));
+        assert_eq!(decimal(&[62, 255, 248, 0]), Some(("br #-8".to_string(), 4)));
+        assert_eq!(decimal(&[63, 1, 2, 3]), Some(("cas $1 $2 $3".to_string(), 4)));
+        assert_eq!(decimal(&[65, 1, 0, 0]), Some(("rand $1".to_string(), 4)));
+        assert_eq!(decimal(&[65, 1, 0, 1]), Some(("rand $1 $0".to_string(), 4)));
+        assert_eq!(decimal(&[67, 0, 4, 4]), Some(("vadd $0 $4 #4".to_string(), 4)));
+        assert_eq!(decimal(&[5, 0]), None);
+    }
+
+    #[test]
+    fn test_disassemble_wide_instruction() {
+        let bytes = [22, 1, 0, 0, 64, 4, 0, 0, 0, 0, 0, 0];
+        assert_eq!(decimal(&bytes), Some(("loadf64 $1 #2.5".to_string(), 12)));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let lines = disassemble(&[0, 0, 0, 100, 5, 0, 0, 0], 64, NumberFormat::Decimal);
+        assert_eq!(lines, vec!["0064: load $0 #100".to_string(), "0068: hlt".to_string()]);
+    }
+
+    #[test]
+    fn test_disassemble_in_hex() {
+        let instruction = disassemble_instruction(&[0, 1, 1, 244], NumberFormat::Hex);
+        assert_eq!(instruction, Some(("load $1 #0x01f4".to_string(), 4)));
+        let instruction = disassemble_instruction(&[50, 0, 1, 8], NumberFormat::Binary);
+        assert_eq!(instruction, Some(("sw $0 $1 #0b00001000".to_string(), 4)));
+    }
+}
@@ -0,0 +1,114 @@
+//! The one place that decides how multi-byte values are laid out, so the assembler, the disassembler, the VM and
+//! other tools producing programs all agree:
+//!
+//! - Code is big-endian, most significant byte first: 16-bit immediates, the 32-bit immediate in front of each wide
+//!   instruction, float immediates, and the instruction words WCODE writes. An instruction reads the same in a hex
+//!   dump as it does written out.
+//! - Header fields are little-endian u32s.
+//! - Heap words, which LW and SW move between registers and memory, are little-endian, as is anything else read from
+//!   the heap.
+
+use byteorder::{BigEndian, ByteOrder, LittleEndian};
+
+/// Appends a 16-bit immediate the way it's stored in an instruction
+pub fn push_code_u16(out: &mut Vec<u8>, value: u16) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Reads a 16-bit immediate from the first two bytes of `bytes`
+pub fn read_code_u16(bytes: &[u8]) -> u16 {
+    BigEndian::read_u16(bytes)
+}
+
+/// Appends a 32-bit value the way it's stored in code, such as the immediate in front of a wide instruction
+pub fn push_code_i32(out: &mut Vec<u8>, value: i32) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Reads a 32-bit value stored in code from the first four bytes of `bytes`
+pub fn read_code_i32(bytes: &[u8]) -> i32 {
+    BigEndian::read_i32(bytes)
+}
+
+/// Overwrites the first four bytes of `bytes`, which are in code, with a 32-bit value
+pub fn write_code_i32(bytes: &mut [u8], value: i32) {
+    BigEndian::write_i32(bytes, value);
+}
+
+/// Appends a float immediate, which follows the instruction that uses it
+pub fn push_code_f64(out: &mut Vec<u8>, value: f64) {
+    out.extend_from_slice(&value.to_be_bytes());
+}
+
+/// Reads a float immediate from the first eight bytes of `bytes`
+pub fn read_code_f64(bytes: &[u8]) -> f64 {
+    BigEndian::read_f64(bytes)
+}
+
+/// Appends a field of a program's header
+pub fn push_header_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+/// Reads the header field at `offset` in a program
+pub fn read_header_u32(program: &[u8], offset: usize) -> u32 {
+    LittleEndian::read_u32(&program[offset..offset + 4])
+}
+
+/// Reads a word from the first four bytes of `bytes`, which are in the heap
+pub fn read_heap_word(bytes: &[u8]) -> i32 {
+    LittleEndian::read_i32(bytes)
+}
+
+/// Overwrites the first four bytes of `bytes`, which are in the heap, with a word
+pub fn write_heap_word(bytes: &mut [u8], value: i32) {
+    LittleEndian::write_i32(bytes, value);
+}
+
+/// Reads a 16-bit value from the first two bytes of `bytes`, which are in the heap, laid out like a heap word
+pub fn read_heap_i16(bytes: &[u8]) -> i16 {
+    LittleEndian::read_i16(bytes)
+}
+
+/// Reads a float from the first eight bytes of `bytes`, which are in the heap, laid out like a heap word
+pub fn read_heap_f64(bytes: &[u8]) -> f64 {
+    LittleEndian::read_f64(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_round_trip() {
+        let mut code = vec![];
+        push_code_u16(&mut code, 0x1234);
+        push_code_u16(&mut code, -2i16 as u16);
+        push_code_i32(&mut code, -70000);
+        push_code_f64(&mut code, 1.5);
+        assert_eq!(&code[..4], &[0x12, 0x34, 0xff, 0xfe]);
+
+        assert_eq!(read_code_u16(&code), 0x1234);
+        assert_eq!(read_code_u16(&code[2..]) as i16, -2);
+        assert_eq!(read_code_i32(&code[4..]), -70000);
+        assert_eq!(read_code_f64(&code[8..]), 1.5);
+
+        write_code_i32(&mut code[4..8], 0x0102_0304);
+        assert_eq!(&code[4..8], &[1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_header_and_heap_round_trip() {
+        let mut header = vec![0; 4];
+        push_header_u32(&mut header, 0x0102_0304);
+        assert_eq!(&header[4..], &[4, 3, 2, 1]);
+        assert_eq!(read_header_u32(&header, 4), 0x0102_0304);
+
+        let mut word = [0; 4];
+        write_heap_word(&mut word, -7);
+        assert_eq!(word, [0xf9, 0xff, 0xff, 0xff]);
+        assert_eq!(read_heap_word(&word), -7);
+        assert_eq!(read_heap_i16(&word), -7);
+        assert_eq!(read_heap_f64(&2.5f64.to_le_bytes()), 2.5);
+    }
+}
@@ -0,0 +1,265 @@
+use crate::encoding::{read_heap_f64, read_heap_i16, read_heap_word};
+
+use std::fmt;
+
+/// How integers are displayed by the disassembler and the REPL
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum NumberFormat {
+    #[default]
+    Decimal,
+    Hex,
+    Binary,
+}
+
+impl NumberFormat {
+    /// Parses the name used by the REPL's `.format` command
+    pub fn from_name(name: &str) -> Option<NumberFormat> {
+        match name {
+            "dec" | "decimal" => Some(NumberFormat::Decimal),
+            "hex" => Some(NumberFormat::Hex),
+            "bin" | "binary" => Some(NumberFormat::Binary),
+            _ => None,
+        }
+    }
+
+    /// Formats a value that is `bits` wide. Hex and binary are zero-padded to the full width, so values of the same
+    /// width line up; decimal isn't padded.
+    pub fn format_unsigned(self, value: u64, bits: usize) -> String {
+        match self {
+            NumberFormat::Decimal => value.to_string(),
+            NumberFormat::Hex => format!("0x{:0width$x}", value, width = bits / 4),
+            NumberFormat::Binary => format!("0b{:0width$b}", value, width = bits),
+        }
+    }
+
+    /// Formats a register value. Decimal shows it signed, hex and binary show its bits.
+    pub fn format_i32(self, value: i32) -> String {
+        match self {
+            NumberFormat::Decimal => value.to_string(),
+            _ => self.format_unsigned(u64::from(value as u32), 32),
+        }
+    }
+
+    /// Formats a byte for a memory dump, padded so every byte takes up the same width
+    pub fn format_byte(self, byte: u8) -> String {
+        match self {
+            NumberFormat::Decimal => format!("{:3}", byte),
+            NumberFormat::Hex => format!("{:02x}", byte),
+            NumberFormat::Binary => format!("{:08b}", byte),
+        }
+    }
+}
+
+/// What `.hexdump` reads memory as when it isn't shown as raw bytes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DumpElement {
+    I8,
+    I16,
+    I32,
+    F64,
+    /// Null-terminated strings
+    String,
+}
+
+impl DumpElement {
+    /// Parses the name used by the REPL's `.dumpfmt` command
+    pub fn from_name(name: &str) -> Option<DumpElement> {
+        match name {
+            "i8" => Some(DumpElement::I8),
+            "i16" => Some(DumpElement::I16),
+            "i32" => Some(DumpElement::I32),
+            "f64" => Some(DumpElement::F64),
+            "str" | "string" => Some(DumpElement::String),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            DumpElement::I8 => "i8",
+            DumpElement::I16 => "i16",
+            DumpElement::I32 => "i32",
+            DumpElement::F64 => "f64",
+            DumpElement::String => "str",
+        }
+    }
+
+    /// Bytes in one element. Strings are read a byte at a time.
+    pub fn size(self) -> usize {
+        match self {
+            DumpElement::I8 | DumpElement::String => 1,
+            DumpElement::I16 => 2,
+            DumpElement::I32 => 4,
+            DumpElement::F64 => 8,
+        }
+    }
+}
+
+/// Reads memory as an array of elements, such as `i32 12` for the first field of an array of 12 byte structures.
+/// Integers are little-endian like heap words and shown in the REPL's number format.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DumpFormat {
+    pub element: DumpElement,
+    /// Bytes from the start of one element to the start of the next. None packs them together, which for strings
+    /// means each starts after the null ending the one before; with a stride, each string is a fixed size field.
+    pub stride: Option<usize>,
+}
+
+impl DumpFormat {
+    /// Parses the arguments of `.dumpfmt`: an element name, optionally followed by a stride
+    pub fn parse(text: &str) -> Result<DumpFormat, String> {
+        let mut words = text.split_whitespace();
+        let name = words.next().unwrap_or("");
+        let element = DumpElement::from_name(name)
+            .ok_or_else(|| format!("Unknown dump format: {}. Expected bytes, i8, i16, i32, f64 or str.", name))?;
+
+        let stride = match words.next() {
+            Some(stride) => match stride.parse::<usize>() {
+                Ok(stride) if stride >= element.size() => Some(stride),
+                _ => return Err(format!("Expected a stride of at least {} bytes, found {}", element.size(), stride)),
+            },
+            None => None,
+        };
+        if words.next().is_some() {
+            return Err("Usage: .dumpfmt bytes|i8|i16|i32|f64|str [stride]".to_string());
+        }
+        Ok(DumpFormat { element, stride })
+    }
+
+    /// Formats `bytes`, whose first byte is at `base`, one line per 16 bytes of elements or one per element when
+    /// they are further apart than that. Each line starts with the address of its first element in hex, and
+    /// elements that would run past the end of `bytes` are left out.
+    pub fn dump(&self, bytes: &[u8], base: usize, format: NumberFormat) -> Vec<String> {
+        if self.element == DumpElement::String {
+            return self.dump_strings(bytes, base);
+        }
+
+        let size = self.element.size();
+        let stride = self.stride.unwrap_or(size);
+        let values: Vec<(usize, String)> = (0..bytes.len())
+            .step_by(stride)
+            .take_while(|offset| offset + size <= bytes.len())
+            .map(|offset| (offset, self.format_element(&bytes[offset..offset + size], format)))
+            .collect();
+
+        let width = values.iter().map(|(_, value)| value.len()).max().unwrap_or(0);
+        values
+            .chunks((16 / stride).max(1))
+            .map(|line| {
+                let values: Vec<String> = line.iter().map(|(_, value)| format!("{:>1$}", value, width)).collect();
+                format!("{:08x}  {}", base + line[0].0, values.join(" "))
+            })
+            .collect()
+    }
+
+    fn format_element(&self, bytes: &[u8], format: NumberFormat) -> String {
+        let signed = |value: i64, bits: usize| match format {
+            NumberFormat::Decimal => value.to_string(),
+            _ => format.format_unsigned(value as u64 & (u64::MAX >> (64 - bits)), bits),
+        };
+        match self.element {
+            DumpElement::I8 => signed(i64::from(bytes[0] as i8), 8),
+            DumpElement::I16 => signed(i64::from(read_heap_i16(bytes)), 16),
+            DumpElement::I32 => signed(i64::from(read_heap_word(bytes)), 32),
+            DumpElement::F64 => format!("{:?}", read_heap_f64(bytes)),
+            DumpElement::String => unreachable!("strings are dumped by dump_strings"),
+        }
+    }
+
+    /// One line per string. Packed, the empty strings between runs of nulls are skipped.
+    fn dump_strings(&self, bytes: &[u8], base: usize) -> Vec<String> {
+        let text = |field: &[u8]| {
+            let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+            format!("{:?}", String::from_utf8_lossy(&field[..end]))
+        };
+
+        if let Some(stride) = self.stride {
+            return bytes
+                .chunks(stride)
+                .enumerate()
+                .map(|(index, field)| format!("{:08x}  {}", base + index * stride, text(field)))
+                .collect();
+        }
+
+        let mut lines = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let length = bytes[offset..].iter().position(|byte| *byte == 0).unwrap_or(bytes.len() - offset);
+            if length > 0 {
+                lines.push(format!("{:08x}  {}", base + offset, text(&bytes[offset..])));
+            }
+            offset += length + 1;
+        }
+        lines
+    }
+}
+
+impl fmt::Display for DumpFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.element.name())?;
+        if let Some(stride) = self.stride {
+            write!(f, " {}", stride)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(NumberFormat::from_name("hex"), Some(NumberFormat::Hex));
+        assert_eq!(NumberFormat::from_name("bin"), Some(NumberFormat::Binary));
+        assert_eq!(NumberFormat::from_name("decimal"), Some(NumberFormat::Decimal));
+        assert!(NumberFormat::from_name("octal").is_none());
+    }
+
+    #[test]
+    fn test_format_unsigned() {
+        assert_eq!(NumberFormat::Decimal.format_unsigned(500, 16), "500");
+        assert_eq!(NumberFormat::Hex.format_unsigned(500, 16), "0x01f4");
+        assert_eq!(NumberFormat::Binary.format_unsigned(5, 8), "0b00000101");
+    }
+
+    #[test]
+    fn test_format_i32() {
+        assert_eq!(NumberFormat::Decimal.format_i32(-1), "-1");
+        assert_eq!(NumberFormat::Hex.format_i32(-1), "0xffffffff");
+        assert_eq!(NumberFormat::Binary.format_i32(2), format!("0b{}10", "0".repeat(30)));
+    }
+
+    #[test]
+    fn test_format_byte() {
+        assert_eq!(NumberFormat::Decimal.format_byte(7), "  7");
+        assert_eq!(NumberFormat::Hex.format_byte(7), "07");
+        assert_eq!(NumberFormat::Binary.format_byte(7), "00000111");
+    }
+
+    #[test]
+    fn test_dump_format() {
+        assert_eq!(DumpFormat::parse("i32 12"), Ok(DumpFormat { element: DumpElement::I32, stride: Some(12) }));
+        assert!(DumpFormat::parse("i32 2").is_err());
+        assert!(DumpFormat::parse("u8").is_err());
+        assert_eq!(DumpFormat::parse("str").unwrap().to_string(), "str");
+
+        let bytes = [1, 0, 0, 0, 0xfe, 0xff, 0xff, 0xff, 3, 0, 0, 0];
+        let i32s = DumpFormat::parse("i32").unwrap();
+        assert_eq!(i32s.dump(&bytes, 16, NumberFormat::Decimal), vec!["00000010   1 -2  3"]);
+        let i16s = DumpFormat::parse("i16 4").unwrap();
+        assert_eq!(i16s.dump(&bytes, 0, NumberFormat::Hex), vec!["00000000  0x0001 0xfffe 0x0003"]);
+        let f64s = DumpFormat::parse("f64").unwrap();
+        assert_eq!(f64s.dump(&1.5f64.to_le_bytes(), 0, NumberFormat::Decimal), vec!["00000000  1.5"]);
+    }
+
+    #[test]
+    fn test_dump_strings() {
+        let bytes = b"hi\0\0there\0x";
+        let packed = DumpFormat::parse("str").unwrap();
+        let lines = packed.dump(bytes, 0, NumberFormat::Decimal);
+        assert_eq!(lines, vec!["00000000  \"hi\"", "00000004  \"there\"", "0000000a  \"x\""]);
+        let fields = DumpFormat::parse("str 4").unwrap();
+        assert_eq!(fields.dump(bytes, 0, NumberFormat::Decimal)[..2], ["00000000  \"hi\"", "00000004  \"ther\""]);
+    }
+}
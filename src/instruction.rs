@@ -1,5 +1,22 @@
 use nom::types::CompleteStr;
 
+/// Final operand byte of SHL/SHR when the shift amount is an immediate value
+pub const SHIFT_IMMEDIATE: u8 = 0;
+/// Final operand byte of SHL/SHR when the shift amount is held in a register
+pub const SHIFT_REGISTER: u8 = 1;
+/// Final operand byte of RAND when the result isn't bounded
+pub const RAND_UNBOUNDED: u8 = 0;
+/// Final operand byte of RAND when the register in the third byte holds an upper bound for the result
+pub const RAND_BOUNDED: u8 = 1;
+/// Final operand byte of HLT when it is used without an exit code
+pub const HLT_WITHOUT_CODE: u8 = 0;
+/// Final operand byte of HLT when the register in the second byte holds the exit code
+pub const HLT_WITH_CODE: u8 = 1;
+/// Final operand byte of RECV when it only checks for a message
+pub const RECV_POLL: u8 = 0;
+/// Final operand byte of RECV when it waits for a message
+pub const RECV_WAIT: u8 = 1;
+
 /// Represents an opcode, which tells our interpreter what to do with the following operands
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Opcode {
@@ -52,61 +69,171 @@ pub enum Opcode {
     POP,
     CALL,
     RET,
+    FREE,
+    LW,
+    SW,
+    SYSCALL,
+    HCALL,
+    SETTRAP,
+    IRET,
+    JZ,
+    JNZ,
+    JC,
+    JO,
+    JNO,
+    ORI,
+    JMPR,
+    /// Jumps by a signed 16-bit offset from the address of the BR itself
+    BR,
+    CAS,
+    XADD,
+    RAND,
+    WCODE,
+    /// Adds a range of registers to another range element by element: `vadd $0 $4 #4` is $0-$3 += $4-$7
+    VADD,
+    /// Multiplies a range of registers by another range element by element
+    VMUL,
+    /// Starts a green thread at a label: `spawn $id @worker`
+    SPAWN,
+    /// Lets the next green thread waiting to run have a turn
+    YIELD,
+    /// Puts a word in another VM's mailbox: `send $to $message`
+    SEND,
+    /// Takes a word from the VM's own mailbox, waiting for one unless told not to: `recv $0` or `recv $0 #0`
+    RECV,
+    /// Reads how many instructions the VM has retired, wrapping at 32 bits, so loops can time themselves: `rdcycle $0`
+    RDCYCLE,
+    /// Writes out what PRTS and the print syscall have buffered: `flush`
+    FLUSH,
+    /// Assembler pseudo-instruction that loads a full 32-bit value by expanding into LUI and ORI. It never appears
+    /// in bytecode.
+    LI,
 }
 
+impl Opcode {
+    /// Decodes the first byte of an instruction. This is what the VM does for every instruction it runs, so it looks
+    /// the byte up in a table built when compiling rather than matching on it.
+    #[inline]
+    pub fn from_byte(byte: u8) -> Opcode {
+        OPCODE_TABLE[byte as usize]
+    }
+
+    /// Whether the opcode works on the float registers
+    pub fn uses_floats(self) -> bool {
+        matches!(
+            self,
+            Opcode::LOADF64
+                | Opcode::ADDF64
+                | Opcode::SUBF64
+                | Opcode::MULF64
+                | Opcode::DIVF64
+                | Opcode::EQF64
+                | Opcode::NEQF64
+                | Opcode::GTF64
+                | Opcode::GTEF64
+                | Opcode::LTF64
+                | Opcode::LTEF64
+        )
+    }
+}
+
+/// Opcode of every possible first byte of an instruction, so decoding one is a single index instead of a match
+static OPCODE_TABLE: [Opcode; 256] = {
+    let mut table = [Opcode::IGL; 256];
+    let mut byte = 0;
+    while byte < 256 {
+        table[byte] = decode_opcode(byte as u8);
+        byte += 1;
+    }
+    table
+};
+
 impl From<u8> for Opcode {
     fn from(v: u8) -> Self {
-        match v {
-            0 => Opcode::LOAD,
-            1 => Opcode::ADD,
-            2 => Opcode::SUB,
-            3 => Opcode::MUL,
-            4 => Opcode::DIV,
-            5 => Opcode::HLT,
-            6 => Opcode::JMP,
-            7 => Opcode::JMPF,
-            8 => Opcode::JMPB,
-            9 => Opcode::EQ,
-            10 => Opcode::NEQ,
-            11 => Opcode::GTE,
-            12 => Opcode::LTE,
-            13 => Opcode::LT,
-            14 => Opcode::GT,
-            15 => Opcode::JMPE,
-            16 => Opcode::NOP,
-            17 => Opcode::ALOC,
-            18 => Opcode::INC,
-            19 => Opcode::DEC,
-            20 => Opcode::DJMPE,
-            21 => Opcode::PRTS,
-            22 => Opcode::LOADF64,
-            23 => Opcode::ADDF64,
-            24 => Opcode::SUBF64,
-            25 => Opcode::MULF64,
-            26 => Opcode::DIVF64,
-            27 => Opcode::EQF64,
-            28 => Opcode::NEQF64,
-            29 => Opcode::GTF64,
-            30 => Opcode::GTEF64,
-            31 => Opcode::LTF64,
-            32 => Opcode::LTEF64,
-            33 => Opcode::SHL,
-            34 => Opcode::SHR,
-            35 => Opcode::AND,
-            36 => Opcode::OR,
-            37 => Opcode::XOR,
-            38 => Opcode::NOT,
-            39 => Opcode::LUI,
-            40 => Opcode::CLOOP,
-            41 => Opcode::LOOP,
-            42 => Opcode::LOADM,
-            43 => Opcode::SETM,
-            44 => Opcode::PUSH,
-            45 => Opcode::POP,
-            46 => Opcode::CALL,
-            47 => Opcode::RET,
-            _ => Opcode::IGL,
-        }
+        Opcode::from_byte(v)
+    }
+}
+
+/// Decodes the first byte of an instruction by matching on it, which `OPCODE_TABLE` is built from. Bytes that
+/// aren't an opcode are IGL.
+pub const fn decode_opcode(v: u8) -> Opcode {
+    match v {
+        0 => Opcode::LOAD,
+        1 => Opcode::ADD,
+        2 => Opcode::SUB,
+        3 => Opcode::MUL,
+        4 => Opcode::DIV,
+        5 => Opcode::HLT,
+        6 => Opcode::JMP,
+        7 => Opcode::JMPF,
+        8 => Opcode::JMPB,
+        9 => Opcode::EQ,
+        10 => Opcode::NEQ,
+        11 => Opcode::GTE,
+        12 => Opcode::LTE,
+        13 => Opcode::LT,
+        14 => Opcode::GT,
+        15 => Opcode::JMPE,
+        16 => Opcode::NOP,
+        17 => Opcode::ALOC,
+        18 => Opcode::INC,
+        19 => Opcode::DEC,
+        20 => Opcode::DJMPE,
+        21 => Opcode::PRTS,
+        22 => Opcode::LOADF64,
+        23 => Opcode::ADDF64,
+        24 => Opcode::SUBF64,
+        25 => Opcode::MULF64,
+        26 => Opcode::DIVF64,
+        27 => Opcode::EQF64,
+        28 => Opcode::NEQF64,
+        29 => Opcode::GTF64,
+        30 => Opcode::GTEF64,
+        31 => Opcode::LTF64,
+        32 => Opcode::LTEF64,
+        33 => Opcode::SHL,
+        34 => Opcode::SHR,
+        35 => Opcode::AND,
+        36 => Opcode::OR,
+        37 => Opcode::XOR,
+        38 => Opcode::NOT,
+        39 => Opcode::LUI,
+        40 => Opcode::CLOOP,
+        41 => Opcode::LOOP,
+        42 => Opcode::LOADM,
+        43 => Opcode::SETM,
+        44 => Opcode::PUSH,
+        45 => Opcode::POP,
+        46 => Opcode::CALL,
+        47 => Opcode::RET,
+        48 => Opcode::FREE,
+        49 => Opcode::LW,
+        50 => Opcode::SW,
+        51 => Opcode::SYSCALL,
+        52 => Opcode::HCALL,
+        53 => Opcode::SETTRAP,
+        54 => Opcode::IRET,
+        55 => Opcode::JZ,
+        56 => Opcode::JNZ,
+        57 => Opcode::JC,
+        58 => Opcode::JO,
+        59 => Opcode::JNO,
+        60 => Opcode::ORI,
+        61 => Opcode::JMPR,
+        62 => Opcode::BR,
+        63 => Opcode::CAS,
+        64 => Opcode::XADD,
+        65 => Opcode::RAND,
+        66 => Opcode::WCODE,
+        67 => Opcode::VADD,
+        68 => Opcode::VMUL,
+        69 => Opcode::SPAWN,
+        70 => Opcode::YIELD,
+        71 => Opcode::SEND,
+        72 => Opcode::RECV,
+        73 => Opcode::RDCYCLE,
+        74 => Opcode::FLUSH,
+        _ => Opcode::IGL,
     }
 }
 
@@ -161,7 +288,34 @@ impl From<Opcode> for u8 {
             Opcode::POP => 45,
             Opcode::CALL => 46,
             Opcode::RET => 47,
-            Opcode::IGL => 100,
+            Opcode::FREE => 48,
+            Opcode::LW => 49,
+            Opcode::SW => 50,
+            Opcode::SYSCALL => 51,
+            Opcode::HCALL => 52,
+            Opcode::SETTRAP => 53,
+            Opcode::IRET => 54,
+            Opcode::JZ => 55,
+            Opcode::JNZ => 56,
+            Opcode::JC => 57,
+            Opcode::JO => 58,
+            Opcode::JNO => 59,
+            Opcode::ORI => 60,
+            Opcode::JMPR => 61,
+            Opcode::BR => 62,
+            Opcode::CAS => 63,
+            Opcode::XADD => 64,
+            Opcode::RAND => 65,
+            Opcode::WCODE => 66,
+            Opcode::VADD => 67,
+            Opcode::VMUL => 68,
+            Opcode::SPAWN => 69,
+            Opcode::YIELD => 70,
+            Opcode::SEND => 71,
+            Opcode::RECV => 72,
+            Opcode::RDCYCLE => 73,
+            Opcode::FLUSH => 74,
+            Opcode::LI | Opcode::IGL => 100,
         }
     }
 }
@@ -219,6 +373,34 @@ impl<'a> From<CompleteStr<'a>> for Opcode {
             CompleteStr("pop") => Opcode::POP,
             CompleteStr("call") => Opcode::CALL,
             CompleteStr("ret") => Opcode::RET,
+            CompleteStr("free") => Opcode::FREE,
+            CompleteStr("lw") => Opcode::LW,
+            CompleteStr("sw") => Opcode::SW,
+            CompleteStr("syscall") => Opcode::SYSCALL,
+            CompleteStr("hcall") => Opcode::HCALL,
+            CompleteStr("settrap") => Opcode::SETTRAP,
+            CompleteStr("iret") => Opcode::IRET,
+            CompleteStr("jz") => Opcode::JZ,
+            CompleteStr("jnz") => Opcode::JNZ,
+            CompleteStr("jc") => Opcode::JC,
+            CompleteStr("jo") => Opcode::JO,
+            CompleteStr("jno") => Opcode::JNO,
+            CompleteStr("ori") => Opcode::ORI,
+            CompleteStr("jmpr") => Opcode::JMPR,
+            CompleteStr("br") => Opcode::BR,
+            CompleteStr("cas") => Opcode::CAS,
+            CompleteStr("xadd") => Opcode::XADD,
+            CompleteStr("rand") => Opcode::RAND,
+            CompleteStr("wcode") => Opcode::WCODE,
+            CompleteStr("vadd") => Opcode::VADD,
+            CompleteStr("vmul") => Opcode::VMUL,
+            CompleteStr("spawn") => Opcode::SPAWN,
+            CompleteStr("yield") => Opcode::YIELD,
+            CompleteStr("send") => Opcode::SEND,
+            CompleteStr("recv") => Opcode::RECV,
+            CompleteStr("rdcycle") => Opcode::RDCYCLE,
+            CompleteStr("flush") => Opcode::FLUSH,
+            CompleteStr("li") => Opcode::LI,
             _ => Opcode::IGL,
         }
     }
@@ -245,6 +427,18 @@ mod tests {
         assert_eq!(opcode, Opcode::HLT);
     }
 
+    #[test]
+    fn test_from_byte() {
+        for byte in 0..=255u8 {
+            let opcode = Opcode::from_byte(byte);
+            assert_eq!(opcode, decode_opcode(byte));
+            if opcode != Opcode::IGL {
+                assert_eq!(u8::from(opcode), byte);
+            }
+        }
+        assert_eq!(Opcode::from_byte(200), Opcode::IGL);
+    }
+
     #[test]
     fn test_create_instruction() {
         let instruction = Instruction::new(Opcode::HLT);
@@ -0,0 +1,14 @@
+#[macro_use]
+extern crate nom;
+
+#[macro_use]
+extern crate log;
+
+pub mod assembler;
+pub mod disassembler;
+pub mod encoding;
+pub mod formatting;
+pub mod instruction;
+pub mod repl;
+pub mod tools;
+pub mod vm;
@@ -1,51 +1,464 @@
-#[macro_use]
-extern crate nom;
-
 #[macro_use]
 extern crate clap;
 
-use clap::{Arg, App, SubCommand};
-use std::path::Path;
+use clap::App;
+use iridium::{assembler, repl, tools, vm};
 use std::fs::File;
-
-pub mod assembler;
-pub mod instruction;
-pub mod repl;
-pub mod vm;
+use std::io::Read;
+use std::path::Path;
 
 fn main() {
+    env_logger::init();
     let yaml = load_yaml!("cli.yml");
     let matches = App::from_yaml(yaml).get_matches();
+
+    if let Some(diff_matches) = matches.subcommand_matches("diff") {
+        let old = read_binary_file(diff_matches.value_of("FIRST").unwrap());
+        let new = read_binary_file(diff_matches.value_of("SECOND").unwrap());
+        let lines = tools::diff::diff_programs(&old, &new);
+        let identical = run_diff(&lines);
+        std::process::exit(if identical { 0 } else { 1 });
+    }
+
+    if let Some(snapdiff_matches) = matches.subcommand_matches("snapdiff") {
+        let old = read_snapshot(snapdiff_matches.value_of("FIRST").unwrap());
+        let new = read_snapshot(snapdiff_matches.value_of("SECOND").unwrap());
+        let symbols = snapdiff_matches.value_of("SOURCE").map(|source| {
+            let mut asm = assembler::Assembler::new();
+            if let Err(errors) = asm.assemble(&read_file(source)) {
+                for error in errors {
+                    println!("{}", error);
+                }
+                std::process::exit(1);
+            }
+            asm.symbols
+        });
+
+        let changes = tools::snapdiff::diff_snapshots(&old, &new);
+        for line in tools::snapdiff::format_changes(&changes, symbols.as_ref()) {
+            println!("{}", line);
+        }
+        std::process::exit(if changes.is_empty() { 0 } else { 1 });
+    }
+
+    if let Some(objcopy_matches) = matches.subcommand_matches("objcopy") {
+        let program = read_binary_file(objcopy_matches.value_of("FILE").unwrap());
+        let section = objcopy_matches.value_of("SECTION").unwrap();
+        let output = objcopy_matches.value_of("OUTPUT").unwrap();
+
+        match tools::objcopy::extract_section(&program, section) {
+            Some(bytes) => {
+                if let Err(e) = std::fs::write(output, bytes) {
+                    println!("Unable to write {}: {}", output, e);
+                    std::process::exit(1);
+                }
+            }
+            None => {
+                println!("No section named {} found", section);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
+
+    if let Some(info_matches) = matches.subcommand_matches("info") {
+        let program = read_binary_file(info_matches.value_of("FILE").unwrap());
+        for line in tools::info::describe(&program) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(analyze_matches) = matches.subcommand_matches("analyze") {
+        let program = read_binary_file(analyze_matches.value_of("FILE").unwrap());
+        for line in tools::analyze::format_analysis(&tools::analyze::analyze(&program)) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(size_matches) = matches.subcommand_matches("size") {
+        let program = read_binary_file(size_matches.value_of("FILE").unwrap());
+        for line in tools::size::format_sizes(&tools::size::section_sizes(&program)) {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(bench_matches) = matches.subcommand_matches("bench") {
+        let rounds = bench_matches.value_of("ROUNDS");
+        if bench_matches.is_present("OPCODES") {
+            let rounds = match rounds.map(str::parse::<u16>) {
+                Some(Ok(rounds)) => rounds,
+                Some(Err(_)) => {
+                    println!("Invalid number of rounds, expected an integer from 0 to {}", u16::MAX);
+                    std::process::exit(1);
+                }
+                None => tools::bench::DEFAULT_OPCODE_ROUNDS,
+            };
+            match tools::bench::bench_opcodes(rounds) {
+                Ok(timings) => {
+                    for line in tools::bench::opcode_timing_lines(&timings) {
+                        println!("{}", line);
+                    }
+                }
+                Err(e) => {
+                    println!("An opcode benchmark failed: {}", e);
+                    std::process::exit(1);
+                }
+            }
+            return;
+        }
+
+        let rounds = match rounds.map(str::parse::<u32>) {
+            Some(Ok(rounds)) => rounds,
+            Some(Err(_)) => {
+                println!("Invalid number of rounds, expected a non-negative integer");
+                std::process::exit(1);
+            }
+            None => tools::bench::DEFAULT_DECODE_ROUNDS,
+        };
+        for line in tools::bench::bench_decode(rounds).to_lines() {
+            println!("{}", line);
+        }
+        return;
+    }
+
+    if let Some(test_matches) = matches.subcommand_matches("test") {
+        let files: Vec<&str> = test_matches.values_of("FILES").unwrap().collect();
+        let passed = files.iter().filter(|file| run_test_file(file)).count();
+        println!("{} of {} programs passed", passed, files.len());
+        std::process::exit(if passed == files.len() { 0 } else { 1 });
+    }
+
     let target_file = matches.value_of("INPUT_FILE");
 
     match target_file {
         Some(filename) => {
             let program = read_file(filename);
             let mut asm = assembler::Assembler::new();
+
+            // Like a C compiler, look next to the source file first, then in -I directories, then the environment
+            if let Some(directory) = Path::new(filename).parent() {
+                asm.add_include_path(directory);
+            }
+            if let Some(paths) = matches.values_of("INCLUDE_PATH") {
+                for path in paths {
+                    asm.add_include_path(path);
+                }
+            }
+            asm.add_include_paths_from_env();
+
+            asm.set_source_name(filename);
+            if let Some(target) = matches.value_of("TARGET") {
+                match assembler::target::Target::parse(target) {
+                    Ok(target) => asm.set_target(target),
+                    Err(e) => {
+                        println!("{}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            if let Some(definitions) = matches.values_of("DEFINE") {
+                for definition in definitions {
+                    match parse_definition(definition) {
+                        Some((name, value)) => asm.define_symbol(name, value),
+                        None => {
+                            println!("Invalid definition, expected -Dname or -Dname=integer: {}", definition);
+                            std::process::exit(1);
+                        }
+                    }
+                }
+            }
+
             let mut vm = vm::VM::new();
             let program = asm.assemble(&program);
 
             match program {
-                Some(p) => {
+                Ok(p) => {
+                    if matches.is_present("SIZE_REPORT") {
+                        print_size_report(&p, &asm.symbols);
+                    }
+                    if matches.is_present("AUDIT") {
+                        let replay_safe = run_audit(&p);
+                        std::process::exit(if replay_safe { 0 } else { 1 });
+                    }
+                    if let Some(committed) = matches.value_of("FROZEN") {
+                        let unchanged = check_frozen(committed, &p);
+                        std::process::exit(if unchanged { 0 } else { 1 });
+                    }
+                    if let Some(output) = matches.value_of("OUTPUT_FILE") {
+                        if let Err(e) = std::fs::write(output, &p) {
+                            println!("Unable to write {}: {}", output, e);
+                            std::process::exit(1);
+                        }
+                        return;
+                    }
+                    if let Some(limit) = matches.value_of("MAX_HEAP") {
+                        match limit.parse::<usize>() {
+                            Ok(limit) => vm.set_max_heap_size(Some(limit)),
+                            Err(_) => {
+                                println!("Invalid heap size, expected a number of bytes: {}", limit);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    if let Some(size) = matches.value_of("OUTPUT_BUFFER") {
+                        match size.parse::<usize>() {
+                            Ok(size) => vm.set_output_buffer(size),
+                            Err(_) => {
+                                println!("Invalid output buffer size, expected a number of bytes: {}", size);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    vm.set_strict_jumps(matches.is_present("STRICT_JUMPS"));
+                    vm.set_stack_canaries(matches.is_present("STACK_CANARIES"));
+                    if matches.is_present("HEAP_REPORT") {
+                        vm.set_heap_tracking(true);
+                        vm.attach_symbols(Some(asm.symbols.clone()));
+                    }
+                    vm.map_standard_devices();
+                    if let Some(rules) = matches.values_of("ALLOW_UDP") {
+                        let mut policy = vm::sockets::NetworkPolicy::new();
+                        for rule in rules {
+                            match vm::sockets::NetworkPolicy::parse_rule(rule) {
+                                Ok(rule) => policy.allow(rule),
+                                Err(e) => {
+                                    println!("{}", e);
+                                    std::process::exit(1);
+                                }
+                            }
+                        }
+                        vm.set_network_policy(policy);
+                    }
+                    if let Some(path) = matches.value_of("KV_STORE") {
+                        match vm::kv::KeyValueStore::open(path) {
+                            Ok(store) => vm.set_kv_store(Some(store)),
+                            Err(e) => {
+                                println!("Unable to open the key-value store {}: {}", path, e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    if let Some(core) = matches.value_of("PIN_CORE") {
+                        let pinned = core.parse::<usize>().map_err(|_| format!("Invalid core, expected a non-negative integer: {}", core));
+                        if let Err(e) = pinned.and_then(vm::affinity::pin_current_thread) {
+                            println!("{}", e);
+                            std::process::exit(1);
+                        }
+                    }
+                    if let Some(seed) = matches.value_of("SEED") {
+                        match seed.parse::<u64>() {
+                            Ok(seed) => vm.seed_random(seed),
+                            Err(_) => {
+                                println!("Invalid seed, expected a non-negative integer: {}", seed);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    if let Some(path) = matches.value_of("REPLAY") {
+                        match vm::record::Recording::load(path) {
+                            Ok(recording) => vm.replay(recording),
+                            Err(e) => {
+                                println!("Unable to read the recording {}: {}", path, e);
+                                std::process::exit(1);
+                            }
+                        }
+                    }
+                    let record_to = matches.value_of("RECORD");
+                    if record_to.is_some() {
+                        vm.start_recording();
+                    }
+                    let stats_out = matches.value_of("STATS_OUT");
+                    if stats_out.is_some() {
+                        vm.enable_stats();
+                    }
                     vm.add_bytes(p);
-                    vm.run();
-                    std::process::exit(0);
+                    let result = match matches.value_of("MAX_INSTRUCTIONS") {
+                        Some(budget) => match budget.parse::<u64>() {
+                            Ok(budget) => vm.run_with_budget(budget),
+                            Err(_) => {
+                                println!("Invalid instruction budget, expected a non-negative integer: {}", budget);
+                                std::process::exit(1);
+                            }
+                        },
+                        None => vm.run(),
+                    };
+                    if let (Some(path), Some(stats)) = (stats_out, vm.stats()) {
+                        write_stats(path, stats);
+                    }
+                    if let (Some(path), Some(recording)) = (record_to, vm.take_recording()) {
+                        if let Err(e) = recording.save(path) {
+                            println!("Unable to write the recording {}: {}", path, e);
+                        }
+                    }
+                    for line in vm.heap_report().unwrap_or_default() {
+                        println!("{}", line);
+                    }
+                    match result {
+                        Ok(result) => std::process::exit(result.exit_code),
+                        Err(e) => {
+                            println!("{}", tools::fault::FaultReport::new(&vm, e, Some(&asm.symbols)));
+                            std::process::exit(1);
+                        }
+                    }
+                }
+                Err(errors) => {
+                    for error in errors {
+                        println!("{}", error);
+                    }
+                    std::process::exit(1);
                 }
-                None => {}
             }
         }
         None => {
-            start_repl();
+            // clap only accepts text and json
+            let output_mode = match matches.value_of("REPL_FORMAT") {
+                Some("json") => repl::protocol::OutputMode::Json,
+                _ => repl::protocol::OutputMode::Text,
+            };
+            start_repl(!matches.is_present("NO_PAGER"), output_mode);
         }
     }
 }
 
 /// Starts a REPL that will run until the user kills it
-fn start_repl() {
+fn start_repl(paged: bool, output_mode: repl::protocol::OutputMode) {
     let mut repl = repl::REPL::new();
+    if !paged {
+        repl.set_page_size(None);
+    }
+    repl.set_output_mode(output_mode);
     repl.run();
 }
 
+/// Assembles and runs a program, then checks its `;; assert` annotations. Prints what failed and returns true if
+/// everything passed.
+fn run_test_file(filename: &str) -> bool {
+    let source = read_file(filename);
+    let (assertions, mut failures) = tools::verify::parse_assertions(&source);
+
+    let mut asm = assembler::Assembler::new();
+    if let Some(directory) = Path::new(filename).parent() {
+        asm.add_include_path(directory);
+    }
+    asm.add_include_paths_from_env();
+    asm.set_source_name(filename);
+
+    match asm.assemble(&source) {
+        Ok(program) => {
+            let mut vm = vm::VM::new();
+            vm.add_bytes(program);
+            if let Err(e) = vm.run() {
+                failures.push(tools::fault::FaultReport::new(&vm, e, Some(&asm.symbols)).to_string());
+            }
+            failures.append(&mut tools::verify::check_assertions(&assertions, &vm.registers, &asm.symbols));
+        }
+        Err(errors) => failures.extend(errors.iter().map(|error| error.to_string())),
+    }
+
+    if failures.is_empty() {
+        println!("PASS {} ({} assertions)", filename, assertions.len());
+        return true;
+    }
+
+    println!("FAIL {}", filename);
+    for failure in failures {
+        println!("    {}", failure);
+    }
+    false
+}
+
+/// Prints the lines of a diff that aren't the same in both programs, followed by a summary. Returns true if the
+/// programs were identical.
+fn run_diff(lines: &[tools::diff::DiffLine]) -> bool {
+    use tools::diff::DiffLine;
+
+    let (mut added, mut removed, mut changed) = (0, 0, 0);
+    for line in lines {
+        match line {
+            DiffLine::Same { .. } => continue,
+            DiffLine::Added { .. } => added += 1,
+            DiffLine::Removed { .. } => removed += 1,
+            DiffLine::Changed { .. } => changed += 1,
+        }
+        println!("{}", line);
+    }
+
+    println!("{} added, {} removed, {} changed", added, removed, changed);
+    added + removed + changed == 0
+}
+
+/// Compares a freshly assembled program with a committed binary, printing how they differ if they do. Returns true
+/// if they are byte for byte the same.
+fn check_frozen(committed: &str, program: &[u8]) -> bool {
+    let expected = read_binary_file(committed);
+    if expected == program {
+        println!("{} is up to date", committed);
+        return true;
+    }
+
+    let offset = expected.iter().zip(program).position(|(a, b)| a != b).unwrap_or_else(|| expected.len().min(program.len()));
+    println!("{} differs from the assembled program, starting at byte {}", committed, offset);
+    run_diff(&tools::diff::diff_programs(&expected, program));
+    false
+}
+
+/// Prints what the determinism audit found. Returns true if the program is safe to replay.
+fn run_audit(program: &[u8]) -> bool {
+    let report = vm::audit::audit(program);
+
+    for number in &report.nondeterministic_syscalls {
+        println!("Nondeterministic syscall used: {}", number);
+    }
+    for name in &report.nondeterministic_opcodes {
+        println!("Nondeterministic opcode used: {}", name);
+    }
+    for address in &report.device_addresses {
+        println!("Device accessed at address {}", address);
+    }
+    for difference in &report.differences {
+        println!("Runs differed: {}", difference);
+    }
+
+    let replay_safe = report.is_replay_safe();
+    println!("{}", if replay_safe { "Program is replay-safe" } else { "Program is not replay-safe" });
+    replay_safe
+}
+
+/// Writes run statistics to a file, as JSON or CSV depending on its extension
+fn write_stats(path: &str, stats: &vm::stats::RunStats) {
+    let contents = if path.ends_with(".json") { stats.to_json() } else { stats.to_csv() };
+    if let Err(e) = std::fs::write(path, contents) {
+        println!("Unable to write {}: {}", path, e);
+    }
+}
+
+/// Prints the sizes of the sections of a freshly assembled program, followed by the sizes of its symbols
+fn print_size_report(program: &[u8], symbols: &assembler::symbols::SymbolTable) {
+    println!("Sections:");
+    for line in tools::size::format_sizes(&tools::size::section_sizes(program)) {
+        println!("{}", line);
+    }
+
+    println!("Symbols:");
+    for line in tools::size::format_sizes(&tools::size::symbol_sizes(program, symbols)) {
+        println!("{}", line);
+    }
+}
+
+/// Splits a -D definition into a name and value. `NAME` on its own defines the symbol as 1.
+fn parse_definition(definition: &str) -> Option<(&str, i32)> {
+    let mut parts = definition.splitn(2, '=');
+    let name = parts.next().filter(|name| !name.is_empty())?;
+
+    match parts.next() {
+        Some(value) => value.parse::<i32>().ok().map(|value| (name, value)),
+        None => Some((name, 1)),
+    }
+}
+
 /// Attempts to read a file and return the contents. Exits if unable to read the file for any reason.
 fn read_file(tmp: &str) -> String {
     let filename = Path::new(tmp);
@@ -55,9 +468,7 @@ fn read_file(tmp: &str) -> String {
             let mut contents = String::new();
 
             match fh.read_to_string(&mut contents) {
-                Ok(_) => {
-                    return contents;
-                }
+                Ok(_) => contents,
                 Err(e) => {
                     println!("There was an error reading file: {:?}", e);
                     std::process::exit(1);
@@ -69,4 +480,26 @@ fn read_file(tmp: &str) -> String {
             std::process::exit(1);
         }
     }
-}
\ No newline at end of file
+}
+
+/// Reads a saved VM snapshot. Exits if unable to read or decode it.
+fn read_snapshot(filename: &str) -> vm::snapshot::VMState {
+    match vm::snapshot::VMState::load(filename) {
+        Ok(state) => state,
+        Err(e) => {
+            println!("Unable to read the snapshot {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Reads an assembled program. Exits if unable to read the file for any reason.
+fn read_binary_file(filename: &str) -> Vec<u8> {
+    match std::fs::read(filename) {
+        Ok(contents) => contents,
+        Err(e) => {
+            println!("Unable to read {}: {}", filename, e);
+            std::process::exit(1);
+        }
+    }
+}
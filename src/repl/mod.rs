@@ -1,31 +1,104 @@
 use crate::assembler::program_parsers::program;
+use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
+use crate::disassembler::disassemble;
+use crate::formatting::{DumpFormat, NumberFormat};
+use crate::repl::pager::{page, terminal_page_size};
+use crate::repl::protocol::{OutputMode, Response};
+use crate::repl::scripting::{evaluate, expand_alias, hexdump, parse_alias, parse_let, parse_redirect, Variables};
+use crate::tools::verify::{check_assertions, parse_assertions, Assertion, ASSERTION_PREFIX};
+use crate::vm::snapshot::VMState;
+use crate::vm::watchpoints::Watchpoint;
 use crate::vm::VM;
 
 use nom::types::CompleteStr;
 use std;
+use std::collections::HashMap;
+use std::env;
+use std::fs::{File, OpenOptions};
 use std::io;
-use std::io::Write;
-use std::path::Path;
-use std::fs::File;
 use std::io::Read;
-use std::num::ParseIntError;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// File in the home directory holding commands to run when the REPL starts
+pub const RC_FILE_NAME: &str = ".iridiumrc";
+
+pub mod pager;
+pub mod protocol;
+pub mod scripting;
 
 /// Core structure for the REPL for the Assembler
 pub struct REPL {
     vm: VM,
     command_buffer: Vec<String>,
+    /// Labels declared in the REPL, pointing at their offsets in the VM's program
+    symbols: SymbolTable,
+    /// Values bound with `let`
+    variables: Variables,
+    /// Shorthand commands defined with `.alias`
+    aliases: HashMap<String, String>,
+    /// Where commands are run from at startup and aliases are saved to
+    rc_path: Option<PathBuf>,
+    /// Lines shown at a time by long listings, or None to print them all at once
+    page_size: Option<usize>,
+    /// How integers are shown in listings, set with `.format`
+    number_format: NumberFormat,
+    /// What `.hexdump` reads memory as, set with `.dumpfmt`, or None for raw bytes
+    dump_format: Option<DumpFormat>,
+    /// File the listing of the command being run is going to, when it was redirected with > or >>
+    redirect: Option<File>,
+    /// Whether replies are meant for a person or a frontend
+    output_mode: OutputMode,
+    /// What the command being run has produced so far, when replies are JSON
+    response: Response,
+    /// `;; assert` annotations from the loaded file and typed lines, checked by `.verify`
+    assertions: Vec<Assertion>,
+}
+
+impl Default for REPL {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl REPL {
     pub fn new() -> REPL {
+        // Statistics are always collected, so .profile can report on everything typed so far
+        let mut vm = VM::new();
+        vm.enable_stats();
+
         REPL {
-            vm: VM::new(),
+            vm,
             command_buffer: vec![],
+            symbols: SymbolTable::new(),
+            variables: Variables::new(),
+            aliases: HashMap::new(),
+            rc_path: env::var_os("HOME").map(|home| Path::new(&home).join(RC_FILE_NAME)),
+            page_size: Some(terminal_page_size()),
+            number_format: NumberFormat::Decimal,
+            dump_format: None,
+            redirect: None,
+            output_mode: OutputMode::Text,
+            response: Response::default(),
+            assertions: vec![],
         }
     }
 
+    /// Sets how many lines long listings show at a time. None turns paging off.
+    pub fn set_page_size(&mut self, page_size: Option<usize>) {
+        self.page_size = page_size;
+    }
+
+    /// Sets whether commands are answered with text or a line of JSON each
+    pub fn set_output_mode(&mut self, output_mode: OutputMode) {
+        self.output_mode = output_mode;
+    }
+
     pub fn run(&mut self) {
-        println!("Welcome to Iridium!");
+        if self.output_mode == OutputMode::Text {
+            println!("Welcome to Iridium!");
+        }
+        self.run_rc_file();
 
         loop {
             let mut buffer = String::new();
@@ -33,84 +106,570 @@ impl REPL {
             // Blocking call until the user types in a command
             let stdin = io::stdin();
 
-            print!(">>> ");
+            if self.output_mode == OutputMode::Text {
+                print!(">>> ");
+            }
 
             io::stdout().flush().expect("Unable to flush stdout");
-            stdin
+            let read = stdin
                 .read_line(&mut buffer)
                 .expect("Unable to read line from user");
 
+            // A frontend closing its end of the pipe is the same as .quit
+            if read == 0 {
+                std::process::exit(0);
+            }
+
             let buffer = buffer.trim();
 
             self.command_buffer.push(buffer.to_string());
+            self.execute_command(buffer);
 
-            match buffer {
-                ".quit" => {
-                    println!("Farewell!");
-                    std::process::exit(0);
+            // Aliases defined at the prompt are kept for later sessions
+            if parse_alias(buffer).is_some() {
+                self.save_to_rc_file(buffer);
+            }
+        }
+    }
+
+    /// Runs one line of input, which is either a REPL command or assembly to execute
+    pub fn execute_command(&mut self, command: &str) {
+        self.response = Response::new(command);
+        self.run_command(command);
+        self.send_response();
+    }
+
+    fn run_command(&mut self, command: &str) {
+        // Aliases are stored as typed, so any {variables} in them are substituted when they're used
+        if command.starts_with(".alias") {
+            self.alias(command);
+            return;
+        }
+
+        let expanded = expand_alias(command, &self.aliases);
+        self.variables.set("pc", self.vm.pc() as i32);
+
+        let line = match self.variables.substitute(&expanded) {
+            Ok(line) => line,
+            Err(e) => {
+                self.print_error(&e);
+                return;
+            }
+        };
+        let (buffer, target) = parse_redirect(&line);
+
+        if let Some(target) = target {
+            let file = OpenOptions::new()
+                .create(true)
+                .write(true)
+                .append(target.append)
+                .truncate(!target.append)
+                .open(target.path);
+
+            match file {
+                Ok(file) => self.redirect = Some(file),
+                Err(e) => {
+                    self.print_error(&format!("Unable to open {}: {}", target.path, e));
+                    return;
                 }
-                ".history" => {
-                    for command in &self.command_buffer {
-                        println!("{}", command);
-                    }
+            }
+        }
+
+        self.execute_expanded_command(buffer);
+        self.redirect = None;
+    }
+
+    /// Runs a command once aliases, variables and redirection have been dealt with
+    fn execute_expanded_command(&mut self, buffer: &str) {
+        if let Some((name, expression)) = parse_let(buffer) {
+            match evaluate(expression, &self.variables, &self.vm.registers, &self.symbols) {
+                Ok(value) => {
+                    self.variables.set(name, value);
+                    self.print_line(&format!("{} = {}", name, value));
                 }
-                ".program" => {
-                    println!("Listing instructions currently in VM's program vector:");
-                    for instruction in &self.vm.program {
-                        println!("{}", instruction);
-                    }
-                    println!("End of Program Listing");
+                Err(e) => self.print_error(&e),
+            }
+            return;
+        }
+
+        if buffer.starts_with(".hexdump") {
+            self.hexdump(buffer);
+            return;
+        }
+
+        if let Some(format) = buffer.strip_prefix(".dumpfmt") {
+            self.set_dump_format(format.trim());
+            return;
+        }
+
+        if let Some(name) = buffer.strip_prefix(".format") {
+            let name = name.trim();
+            match NumberFormat::from_name(name) {
+                Some(format) => self.number_format = format,
+                None => self.print_error(&format!("Unknown format: {}. Expected dec, hex or bin.", name)),
+            }
+            return;
+        }
+
+        if buffer.contains(ASSERTION_PREFIX) {
+            let line = self.command_buffer.len();
+            match Assertion::parse(buffer, line) {
+                Some(Ok(assertion)) => self.assertions.push(assertion),
+                Some(Err(e)) => self.print_error(&e),
+                None => {}
+            }
+
+            // An assertion on its own is only recorded, one after an instruction doesn't stop it running
+            if buffer.trim_start().starts_with(ASSERTION_PREFIX) {
+                return;
+            }
+        }
+
+        if let Some(path) = buffer.strip_prefix(".snapshot ") {
+            let path = path.trim();
+            match self.vm.snapshot().save(path) {
+                Ok(()) => self.print_line(&format!("Saved the VM to {}", path)),
+                Err(e) => self.print_error(&format!("Unable to save the VM to {}: {}", path, e)),
+            }
+            return;
+        }
+
+        if let Some(path) = buffer.strip_prefix(".restore ") {
+            let path = path.trim();
+            match VMState::load(path) {
+                Ok(state) => match self.vm.restore(state) {
+                    Ok(()) => self.print_line(&format!("Restored the VM from {}", path)),
+                    Err(e) => self.print_error(&format!("Unable to restore the VM from {}: {}", path, e)),
+                },
+                Err(e) => self.print_error(&format!("Unable to restore the VM from {}: {}", path, e)),
+            }
+            return;
+        }
+
+        if let Some(address) = buffer.strip_prefix(".break") {
+            self.set_breakpoint(address.trim());
+            return;
+        }
+
+        if let Some(address) = buffer.strip_prefix(".clear ") {
+            match evaluate(address.trim(), &self.variables, &self.vm.registers, &self.symbols) {
+                Ok(address) if address >= 0 && self.vm.clear_breakpoint(address as usize) => {
+                    self.print_line(&format!("Cleared the breakpoint at {}", address));
                 }
-                ".registers" => {
-                    println!("Listing registers and all contents:");
-                    println!("{:#?}", self.vm.registers);
-                    println!("End of Register Listing")
+                Ok(address) => self.print_error(&format!("No breakpoint at {}", address)),
+                Err(e) => self.print_error(&e),
+            }
+            return;
+        }
+
+        if let Some(target) = buffer.strip_prefix(".watch") {
+            self.watch(target.trim());
+            return;
+        }
+
+        if let Some(target) = buffer.strip_prefix(".unwatch ") {
+            match self.watchpoint(target.trim()) {
+                Ok(watchpoint) if self.vm.remove_watchpoint(watchpoint) => {
+                    self.print_line(&format!("Stopped watching {}", watchpoint));
                 }
-                ".load_file" => {
+                Ok(watchpoint) => self.print_error(&format!("{} isn't being watched", watchpoint)),
+                Err(e) => self.print_error(&e),
+            }
+            return;
+        }
+
+        if buffer.starts_with(".disassemble") {
+            self.disassemble(buffer);
+            return;
+        }
+
+        match buffer {
+            ".quit" => {
+                self.print_line("Farewell!");
+                self.send_response();
+                std::process::exit(0);
+            }
+            ".history" => {
+                let lines = self.command_buffer.clone();
+                self.print_paged(&lines);
+            }
+            ".program" => {
+                self.print_line("Listing instructions currently in VM's program vector:");
+                let lines: Vec<String> = self.vm.program.iter().map(|byte| byte.to_string()).collect();
+                self.print_paged(&lines);
+                self.print_line("End of Program Listing");
+            }
+            ".loaded" => {
+                let lines = self.vm.loaded_program().to_lines();
+                self.print_paged(&lines);
+            }
+            ".profile" => {
+                let lines = self.vm.profile().map(|profile| profile.to_lines()).unwrap_or_default();
+                self.print_paged(&lines);
+            }
+            ".profile reset" => {
+                self.vm.enable_stats();
+                self.print_line("Profile reset");
+            }
+            ".step" => {
+                let step = self.vm.step();
+                self.print_line(&step.to_string());
+            }
+            ".continue" => match self.vm.run() {
+                Ok(result) => self.print_line(&format!("Program finished with exit code {}", result.exit_code)),
+                Err(e) => self.print_error(&e.to_string()),
+            },
+            ".pager on" => {
+                self.page_size = Some(terminal_page_size());
+            }
+            ".pager off" => {
+                self.page_size = None;
+            }
+            ".verify" => {
+                let failures = check_assertions(&self.assertions, &self.vm.registers, &self.symbols);
+                if failures.is_empty() {
+                    self.print_line(&format!("{} assertions passed", self.assertions.len()));
+                }
+                for failure in failures {
+                    self.print_error(&format!("Failed {}", failure));
+                }
+            }
+            ".registers" => {
+                let mut lines = vec!["Listing registers and all contents:".to_string()];
+                for (index, (value, float)) in self.vm.registers.iter().zip(self.vm.float_registers.iter()).enumerate() {
+                    let value = self.number_format.format_i32(*value);
+                    lines.push(format!("${:<2} {:>34}  {:?}", index, value, float));
+                }
+                lines.push(format!("Flags: {}", self.vm.flags()));
+                lines.push("End of Register Listing".to_string());
+                self.print_paged(&lines);
+            }
+            ".load_file" => {
+                if self.output_mode == OutputMode::Text {
                     print!("Please enter the path to the file you wish to load: ");
                     io::stdout().flush().expect("Unable to flush stdout");
+                }
 
-                    let mut tmp = String::new();
-                    stdin.read_line(&mut tmp).expect("Unable to read line from user");
+                let mut tmp = String::new();
+                io::stdin().read_line(&mut tmp).expect("Unable to read line from user");
 
-                    let tmp = tmp.trim();
-                    let filename = Path::new(&tmp);
-                    let mut f = File::open(Path::new(&filename)).expect("File not found");
-                    let mut contents = String::new();
-                    
-                    f.read_to_string(&mut contents).expect("There was an error reading from the file");
+                let tmp = tmp.trim();
+                let filename = Path::new(&tmp);
+                let mut f = File::open(Path::new(&filename)).expect("File not found");
+                let mut contents = String::new();
+                
+                f.read_to_string(&mut contents).expect("There was an error reading from the file");
 
-                    let program = match program(CompleteStr(&contents)) {
-                        Ok((remainder, program)) => {
-                            program
-                        }
-                        Err(e) => {
-                            println!("Unable to parse input: {:?}", e);
-                            continue;
-                        }
-                    };
+                let program = match program(CompleteStr(&contents)) {
+                    Ok((_remainder, program)) => {
+                        program
+                    }
+                    Err(e) => {
+                        self.print_error(&format!("Unable to parse input: {:?}", e));
+                        return;
+                    }
+                };
 
-                    self.vm.program.append(&mut program.to_bytes());
+                // The file's assertions replace any from before, since they describe the program being loaded
+                let (assertions, errors) = parse_assertions(&contents);
+                self.assertions = assertions;
+                for error in errors {
+                    self.print_error(&error);
                 }
-                _ => {
-                    let parsed_program = program(CompleteStr(buffer));
 
-                    if !parsed_program.is_ok() {
-                        println!("Unable to parse input");
-                        continue;
+                let symbols = SymbolTable::new();
+                self.vm.program.append(&mut program.to_bytes(&symbols));
+            }
+            _ => {
+                let parsed_program = program(CompleteStr(buffer));
+
+                if parsed_program.is_err() {
+                    self.print_error("Unable to parse input");
+                    return;
+                }
+
+                let (_, result) = parsed_program.unwrap();
+
+                // Labels point at where the instruction they're on is about to be added
+                let mut labelled = false;
+                for instruction in &result.instructions {
+                    if let Some(name) = instruction.get_label_name() {
+                        let offset = self.vm.program.len() as u32;
+                        if !self.symbols.set_symbol_offset(&name, offset) {
+                            self.symbols.add_symbol(Symbol::new_with_offset(name, SymbolType::Label, offset));
+                        }
+                        labelled = true;
                     }
+                }
+                if labelled {
+                    self.vm.attach_symbols(Some(self.symbols.clone()));
+                }
 
-                    let (_, result) = parsed_program.unwrap();
-                    let bytecode = result.to_bytes();
+                let bytecode = result.to_bytes_at(&self.symbols, self.vm.program.len() as u32);
+                self.response.set_bytecode(&bytecode);
+
+                // TODO: Make a function to let us add bytes to the VM
+                for byte in bytecode {
+                    self.vm.add_byte(byte);
+                }
+
+                if let Err(e) = self.vm.run_once() {
+                    self.print_error(&e.to_string());
+                }
+            }
+        }
+    }
+
+    /// Runs the commands in the rc file, if there is one, such as the aliases saved by earlier sessions
+    fn run_rc_file(&mut self) {
+        let path = match &self.rc_path {
+            Some(path) => path.clone(),
+            None => return,
+        };
+
+        let contents = match std::fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(_) => return,
+        };
+
+        for line in contents.lines().map(str::trim) {
+            // Frontends only get replies to the commands they sent, not to the rc file's
+            if !line.is_empty() && !line.starts_with(';') {
+                self.run_command(line);
+            }
+        }
+    }
+
+    fn save_to_rc_file(&self, command: &str) {
+        let path = match &self.rc_path {
+            Some(path) => path,
+            None => return,
+        };
+
+        let result = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .and_then(|mut file| writeln!(file, "{}", command));
+
+        if let Err(e) = result {
+            println!("Unable to save to {}: {}", path.display(), e);
+        }
+    }
+
+    /// Prints a message about the command being run, or adds it to the command's JSON reply
+    fn print_line(&mut self, line: &str) {
+        match self.output_mode {
+            OutputMode::Text => println!("{}", line),
+            OutputMode::Json => self.response.push_output(line),
+        }
+    }
+
+    /// Reports why a command failed
+    fn print_error(&mut self, message: &str) {
+        match self.output_mode {
+            OutputMode::Text => println!("{}", message),
+            OutputMode::Json => self.response.set_error(message),
+        }
+    }
+
+    /// Prints the JSON reply for the command that just ran. Text replies have already been printed as they happened.
+    fn send_response(&mut self) {
+        if self.output_mode == OutputMode::Json {
+            println!("{}", self.response.to_json(&self.vm));
+            io::stdout().flush().expect("Unable to flush stdout");
+        }
+    }
+
+    /// Prints a listing a page at a time, or writes it to the file the command was redirected to
+    fn print_paged(&mut self, lines: &[String]) {
+        if self.output_mode == OutputMode::Json && self.redirect.is_none() {
+            for line in lines {
+                self.response.push_output(line);
+            }
+            return;
+        }
+
+        let result = match self.redirect.as_mut() {
+            Some(file) => page(lines, None, &mut io::empty(), file),
+            None => page(lines, self.page_size, &mut io::stdin().lock(), &mut io::stdout()),
+        };
+
+        if let Err(e) = result {
+            self.print_error(&format!("Unable to write output: {}", e));
+        }
+    }
+
+    /// Handles `.break address`, where the address can be an expression such as `@loop + 4`, or lists the
+    /// breakpoints when given no address
+    fn set_breakpoint(&mut self, address: &str) {
+        if address.is_empty() {
+            let lines: Vec<String> = self.vm.breakpoints().map(|breakpoint| breakpoint.address.to_string()).collect();
+            if lines.is_empty() {
+                self.print_line("No breakpoints");
+            }
+            for line in lines {
+                self.print_line(&line);
+            }
+            return;
+        }
 
-                    // TODO: Make a function to let us add bytes to the VM
-                    for byte in bytecode {
-                        self.vm.add_byte(byte);
+        match evaluate(address, &self.variables, &self.vm.registers, &self.symbols) {
+            Ok(address) if address >= 0 => {
+                self.vm.set_breakpoint(address as usize);
+                self.print_line(&format!("Breakpoint set at {}", address));
+            }
+            Ok(address) => self.print_error(&format!("Invalid address: {}", address)),
+            Err(e) => self.print_error(&e),
+        }
+    }
+
+    /// Handles `.watch $register` and `.watch address`, where the address of the heap word can be an expression, or
+    /// lists the watchpoints when given neither
+    fn watch(&mut self, target: &str) {
+        if target.is_empty() {
+            let lines: Vec<String> = self.vm.watchpoints().map(|watchpoint| watchpoint.to_string()).collect();
+            if lines.is_empty() {
+                self.print_line("No watchpoints");
+            }
+            for line in lines {
+                self.print_line(&line);
+            }
+            return;
+        }
+
+        match self.watchpoint(target) {
+            Ok(watchpoint) if self.vm.add_watchpoint(watchpoint) => {
+                self.print_line(&format!("Watching {}", watchpoint));
+            }
+            Ok(watchpoint) => self.print_error(&format!("Already watching {}", watchpoint)),
+            Err(e) => self.print_error(&e),
+        }
+    }
+
+    /// Reads the target of `.watch` or `.unwatch`. A bare register means the register itself rather than its value.
+    fn watchpoint(&self, target: &str) -> Result<Watchpoint, String> {
+        if let Some(number) = target.strip_prefix('$').and_then(|number| number.parse::<usize>().ok()) {
+            if number >= self.vm.registers.len() {
+                return Err(format!("No such register: ${}", number));
+            }
+            return Ok(Watchpoint::Register(number));
+        }
+
+        match evaluate(target, &self.variables, &self.vm.registers, &self.symbols)? {
+            address if address >= 0 => Ok(Watchpoint::Heap(address as usize)),
+            address => Err(format!("Invalid address: {}", address)),
+        }
+    }
+
+    /// Handles `.alias name 'command'`, or lists the aliases when given no arguments
+    fn alias(&mut self, command: &str) {
+        if command.trim() == ".alias" {
+            let mut lines: Vec<String> = self.aliases.iter().map(|(name, expansion)| format!("{} = '{}'", name, expansion)).collect();
+            lines.sort();
+            for line in lines {
+                self.print_line(&line);
+            }
+            return;
+        }
+
+        match parse_alias(command) {
+            Some((name, expansion)) => {
+                self.aliases.insert(name.to_string(), expansion.to_string());
+            }
+            None => self.print_error("Usage: .alias name 'command'"),
+        }
+    }
+
+    /// Handles `.disassemble [start end]`, which disassembles the whole program when no range is given
+    fn disassemble(&mut self, command: &str) {
+        let arguments: Vec<&str> = command.split_whitespace().skip(1).collect();
+        let program_length = self.vm.program.len();
+
+        let (start, end) = match arguments.len() {
+            0 => (0, program_length),
+            2 => {
+                let mut bounds = vec![];
+                for argument in &arguments {
+                    match evaluate(argument, &self.variables, &self.vm.registers, &self.symbols) {
+                        // Ranges are clamped to the program so `pc-8 pc+8` works near either end of it
+                        Ok(value) => bounds.push((value.max(0) as usize).min(program_length)),
+                        Err(e) => {
+                            self.print_error(&e);
+                            return;
+                        }
                     }
+                }
+                (bounds[0], bounds[1].max(bounds[0]))
+            }
+            _ => {
+                self.print_error("Usage: .disassemble [start end]");
+                return;
+            }
+        };
+
+        let lines = disassemble(&self.vm.program[start..end], start, self.number_format);
+        self.print_paged(&lines);
+    }
 
-                    self.vm.run_once();
+    /// Shows the dump format, or sets it from the arguments of `.dumpfmt`
+    fn set_dump_format(&mut self, format: &str) {
+        match format {
+            "" => {
+                let current = self.dump_format.map_or("bytes".to_string(), |format| format.to_string());
+                self.print_line(&format!("Dump format: {}", current));
+            }
+            "bytes" => self.dump_format = None,
+            _ => match DumpFormat::parse(format) {
+                Ok(format) => self.dump_format = Some(format),
+                Err(e) => self.print_error(&e),
+            },
+        }
+    }
+
+    /// Handles `.hexdump heap|program <address> <length>`
+    fn hexdump(&mut self, command: &str) {
+        let arguments: Vec<&str> = command.split_whitespace().skip(1).collect();
+        if arguments.len() != 3 {
+            self.print_error("Usage: .hexdump heap|program <address> <length>");
+            return;
+        }
+
+        let mut numbers = vec![];
+        for argument in &arguments[1..] {
+            match evaluate(argument, &self.variables, &self.vm.registers, &self.symbols) {
+                Ok(value) if value >= 0 => numbers.push(value as usize),
+                Ok(value) => {
+                    self.print_error(&format!("Expected a non-negative number, found {}", value));
+                    return;
+                }
+                Err(e) => {
+                    self.print_error(&e);
+                    return;
                 }
             }
         }
+
+        let memory = match arguments[0] {
+            "heap" => self.vm.heap(),
+            "program" => self.vm.program.as_slice(),
+            other => {
+                self.print_error(&format!("Unknown memory to dump: {}. Expected heap or program.", other));
+                return;
+            }
+        };
+
+        let (address, length) = (numbers[0], numbers[1]);
+        let dump = match memory.get(address..address.saturating_add(length)) {
+            Some(bytes) => Ok(match self.dump_format {
+                Some(format) => format.dump(bytes, address, self.number_format),
+                None => hexdump(bytes, address, self.number_format),
+            }),
+            None => Err(format!("{} bytes at {} is outside of the {} ({} bytes)", length, address, arguments[0], memory.len())),
+        };
+        match dump {
+            Ok(lines) => self.print_paged(&lines),
+            Err(e) => self.print_error(&e),
+        }
     }
 }
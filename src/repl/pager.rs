@@ -0,0 +1,91 @@
+use std::env;
+use std::io::{self, BufRead, Write};
+
+/// Lines per page when the terminal size isn't known
+pub const DEFAULT_PAGE_SIZE: usize = 24;
+
+/// Works out how many lines fit on the terminal from `LINES`, leaving one for the continue prompt
+pub fn terminal_page_size() -> usize {
+    env::var("LINES")
+        .ok()
+        .and_then(|lines| lines.parse::<usize>().ok())
+        .filter(|lines| *lines > 1)
+        .map_or(DEFAULT_PAGE_SIZE, |lines| lines - 1)
+}
+
+/// Writes lines a page at a time, waiting for enter between pages. Typing q stops the output early. With no page
+/// size, everything is written at once.
+pub fn page<R: BufRead, W: Write>(lines: &[String], page_size: Option<usize>, input: &mut R, output: &mut W) -> io::Result<()> {
+    let page_size = match page_size {
+        Some(size) if size > 0 && lines.len() > size => size,
+        _ => {
+            for line in lines {
+                writeln!(output, "{}", line)?;
+            }
+            return Ok(());
+        }
+    };
+
+    let mut shown = 0;
+    for page in lines.chunks(page_size) {
+        for line in page {
+            writeln!(output, "{}", line)?;
+        }
+
+        shown += page.len();
+        if shown == lines.len() {
+            break;
+        }
+
+        write!(output, "-- More ({} lines left, enter to continue, q to stop) --", lines.len() - shown)?;
+        output.flush()?;
+
+        let mut answer = String::new();
+        if input.read_line(&mut answer)? == 0 || answer.trim() == "q" {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(count: usize) -> Vec<String> {
+        (0..count).map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn test_short_output_is_not_paged() {
+        let mut output = vec![];
+        page(&lines(3), Some(5), &mut &b""[..], &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "0\n1\n2\n");
+    }
+
+    #[test]
+    fn test_pages_wait_for_enter() {
+        let mut output = vec![];
+        page(&lines(5), Some(2), &mut &b"\n\n"[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("0\n1\n-- More (3 lines left"));
+        assert!(output.ends_with("4\n"));
+    }
+
+    #[test]
+    fn test_quit_stops_output() {
+        let mut output = vec![];
+        page(&lines(5), Some(2), &mut &b"q\n"[..], &mut output).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1\n"));
+        assert!(!output.contains("2\n"));
+    }
+
+    #[test]
+    fn test_no_pager() {
+        let mut output = vec![];
+        page(&lines(50), None, &mut &b""[..], &mut output).unwrap();
+        assert_eq!(output.iter().filter(|byte| **byte == b'\n').count(), 50);
+    }
+}
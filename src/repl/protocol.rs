@@ -0,0 +1,122 @@
+use crate::vm::VM;
+
+/// What the REPL prints in reply to each command
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputMode {
+    /// Messages and listings for a person at a terminal
+    Text,
+    /// One JSON object per command, for frontends driving the REPL as a backend process
+    Json,
+}
+
+/// Everything a command produced, reported as a single line of JSON once it has finished
+#[derive(Debug, Default)]
+pub struct Response {
+    command: String,
+    output: Vec<String>,
+    error: Option<String>,
+    bytecode: Option<Vec<u8>>,
+}
+
+impl Response {
+    pub fn new(command: &str) -> Response {
+        Response {
+            command: command.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Adds a line of the command's listing or messages
+    pub fn push_output(&mut self, line: &str) {
+        self.output.push(line.to_string());
+    }
+
+    /// Marks the command as failed. Only the first error is kept, since later ones tend to follow from it.
+    pub fn set_error(&mut self, message: &str) {
+        if self.error.is_none() {
+            self.error = Some(message.to_string());
+        }
+    }
+
+    /// Records the bytecode assembled from the command, when it was assembly
+    pub fn set_bytecode(&mut self, bytecode: &[u8]) {
+        self.bytecode = Some(bytecode.to_vec());
+    }
+
+    /// Serializes the response, along with the state of the VM after the command ran, as one line of JSON
+    pub fn to_json(&self, vm: &VM) -> String {
+        let output: Vec<String> = self.output.iter().map(|line| json_string(line)).collect();
+        let error = self.error.as_ref().map_or("null".to_string(), |e| json_string(e));
+        let bytecode = self.bytecode.as_ref().map_or("null".to_string(), |bytes| json_array(bytes));
+
+        format!(
+            "{{\"command\":{},\"ok\":{},\"output\":[{}],\"error\":{},\"bytecode\":{},\"registers\":{},\"pc\":{},\"flags\":{}}}",
+            json_string(&self.command),
+            self.error.is_none(),
+            output.join(","),
+            error,
+            bytecode,
+            json_array(&vm.registers),
+            vm.pc(),
+            json_string(&vm.flags().to_string())
+        )
+    }
+}
+
+/// Quotes a string for JSON, escaping the characters that can't appear in a JSON string as they are
+pub fn json_string(value: &str) -> String {
+    let mut result = String::with_capacity(value.len() + 2);
+    result.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => result.push_str("\\\""),
+            '\\' => result.push_str("\\\\"),
+            '\n' => result.push_str("\\n"),
+            '\r' => result.push_str("\\r"),
+            '\t' => result.push_str("\\t"),
+            c if (c as u32) < 0x20 => result.push_str(&format!("\\u{:04x}", c as u32)),
+            c => result.push(c),
+        }
+    }
+    result.push('"');
+    result
+}
+
+fn json_array<T: ToString>(values: &[T]) -> String {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    format!("[{}]", values.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_string() {
+        assert_eq!(json_string("load $1 #5"), "\"load $1 #5\"");
+        assert_eq!(json_string("say \"hi\"\n"), "\"say \\\"hi\\\"\\n\"");
+        assert_eq!(json_string("a\\b\u{1}"), "\"a\\\\b\\u0001\"");
+    }
+
+    #[test]
+    fn test_response_to_json() {
+        let vm = VM::new();
+        let mut response = Response::new("load $0 #1");
+        response.set_bytecode(&[0, 0, 0, 1]);
+        let json = response.to_json(&vm);
+        assert!(json.starts_with("{\"command\":\"load $0 #1\",\"ok\":true,\"output\":[],\"error\":null,\"bytecode\":[0,0,0,1],"));
+        assert!(json.contains("\"registers\":[0,0,"));
+        assert!(json.ends_with("\"pc\":0,\"flags\":\"----\"}"));
+        assert!(!json.contains('\n'));
+    }
+
+    #[test]
+    fn test_response_error() {
+        let mut response = Response::new(".bogus");
+        response.push_output("partial");
+        response.set_error("first");
+        response.set_error("second");
+        let json = response.to_json(&VM::new());
+        assert!(json.contains("\"ok\":false,\"output\":[\"partial\"],\"error\":\"first\",\"bytecode\":null"));
+    }
+}
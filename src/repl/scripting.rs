@@ -0,0 +1,287 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::formatting::NumberFormat;
+
+use std::collections::HashMap;
+
+/// Values bound with `let name = expression`, which later commands can use as `{name}`
+#[derive(Debug, Default)]
+pub struct Variables {
+    values: HashMap<String, i32>,
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        Variables::default()
+    }
+
+    pub fn set(&mut self, name: &str, value: i32) {
+        self.values.insert(name.to_string(), value);
+    }
+
+    pub fn get(&self, name: &str) -> Option<i32> {
+        self.values.get(name).copied()
+    }
+
+    /// Replaces each `{name}` in a command with the value of the variable
+    pub fn substitute(&self, line: &str) -> Result<String, String> {
+        let mut result = String::new();
+        let mut rest = line;
+
+        while let Some(start) = rest.find('{') {
+            let end = match rest[start..].find('}') {
+                Some(end) => start + end,
+                None => return Err(format!("Unclosed {{ in: {}", line)),
+            };
+
+            let name = rest[start + 1..end].trim();
+            let value = self.get(name).ok_or_else(|| format!("Unknown variable: {}", name))?;
+            result.push_str(&rest[..start]);
+            result.push_str(&value.to_string());
+            rest = &rest[end + 1..];
+        }
+
+        result.push_str(rest);
+        Ok(result)
+    }
+}
+
+/// Splits `let name = expression` into the name and the expression, or returns None if the line isn't a `let`
+pub fn parse_let(line: &str) -> Option<(&str, &str)> {
+    let binding = line.strip_prefix("let ")?;
+    let (name, expression) = binding.split_at(binding.find('=')?);
+    let name = name.trim();
+
+    if name.is_empty() || !name.chars().all(|c| c.is_alphanumeric() || c == '_') {
+        return None;
+    }
+
+    Some((name, expression[1..].trim()))
+}
+
+/// Where a command's listing should be written instead of the terminal
+#[derive(Debug, PartialEq)]
+pub struct Redirect<'a> {
+    pub path: &'a str,
+    /// True for `>>`, which adds to the end of the file instead of replacing it
+    pub append: bool,
+}
+
+/// Splits `.command > file` or `.command >> file` into the command and where its output goes. Only REPL commands
+/// (starting with a dot) can be redirected.
+pub fn parse_redirect(line: &str) -> (&str, Option<Redirect<'_>>) {
+    if !line.starts_with('.') {
+        return (line, None);
+    }
+
+    let position = match line.find('>') {
+        Some(position) => position,
+        None => return (line, None),
+    };
+
+    let (command, target) = line.split_at(position);
+    let (append, path) = match target.strip_prefix(">>") {
+        Some(path) => (true, path),
+        None => (false, &target[1..]),
+    };
+
+    let path = path.trim();
+    if path.is_empty() {
+        return (line, None);
+    }
+
+    (command.trim_end(), Some(Redirect { path, append }))
+}
+
+/// Splits `.alias name 'command'` into the name and the command it stands for
+pub fn parse_alias(line: &str) -> Option<(&str, &str)> {
+    let definition = line.strip_prefix(".alias ")?.trim();
+    let (name, expansion) = definition.split_at(definition.find(char::is_whitespace)?);
+    let expansion = expansion.trim();
+
+    if expansion.len() < 2 || !expansion.starts_with('\'') || !expansion.ends_with('\'') {
+        return None;
+    }
+
+    Some((name, &expansion[1..expansion.len() - 1]))
+}
+
+/// Replaces the first word of a command with what it stands for if it is an alias. Anything after the alias is
+/// kept, so `d 4` with `d` aliased to `.hexdump heap` becomes `.hexdump heap 4`. Aliases aren't expanded again, so
+/// they can't loop.
+pub fn expand_alias(line: &str, aliases: &HashMap<String, String>) -> String {
+    let name = line.split_whitespace().next().unwrap_or("");
+
+    match aliases.get(name) {
+        Some(expansion) => format!("{}{}", expansion, &line.trim_start()[name.len()..]),
+        None => line.to_string(),
+    }
+}
+
+/// Evaluates a sum such as `@buffer + 8 - $1`. Terms can be integers (decimal, or hex with 0x), `$n` for the value
+/// of a register, `@name` for the offset of a label and plain names for variables.
+pub fn evaluate(expression: &str, variables: &Variables, registers: &[i32; 32], symbols: &SymbolTable) -> Result<i32, String> {
+    let mut total: i32 = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    let mut terms = vec![];
+
+    for c in expression.chars() {
+        if c == '+' || c == '-' {
+            terms.push((sign, term.trim().to_string()));
+            term.clear();
+            sign = if c == '+' { 1 } else { -1 };
+        } else {
+            term.push(c);
+        }
+    }
+    terms.push((sign, term.trim().to_string()));
+
+    for (index, (sign, term)) in terms.into_iter().enumerate() {
+        // A leading sign leaves an empty first term, like the 0 in 0 - 5
+        if term.is_empty() && index == 0 {
+            continue;
+        }
+
+        let value = evaluate_term(&term, variables, registers, symbols)?;
+        total = total
+            .checked_add(sign * value)
+            .ok_or_else(|| format!("Overflow evaluating: {}", expression))?;
+    }
+
+    Ok(total)
+}
+
+fn evaluate_term(term: &str, variables: &Variables, registers: &[i32; 32], symbols: &SymbolTable) -> Result<i32, String> {
+    if term.is_empty() {
+        return Err("Missing a value in the expression".to_string());
+    }
+
+    if let Some(register) = term.strip_prefix('$') {
+        return match register.parse::<usize>() {
+            Ok(register) if register < registers.len() => Ok(registers[register]),
+            _ => Err(format!("Invalid register: {}", term)),
+        };
+    }
+
+    if let Some(label) = term.strip_prefix('@') {
+        return symbols
+            .symbol_value(label)
+            .map(|offset| offset as i32)
+            .ok_or_else(|| format!("Unknown label: {}", label));
+    }
+
+    if let Some(hex) = term.strip_prefix("0x") {
+        return i32::from_str_radix(hex, 16).map_err(|_| format!("Invalid number: {}", term));
+    }
+
+    if term.starts_with(|c: char| c.is_ascii_digit()) {
+        return term.parse::<i32>().map_err(|_| format!("Invalid number: {}", term));
+    }
+
+    variables.get(term).ok_or_else(|| format!("Unknown variable: {}", term))
+}
+
+/// Formats bytes as lines of sixteen values with their printable characters alongside. `base` is the address of
+/// `bytes[0]`. Addresses are always hex; the bytes use the given format, padded so the columns line up.
+pub fn hexdump(bytes: &[u8], base: usize, format: NumberFormat) -> Vec<String> {
+    let byte_width = format.format_byte(0).len();
+    let column_width = 16 * (byte_width + 1) - 1;
+
+    bytes
+        .chunks(16)
+        .enumerate()
+        .map(|(index, chunk)| {
+            let values: Vec<String> = chunk.iter().map(|byte| format.format_byte(*byte)).collect();
+            let text: String = chunk
+                .iter()
+                .map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { *byte as char } else { '.' })
+                .collect();
+            format!("{:08x}  {:<width$}  |{}|", base + index * 16, values.join(" "), text, width = column_width)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
+
+    #[test]
+    fn test_substitute() {
+        let mut variables = Variables::new();
+        variables.set("addr", 72);
+        assert_eq!(variables.substitute(".hexdump heap {addr} 16"), Ok(".hexdump heap 72 16".to_string()));
+        assert_eq!(variables.substitute("load $0 #{ addr }"), Ok("load $0 #72".to_string()));
+        assert!(variables.substitute("{missing}").is_err());
+        assert!(variables.substitute("{addr").is_err());
+    }
+
+    #[test]
+    fn test_parse_let() {
+        assert_eq!(parse_let("let addr = @buffer + 8"), Some(("addr", "@buffer + 8")));
+        assert_eq!(parse_let("let x=1"), Some(("x", "1")));
+        assert!(parse_let("load $0 #1").is_none());
+        assert!(parse_let("let = 1").is_none());
+    }
+
+    #[test]
+    fn test_parse_redirect() {
+        assert_eq!(
+            parse_redirect(".disassemble > listing.txt"),
+            (".disassemble", Some(Redirect { path: "listing.txt", append: false }))
+        );
+        assert_eq!(
+            parse_redirect(".hexdump heap 0 16 >> dump.txt"),
+            (".hexdump heap 0 16", Some(Redirect { path: "dump.txt", append: true }))
+        );
+        assert_eq!(parse_redirect(".history"), (".history", None));
+        assert_eq!(parse_redirect(".history >"), (".history >", None));
+        assert_eq!(parse_redirect("load $0 #1"), ("load $0 #1", None));
+    }
+
+    #[test]
+    fn test_parse_alias() {
+        assert_eq!(parse_alias(".alias d '.disassemble pc-8 pc+8'"), Some(("d", ".disassemble pc-8 pc+8")));
+        assert!(parse_alias(".alias d .disassemble").is_none());
+        assert!(parse_alias(".alias d").is_none());
+        assert!(parse_alias(".alias").is_none());
+    }
+
+    #[test]
+    fn test_expand_alias() {
+        let mut aliases = HashMap::new();
+        aliases.insert("d".to_string(), ".disassemble pc-8 pc+8".to_string());
+        aliases.insert("hd".to_string(), ".hexdump heap".to_string());
+        assert_eq!(expand_alias("d", &aliases), ".disassemble pc-8 pc+8");
+        assert_eq!(expand_alias("hd 0 16", &aliases), ".hexdump heap 0 16");
+        assert_eq!(expand_alias("dd", &aliases), "dd");
+        assert_eq!(expand_alias("load $0 #1", &aliases), "load $0 #1");
+    }
+
+    #[test]
+    fn test_evaluate() {
+        let mut variables = Variables::new();
+        variables.set("size", 4);
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset("buffer".to_string(), SymbolType::Label, 64));
+        let mut registers = [0; 32];
+        registers[1] = 100;
+
+        assert_eq!(evaluate("@buffer + 8", &variables, &registers, &symbols), Ok(72));
+        assert_eq!(evaluate("$1 - size + 0x10", &variables, &registers, &symbols), Ok(112));
+        assert_eq!(evaluate("-5", &variables, &registers, &symbols), Ok(-5));
+        assert!(evaluate("@missing", &variables, &registers, &symbols).is_err());
+        assert!(evaluate("$40", &variables, &registers, &symbols).is_err());
+        assert!(evaluate("1 +", &variables, &registers, &symbols).is_err());
+    }
+
+    #[test]
+    fn test_hexdump() {
+        let lines = hexdump(b"hi\0", 16, NumberFormat::Hex);
+        assert_eq!(lines, vec![format!("00000010  {:<47}  |hi.|", "68 69 00")]);
+        assert_eq!(hexdump(&[0; 20], 0, NumberFormat::Hex).len(), 2);
+
+        let lines = hexdump(b"hi", 0, NumberFormat::Decimal);
+        assert_eq!(lines, vec![format!("00000000  {:<63}  |hi|", "104 105")]);
+    }
+}
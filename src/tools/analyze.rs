@@ -0,0 +1,204 @@
+use crate::assembler::{code_start, is_wide_encoding, WIDE_IMMEDIATE_LENGTH};
+use crate::disassembler::disassemble_instruction;
+use crate::formatting::NumberFormat;
+use crate::instruction::Opcode;
+
+use std::collections::HashMap;
+
+/// How many of the most common opcode pairs and superinstruction candidates are reported
+pub const TOP_PAIRS: usize = 10;
+
+/// Statistics about the bytecode of an assembled program, for seeing why it is the size it is and which
+/// instruction sequences are worth fusing
+#[derive(Debug, PartialEq)]
+pub struct Analysis {
+    pub code_bytes: usize,
+    pub instructions: usize,
+    /// Shannon entropy of the whole program's bytes, in bits per byte. Close to 8 means there's little left to
+    /// compress.
+    pub byte_entropy: f64,
+    /// Shannon entropy of the opcodes, in bits per instruction
+    pub opcode_entropy: f64,
+    /// Opcodes by how often they appear, most common first
+    pub opcodes: Vec<(String, usize)>,
+    /// Adjacent pairs of opcodes by how often they appear, most common first
+    pub pairs: Vec<((String, String), usize)>,
+    /// Pairs that could become a single superinstruction, with the bytes that would save, most first. A pair only
+    /// counts where control can't leave between its two instructions.
+    pub candidates: Vec<((String, String), usize)>,
+}
+
+/// Analyzes the code of an assembled program, or the whole of raw bytecode without a header
+pub fn analyze(program: &[u8]) -> Analysis {
+    let start = code_start(program).unwrap_or(0);
+    let prefix = if is_wide_encoding(program) { WIDE_IMMEDIATE_LENGTH } else { 0 };
+
+    let mut opcodes = vec![];
+    let mut offset = start;
+    while offset + prefix < program.len() {
+        let bytes = &program[offset + prefix..];
+        let length = match disassemble_instruction(bytes, NumberFormat::Decimal) {
+            Some((_, length)) => length,
+            None => break,
+        };
+        opcodes.push(Opcode::from(bytes[0]));
+        offset += prefix + length;
+    }
+
+    let mut opcode_counts = HashMap::new();
+    for opcode in &opcodes {
+        *opcode_counts.entry(name(*opcode)).or_insert(0) += 1;
+    }
+
+    let mut pair_counts = HashMap::new();
+    let mut candidate_counts = HashMap::new();
+    for pair in opcodes.windows(2) {
+        let names = (name(pair[0]), name(pair[1]));
+        *pair_counts.entry(names.clone()).or_insert(0) += 1;
+        if !transfers_control(pair[0]) {
+            *candidate_counts.entry(names).or_insert(0) += 1;
+        }
+    }
+
+    // Fusing a pair saves an opcode and its operand bytes each time it appears, which is one instruction slot
+    let slot = 4 + prefix;
+    let candidates = candidate_counts
+        .into_iter()
+        .filter(|(_, count)| *count > 1)
+        .map(|(pair, count)| (pair, count * slot))
+        .collect();
+
+    Analysis {
+        code_bytes: program.len() - start.min(program.len()),
+        instructions: opcodes.len(),
+        byte_entropy: entropy(&byte_counts(program)),
+        opcode_entropy: entropy(&opcode_counts.values().copied().collect::<Vec<_>>()),
+        opcodes: most_common(opcode_counts.into_iter().collect(), usize::MAX),
+        pairs: most_common(pair_counts.into_iter().collect(), TOP_PAIRS),
+        candidates: most_common(candidates, TOP_PAIRS),
+    }
+}
+
+/// Formats an analysis as the lines `iridium analyze` prints
+pub fn format_analysis(analysis: &Analysis) -> Vec<String> {
+    let mut lines = vec![
+        format!("code: {} bytes, {} instructions", analysis.code_bytes, analysis.instructions),
+        format!("byte entropy: {:.2} bits per byte", analysis.byte_entropy),
+        format!("opcode entropy: {:.2} bits per instruction", analysis.opcode_entropy),
+        "opcodes:".to_string(),
+    ];
+    for (opcode, count) in &analysis.opcodes {
+        lines.push(format!("{:>8}  {}", count, opcode));
+    }
+
+    lines.push("most common pairs:".to_string());
+    for ((first, second), count) in &analysis.pairs {
+        lines.push(format!("{:>8}  {} {}", count, first, second));
+    }
+
+    lines.push("superinstruction candidates, by bytes saved:".to_string());
+    if analysis.candidates.is_empty() {
+        lines.push("    none".to_string());
+    }
+    for ((first, second), saved) in &analysis.candidates {
+        lines.push(format!("{:>8}  {} {}", saved, first, second));
+    }
+
+    lines
+}
+
+fn name(opcode: Opcode) -> String {
+    format!("{:?}", opcode).to_lowercase()
+}
+
+/// True for opcodes after which the next instruction might not run, so they can't start a fused pair
+fn transfers_control(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::JMP
+            | Opcode::JMPF
+            | Opcode::JMPB
+            | Opcode::JMPE
+            | Opcode::DJMPE
+            | Opcode::JZ
+            | Opcode::JNZ
+            | Opcode::JC
+            | Opcode::JO
+            | Opcode::JNO
+            | Opcode::JMPR
+            | Opcode::BR
+            | Opcode::LOOP
+            | Opcode::CALL
+            | Opcode::RET
+            | Opcode::IRET
+            | Opcode::HLT
+            | Opcode::SPAWN
+            | Opcode::YIELD
+            | Opcode::IGL
+    )
+}
+
+fn byte_counts(bytes: &[u8]) -> Vec<usize> {
+    let mut counts = vec![0; 256];
+    for byte in bytes {
+        counts[*byte as usize] += 1;
+    }
+    counts
+}
+
+/// Shannon entropy, in bits, of a distribution given as counts
+fn entropy(counts: &[usize]) -> f64 {
+    let total: usize = counts.iter().sum();
+    let entropy: f64 = counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / total as f64;
+            -p * p.log2()
+        })
+        .sum();
+    // A distribution with one outcome sums to -0.0, which would print as "-0.00". Adding 0.0 makes it 0.0.
+    entropy + 0.0
+}
+
+/// Sorts by count, most first and then by name so the order is stable, keeping at most `limit`
+fn most_common<T: Ord>(mut entries: Vec<(T, usize)>, limit: usize) -> Vec<(T, usize)> {
+    entries.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    entries.truncate(limit);
+    entries
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn pair(first: &str, second: &str) -> (String, String) {
+        (first.to_string(), second.to_string())
+    }
+
+    #[test]
+    fn test_analyze() {
+        let source = ".data\n.code\nload $0 #1\nadd $0 $0 $1\nload $0 #2\nadd $0 $0 $1\nload $0 #3\njmp $0\nadd $0 $0 $1\njmp $0\n\
+                      add $0 $0 $1\nhlt";
+        let analysis = analyze(&Assembler::new().assemble(source).unwrap());
+
+        assert_eq!(analysis.code_bytes, 40);
+        assert_eq!(analysis.instructions, 10);
+        assert_eq!(analysis.opcodes[0], ("add".to_string(), 4));
+        assert_eq!(&analysis.pairs[..3], &[(pair("add", "load"), 2), (pair("jmp", "add"), 2), (pair("load", "add"), 2)]);
+        // The jmp could go anywhere, so the add after it can't be fused with it
+        assert_eq!(analysis.candidates, vec![(pair("add", "load"), 8), (pair("load", "add"), 8)]);
+        assert!(analysis.byte_entropy > 0.0 && analysis.byte_entropy < 8.0);
+        assert!((analysis.opcode_entropy - 1.846).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_entropy() {
+        assert_eq!(entropy(&[4]), 0.0);
+        assert!(entropy(&[4]).is_sign_positive());
+        assert_eq!(format!("{:.2}", entropy(&[4])), "0.00");
+        assert_eq!(entropy(&[1, 1]), 1.0);
+        assert_eq!(entropy(&[1, 1, 1, 1, 0]), 2.0);
+    }
+}
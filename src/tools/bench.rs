@@ -0,0 +1,181 @@
+use crate::encoding::{push_code_f64, push_code_u16};
+use crate::instruction::{decode_opcode, Opcode, RAND_UNBOUNDED, SHIFT_IMMEDIATE};
+use crate::vm::errors::VMError;
+use crate::vm::VM;
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+/// Decodes each run through every possible opcode byte this many times
+pub const DEFAULT_DECODE_ROUNDS: u32 = 100_000;
+
+/// Times each opcode benchmark loops over its copies of the instruction, unless told otherwise
+pub const DEFAULT_OPCODE_ROUNDS: u16 = 10_000;
+
+/// Copies of the instruction in the body of each opcode benchmark's loop, so the LOOP closing it adds little
+pub const OPCODE_BENCH_LENGTH: usize = 64;
+
+/// How long decoding the same bytes took with the VM's table and with the match it replaced
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodeTimings {
+    /// Bytes each way decoded
+    pub decodes: u64,
+    pub table: Duration,
+    pub matched: Duration,
+}
+
+impl DecodeTimings {
+    pub fn to_lines(&self) -> Vec<String> {
+        let per_decode = |time: Duration| time.as_nanos() as f64 / self.decodes.max(1) as f64;
+        vec![
+            format!("{} decodes each", self.decodes),
+            format!("table: {:>8.3} ns/decode", per_decode(self.table)),
+            format!("match: {:>8.3} ns/decode", per_decode(self.matched)),
+        ]
+    }
+}
+
+/// Times decoding every byte from 0 to 255, `rounds` times over, first with `Opcode::from_byte` and then with
+/// `decode_opcode`
+pub fn bench_decode(rounds: u32) -> DecodeTimings {
+    let bytes: Vec<u8> = (0..=255).collect();
+    let time = |decode: fn(u8) -> Opcode| {
+        let started = Instant::now();
+        for _ in 0..rounds {
+            for byte in &bytes {
+                black_box(decode(black_box(*byte)));
+            }
+        }
+        started.elapsed()
+    };
+
+    DecodeTimings {
+        decodes: u64::from(rounds) * bytes.len() as u64,
+        table: time(Opcode::from_byte),
+        matched: time(decode_opcode),
+    }
+}
+
+/// How long one opcode's handler took to run, over many copies of the same instruction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeTiming {
+    pub opcode: Opcode,
+    /// Copies of the instruction run
+    pub executed: u64,
+    pub time: Duration,
+}
+
+impl OpcodeTiming {
+    pub fn nanoseconds_per_op(&self) -> f64 {
+        self.time.as_nanos() as f64 / self.executed.max(1) as f64
+    }
+}
+
+/// Formats the timings as a table, one opcode per line. NOP comes first, as what every other opcode costs at least.
+pub fn opcode_timing_lines(timings: &[OpcodeTiming]) -> Vec<String> {
+    let mut lines = vec![format!("{} runs of each opcode", timings.first().map_or(0, |timing| timing.executed))];
+    for timing in timings {
+        lines.push(format!("{:<8} {:>8.3} ns/op", format!("{:?}", timing.opcode), timing.nanoseconds_per_op()));
+    }
+    lines
+}
+
+/// The instructions timed by `bench_opcodes`, each with operands that leave it safe to run over and over. Results go
+/// to $2 and sources are $0 = 6 and $1 = 3, or $f0 = 1.5 and $f1 = 2.5. $3 = 2 makes JMPF, which jumps from its
+/// second byte, land on the next instruction, $4 is the address of a heap word and $5 = 4 is a size to allocate.
+/// Conditional jumps are timed not taken. Opcodes that halt, jump away, do I/O or use the loop counter aren't timed.
+fn opcode_benches() -> Vec<(Opcode, Vec<u8>)> {
+    let instruction = |opcode: Opcode, [first, second, third]: [u8; 3]| {
+        (opcode, vec![opcode.into(), first, second, third])
+    };
+    let mut load_float = instruction(Opcode::LOADF64, [2, 0, 0]);
+    push_code_f64(&mut load_float.1, 0.5);
+
+    vec![
+        instruction(Opcode::NOP, [0, 0, 0]),
+        instruction(Opcode::LOAD, [2, 1, 244]),
+        instruction(Opcode::LUI, [2, 0, 1]),
+        instruction(Opcode::ORI, [2, 0, 1]),
+        instruction(Opcode::ADD, [0, 1, 2]),
+        instruction(Opcode::SUB, [0, 1, 2]),
+        instruction(Opcode::MUL, [0, 1, 2]),
+        instruction(Opcode::DIV, [0, 1, 2]),
+        instruction(Opcode::SHL, [2, 1, SHIFT_IMMEDIATE]),
+        instruction(Opcode::SHR, [2, 1, SHIFT_IMMEDIATE]),
+        instruction(Opcode::EQ, [0, 1, 0]),
+        instruction(Opcode::JZ, [3, 0, 0]),
+        instruction(Opcode::JMPF, [3, 0, 0]),
+        instruction(Opcode::BR, [0, 4, 0]),
+        instruction(Opcode::LW, [2, 4, 0]),
+        instruction(Opcode::SW, [0, 4, 0]),
+        instruction(Opcode::CAS, [4, 2, 0]),
+        instruction(Opcode::XADD, [2, 4, 1]),
+        instruction(Opcode::ALOC, [5, 2, 0]),
+        instruction(Opcode::RAND, [2, 0, RAND_UNBOUNDED]),
+        instruction(Opcode::VADD, [8, 12, 4]),
+        instruction(Opcode::VMUL, [8, 12, 4]),
+        instruction(Opcode::RDCYCLE, [2, 0, 0]),
+        load_float,
+        instruction(Opcode::ADDF64, [0, 1, 2]),
+        instruction(Opcode::SUBF64, [0, 1, 2]),
+        instruction(Opcode::MULF64, [0, 1, 2]),
+        instruction(Opcode::DIVF64, [0, 1, 2]),
+        instruction(Opcode::EQF64, [0, 1, 0]),
+        instruction(Opcode::LTF64, [0, 1, 0]),
+    ]
+}
+
+/// Times the handler of each opcode on its own, so a change that slows one down shows up against that opcode. Each is
+/// run by a program that loops `rounds` times over `OPCODE_BENCH_LENGTH` copies of the instruction, so the time per
+/// op includes dispatching the instruction and a small share of the LOOP. Fails if one of the programs does.
+pub fn bench_opcodes(rounds: u16) -> Result<Vec<OpcodeTiming>, VMError> {
+    opcode_benches().into_iter().map(|(opcode, instruction)| bench_opcode(opcode, &instruction, rounds)).collect()
+}
+
+fn bench_opcode(opcode: Opcode, instruction: &[u8], rounds: u16) -> Result<OpcodeTiming, VMError> {
+    let mut program = vec![Opcode::CLOOP.into()];
+    push_code_u16(&mut program, rounds);
+    program.push(0);
+    for _ in 0..OPCODE_BENCH_LENGTH {
+        program.extend_from_slice(instruction);
+    }
+    // Loops back to the first copy, just after the CLOOP
+    program.push(Opcode::LOOP.into());
+    push_code_u16(&mut program, 4);
+    program.push(0);
+
+    let mut vm = VM::new();
+    vm.program = program;
+    let word = vm.allocate(4).expect("the heap has room for a word");
+    vm.registers[..6].copy_from_slice(&[6, 3, 0, 2, word as i32, 4]);
+    vm.float_registers[..2].copy_from_slice(&[1.5, 2.5]);
+
+    let started = Instant::now();
+    vm.run()?;
+    let time = started.elapsed();
+    Ok(OpcodeTiming { opcode, executed: u64::from(rounds) * OPCODE_BENCH_LENGTH as u64, time })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bench_decode() {
+        let timings = bench_decode(2);
+        assert_eq!(timings.decodes, 512);
+        assert_eq!(timings.to_lines().len(), 3);
+    }
+
+    #[test]
+    fn test_bench_opcodes() {
+        let timings = bench_opcodes(2).unwrap();
+        assert_eq!(timings.len(), opcode_benches().len());
+        assert_eq!(timings[0].opcode, Opcode::NOP);
+        assert!(timings.iter().all(|timing| timing.executed == 2 * OPCODE_BENCH_LENGTH as u64));
+
+        let lines = opcode_timing_lines(&timings);
+        assert_eq!(lines[0], "128 runs of each opcode");
+        assert!(lines[1].starts_with("NOP "));
+    }
+}
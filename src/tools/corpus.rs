@@ -0,0 +1,105 @@
+use crate::vm::random::Xorshift;
+
+use std::fmt::Write;
+
+/// Source for a tight loop of arithmetic, run `iterations` times: ADD, SUB, MUL, SHR and EQ on $0-$3, closed by LOOP.
+/// It finishes by running off its end.
+pub fn arithmetic_loop(iterations: u16) -> String {
+    let mut source = String::from(".data\n.code\nload $1 #3\n");
+    writeln!(source, "cloop #{}", iterations).unwrap();
+    source.push_str("top: add $0 $1 $2\nsub $2 $0 $3\nmul $3 $1 $0\nshr $0 #3\neq $0 $1\nloop @top\n");
+    source
+}
+
+/// Source for a program that spends most of its time jumping. It runs through a chain of `blocks` small blocks,
+/// `iterations` times. The blocks are laid out in reverse, so the jumps between them go backwards. They take turns
+/// ending in BR, JMP through a register and a taken JZ.
+pub fn jump_heavy(blocks: usize, iterations: u16) -> String {
+    let mut source = String::from(".data\n.code\nload $1 #1\n");
+    writeln!(source, "cloop #{}", iterations).unwrap();
+    source.push_str("top: load $4 @block0\njmp $4\n");
+
+    for block in (0..blocks).rev() {
+        let next = if block + 1 == blocks { "next".to_string() } else { format!("block{}", block + 1) };
+        writeln!(source, "block{}: add $0 $1 $0", block).unwrap();
+        match block % 3 {
+            0 => writeln!(source, "br @{}", next).unwrap(),
+            1 => writeln!(source, "load $4 @{}\njmp $4", next).unwrap(),
+            _ => writeln!(source, "eq $0 $0\nload $4 @{}\njz $4", next).unwrap(),
+        }
+    }
+    source.push_str("next: loop @top\n");
+    source
+}
+
+/// Source of `lines` lines of arithmetic with no labels, so assembling it does the same work for every line. How
+/// long it takes to assemble should grow in step with `lines`.
+pub fn long_source(lines: usize) -> String {
+    let mut source = String::from(".data\n.code\n");
+    for line in 0..lines {
+        writeln!(source, "add ${} $1 $2", line % 32).unwrap();
+    }
+    source
+}
+
+/// A large source file of `functions` functions, each with a string in the read-only data, called in turn from the
+/// entry point. The functions mix loads, arithmetic, a counted loop, comparisons, comments and a print, with operands
+/// picked by a generator seeded with `seed`, so the same arguments always give the same source.
+pub fn large_source(functions: usize, seed: u64) -> String {
+    let mut random = Xorshift::new(seed);
+    let mut source = String::from("; Generated by tools::corpus::large_source\n.data\n");
+    for function in 0..functions {
+        writeln!(source, "message{}: .asciiz 'function {} done'", function, function).unwrap();
+    }
+
+    source.push_str(".code\n");
+    for function in 0..functions {
+        writeln!(source, "call @function{}", function).unwrap();
+    }
+    source.push_str("hlt\n");
+
+    for function in 0..functions {
+        writeln!(source, "\n; Function {}", function).unwrap();
+        writeln!(source, "function{}: load $1 #{}", function, random.below(1000)).unwrap();
+        writeln!(source, "load $2 #{}", random.below(1000)).unwrap();
+        writeln!(source, "cloop #{}", 1 + random.below(8)).unwrap();
+        writeln!(source, "loop{}: add $1 $2 $3", function).unwrap();
+        writeln!(source, "mul $3 $2 $1 ; keep $1 growing").unwrap();
+        writeln!(source, "shl $2 #{}", random.below(4)).unwrap();
+        writeln!(source, "loop @loop{}", function).unwrap();
+        source.push_str("eq $1 $2\n");
+        writeln!(source, "prts @message{}", function).unwrap();
+        source.push_str("ret\n");
+    }
+    source
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+
+    /// Runs the program assembled from `source`, returning how many instructions it executed
+    fn run(source: &str) -> u64 {
+        let mut vm = VM::new();
+        vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        vm.run().unwrap().instructions_executed
+    }
+
+    #[test]
+    fn test_corpus_programs_run() {
+        assert_eq!(run(&arithmetic_loop(10)), 2 + 6 * 10);
+        // Each pass runs 3 instructions around the blocks, which take 2, 3 and 4 instructions in turn
+        assert_eq!(run(&jump_heavy(6, 5)), 2 + 5 * (3 + 18));
+    }
+
+    #[test]
+    fn test_large_source() {
+        let source = large_source(20, 3);
+        assert_eq!(source, large_source(20, 3));
+        assert!(source.lines().count() > 20 * 10);
+        run(&source);
+        assert_eq!(run(&long_source(100)), 100);
+    }
+}
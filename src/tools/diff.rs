@@ -0,0 +1,143 @@
+use crate::assembler::code_start;
+use crate::disassembler::instructions;
+use crate::formatting::NumberFormat;
+
+/// One line of the difference between two programs. Instructions are shown with their offset in their program.
+#[derive(Debug, PartialEq)]
+pub enum DiffLine {
+    Same { offset: usize, text: String },
+    Removed { offset: usize, text: String },
+    Added { offset: usize, text: String },
+    Changed { old_offset: usize, old_text: String, new_offset: usize, new_text: String },
+}
+
+impl DiffLine {
+    pub fn is_same(&self) -> bool {
+        matches!(self, DiffLine::Same { .. })
+    }
+}
+
+impl std::fmt::Display for DiffLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DiffLine::Same { offset, text } => write!(f, "  {:04}: {}", offset, text),
+            DiffLine::Removed { offset, text } => write!(f, "- {:04}: {}", offset, text),
+            DiffLine::Added { offset, text } => write!(f, "+ {:04}: {}", offset, text),
+            DiffLine::Changed { old_offset, old_text, new_offset, new_text } => {
+                write!(f, "~ {:04}: {} => {:04}: {}", old_offset, old_text, new_offset, new_text)
+            }
+        }
+    }
+}
+
+/// Disassembles the code of a program, skipping the header and read-only data if it has them
+fn program_instructions(program: &[u8]) -> Vec<(usize, String)> {
+    let start = code_start(program).unwrap_or(0);
+    instructions(&program[start..], start, NumberFormat::Decimal)
+}
+
+/// Compares two programs instruction by instruction. Offsets are ignored when matching instructions up, so inserting
+/// an instruction shows up as one addition rather than every following instruction changing.
+pub fn diff_programs(old: &[u8], new: &[u8]) -> Vec<DiffLine> {
+    let old = program_instructions(old);
+    let new = program_instructions(new);
+
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = if old[i].1 == new[j].1 {
+                lengths[i + 1][j + 1] + 1
+            } else {
+                lengths[i + 1][j].max(lengths[i][j + 1])
+            };
+        }
+    }
+
+    let mut lines = vec![];
+    let mut removed = vec![];
+    let mut added = vec![];
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i].1 == new[j].1 {
+            flush_changes(&mut lines, &mut removed, &mut added);
+            lines.push(DiffLine::Same { offset: new[j].0, text: new[j].1.clone() });
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            added.push(new[j].clone());
+            j += 1;
+        } else {
+            removed.push(old[i].clone());
+            i += 1;
+        }
+    }
+
+    flush_changes(&mut lines, &mut removed, &mut added);
+    lines
+}
+
+/// Turns a run of removed and added instructions into diff lines, pairing them up as changes where possible
+fn flush_changes(lines: &mut Vec<DiffLine>, removed: &mut Vec<(usize, String)>, added: &mut Vec<(usize, String)>) {
+    let mut removed_instructions = removed.drain(..);
+    let mut added_instructions = added.drain(..);
+
+    loop {
+        match (removed_instructions.next(), added_instructions.next()) {
+            (Some((old_offset, old_text)), Some((new_offset, new_text))) => {
+                lines.push(DiffLine::Changed { old_offset, old_text, new_offset, new_text });
+            }
+            (Some((offset, text)), None) => lines.push(DiffLine::Removed { offset, text }),
+            (None, Some((offset, text))) => lines.push(DiffLine::Added { offset, text }),
+            (None, None) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        Assembler::new().assemble(source).unwrap()
+    }
+
+    #[test]
+    fn test_identical_programs() {
+        let program = assemble(".data\n.code\nload $0 #1\nhlt");
+        let lines = diff_programs(&program, &program);
+        assert_eq!(lines.len(), 2);
+        assert!(lines.iter().all(|line| line.is_same()));
+    }
+
+    #[test]
+    fn test_added_and_changed_instructions() {
+        let old = assemble(".data\n.code\nload $0 #1\nhlt");
+        let new = assemble(".data\nhi: .asciiz 'hi'\n.code\nload $1 #5\nload $0 #2\nhlt");
+        let lines = diff_programs(&old, &new);
+        assert_eq!(
+            lines,
+            vec![
+                DiffLine::Changed {
+                    old_offset: 64,
+                    old_text: "load $0 #1".to_string(),
+                    new_offset: 67,
+                    new_text: "load $1 #5".to_string()
+                },
+                DiffLine::Added { offset: 71, text: "load $0 #2".to_string() },
+                DiffLine::Same { offset: 75, text: "hlt".to_string() },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_removed_instruction() {
+        let old = assemble(".data\n.code\nload $0 #1\nload $1 #1\nhlt");
+        let new = assemble(".data\n.code\nload $0 #1\nhlt");
+        let lines = diff_programs(&old, &new);
+        assert_eq!(lines[1], DiffLine::Removed { offset: 68, text: "load $1 #1".to_string() });
+        assert_eq!(lines[1].to_string(), "- 0068: load $1 #1");
+    }
+}
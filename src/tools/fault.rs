@@ -0,0 +1,122 @@
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::WIDE_IMMEDIATE_LENGTH;
+use crate::disassembler::disassemble_instruction;
+use crate::formatting::NumberFormat;
+use crate::vm::errors::VMError;
+use crate::vm::VM;
+
+use std::fmt;
+
+/// Everything known about where a program failed: the error, the instruction at its PC, the registers that
+/// instruction uses and the label it comes after
+#[derive(Debug, Clone, PartialEq)]
+pub struct FaultReport {
+    pub error: VMError,
+    /// The failing instruction disassembled, if there is a whole one at the PC
+    pub instruction: Option<String>,
+    /// Number and value of each integer register the instruction names
+    pub registers: Vec<(usize, i32)>,
+    /// The closest label at or before the PC, with how far past it the PC is
+    pub nearest_label: Option<(String, usize)>,
+}
+
+impl FaultReport {
+    /// Gathers the context of an error from the VM it happened in. `symbols` are the ones the program was assembled
+    /// with, if they are at hand.
+    pub fn new(vm: &VM, error: VMError, symbols: Option<&SymbolTable>) -> FaultReport {
+        // The program never started, so there's no instruction to point at
+        if let VMError::IncompatibleTarget { .. } = error {
+            return FaultReport { error, instruction: None, registers: vec![], nearest_label: None };
+        }
+
+        let pc = error.pc();
+        let start = if vm.is_wide() { pc + WIDE_IMMEDIATE_LENGTH } else { pc };
+        let instruction = vm
+            .program
+            .get(start..)
+            .and_then(|bytes| disassemble_instruction(bytes, NumberFormat::Decimal))
+            .map(|(text, _)| text);
+
+        let registers = instruction.as_ref().map(|text| register_operands(text)).unwrap_or_default();
+        let registers = registers.into_iter().map(|number| (number, vm.registers[number])).collect();
+        let nearest_label = symbols.and_then(|symbols| nearest_label(symbols, pc));
+
+        FaultReport {
+            error,
+            instruction,
+            registers,
+            nearest_label,
+        }
+    }
+}
+
+impl fmt::Display for FaultReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.error)?;
+        if let Some(instruction) = &self.instruction {
+            write!(f, "\n  instruction: {}", instruction)?;
+        }
+        if !self.registers.is_empty() {
+            let values: Vec<String> = self.registers.iter().map(|(number, value)| format!("${} = {}", number, value)).collect();
+            write!(f, "\n  registers: {}", values.join(", "))?;
+        }
+        match &self.nearest_label {
+            Some((name, 0)) => write!(f, "\n  at: {}", name)?,
+            Some((name, distance)) => write!(f, "\n  at: {} + {}", name, distance)?,
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+/// Register numbers named in disassembled text, in order and without repeats
+fn register_operands(text: &str) -> Vec<usize> {
+    let mut registers = vec![];
+    for operand in text.split_whitespace().filter_map(|word| word.strip_prefix('$')) {
+        if let Ok(number) = operand.parse::<usize>() {
+            if number < 32 && !registers.contains(&number) {
+                registers.push(number);
+            }
+        }
+    }
+    registers
+}
+
+/// The label with the highest offset at or before `pc`, and how far past it `pc` is
+pub(crate) fn nearest_label(symbols: &SymbolTable, pc: usize) -> Option<(String, usize)> {
+    symbols
+        .symbols
+        .iter()
+        .filter(|symbol| *symbol.symbol_type() == SymbolType::Label)
+        .filter_map(|symbol| symbol.offset().map(|offset| (symbol.name(), offset as usize)))
+        .filter(|(_, offset)| *offset <= pc)
+        .max_by_key(|(_, offset)| *offset)
+        .map(|(name, offset)| (name.to_string(), pc - offset))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_fault_report() {
+        let mut asm = Assembler::new();
+        let source = ".data\n.code\nload $1 #0\ndivide: load $0 #7\ndiv $0 $1 $2\nhlt";
+        let mut vm = VM::new();
+        vm.add_bytes(asm.assemble(source).unwrap());
+        let error = vm.run().unwrap_err();
+
+        let report = FaultReport::new(&vm, error, Some(&asm.symbols));
+        assert_eq!(report.instruction, Some("div $0 $1 $2".to_string()));
+        assert_eq!(report.registers, vec![(0, 7), (1, 0), (2, 0)]);
+        assert_eq!(report.nearest_label, Some(("divide".to_string(), 4)));
+        assert_eq!(
+            report.to_string(),
+            "Division by zero at 72\n  instruction: div $0 $1 $2\n  registers: $0 = 7, $1 = 0, $2 = 0\n  at: divide + 4"
+        );
+
+        let report = FaultReport::new(&vm, VMError::DivisionByZero { pc: 500 }, None);
+        assert_eq!(report.to_string(), "Division by zero at 500");
+    }
+}
@@ -0,0 +1,46 @@
+use crate::assembler::{code_start, is_wide_encoding, program_target, read_metadata, writable_region};
+
+/// Describes an assembled program: its metadata, followed by how it is encoded
+pub fn describe(program: &[u8]) -> Vec<String> {
+    if code_start(program).is_none() {
+        return vec!["No header found, so this isn't an assembled program or it predates headers".to_string()];
+    }
+
+    let mut lines: Vec<String> = read_metadata(program)
+        .into_iter()
+        .map(|(name, value)| format!("{}: {}", name, value))
+        .collect();
+    if lines.is_empty() {
+        lines.push("No metadata".to_string());
+    }
+
+    lines.push(format!("encoding: {}", if is_wide_encoding(program) { "wide" } else { "narrow" }));
+    if let Some(target) = program_target(program) {
+        lines.push(format!("target: {}", target));
+    }
+    if let Some(region) = writable_region(program) {
+        lines.push(format!("writable: {} bytes at {}", region.len(), region.start));
+    }
+
+    lines
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_describe() {
+        let source = ".data\n.author 'Ada Lovelace'\n.version '1.2.0'\n.code\nhlt";
+        let program = Assembler::new().assemble(source).unwrap();
+        assert_eq!(
+            describe(&program),
+            vec!["author: Ada Lovelace", "version: 1.2.0", "encoding: narrow", "target: iridium32"]
+        );
+
+        let program = Assembler::new().assemble(".data\n.code\nhlt").unwrap();
+        assert_eq!(describe(&program), vec!["No metadata", "encoding: narrow", "target: iridium32"]);
+        assert_eq!(describe(&[5, 0, 0, 0]).len(), 1);
+    }
+}
@@ -0,0 +1,34 @@
+use crate::assembler::{code_start, metadata_range, PIE_HEADER_LENGTH};
+
+use std::ops::Range;
+
+pub mod analyze;
+pub mod bench;
+pub mod corpus;
+pub mod diff;
+pub mod fault;
+pub mod info;
+pub mod objcopy;
+pub mod size;
+pub mod snapdiff;
+pub mod verify;
+
+/// Byte ranges of the sections of an assembled program, by name. Programs without a header are all code, and
+/// `.metadata` is only listed if the program has some.
+pub fn section_ranges(program: &[u8]) -> Vec<(&'static str, Range<usize>)> {
+    let start = match code_start(program) {
+        Some(start) => start,
+        None => return vec![(".code", 0..program.len())],
+    };
+
+    let mut ranges = vec![("header", 0..PIE_HEADER_LENGTH)];
+    match metadata_range(program) {
+        Some(metadata) => {
+            ranges.push((".data", PIE_HEADER_LENGTH..metadata.start));
+            ranges.push((".metadata", metadata));
+        }
+        None => ranges.push((".data", PIE_HEADER_LENGTH..start)),
+    }
+    ranges.push((".code", start..program.len()));
+    ranges
+}
@@ -0,0 +1,30 @@
+use crate::tools::section_ranges;
+
+/// Returns the bytes of the named section of a program, or None if it has no such section
+pub fn extract_section<'a>(program: &'a [u8], name: &str) -> Option<&'a [u8]> {
+    section_ranges(program)
+        .into_iter()
+        .find(|(section, _)| *section == name)
+        .map(|(_, range)| &program[range])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_extract_section() {
+        let program = Assembler::new().assemble(".data\nhi: .asciiz 'hi'\n.code\nhlt").unwrap();
+        assert_eq!(extract_section(&program, ".data"), Some(&b"hi\0"[..]));
+        assert_eq!(extract_section(&program, ".code"), Some(&[5, 0, 0, 0][..]));
+        assert_eq!(extract_section(&program, "header").map(|header| header.len()), Some(64));
+        assert!(extract_section(&program, ".bss").is_none());
+    }
+
+    #[test]
+    fn test_extract_section_without_header() {
+        assert_eq!(extract_section(&[5, 0, 0, 0], ".code"), Some(&[5, 0, 0, 0][..]));
+        assert!(extract_section(&[5, 0, 0, 0], ".data").is_none());
+    }
+}
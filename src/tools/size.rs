@@ -0,0 +1,120 @@
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::{code_start, metadata_range, PIE_HEADER_LENGTH};
+use crate::tools::section_ranges;
+
+/// Number of bytes a section or symbol takes up in an assembled program
+#[derive(Debug, PartialEq)]
+pub struct SizeEntry {
+    pub name: String,
+    pub bytes: usize,
+}
+
+impl SizeEntry {
+    fn new(name: &str, bytes: usize) -> SizeEntry {
+        SizeEntry { name: name.to_string(), bytes }
+    }
+}
+
+/// Splits a program into its header, read-only data and code
+pub fn section_sizes(program: &[u8]) -> Vec<SizeEntry> {
+    section_ranges(program)
+        .into_iter()
+        .map(|(name, range)| SizeEntry::new(name, range.len()))
+        .collect()
+}
+
+/// Attributes the bytes of a program to the symbols that label them, largest first. A symbol covers everything up
+/// to the next symbol in the same section, and bytes before the first symbol of a section are reported as
+/// `<unlabelled .data>` or `<unlabelled .code>`.
+pub fn symbol_sizes(program: &[u8], symbols: &SymbolTable) -> Vec<SizeEntry> {
+    let start = match code_start(program) {
+        Some(start) => start,
+        None => return vec![],
+    };
+
+    // Strings are stored with offsets into the read-only data, labels with offsets into the whole program
+    let data_end = metadata_range(program).map_or(start, |metadata| metadata.start);
+    let mut sizes = symbols_in_section(symbols, SymbolType::IrString, PIE_HEADER_LENGTH, data_end, ".data");
+    sizes.append(&mut symbols_in_section(symbols, SymbolType::Label, start, program.len(), ".code"));
+    sizes.sort_by(|a, b| b.bytes.cmp(&a.bytes).then_with(|| a.name.cmp(&b.name)));
+    sizes
+}
+
+fn symbols_in_section(
+    symbols: &SymbolTable,
+    symbol_type: SymbolType,
+    section_start: usize,
+    section_end: usize,
+    section_name: &str,
+) -> Vec<SizeEntry> {
+    let relative = symbol_type == SymbolType::IrString;
+    let mut starts: Vec<(usize, &str)> = symbols
+        .symbols
+        .iter()
+        .filter(|symbol| *symbol.symbol_type() == symbol_type)
+        .filter_map(|symbol| {
+            let offset = symbol.offset()? as usize;
+            let offset = if relative { section_start + offset } else { offset };
+            Some((offset, symbol.name()))
+        })
+        .filter(|(offset, _)| *offset >= section_start && *offset <= section_end)
+        .collect();
+    starts.sort();
+
+    let mut sizes = vec![];
+    let first = starts.first().map_or(section_end, |(offset, _)| *offset);
+    if first > section_start {
+        sizes.push(SizeEntry::new(&format!("<unlabelled {}>", section_name), first - section_start));
+    }
+
+    for (index, (offset, name)) in starts.iter().enumerate() {
+        let end = starts.get(index + 1).map_or(section_end, |(next, _)| *next);
+        sizes.push(SizeEntry::new(name, end - offset));
+    }
+
+    sizes
+}
+
+/// Formats a size report as aligned lines of bytes and name
+pub fn format_sizes(entries: &[SizeEntry]) -> Vec<String> {
+    entries.iter().map(|entry| format!("{:>8}  {}", entry.bytes, entry.name)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+
+    #[test]
+    fn test_section_sizes() {
+        let program = Assembler::new().assemble(".data\nhi: .asciiz 'hi'\n.code\nload $0 #1\nhlt").unwrap();
+        assert_eq!(
+            section_sizes(&program),
+            vec![SizeEntry::new("header", 64), SizeEntry::new(".data", 3), SizeEntry::new(".code", 8)]
+        );
+        assert_eq!(section_sizes(&[5, 0, 0, 0]), vec![SizeEntry::new(".code", 4)]);
+    }
+
+    #[test]
+    fn test_symbol_sizes() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\nhi: .asciiz 'hi'\nbye: .asciiz 'goodbye'\n.code\nload $0 #1\nmain: load $1 #2\nload $2 #3\nend: hlt")
+            .unwrap();
+        assert_eq!(
+            symbol_sizes(&program, &asm.symbols),
+            vec![
+                SizeEntry::new("bye", 8),
+                SizeEntry::new("main", 8),
+                SizeEntry::new("<unlabelled .code>", 4),
+                SizeEntry::new("end", 4),
+                SizeEntry::new("hi", 3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_format_sizes() {
+        assert_eq!(format_sizes(&[SizeEntry::new(".code", 12)]), vec!["      12  .code".to_string()]);
+    }
+}
@@ -0,0 +1,180 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::tools::fault::nearest_label;
+use crate::vm::flags::Flags;
+use crate::vm::snapshot::VMState;
+
+use std::ops::Range;
+
+/// Most bytes of a changed heap range shown before the rest are left out
+const SHOWN_BYTES: usize = 16;
+
+/// Something that differs between two snapshots of a VM
+#[derive(Debug, PartialEq)]
+pub enum SnapshotChange {
+    Pc { old: usize, new: usize },
+    Register { number: usize, old: i32, new: i32 },
+    FloatRegister { number: usize, old: f64, new: f64 },
+    Flags { old: Flags, new: Flags },
+    Remainder { old: usize, new: usize },
+    LoopCounter { old: usize, new: usize },
+    CallStack { old: Vec<usize>, new: Vec<usize> },
+    HeapSize { old: usize, new: usize },
+    /// A run of heap bytes that all changed. Bytes past the end of the smaller heap count as zeros.
+    Heap { range: Range<usize>, old: Vec<u8>, new: Vec<u8> },
+    /// Instructions were added to the program, such as by typing them into the REPL
+    ProgramSize { old: usize, new: usize },
+    /// Part of the program both snapshots have was rewritten, such as by WCODE
+    Code { range: Range<usize> },
+}
+
+/// Lists what changed between two snapshots, in the order PC, registers, flags, counters, call stack and memory
+pub fn diff_snapshots(old: &VMState, new: &VMState) -> Vec<SnapshotChange> {
+    let mut changes = vec![];
+    if old.pc != new.pc {
+        changes.push(SnapshotChange::Pc { old: old.pc, new: new.pc });
+    }
+    for (number, (old, new)) in old.registers.iter().zip(new.registers.iter()).enumerate() {
+        if old != new {
+            changes.push(SnapshotChange::Register { number, old: *old, new: *new });
+        }
+    }
+    for (number, (old, new)) in old.float_registers.iter().zip(new.float_registers.iter()).enumerate() {
+        // Compared by bits, so a NaN that stayed the same isn't reported as changing
+        if old.to_bits() != new.to_bits() {
+            changes.push(SnapshotChange::FloatRegister { number, old: *old, new: *new });
+        }
+    }
+    if old.flags != new.flags {
+        changes.push(SnapshotChange::Flags { old: old.flags, new: new.flags });
+    }
+    if old.remainder != new.remainder {
+        changes.push(SnapshotChange::Remainder { old: old.remainder, new: new.remainder });
+    }
+    if old.loop_counter != new.loop_counter {
+        changes.push(SnapshotChange::LoopCounter { old: old.loop_counter, new: new.loop_counter });
+    }
+    if old.call_stack != new.call_stack {
+        changes.push(SnapshotChange::CallStack { old: old.call_stack.clone(), new: new.call_stack.clone() });
+    }
+
+    let (old_heap, new_heap) = (old.heap.as_slice(), new.heap.as_slice());
+    if old_heap.len() != new_heap.len() {
+        changes.push(SnapshotChange::HeapSize { old: old_heap.len(), new: new_heap.len() });
+    }
+    for range in changed_ranges(old_heap, new_heap) {
+        let old = range_bytes(old_heap, &range);
+        let new = range_bytes(new_heap, &range);
+        changes.push(SnapshotChange::Heap { range, old, new });
+    }
+    if old.program.len() != new.program.len() {
+        changes.push(SnapshotChange::ProgramSize { old: old.program.len(), new: new.program.len() });
+    }
+    let common = old.program.len().min(new.program.len());
+    for range in changed_ranges(&old.program[..common], &new.program[..common]) {
+        changes.push(SnapshotChange::Code { range });
+    }
+
+    changes
+}
+
+/// Formats changes one per line. With the symbols the program was assembled with, addresses in the code are shown
+/// relative to the label they come after.
+pub fn format_changes(changes: &[SnapshotChange], symbols: Option<&SymbolTable>) -> Vec<String> {
+    let at = |address: usize| match symbols.and_then(|symbols| nearest_label(symbols, address)) {
+        Some((name, 0)) => format!("{} ({})", address, name),
+        Some((name, distance)) => format!("{} ({} + {})", address, name, distance),
+        None => address.to_string(),
+    };
+
+    changes
+        .iter()
+        .map(|change| match change {
+            SnapshotChange::Pc { old, new } => format!("pc: {} => {}", at(*old), at(*new)),
+            SnapshotChange::Register { number, old, new } => format!("${}: {} => {}", number, old, new),
+            SnapshotChange::FloatRegister { number, old, new } => format!("$f{}: {:?} => {:?}", number, old, new),
+            SnapshotChange::Flags { old, new } => format!("flags: {} => {}", old, new),
+            SnapshotChange::Remainder { old, new } => format!("remainder: {} => {}", old, new),
+            SnapshotChange::LoopCounter { old, new } => format!("loop counter: {} => {}", old, new),
+            SnapshotChange::CallStack { old, new } => {
+                let list = |stack: &[usize]| stack.iter().map(|address| at(*address)).collect::<Vec<_>>().join(", ");
+                format!("call stack: [{}] => [{}]", list(old), list(new))
+            }
+            SnapshotChange::HeapSize { old, new } => format!("heap size: {} => {} bytes", old, new),
+            SnapshotChange::Heap { range, old, new } => {
+                format!("heap {}..{}: {} => {}", range.start, range.end, hex(old), hex(new))
+            }
+            SnapshotChange::ProgramSize { old, new } => format!("program size: {} => {} bytes", old, new),
+            SnapshotChange::Code { range } => format!("code {}..{} rewritten", at(range.start), range.end),
+        })
+        .collect()
+}
+
+/// The runs of offsets where two byte strings differ, treating bytes past the end of the shorter one as zeros
+fn changed_ranges(old: &[u8], new: &[u8]) -> Vec<Range<usize>> {
+    let byte = |bytes: &[u8], offset: usize| bytes.get(offset).copied().unwrap_or(0);
+
+    let mut ranges: Vec<Range<usize>> = vec![];
+    for offset in 0..old.len().max(new.len()) {
+        if byte(old, offset) == byte(new, offset) {
+            continue;
+        }
+        match ranges.last_mut() {
+            Some(range) if range.end == offset => range.end += 1,
+            _ => ranges.push(offset..offset + 1),
+        }
+    }
+    ranges
+}
+
+fn range_bytes(bytes: &[u8], range: &Range<usize>) -> Vec<u8> {
+    range.clone().map(|offset| bytes.get(offset).copied().unwrap_or(0)).collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let shown: Vec<String> = bytes.iter().take(SHOWN_BYTES).map(|byte| format!("{:02x}", byte)).collect();
+    if bytes.len() > SHOWN_BYTES {
+        format!("{} ... ({} bytes)", shown.join(" "), bytes.len())
+    } else {
+        shown.join(" ")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::VM;
+
+    #[test]
+    fn test_diff_snapshots() {
+        let mut asm = Assembler::new();
+        let source = ".data\n.code\nload $0 #4\naloc $0 $2\nload $1 #7\nstore: sw $1 $2 #0\nhlt";
+        let mut vm = VM::new();
+        vm.add_bytes(asm.assemble(source).unwrap());
+        vm.run_with_budget(2).unwrap_err();
+        let before = vm.snapshot();
+        vm.run().unwrap();
+        let after = vm.snapshot();
+
+        let changes = diff_snapshots(&before, &after);
+        assert_eq!(
+            changes,
+            vec![
+                SnapshotChange::Pc { old: 72, new: 84 },
+                SnapshotChange::Register { number: 1, old: 0, new: 7 },
+                SnapshotChange::Heap { range: 0..1, old: vec![0], new: vec![7] },
+            ]
+        );
+        assert_eq!(
+            format_changes(&changes, Some(&asm.symbols)),
+            vec!["pc: 72 => 84 (store + 8)", "$1: 0 => 7", "heap 0..1: 00 => 07"]
+        );
+        assert!(diff_snapshots(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn test_changed_ranges() {
+        assert_eq!(changed_ranges(&[1, 2, 3, 4], &[1, 9, 9, 4, 0, 5]), vec![1..3, 5..6]);
+        assert_eq!(hex(&[0; 20]), "00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 ... (20 bytes)");
+    }
+}
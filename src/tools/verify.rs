@@ -0,0 +1,126 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::repl::scripting::{evaluate, Variables};
+
+/// Comment prefix that marks a line as an assertion about the state the program finishes in
+pub const ASSERTION_PREFIX: &str = ";; assert";
+
+/// Comparisons an assertion can make, longest first so `<=` isn't read as `<`
+const COMPARISONS: [&str; 6] = ["==", "!=", "<=", ">=", "<", ">"];
+
+/// A `;; assert $0 == 120` annotation. Both sides are expressions as understood by the REPL's `let`, so they can use
+/// registers, label offsets and integers.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Assertion {
+    /// Line of the source the assertion is on, counting from 1
+    pub line: usize,
+    pub left: String,
+    pub comparison: String,
+    pub right: String,
+}
+
+impl Assertion {
+    /// Reads an assertion from a line, which can follow an instruction: `add $0 $1 $0 ;; assert $0 == 3`. Returns None
+    /// if the line has no assertion on it, or an error if the assertion is malformed.
+    pub fn parse(text: &str, line: usize) -> Option<Result<Assertion, String>> {
+        let start = text.find(ASSERTION_PREFIX)?;
+        let condition = text[start + ASSERTION_PREFIX.len()..].trim();
+
+        let comparison = COMPARISONS
+            .iter()
+            .filter_map(|comparison| condition.find(comparison).map(|index| (index, *comparison)))
+            .min_by_key(|(index, comparison)| (*index, std::cmp::Reverse(comparison.len())));
+
+        Some(match comparison {
+            Some((index, comparison)) => Ok(Assertion {
+                line,
+                left: condition[..index].trim().to_string(),
+                comparison: comparison.to_string(),
+                right: condition[index + comparison.len()..].trim().to_string(),
+            }),
+            None => Err(format!("line {}: expected a comparison such as == in: {}", line, condition)),
+        })
+    }
+
+    /// Evaluates the assertion against the registers a program finished with. Returns an explanation if it doesn't
+    /// hold.
+    pub fn check(&self, registers: &[i32; 32], symbols: &SymbolTable) -> Result<(), String> {
+        let variables = Variables::new();
+        let evaluate_side = |side: &str| evaluate(side, &variables, registers, symbols).map_err(|e| format!("{}: {}", self, e));
+        let left = evaluate_side(&self.left)?;
+        let right = evaluate_side(&self.right)?;
+
+        let holds = match self.comparison.as_str() {
+            "==" => left == right,
+            "!=" => left != right,
+            "<=" => left <= right,
+            ">=" => left >= right,
+            "<" => left < right,
+            _ => left > right,
+        };
+
+        if holds {
+            Ok(())
+        } else {
+            Err(format!("{}: {} is {}", self, self.left, left))
+        }
+    }
+}
+
+impl std::fmt::Display for Assertion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "line {}: assert {} {} {}", self.line, self.left, self.comparison, self.right)
+    }
+}
+
+/// Collects the assertions in a source file, along with errors for any that couldn't be read
+pub fn parse_assertions(source: &str) -> (Vec<Assertion>, Vec<String>) {
+    let mut assertions = vec![];
+    let mut errors = vec![];
+
+    for (index, line) in source.lines().enumerate() {
+        match Assertion::parse(line, index + 1) {
+            Some(Ok(assertion)) => assertions.push(assertion),
+            Some(Err(e)) => errors.push(e),
+            None => {}
+        }
+    }
+
+    (assertions, errors)
+}
+
+/// Checks each assertion, returning why the ones that failed did
+pub fn check_assertions(assertions: &[Assertion], registers: &[i32; 32], symbols: &SymbolTable) -> Vec<String> {
+    assertions
+        .iter()
+        .filter_map(|assertion| assertion.check(registers, symbols).err())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_assertion() {
+        let assertion = Assertion::parse("add $0 $1 $0 ;; assert $0 <= 120", 3).unwrap().unwrap();
+        assert_eq!(assertion.line, 3);
+        assert_eq!(assertion.left, "$0");
+        assert_eq!(assertion.comparison, "<=");
+        assert_eq!(assertion.right, "120");
+        assert!(Assertion::parse("; assert $0 == 1", 1).is_none());
+        assert!(Assertion::parse(";; assert $0", 1).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_check_assertions() {
+        let source = "load $0 #120\n;; assert $0 == 120\n;; assert $0 + 1 == $1\n;; assert $0 > 100";
+        let (assertions, errors) = parse_assertions(source);
+        assert_eq!(assertions.len(), 3);
+        assert!(errors.is_empty());
+
+        let mut registers = [0; 32];
+        registers[0] = 120;
+        let failures = check_assertions(&assertions, &registers, &SymbolTable::new());
+        assert_eq!(failures, vec!["line 3: assert $0 + 1 == $1: $0 + 1 is 121".to_string()]);
+    }
+}
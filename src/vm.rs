@@ -1,289 +0,0 @@
-use crate::instruction::Opcode;
-use crate::assembler::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
-
-pub struct VM {
-    /// Array that simulates having hardware registers
-    pub registers: [i32; 32],
-    /// Program counter that tracks which byte is being executed
-    pc: usize,
-    /// The bytecode of the program being run
-    pub program: Vec<u8>,
-    /// Contains the remainder of modulo division ops
-    remainder: usize,
-    /// Contains the result of the last comparison operation
-    equal_flag: bool,
-    /// Represents our heap memory
-    heap: Vec<u8>,
-    /// Contains the read-only section data
-    ro_data: Vec<u8>,
-}
-
-impl VM {
-    pub fn new() -> VM {
-        VM {
-            registers: [0; 32],
-            program: vec![],
-            pc: 0,
-            remainder: 0,
-            equal_flag: false,
-            heap: vec![],
-            ro_data: vec![],
-        }
-    }
-
-    pub fn get_test_vm() -> VM {
-        let mut test_vm = VM::new();
-        test_vm.registers[0] = 5;
-        test_vm.registers[1] = 10;
-        // test_vm.float_registers[0] = 5.0;
-        // test_vm.float_registers[1] = 10.0;
-        test_vm
-    }
-
-    pub fn run(&mut self) {
-        let mut is_done = false;
-
-        while !is_done {
-            is_done = self.execute_instruction();
-        }
-    }
-
-    pub fn run_once(&mut self) {
-        self.execute_instruction();
-    }
-
-    pub fn execute_instruction(&mut self) -> bool {
-        // If our program counter has exceeded the length
-        // of the program itself, something has gone awry
-        if self.pc >= self.program.len() {
-            return true;
-        }
-
-        match self.decode_opcode() {
-            Opcode::LOAD => {
-                let register = self.next_8_bits() as usize;
-                let number = self.next_16_bits() as u16;
-
-                // Our registers are i32s, so we need to cast it.
-                self.registers[register] = number as i32;
-            }
-            Opcode::HLT => {
-                println!("HLT encountered");
-                return true;
-            }
-            Opcode::IGL => {
-                println!("Illegal instruction encountered");
-                return true;
-            }
-            Opcode::ADD => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 + register2;
-            }
-            Opcode::DIV => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                self.registers[self.next_8_bits() as usize] = register1 / register2;
-                self.remainder = (register1 % register2) as usize;
-            }
-            Opcode::JMP => {
-                let target = self.registers[self.next_8_bits() as usize];
-                self.pc = target as usize;
-            }
-            Opcode::JMPF => {
-                let value = self.registers[self.next_8_bits() as usize];
-                self.pc += value as usize;
-            }
-            Opcode::EQ => {
-                let register1 = self.registers[self.next_8_bits() as usize];
-                let register2 = self.registers[self.next_8_bits() as usize];
-                if register1 == register2 {
-                    self.equal_flag = true;
-                } else {
-                    self.equal_flag = false;
-                }
-                self.next_8_bits();
-            }
-            Opcode::JMPE => {
-                let register = self.next_8_bits() as usize;
-                let target = self.registers[register];
-                if self.equal_flag {
-                    self.pc = target as usize;
-                }
-            }
-            Opcode::ALOC => {
-                let register = self.next_8_bits() as usize;
-                let bytes = self.registers[register];
-                let new_end = self.heap.len() as i32 + bytes;
-                self.heap.resize(new_end as usize, 0);
-            }
-            Opcode::PRTS => {
-                let starting_offset = self.next_16_bits() as usize;
-                let mut ending_offset = starting_offset;
-                let slice = self.ro_data.as_slice();
-
-                while slice[ending_offset] != 0 {
-                    ending_offset += 1;
-                }
-
-                let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
-                
-                match result {
-                    Ok(s) => { print!("{}", s); }
-                    Err(e) => { println!("Error decoding string for prts instruction: {:#?}", e) }
-                }
-            }
-            _ => {
-                println!("Unrecognized opcode found! Terminating!");
-                return false;
-            }
-        }
-
-        false
-    }
-
-    fn decode_opcode(&mut self) -> Opcode {
-        let opcode = Opcode::from(self.program[self.pc]);
-        self.pc += 1;
-        opcode
-    }
-
-    fn next_8_bits(&mut self) -> u8 {
-        let result = self.program[self.pc];
-        self.pc += 1;
-        result
-    }
-
-    fn next_16_bits(&mut self) -> u16 {
-        let result = ((self.program[self.pc] as u16) << 8) | self.program[self.pc + 1] as u16;
-        self.pc += 2;
-        result
-    }
-
-    pub fn add_byte(&mut self, b: u8) {
-        self.program.push(b);
-    }
-
-    /// Adds an arbitrary byte to the VM's program
-    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
-        self.program.append(&mut b);
-    }
-
-    /// Processes the header of bytecode the VM wants to execute
-    fn verify_header(&self) -> bool {
-        if self.program[0..4] != PIE_HEADER_PREFIX {
-            return false;
-        }
-
-        true
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_create_vm() {
-        let test_vm = VM::new();
-        assert_eq!(test_vm.registers[0], 0)
-    }
-
-    #[test]
-    fn test_opcode_hlt() {
-        let mut test_vm = VM::new();
-        let test_bytes = vec![0, 0, 0, 0];
-        test_vm.program = test_bytes;
-        test_vm.run();
-        assert_eq!(test_vm.pc, 1);
-    }
-
-    #[test]
-    fn test_opcode_igl() {
-        let mut test_vm = VM::new();
-        let test_bytes = vec![200, 0, 0, 0];
-        test_vm.program = test_bytes;
-        test_vm.run();
-        assert_eq!(test_vm.pc, 1);
-    }
-
-    #[test]
-    fn test_load_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        // Remember, this is how we represent 500 using two u8s in little endian format
-        test_vm.program = vec![0, 0, 1, 244];
-        test_vm.run();
-        assert_eq!(test_vm.registers[0], 500);
-    }
-
-    #[test]
-    fn test_jmp_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.registers[0] = 1;
-        test_vm.program = vec![7, 0, 0, 0];
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 1);
-    }
-
-    #[test]
-    fn test_jmpf_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.registers[0] = 2;
-        test_vm.program = vec![8, 0, 0, 0, 6, 0, 0, 0];
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 4);
-    }
-
-    #[test]
-    fn test_eq_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.registers[0] = 10;
-        test_vm.registers[1] = 10;
-        test_vm.program = vec![10, 0, 1, 0, 10, 0, 1, 0];
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, true);
-        test_vm.registers[1] = 20;
-        test_vm.run_once();
-        assert_eq!(test_vm.equal_flag, false);
-    }
-
-    #[test]
-    fn test_jeq_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.registers[0] = 7;
-        test_vm.equal_flag = true;
-        test_vm.program = vec![16, 0, 0, 0, 17, 0, 0, 0, 17, 0, 0, 0];
-        test_vm.run_once();
-        assert_eq!(test_vm.pc, 7);
-    }
-
-    #[test]
-    fn test_aloc_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.registers[0] = 1024;
-        test_vm.program = vec![17, 0, 0, 0];
-        test_vm.run_once();
-        assert_eq!(test_vm.heap.len(), 1024);
-    }
-
-    fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
-        let mut prepension = vec![];
-        for byte in PIE_HEADER_PREFIX.into_iter() {
-            prepension.push(byte.clone());
-        }
-        while prepension.len() <= PIE_HEADER_LENGTH {
-            prepension.push(0);
-        }
-        prepension.append(&mut b);
-        prepension
-    }
-
-    #[test]
-    fn test_mul_opcode() {
-        let mut test_vm = VM::get_test_vm();
-        test_vm.program = vec![3, 0, 1, 2];
-        test_vm.program = prepend_header(test_vm.program);
-        test_vm.run();
-        assert_eq!(test_vm.registers[2], 50);
-    }
-}
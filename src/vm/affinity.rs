@@ -0,0 +1,55 @@
+use std::thread::{self, JoinHandle};
+
+/// Pins the calling thread to a core, so a VM running on it isn't moved between cores by the OS. That cuts the
+/// jitter in benchmarks and soak tests running many VMs. Cores are numbered from 0. Only supported on Linux.
+#[cfg(target_os = "linux")]
+pub fn pin_current_thread(core: usize) -> Result<(), String> {
+    // CPU_SET indexes a fixed size bitmap, so cores past its end have to be turned away before it sees them
+    if core >= 8 * std::mem::size_of::<libc::cpu_set_t>() {
+        return Err(format!("There is no core {}", core));
+    }
+
+    let result = unsafe {
+        let mut set: libc::cpu_set_t = std::mem::zeroed();
+        libc::CPU_SET(core, &mut set);
+        libc::sched_setaffinity(0, std::mem::size_of::<libc::cpu_set_t>(), &set)
+    };
+    if result != 0 {
+        let error = std::io::Error::last_os_error();
+        return Err(format!("Unable to pin the thread to core {}: {}", core, error));
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn pin_current_thread(_core: usize) -> Result<(), String> {
+    Err("Pinning threads to cores is only supported on Linux".to_string())
+}
+
+/// Runs `f` on a new thread pinned to `core`. VMs can't move between threads, so `f` should create the VM it runs.
+/// The thread gives back why it couldn't be pinned, without calling `f`, if it couldn't be.
+pub fn spawn_pinned<F, T>(core: usize, f: F) -> JoinHandle<Result<T, String>>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    thread::spawn(move || {
+        pin_current_thread(core)?;
+        Ok(f())
+    })
+}
+
+#[cfg(all(test, target_os = "linux"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_spawn_pinned() {
+        // The core the test is running on is one it's allowed to use
+        let core = unsafe { libc::sched_getcpu() } as usize;
+        let handle = spawn_pinned(core, move || unsafe { libc::sched_getcpu() } as usize);
+        assert_eq!(handle.join().unwrap(), Ok(core));
+
+        assert!(spawn_pinned(usize::MAX, || ()).join().unwrap().is_err());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::tools::fault::nearest_label;
+
+use std::collections::BTreeMap;
+
+/// What the ALOCs at one offset in the program have allocated
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AllocationSite {
+    /// Offset of the ALOC
+    pub pc: usize,
+    pub allocations: usize,
+    pub frees: usize,
+    /// Bytes allocated over the whole run
+    pub bytes: usize,
+    /// Bytes in blocks from here that are still allocated
+    pub bytes_in_use: usize,
+    /// Largest value `bytes_in_use` has reached
+    pub peak_bytes_in_use: usize,
+}
+
+/// A block that was never freed, with the ALOC that allocated it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Leak {
+    pub address: usize,
+    pub size: usize,
+    pub pc: usize,
+}
+
+/// Heap statistics kept for each ALOC in the program, for finding where memory goes and which blocks are never
+/// freed. Turned on with `VMBuilder::heap_tracking` or `VM::set_heap_tracking`.
+#[derive(Debug, Default, Clone)]
+pub struct AllocationTracker {
+    sites: BTreeMap<usize, AllocationSite>,
+    /// Allocated blocks, as address -> (size, offset of the ALOC)
+    blocks: BTreeMap<usize, (usize, usize)>,
+}
+
+impl AllocationTracker {
+    pub fn new() -> AllocationTracker {
+        AllocationTracker::default()
+    }
+
+    /// Records that the ALOC at `pc` allocated `size` bytes at `address`
+    pub fn allocated(&mut self, pc: usize, address: usize, size: usize) {
+        let site = self.sites.entry(pc).or_insert_with(|| AllocationSite { pc, ..AllocationSite::default() });
+        site.allocations += 1;
+        site.bytes += size;
+        site.bytes_in_use += size;
+        site.peak_bytes_in_use = site.peak_bytes_in_use.max(site.bytes_in_use);
+        self.blocks.insert(address, (size, pc));
+    }
+
+    /// Records that the block at `address` was freed. Blocks the tracker didn't see allocated, such as ones the host
+    /// allocated, are ignored.
+    pub fn freed(&mut self, address: usize) {
+        if let Some((size, pc)) = self.blocks.remove(&address) {
+            if let Some(site) = self.sites.get_mut(&pc) {
+                site.frees += 1;
+                site.bytes_in_use -= size;
+            }
+        }
+    }
+
+    /// The allocation sites, in the order they appear in the program
+    pub fn sites(&self) -> impl Iterator<Item = &AllocationSite> {
+        self.sites.values()
+    }
+
+    /// The blocks still allocated, lowest address first
+    pub fn leaks(&self) -> Vec<Leak> {
+        self.blocks.iter().map(|(address, (size, pc))| Leak { address: *address, size: *size, pc: *pc }).collect()
+    }
+
+    /// Formats the statistics of each site, followed by the blocks never freed. Sites are named by the label before
+    /// them when `symbols` are given.
+    pub fn report(&self, symbols: Option<&SymbolTable>) -> Vec<String> {
+        let place = |pc: usize| match symbols.and_then(|symbols| nearest_label(symbols, pc)) {
+            Some((name, 0)) => format!("{} ({})", pc, name),
+            Some((name, distance)) => format!("{} ({} + {})", pc, name, distance),
+            None => pc.to_string(),
+        };
+
+        let mut lines = vec!["allocation sites:".to_string()];
+        for site in self.sites() {
+            lines.push(format!(
+                "  {}: {} allocations, {} frees, {} bytes, peak {} bytes in use",
+                place(site.pc),
+                site.allocations,
+                site.frees,
+                site.bytes,
+                site.peak_bytes_in_use
+            ));
+        }
+
+        let leaks = self.leaks();
+        let leaked: usize = leaks.iter().map(|leak| leak.size).sum();
+        lines.push(format!("{} blocks never freed, {} bytes", leaks.len(), leaked));
+        for leak in leaks {
+            lines.push(format!("  {} bytes at {}, allocated at {}", leak.size, leak.address, place(leak.pc)));
+        }
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::symbols::{Symbol, SymbolType};
+
+    #[test]
+    fn test_tracker() {
+        let mut tracker = AllocationTracker::new();
+        tracker.allocated(72, 0, 16);
+        tracker.allocated(72, 16, 16);
+        tracker.allocated(64, 32, 4);
+        tracker.freed(0);
+        tracker.freed(100);
+        tracker.allocated(72, 0, 8);
+
+        let site = tracker.sites().find(|site| site.pc == 72).unwrap();
+        assert_eq!((site.allocations, site.frees, site.bytes), (3, 1, 40));
+        assert_eq!((site.bytes_in_use, site.peak_bytes_in_use), (24, 32));
+        assert_eq!(tracker.leaks()[0], Leak { address: 0, size: 8, pc: 72 });
+
+        let mut symbols = SymbolTable::new();
+        symbols.add_symbol(Symbol::new_with_offset("grow".to_string(), SymbolType::Label, 68));
+        let report = tracker.report(Some(&symbols));
+        assert_eq!(report[1], "  64: 1 allocations, 0 frees, 4 bytes, peak 4 bytes in use");
+        assert_eq!(report[3], "3 blocks never freed, 28 bytes");
+        assert_eq!(report[4], "  8 bytes at 0, allocated at 72 (grow + 4)");
+    }
+}
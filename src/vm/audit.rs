@@ -0,0 +1,196 @@
+use crate::instruction::Opcode;
+use crate::vm::errors::VMError;
+use crate::vm::flags::Flags;
+use crate::vm::run_result::RunResult;
+use crate::vm::syscalls::{NONDETERMINISTIC_SYSCALLS, SYS_TIME};
+use crate::vm::VM;
+
+use std::io;
+
+/// Opcodes whose results don't only depend on the program, so two runs of a program that uses them can differ
+pub const NONDETERMINISTIC_OPCODES: [Opcode; 2] = [Opcode::RAND, Opcode::RECV];
+
+/// Seed given to RAND in both runs, so it produces the same numbers each time
+const AUDIT_SEED: u64 = 1;
+
+/// The outcome of running a program twice to check it behaves the same way both times
+#[derive(Debug, Default, PartialEq)]
+pub struct AuditReport {
+    /// Syscalls the program made whose results come from outside the VM
+    pub nondeterministic_syscalls: Vec<u16>,
+    /// Opcodes the program used whose results vary between runs, such as `rand`
+    pub nondeterministic_opcodes: Vec<String>,
+    /// Addresses in the device range the program used LW or SW on, which reach devices outside the VM
+    pub device_addresses: Vec<usize>,
+    /// Parts of the final state that weren't the same after both runs
+    pub differences: Vec<String>,
+}
+
+impl AuditReport {
+    /// True if the program can be replayed and is expected to reach the same state every time
+    pub fn is_replay_safe(&self) -> bool {
+        self.nondeterministic_syscalls.is_empty()
+            && self.nondeterministic_opcodes.is_empty()
+            && self.device_addresses.is_empty()
+            && self.differences.is_empty()
+    }
+}
+
+/// What a run of a program left behind. Floats are kept as bits so NaNs compare equal to themselves.
+#[derive(Debug, PartialEq)]
+struct FinalState {
+    registers: [i32; 32],
+    float_registers: Vec<u64>,
+    flags: Flags,
+    heap: Vec<u8>,
+    pc: usize,
+    exit_code: Option<i32>,
+    error: Option<VMError>,
+}
+
+impl FinalState {
+    fn of(vm: &VM, result: Result<RunResult, VMError>) -> FinalState {
+        FinalState {
+            registers: vm.registers,
+            float_registers: vm.float_registers.iter().map(|value| value.to_bits()).collect(),
+            flags: vm.flags(),
+            heap: vm.heap().to_vec(),
+            pc: vm.pc(),
+            exit_code: vm.exit_code(),
+            error: result.err(),
+        }
+    }
+
+    /// Describes each part of the state that differs from `other`
+    fn differences(&self, other: &FinalState) -> Vec<String> {
+        let mut differences = vec![];
+
+        for (index, (first, second)) in self.registers.iter().zip(other.registers.iter()).enumerate() {
+            if first != second {
+                differences.push(format!("${} was {} then {}", index, first, second));
+            }
+        }
+        for (index, (first, second)) in self.float_registers.iter().zip(other.float_registers.iter()).enumerate() {
+            if first != second {
+                differences.push(format!("float ${} was {} then {}", index, f64::from_bits(*first), f64::from_bits(*second)));
+            }
+        }
+        if self.flags != other.flags {
+            differences.push(format!("flags were {} then {}", self.flags, other.flags));
+        }
+        if self.heap != other.heap {
+            differences.push("heap contents differed".to_string());
+        }
+        if self.pc != other.pc {
+            differences.push(format!("pc was {} then {}", self.pc, other.pc));
+        }
+        if self.exit_code != other.exit_code {
+            differences.push(format!("exit code was {:?} then {:?}", self.exit_code, other.exit_code));
+        }
+        if self.error != other.error {
+            let describe = |error: &Option<VMError>| error.as_ref().map_or("none".to_string(), |error| error.to_string());
+            differences.push(format!("error was {} then {}", describe(&self.error), describe(&other.error)));
+        }
+
+        differences
+    }
+}
+
+/// Runs a program twice and compares the state each run finishes in. Syscalls that read the outside world get empty
+/// input or always return 0 and RAND is given the same seed, so both runs get the same input, and any
+/// use of them is reported since the program would behave differently once they're real. No devices are mapped, so
+/// LW and SW in the device range fault the same way in both runs, and the addresses they used are reported too.
+pub fn audit(program: &[u8]) -> AuditReport {
+    let (first, vm) = audited_run(program);
+    let (second, _) = audited_run(program);
+
+    let opcodes_used = &vm.stats().expect("audited runs collect statistics").opcodes;
+    AuditReport {
+        nondeterministic_syscalls: vm
+            .syscalls_used()
+            .iter()
+            .copied()
+            .filter(|number| NONDETERMINISTIC_SYSCALLS.contains(number))
+            .collect(),
+        nondeterministic_opcodes: NONDETERMINISTIC_OPCODES
+            .iter()
+            .map(|opcode| format!("{:?}", opcode).to_lowercase())
+            .filter(|name| opcodes_used.contains_key(name))
+            .collect(),
+        device_addresses: vm.devices_used().iter().copied().collect(),
+        differences: first.differences(&second),
+    }
+}
+
+fn audited_run(program: &[u8]) -> (FinalState, VM) {
+    fn zero_result(vm: &mut VM) -> bool {
+        vm.registers[0] = 0;
+        false
+    }
+
+    let mut vm = VM::new();
+    vm.set_input(Box::new(io::empty()));
+    vm.syscalls_mut().register(SYS_TIME, zero_result);
+    vm.seed_random(AUDIT_SEED);
+    vm.enable_stats();
+    vm.add_bytes(program.to_vec());
+    let result = vm.run();
+
+    (FinalState::of(&vm, result), vm)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::devices::{CONSOLE_ADDRESS, TIMER_ADDRESS};
+    use crate::vm::run_result::HaltedBy;
+
+    fn assemble(source: &str) -> Vec<u8> {
+        Assembler::new().assemble(source).unwrap()
+    }
+
+    #[test]
+    fn test_deterministic_program() {
+        let report = audit(&assemble(".data\n.code\nload $0 #5\nload $1 #6\nadd $0 $1 $2\nhlt"));
+        assert_eq!(report, AuditReport::default());
+        assert!(report.is_replay_safe());
+    }
+
+    #[test]
+    fn test_time_syscall_is_flagged() {
+        let report = audit(&assemble(".data\n.code\nsyscall #3\nhlt"));
+        assert_eq!(report.nondeterministic_syscalls, vec![SYS_TIME]);
+        assert!(report.differences.is_empty());
+        assert!(!report.is_replay_safe());
+    }
+
+    #[test]
+    fn test_rand_is_flagged() {
+        let report = audit(&assemble(".data\n.code\nrand $0\nhlt"));
+        assert_eq!(report.nondeterministic_opcodes, vec!["rand".to_string()]);
+        assert!(report.differences.is_empty());
+        assert!(!report.is_replay_safe());
+    }
+
+    #[test]
+    fn test_device_access_is_flagged() {
+        let report = audit(&assemble(".data\n.code\nli $1 #1879052288\nlw $0 $1\nhlt"));
+        assert_eq!(report.device_addresses, vec![TIMER_ADDRESS]);
+        assert!(!report.is_replay_safe());
+
+        let report = audit(&assemble(".data\n.code\nli $1 #1879048192\nsw $0 $1\nhlt"));
+        assert_eq!(report.device_addresses, vec![CONSOLE_ADDRESS]);
+        assert!(!report.is_replay_safe());
+    }
+
+    #[test]
+    fn test_state_differences() {
+        let mut vm = VM::new();
+        let result = RunResult { exit_code: 0, instructions_executed: 1, halted_by: HaltedBy::Hlt };
+        let first = FinalState::of(&vm, Ok(result.clone()));
+        vm.registers[3] = 7;
+        let second = FinalState::of(&vm, Ok(result));
+        assert_eq!(first.differences(&second), vec!["$3 was 0 then 7".to_string()]);
+    }
+}
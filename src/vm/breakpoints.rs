@@ -0,0 +1,20 @@
+/// A place the program stops before running the instruction there, added with `VM::add_breakpoint`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Breakpoint {
+    pub address: usize,
+    /// Whether the breakpoint is removed the first time the program stops at it, such as for running to a line
+    pub temporary: bool,
+    /// Times the program has stopped at the breakpoint
+    pub hits: u64,
+}
+
+impl Breakpoint {
+    pub fn new(address: usize) -> Breakpoint {
+        Breakpoint { address, temporary: false, hits: 0 }
+    }
+
+    /// Creates a breakpoint that only stops the program once
+    pub fn temporary(address: usize) -> Breakpoint {
+        Breakpoint { address, temporary: true, hits: 0 }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::vm::errors::VMError;
+use crate::vm::heap::Heap;
+
+use std::collections::BTreeMap;
+
+/// Bytes written after each array a function allocates while stack canaries are on. Unlikely to be stored by
+/// accident, so finding anything else there means something wrote past the end of the array.
+pub const CANARY: [u8; 4] = [0xde, 0xc0, 0x1d, 0x1d];
+
+/// A canary placed after an array allocated inside a call
+#[derive(Debug, Clone, PartialEq)]
+pub struct Canary {
+    /// Address of the array the canary guards
+    pub array: usize,
+    /// How many calls deep the program was when the array was allocated. The canary is checked when that call returns.
+    pub depth: usize,
+    /// Offset of the first instruction that changed the canary, if an instruction has
+    pub overwritten_by: Option<usize>,
+}
+
+/// A canary that no longer holds `CANARY`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmashedCanary {
+    pub array: usize,
+    /// Offset of the instruction that overwrote it, or None if it was written some other way, such as by a syscall
+    pub overwritten_by: Option<usize>,
+}
+
+impl SmashedCanary {
+    /// The error stopping the program when the RET or FREE at `pc` found the canary overwritten
+    pub fn error(self, pc: usize) -> VMError {
+        VMError::StackSmashed { pc, array: self.array, overwritten_by: self.overwritten_by }
+    }
+}
+
+/// Canaries guarding the arrays allocated by the calls that haven't returned yet, keyed by their addresses.
+///
+/// The call stack itself is kept outside of memory the program can reach, so arrays a function allocates stand in for
+/// its stack frame: each gets four extra bytes holding `CANARY` right after it, checked when the function returns.
+#[derive(Debug, Default)]
+pub struct Canaries {
+    placed: BTreeMap<usize, Canary>,
+}
+
+impl Canaries {
+    pub fn new() -> Canaries {
+        Canaries::default()
+    }
+
+    /// Number of extra bytes to allocate for an array, to make room for its canary
+    pub fn padding() -> usize {
+        CANARY.len()
+    }
+
+    /// Writes a canary after the `size` bytes of the array at `array`, which must have been allocated with the padding
+    pub fn place(&mut self, heap: &mut Heap, array: usize, size: usize, depth: usize) {
+        let address = array + size;
+        heap.slice_mut(address, CANARY.len()).unwrap().copy_from_slice(&CANARY);
+        self.placed.insert(address, Canary { array, depth, overwritten_by: None });
+    }
+
+    /// Notes that the instruction at `pc` wrote `length` bytes at `address`, so a canary it changed can be blamed on it
+    pub fn note_write(&mut self, heap: &Heap, address: usize, length: usize, pc: usize) {
+        let first = address.saturating_sub(CANARY.len() - 1);
+        for (canary_address, canary) in self.placed.range_mut(first..address + length) {
+            if canary.overwritten_by.is_none() && heap.slice(*canary_address, CANARY.len()) != Some(&CANARY[..]) {
+                canary.overwritten_by = Some(pc);
+            }
+        }
+    }
+
+    /// Checks and forgets the canaries of the arrays allocated by the call returning from `depth`, and by any calls it
+    /// made that didn't return
+    pub fn check_return(&mut self, heap: &Heap, depth: usize) -> Result<(), SmashedCanary> {
+        let returning: Vec<usize> = self
+            .placed
+            .iter()
+            .filter(|(_, canary)| canary.depth >= depth)
+            .map(|(address, _)| *address)
+            .collect();
+
+        let mut smashed = Ok(());
+        for address in returning {
+            let canary = self.placed.remove(&address).unwrap();
+            if smashed.is_ok() && heap.slice(address, CANARY.len()) != Some(&CANARY[..]) {
+                smashed = Err(SmashedCanary { array: canary.array, overwritten_by: canary.overwritten_by });
+            }
+        }
+        smashed
+    }
+
+    /// Checks and forgets the canary of an array that is being freed, if it has one
+    pub fn check_free(&mut self, heap: &Heap, array: usize) -> Result<(), SmashedCanary> {
+        let address = match self.placed.iter().find(|(_, canary)| canary.array == array) {
+            Some((address, _)) => *address,
+            None => return Ok(()),
+        };
+
+        let canary = self.placed.remove(&address).unwrap();
+        if heap.slice(address, CANARY.len()) != Some(&CANARY[..]) {
+            return Err(SmashedCanary { array, overwritten_by: canary.overwritten_by });
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_canaries() {
+        let mut heap = Heap::new();
+        let mut canaries = Canaries::new();
+        let array = heap.allocate(6 + Canaries::padding()).unwrap();
+        canaries.place(&mut heap, array, 6, 1);
+        assert_eq!(heap.slice(6, 4), Some(&CANARY[..]));
+
+        // A write that leaves the canary alone isn't blamed for anything
+        heap.slice_mut(2, 4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        canaries.note_write(&heap, 2, 4, 100);
+        heap.slice_mut(4, 4).unwrap().copy_from_slice(&[1, 2, 3, 4]);
+        canaries.note_write(&heap, 4, 4, 104);
+        assert_eq!(canaries.check_return(&heap, 2), Ok(()));
+        assert_eq!(canaries.check_return(&heap, 1), Err(SmashedCanary { array, overwritten_by: Some(104) }));
+        assert_eq!(canaries.check_return(&heap, 1), Ok(()));
+    }
+}
@@ -0,0 +1,72 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between threads, which the handles that steer a running VM from outside are built on. Clones share
+/// the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct SharedFlag {
+    set: Arc<AtomicBool>,
+}
+
+impl SharedFlag {
+    pub fn set(&self) {
+        self.set.store(true, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        self.set.store(false, Ordering::Relaxed);
+    }
+
+    pub fn is_set(&self) -> bool {
+        self.set.load(Ordering::Relaxed)
+    }
+
+    /// Clears the flag, returning whether it was set
+    pub fn take(&self) -> bool {
+        self.set.swap(false, Ordering::Relaxed)
+    }
+}
+
+/// Stops a running VM from another thread. Clones share the same flag, so any of them can cancel the VM it came from.
+#[derive(Debug, Clone, Default)]
+pub struct CancelHandle {
+    cancelled: SharedFlag,
+}
+
+impl CancelHandle {
+    pub fn new() -> CancelHandle {
+        CancelHandle::default()
+    }
+
+    /// Asks the VM to stop before its next instruction
+    pub fn cancel(&self) {
+        self.cancelled.set();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.is_set()
+    }
+
+    /// Clears a pending cancellation, returning whether there was one. The VM does this when it stops, so the next
+    /// run isn't cancelled too.
+    pub fn take(&self) -> bool {
+        self.cancelled.take()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancel_handle() {
+        let handle = CancelHandle::new();
+        let clone = handle.clone();
+        assert!(!handle.is_cancelled());
+        clone.cancel();
+        assert!(handle.is_cancelled());
+        assert!(handle.take());
+        assert!(!clone.is_cancelled());
+        assert!(!handle.take());
+    }
+}
@@ -0,0 +1,30 @@
+use std::fmt;
+
+/// A structured record a program emitted on a named output channel with the emit syscall, kept apart from what it
+/// prints so hosts can treat data and logs differently
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutputRecord {
+    pub channel: String,
+    /// What kind of record this is, which is up to the program
+    pub tag: i32,
+    pub data: Vec<u8>,
+}
+
+/// The line a record is written as when nothing has subscribed to them: the channel, the tag in brackets and the
+/// data, with anything that isn't UTF-8 replaced
+impl fmt::Display for OutputRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}[{}] {}", self.channel, self.tag, String::from_utf8_lossy(&self.data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let record = OutputRecord { channel: "metrics".to_string(), tag: 2, data: b"latency=4".to_vec() };
+        assert_eq!(record.to_string(), "metrics[2] latency=4");
+    }
+}
@@ -0,0 +1,160 @@
+use crate::vm::output::DEFAULT_OUTPUT_BUFFER;
+use crate::vm::VM;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+
+/// Number of integer registers, and of float registers, in this VM
+pub const REGISTER_COUNT: usize = 32;
+
+/// How the VM goes from one instruction to the next in `VM::run`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Dispatch {
+    /// Decodes each instruction and matches on its opcode
+    #[default]
+    Match,
+    /// Threads the program into a handler for each instruction ahead of time and calls them one after another,
+    /// leaving instructions without a handler to the match
+    Threaded,
+}
+
+/// How a VM was sized and which of its optional parts are turned on, set with `VM::builder`. Snapshots record it, so
+/// one taken on a VM isn't restored onto a VM the program can't carry on in.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VMConfig {
+    /// Number of integer registers, and of float registers. It's always `REGISTER_COUNT` in this VM, but is recorded
+    /// so a snapshot from a VM with a different register file is refused.
+    pub registers: usize,
+    /// Bytes the heap may grow to, or None for no limit beyond the address space
+    pub heap_limit: Option<usize>,
+    /// Whether the float opcodes run. Without them they are illegal opcodes.
+    pub float_support: bool,
+    /// Whether heap statistics are kept for each ALOC, for `VM::heap_report`
+    pub heap_tracking: bool,
+    /// Whether the program's instructions are decoded once, before they run, rather than each time they run
+    pub predecode: bool,
+    /// How `VM::run` dispatches instructions
+    pub dispatch: Dispatch,
+    /// Bytes of program output held back before being written to stdout, where 0 writes each print straight away
+    pub output_buffer: usize,
+}
+
+impl Default for VMConfig {
+    fn default() -> Self {
+        VMConfig {
+            registers: REGISTER_COUNT,
+            heap_limit: None,
+            float_support: true,
+            heap_tracking: false,
+            predecode: false,
+            dispatch: Dispatch::Match,
+            output_buffer: DEFAULT_OUTPUT_BUFFER,
+        }
+    }
+}
+
+impl VMConfig {
+    /// Checks that a program running on a VM configured like `self` can carry on in a VM configured like `other`
+    pub fn check_compatible(&self, other: &VMConfig) -> Result<(), String> {
+        if self.registers != other.registers {
+            return Err(format!("the snapshot has {} registers, but this VM has {}", self.registers, other.registers));
+        }
+        if self.float_support && !other.float_support {
+            return Err("the snapshot was taken with float support, which this VM doesn't have".to_string());
+        }
+        Ok(())
+    }
+
+    /// Appends the configuration in the snapshot format. The heap limit isn't included, as the heap carries its own,
+    /// and neither are heap tracking, predecoding, dispatch and output buffering, which don't change what the program
+    /// does.
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.write_u16::<LittleEndian>(self.registers as u16).unwrap();
+        out.push(self.float_support as u8);
+    }
+
+    pub fn decode(input: &mut &[u8]) -> io::Result<VMConfig> {
+        let registers = input.read_u16::<LittleEndian>()? as usize;
+        let float_support = input.read_u8()? != 0;
+        Ok(VMConfig { registers, float_support, ..VMConfig::default() })
+    }
+}
+
+/// Sets up a VM that isn't configured the default way, such as
+/// `VM::builder().heap_kb(256).float_support(false).build()`
+#[derive(Debug, Clone, Default)]
+pub struct VMBuilder {
+    config: VMConfig,
+}
+
+impl VMBuilder {
+    pub fn new() -> VMBuilder {
+        VMBuilder::default()
+    }
+
+    /// Caps the heap at this many kilobytes
+    pub fn heap_kb(mut self, kilobytes: usize) -> VMBuilder {
+        self.config.heap_limit = Some(kilobytes * 1024);
+        self
+    }
+
+    /// Turns the float opcodes on or off. They are on by default.
+    pub fn float_support(mut self, on: bool) -> VMBuilder {
+        self.config.float_support = on;
+        self
+    }
+
+    /// Keeps heap statistics for each ALOC in the program and tracks which blocks are never freed. Off by default.
+    pub fn heap_tracking(mut self, on: bool) -> VMBuilder {
+        self.config.heap_tracking = on;
+        self
+    }
+
+    /// Decodes the program's instructions once, before they run, instead of every time they run. Off by default.
+    pub fn predecode(mut self, on: bool) -> VMBuilder {
+        self.config.predecode = on;
+        self
+    }
+
+    /// Picks how `VM::run` dispatches instructions. Matching on each opcode is the default.
+    pub fn dispatch(mut self, dispatch: Dispatch) -> VMBuilder {
+        self.config.dispatch = dispatch;
+        self
+    }
+
+    /// Holds back up to this many bytes of program output before writing it to stdout, or none with 0. 8 KiB by
+    /// default.
+    pub fn output_buffer(mut self, bytes: usize) -> VMBuilder {
+        self.config.output_buffer = bytes;
+        self
+    }
+
+    /// Creates the VM. There's no `registers` setting, as the register file is always `REGISTER_COUNT` wide.
+    pub fn build(self) -> VM {
+        let mut vm = VM::new();
+        vm.set_max_heap_size(self.config.heap_limit);
+        vm.set_heap_tracking(self.config.heap_tracking);
+        vm.set_output_buffer(self.config.output_buffer);
+        vm.config = self.config;
+        vm
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_compatible() {
+        let floats = VMConfig::default();
+        let no_floats = VMConfig { float_support: false, ..VMConfig::default() };
+        assert!(floats.check_compatible(&floats).is_ok());
+        assert!(no_floats.check_compatible(&floats).is_ok());
+        assert!(floats.check_compatible(&no_floats).is_err());
+        assert!(VMConfig { registers: 64, ..VMConfig::default() }.check_compatible(&floats).is_err());
+
+        let mut bytes = vec![];
+        no_floats.encode(&mut bytes);
+        assert_eq!(VMConfig::decode(&mut &bytes[..]).unwrap(), no_floats);
+    }
+}
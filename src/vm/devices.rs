@@ -0,0 +1,165 @@
+use std::io::{self, Write};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+/// Start of the address range reserved for devices. LW and SW at or above it go to the device mapped there instead
+/// of the heap, which never grows this far.
+pub const MMIO_BASE: usize = 0x7000_0000;
+/// Where `VM::map_standard_devices` puts the console
+pub const CONSOLE_ADDRESS: usize = MMIO_BASE;
+/// Where `VM::map_standard_devices` puts the timer
+pub const TIMER_ADDRESS: usize = MMIO_BASE + 0x1000;
+
+/// A virtual peripheral that LW and SW talk to through its address range. Offsets are in bytes from the start of the
+/// range and are always word aligned.
+pub trait Device {
+    fn read(&mut self, offset: usize) -> i32;
+    fn write(&mut self, offset: usize, value: i32);
+}
+
+/// The devices mapped into the VM's address space
+#[derive(Default)]
+pub struct DeviceBus {
+    devices: Vec<(Range<usize>, Box<dyn Device>)>,
+}
+
+impl DeviceBus {
+    pub fn new() -> DeviceBus {
+        DeviceBus::default()
+    }
+
+    /// Maps a device to `length` bytes at `base`, which must be inside the device range and not overlap another
+    /// device
+    pub fn map(&mut self, base: usize, length: usize, device: Box<dyn Device>) -> Result<(), String> {
+        let range = base..base.checked_add(length).ok_or_else(|| "Device range overflows".to_string())?;
+        if range.start < MMIO_BASE || !range.start.is_multiple_of(4) || range.is_empty() {
+            return Err(format!("Devices must be mapped to a word aligned range at or above {}", MMIO_BASE));
+        }
+        if self.devices.iter().any(|(mapped, _)| mapped.start < range.end && range.start < mapped.end) {
+            return Err(format!("A device is already mapped between {} and {}", range.start, range.end - 1));
+        }
+
+        self.devices.push((range, device));
+        Ok(())
+    }
+
+    /// Reads the word at an address in the device range
+    pub fn read(&mut self, address: usize) -> Result<i32, String> {
+        let (offset, device) = self.find(address)?;
+        Ok(device.read(offset))
+    }
+
+    /// Writes a word to an address in the device range
+    pub fn write(&mut self, address: usize, value: i32) -> Result<(), String> {
+        let (offset, device) = self.find(address)?;
+        device.write(offset, value);
+        Ok(())
+    }
+
+    fn find(&mut self, address: usize) -> Result<(usize, &mut Box<dyn Device>), String> {
+        if !address.is_multiple_of(4) {
+            return Err(format!("Misaligned device access at address {}", address));
+        }
+
+        self.devices
+            .iter_mut()
+            .find(|(range, _)| range.start <= address && address + 4 <= range.end)
+            .map(|(range, device)| (address - range.start, device))
+            .ok_or_else(|| format!("No device is mapped at address {}", address))
+    }
+}
+
+/// Writing a word prints its low byte as a character. Reading gives 0.
+pub struct Console;
+
+impl Device for Console {
+    fn read(&mut self, _offset: usize) -> i32 {
+        0
+    }
+
+    fn write(&mut self, _offset: usize, value: i32) {
+        print!("{}", value as u8 as char);
+        io::stdout().flush().ok();
+    }
+}
+
+/// Reading gives the milliseconds since the timer was created, and writing restarts it
+pub struct Timer {
+    started: Instant,
+}
+
+impl Default for Timer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Timer {
+    pub fn new() -> Timer {
+        Timer { started: Instant::now() }
+    }
+}
+
+impl Device for Timer {
+    fn read(&mut self, _offset: usize) -> i32 {
+        self.started.elapsed().as_millis() as i32
+    }
+
+    fn write(&mut self, _offset: usize, _value: i32) {
+        self.started = Instant::now();
+    }
+}
+
+/// One word per pixel, row by row. The host reads what the program drew through the handle from `pixels`.
+pub struct Framebuffer {
+    pixels: Arc<Mutex<Vec<i32>>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer { pixels: Arc::new(Mutex::new(vec![0; width * height])) }
+    }
+
+    /// Bytes the framebuffer takes up when mapped
+    pub fn byte_length(&self) -> usize {
+        self.pixels.lock().unwrap().len() * 4
+    }
+
+    pub fn pixels(&self) -> Arc<Mutex<Vec<i32>>> {
+        self.pixels.clone()
+    }
+}
+
+impl Device for Framebuffer {
+    fn read(&mut self, offset: usize) -> i32 {
+        self.pixels.lock().unwrap()[offset / 4]
+    }
+
+    fn write(&mut self, offset: usize, value: i32) {
+        self.pixels.lock().unwrap()[offset / 4] = value;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_device_bus() {
+        let framebuffer = Framebuffer::new(2, 2);
+        let pixels = framebuffer.pixels();
+        let mut bus = DeviceBus::new();
+        bus.map(MMIO_BASE, framebuffer.byte_length(), Box::new(framebuffer)).unwrap();
+
+        assert_eq!(bus.write(MMIO_BASE + 12, 0xff00ff), Ok(()));
+        assert_eq!(bus.read(MMIO_BASE + 12), Ok(0xff00ff));
+        assert_eq!(*pixels.lock().unwrap(), vec![0, 0, 0, 0xff00ff]);
+
+        assert!(bus.read(MMIO_BASE + 16).is_err());
+        assert!(bus.read(MMIO_BASE + 2).is_err());
+        assert!(bus.map(MMIO_BASE + 8, 4, Box::new(Timer::new())).is_err());
+        assert!(bus.map(0, 4, Box::new(Timer::new())).is_err());
+        assert!(bus.map(MMIO_BASE + 16, 4, Box::new(Timer::new())).is_ok());
+    }
+}
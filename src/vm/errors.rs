@@ -0,0 +1,180 @@
+use crate::vm::watchpoints::Watchpoint;
+
+use std::error::Error;
+use std::fmt;
+use std::ops::Range;
+use std::time::Duration;
+
+/// Why the VM stopped a program early. `pc` is the offset of the instruction that failed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMError {
+    /// An opcode byte the VM doesn't recognize or implement
+    IllegalOpcode { pc: usize, byte: u8 },
+    /// Integer division by zero with no handler for the trap
+    DivisionByZero { pc: usize },
+    /// A bad heap, read-only data or code access with no handler for the trap
+    MemoryFault { pc: usize, reason: String },
+    /// Operands that can't be used, such as a register range running past $31, with no handler for the trap
+    InvalidOperands { pc: usize, reason: String },
+    /// A jump past the end of the program, or before the start of its code when strict jumps are on. Jumping to the
+    /// very end just finishes it.
+    OutOfBoundsJump { pc: usize, target: usize },
+    /// A jump into the middle of an instruction, caught when strict jumps are on
+    MisalignedJump { pc: usize, target: usize },
+    UnknownSyscall { pc: usize, number: u16 },
+    /// Syscall middleware refused to let the program make the syscall
+    SyscallDenied { pc: usize, number: u16 },
+    UnknownHostFunction { pc: usize, number: u16 },
+    UnknownTrap { pc: usize, number: usize },
+    CallDepthExceeded { pc: usize, depth: usize },
+    /// RET with nothing on the call stack
+    ReturnWithoutCall { pc: usize },
+    /// IRET outside of a trap handler
+    ReturnOutsideTrap { pc: usize },
+    /// `run_with_budget` executed as many instructions as it was allowed to. `pc` is where the program will resume.
+    BudgetExhausted { pc: usize, budget: u64 },
+    /// `run_for` ran out of time. `pc` is where the program will resume.
+    TimedOut { pc: usize, limit: Duration },
+    /// Another thread cancelled the run through a `CancelHandle`. `pc` is where the program will resume.
+    Cancelled { pc: usize },
+    /// The host paused the VM with `VM::pause`. `pc` is where the program will resume once it has been resumed.
+    Paused { pc: usize },
+    /// A watchdog callback stopped a program stuck in a small loop covering `range`. `pc` is where it will resume.
+    Stalled { pc: usize, range: Range<usize> },
+    /// The program reached a breakpoint. `pc` is the breakpoint, where the program will resume without stopping there
+    /// again. `hits` counts this stop, and a temporary breakpoint has been removed.
+    Breakpoint { pc: usize, hits: u64, temporary: bool },
+    /// The instruction at `changed_by` changed a watched register or heap word from `old` to `new`. `pc` is where the
+    /// program will resume.
+    Watchpoint { pc: usize, changed_by: usize, watchpoint: Watchpoint, old: i32, new: i32 },
+    /// A replayed program asked for an input that isn't the next one in the recording
+    ReplayDiverged { pc: usize },
+    /// RECV in a VM that hasn't been given a mailbox
+    NoMailbox { pc: usize },
+    /// Reading the program's input failed, such as on a line that isn't valid UTF-8
+    InputFailed { pc: usize, reason: String },
+    /// The program's header says it was built for a VM configuration this one doesn't have
+    IncompatibleTarget { pc: usize, reason: String },
+    /// With stack canaries on, the RET or FREE at `pc` found the canary after the array at `array` overwritten.
+    /// `overwritten_by` is the store that did it, if it was an instruction rather than a syscall.
+    StackSmashed { pc: usize, array: usize, overwritten_by: Option<usize> },
+}
+
+impl VMError {
+    /// True if the program was stopped from outside rather than failing, so running the VM again carries on
+    pub fn is_resumable(&self) -> bool {
+        matches!(
+            self,
+            VMError::BudgetExhausted { .. }
+                | VMError::TimedOut { .. }
+                | VMError::Cancelled { .. }
+                | VMError::Paused { .. }
+                | VMError::Stalled { .. }
+                | VMError::Breakpoint { .. }
+                | VMError::Watchpoint { .. }
+        )
+    }
+
+    /// Offset of the instruction that failed
+    pub fn pc(&self) -> usize {
+        match *self {
+            VMError::IllegalOpcode { pc, .. }
+            | VMError::DivisionByZero { pc }
+            | VMError::MemoryFault { pc, .. }
+            | VMError::InvalidOperands { pc, .. }
+            | VMError::OutOfBoundsJump { pc, .. }
+            | VMError::MisalignedJump { pc, .. }
+            | VMError::UnknownSyscall { pc, .. }
+            | VMError::SyscallDenied { pc, .. }
+            | VMError::UnknownHostFunction { pc, .. }
+            | VMError::UnknownTrap { pc, .. }
+            | VMError::CallDepthExceeded { pc, .. }
+            | VMError::ReturnWithoutCall { pc }
+            | VMError::ReturnOutsideTrap { pc }
+            | VMError::BudgetExhausted { pc, .. }
+            | VMError::TimedOut { pc, .. }
+            | VMError::Cancelled { pc }
+            | VMError::Paused { pc }
+            | VMError::Stalled { pc, .. }
+            | VMError::Breakpoint { pc, .. }
+            | VMError::Watchpoint { pc, .. }
+            | VMError::ReplayDiverged { pc }
+            | VMError::NoMailbox { pc }
+            | VMError::InputFailed { pc, .. }
+            | VMError::IncompatibleTarget { pc, .. }
+            | VMError::StackSmashed { pc, .. } => pc,
+        }
+    }
+}
+
+impl fmt::Display for VMError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VMError::IllegalOpcode { pc, byte } => write!(f, "Illegal opcode {} at {}", byte, pc),
+            VMError::DivisionByZero { pc } => write!(f, "Division by zero at {}", pc),
+            VMError::MemoryFault { pc, ref reason } => write!(f, "Memory fault at {}: {}", pc, reason),
+            VMError::InvalidOperands { pc, ref reason } => write!(f, "Invalid operands at {}: {}", pc, reason),
+            VMError::OutOfBoundsJump { pc, target } => {
+                write!(f, "Jump at {} to {}, which is outside the program's code", pc, target)
+            }
+            VMError::MisalignedJump { pc, target } => {
+                write!(f, "Jump at {} to {}, which is in the middle of an instruction", pc, target)
+            }
+            VMError::UnknownSyscall { pc, number } => write!(f, "Unknown syscall {} at {}", number, pc),
+            VMError::SyscallDenied { pc, number } => write!(f, "Syscall {} at {} was denied", number, pc),
+            VMError::UnknownHostFunction { pc, number } => {
+                write!(f, "No host function registered as {} for the HCALL at {}", number, pc)
+            }
+            VMError::UnknownTrap { pc, number } => write!(f, "Unknown trap number {} at {}", number, pc),
+            VMError::CallDepthExceeded { pc, depth } => {
+                write!(f, "Maximum call depth of {} exceeded at {}", depth, pc)
+            }
+            VMError::ReturnWithoutCall { pc } => write!(f, "RET encountered with an empty call stack at {}", pc),
+            VMError::ReturnOutsideTrap { pc } => write!(f, "IRET encountered outside of a trap handler at {}", pc),
+            VMError::BudgetExhausted { pc, budget } => {
+                write!(f, "Stopped at {} after running the budget of {} instructions", pc, budget)
+            }
+            VMError::TimedOut { pc, limit } => write!(f, "Stopped at {} after running for {:?}", pc, limit),
+            VMError::Cancelled { pc } => write!(f, "Cancelled at {}", pc),
+            VMError::Paused { pc } => write!(f, "Paused at {}", pc),
+            VMError::Stalled { pc, ref range } => {
+                write!(f, "Stopped at {} after looping between {} and {} for too long", pc, range.start, range.end - 1)
+            }
+            VMError::Breakpoint { pc, temporary: true, .. } => write!(f, "Stopped at the temporary breakpoint at {}", pc),
+            VMError::Breakpoint { pc, hits, .. } => {
+                write!(f, "Stopped at the breakpoint at {}, which has been hit {} times", pc, hits)
+            }
+            VMError::Watchpoint { changed_by, watchpoint, old, new, .. } => {
+                write!(f, "The instruction at {} changed {} from {} to {}", changed_by, watchpoint, old, new)
+            }
+            VMError::ReplayDiverged { pc } => write!(f, "Replay diverged from the recording at {}", pc),
+            VMError::NoMailbox { pc } => write!(f, "RECV at {} in a VM without a mailbox", pc),
+            VMError::InputFailed { pc, ref reason } => write!(f, "Unable to read input at {}: {}", pc, reason),
+            VMError::IncompatibleTarget { ref reason, .. } => write!(f, "Unable to run the program: {}", reason),
+            VMError::StackSmashed { pc, array, overwritten_by: Some(culprit) } => write!(
+                f,
+                "The array at {} was overflowed by the instruction at {}, found when its canary was checked at {}",
+                array, culprit, pc
+            ),
+            VMError::StackSmashed { pc, array, overwritten_by: None } => {
+                write!(f, "The array at {} was overflowed, found when its canary was checked at {}", array, pc)
+            }
+        }
+    }
+}
+
+impl Error for VMError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display() {
+        let error = VMError::IllegalOpcode { pc: 68, byte: 200 };
+        assert_eq!(error.to_string(), "Illegal opcode 200 at 68");
+        assert_eq!(error.pc(), 68);
+        let error = VMError::MemoryFault { pc: 4, reason: "Address 9 is misaligned".to_string() };
+        assert_eq!(error.to_string(), "Memory fault at 4: Address 9 is misaligned");
+    }
+}
@@ -0,0 +1,17 @@
+use crate::vm::errors::VMError;
+
+/// Something that happened to a VM, sent to whoever subscribed with `VM::subscribe`, such as a REPL watching a VM
+/// that runs on another thread
+#[derive(Debug, Clone, PartialEq)]
+pub enum VMEvent {
+    /// A run started, or resumed after being stopped
+    Started,
+    /// The program finished, with the code it passed to the exit syscall or 0
+    Halted { code: i32 },
+    /// A run was stopped by a budget, time limit, cancellation, pause or watchdog, and can be resumed
+    Stopped { reason: VMError },
+    /// The program reached a breakpoint, and stopped before running the instruction there. It can be resumed.
+    Breakpoint { pc: usize },
+    /// The program failed and can't carry on
+    Crashed { error: VMError },
+}
@@ -0,0 +1,143 @@
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+
+/// Lowest file descriptor handed out, leaving 0 to 2 for the standard streams as on Unix
+const FIRST_FD: i32 = 3;
+
+/// How a file is opened by the open syscall
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OpenMode {
+    Read,
+    /// Creates the file, or empties it if it exists
+    Write,
+    /// Creates the file, or adds to the end of it if it exists
+    Append,
+}
+
+impl OpenMode {
+    /// Converts the mode number given to the open syscall: 0 to read, 1 to write and 2 to append
+    pub fn from_number(number: i32) -> Option<OpenMode> {
+        match number {
+            0 => Some(OpenMode::Read),
+            1 => Some(OpenMode::Write),
+            2 => Some(OpenMode::Append),
+            _ => None,
+        }
+    }
+}
+
+/// The host files a program has open, by file descriptor. Embedders can turn filesystem access off, in which case
+/// every open fails.
+#[derive(Debug)]
+pub struct FileTable {
+    files: HashMap<i32, File>,
+    next_fd: i32,
+    enabled: bool,
+}
+
+impl Default for FileTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FileTable {
+    pub fn new() -> FileTable {
+        FileTable {
+            files: HashMap::new(),
+            next_fd: FIRST_FD,
+            enabled: true,
+        }
+    }
+
+    /// Allows or denies access to the host's files. Files that are already open stay usable.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Opens a file and returns its file descriptor
+    pub fn open(&mut self, path: &str, mode: OpenMode) -> Result<i32, String> {
+        if !self.enabled {
+            return Err("Filesystem access is disabled".to_string());
+        }
+
+        let mut options = OpenOptions::new();
+        match mode {
+            OpenMode::Read => options.read(true),
+            OpenMode::Write => options.write(true).create(true).truncate(true),
+            OpenMode::Append => options.append(true).create(true),
+        };
+
+        let file = options.open(path).map_err(|e| format!("Unable to open {}: {}", path, e))?;
+        let fd = self.next_fd;
+        self.next_fd += 1;
+        self.files.insert(fd, file);
+        Ok(fd)
+    }
+
+    /// Reads up to `buffer.len()` bytes and returns how many were read, which is 0 at the end of the file
+    pub fn read(&mut self, fd: i32, buffer: &mut [u8]) -> Result<usize, String> {
+        let file = self.files.get_mut(&fd).ok_or_else(|| format!("Bad file descriptor: {}", fd))?;
+        file.read(buffer).map_err(|e| e.to_string())
+    }
+
+    /// Writes all of `data` and returns how many bytes that was
+    pub fn write(&mut self, fd: i32, data: &[u8]) -> Result<usize, String> {
+        let file = self.files.get_mut(&fd).ok_or_else(|| format!("Bad file descriptor: {}", fd))?;
+        file.write_all(data).map(|_| data.len()).map_err(|e| e.to_string())
+    }
+
+    pub fn close(&mut self, fd: i32) -> Result<(), String> {
+        self.files.remove(&fd).map(|_| ()).ok_or_else(|| format!("Bad file descriptor: {}", fd))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+
+    #[test]
+    fn test_write_then_read() {
+        let path = env::temp_dir().join("iridium_files_test_write_then_read.txt");
+        let path = path.to_str().unwrap();
+        let mut files = FileTable::new();
+
+        let fd = files.open(path, OpenMode::Write).unwrap();
+        assert_eq!(fd, FIRST_FD);
+        assert_eq!(files.write(fd, b"hello"), Ok(5));
+        files.close(fd).unwrap();
+
+        let fd = files.open(path, OpenMode::Append).unwrap();
+        files.write(fd, b"!").unwrap();
+        files.close(fd).unwrap();
+
+        let fd = files.open(path, OpenMode::Read).unwrap();
+        let mut buffer = [0; 16];
+        assert_eq!(files.read(fd, &mut buffer), Ok(6));
+        assert_eq!(&buffer[..6], b"hello!");
+        assert_eq!(files.read(fd, &mut buffer), Ok(0));
+        files.close(fd).unwrap();
+
+        assert!(files.close(fd).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut files = FileTable::new();
+        files.set_enabled(false);
+        assert!(files.open("Cargo.toml", OpenMode::Read).is_err());
+    }
+
+    #[test]
+    fn test_open_mode() {
+        assert_eq!(OpenMode::from_number(2), Some(OpenMode::Append));
+        assert_eq!(OpenMode::from_number(3), None);
+    }
+}
@@ -0,0 +1,94 @@
+/// Set when the result was zero, or when a floating point comparison held
+pub const FLAG_ZERO: u8 = 1;
+/// Set when the result was negative
+pub const FLAG_NEGATIVE: u8 = 1 << 1;
+/// Set when the operation carried out of, or borrowed into, the top bit when treated as unsigned
+pub const FLAG_CARRY: u8 = 1 << 2;
+/// Set when the result didn't fit in an i32
+pub const FLAG_OVERFLOW: u8 = 1 << 3;
+
+/// The condition flags, which arithmetic and comparison opcodes update and the conditional jumps test
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Flags {
+    bits: u8,
+}
+
+impl Flags {
+    pub fn new() -> Flags {
+        Flags::default()
+    }
+
+    pub fn from_bits(bits: u8) -> Flags {
+        Flags { bits }
+    }
+
+    /// Flags describing the result of an integer operation
+    pub fn from_result(result: i32, carry: bool, overflow: bool) -> Flags {
+        let mut flags = Flags::new();
+        flags.set(FLAG_ZERO, result == 0);
+        flags.set(FLAG_NEGATIVE, result < 0);
+        flags.set(FLAG_CARRY, carry);
+        flags.set(FLAG_OVERFLOW, overflow);
+        flags
+    }
+
+    /// Flags for a comparison that either held or didn't, such as the floating point comparisons. Only the zero flag
+    /// is set, so JZ and JMPE jump when the comparison held.
+    pub fn from_condition(holds: bool) -> Flags {
+        let mut flags = Flags::new();
+        flags.set(FLAG_ZERO, holds);
+        flags
+    }
+
+    pub fn bits(self) -> u8 {
+        self.bits
+    }
+
+    pub fn is_set(self, flag: u8) -> bool {
+        self.bits & flag != 0
+    }
+
+    pub fn set(&mut self, flag: u8, value: bool) {
+        if value {
+            self.bits |= flag;
+        } else {
+            self.bits &= !flag;
+        }
+    }
+}
+
+impl std::fmt::Display for Flags {
+    /// Shows the flags as ZNCO, with a dash for each flag that is clear
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let names = [(FLAG_ZERO, 'Z'), (FLAG_NEGATIVE, 'N'), (FLAG_CARRY, 'C'), (FLAG_OVERFLOW, 'O')];
+        for (flag, name) in names.iter() {
+            write!(f, "{}", if self.is_set(*flag) { *name } else { '-' })?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_result() {
+        let flags = Flags::from_result(0, false, false);
+        assert!(flags.is_set(FLAG_ZERO));
+        assert!(!flags.is_set(FLAG_NEGATIVE));
+
+        let flags = Flags::from_result(-1, true, true);
+        assert_eq!(flags.bits(), FLAG_NEGATIVE | FLAG_CARRY | FLAG_OVERFLOW);
+        assert_eq!(flags.to_string(), "-NCO");
+    }
+
+    #[test]
+    fn test_set() {
+        let mut flags = Flags::from_condition(true);
+        assert_eq!(flags.bits(), FLAG_ZERO);
+        flags.set(FLAG_CARRY, true);
+        flags.set(FLAG_ZERO, false);
+        assert_eq!(flags, Flags::from_bits(FLAG_CARRY));
+    }
+}
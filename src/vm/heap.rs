@@ -0,0 +1,347 @@
+use crate::encoding::{read_heap_word, write_heap_word};
+use crate::vm::devices::MMIO_BASE;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+
+/// Counters describing how a program has used the heap
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct HeapStats {
+    /// Number of allocations made over the lifetime of the heap
+    pub total_allocations: usize,
+    /// Number of allocations that have been freed
+    pub total_frees: usize,
+    /// Bytes in blocks that are currently allocated
+    pub bytes_in_use: usize,
+    /// Largest value `bytes_in_use` has reached
+    pub peak_bytes_in_use: usize,
+}
+
+/// Heap memory for a VM. Blocks are handed out first-fit from a list of freed blocks, and the heap only grows when
+/// none of them are big enough. It can be given a limit it won't grow past, and never grows into the device range
+/// starting at `MMIO_BASE`.
+#[derive(Debug, Default, Clone)]
+pub struct Heap {
+    /// The memory itself
+    memory: Vec<u8>,
+    /// Allocated blocks, as address -> size
+    allocations: BTreeMap<usize, usize>,
+    /// Freed blocks available for reuse, as address -> size. Adjacent blocks are merged.
+    free_blocks: BTreeMap<usize, usize>,
+    stats: HeapStats,
+    /// Size the heap may not grow past, if it is limited
+    limit: Option<usize>,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::default()
+    }
+
+    /// Allocates a zeroed block of `size` bytes and returns its address. Zero-sized requests get a one byte block so
+    /// every allocation has an address of its own. Returns None if the heap would have to grow past its limit.
+    pub fn allocate(&mut self, size: usize) -> Option<usize> {
+        let size = size.max(1);
+        let reusable = self
+            .free_blocks
+            .iter()
+            .find(|(_, block_size)| **block_size >= size)
+            .map(|(address, block_size)| (*address, *block_size));
+
+        let address = match reusable {
+            Some((address, block_size)) => {
+                self.free_blocks.remove(&address);
+                if block_size > size {
+                    self.free_blocks.insert(address + size, block_size - size);
+                }
+                for byte in &mut self.memory[address..address + size] {
+                    *byte = 0;
+                }
+                address
+            }
+            None => self.grow(size)?,
+        };
+
+        self.allocations.insert(address, size);
+        self.stats.total_allocations += 1;
+        self.stats.bytes_in_use += size;
+        self.stats.peak_bytes_in_use = self.stats.peak_bytes_in_use.max(self.stats.bytes_in_use);
+        Some(address)
+    }
+
+    /// Adds `size` zeroed bytes to the end of the heap without allocating them, like `sbrk`. Returns the address of
+    /// the first new byte, or None if the heap would grow past its limit.
+    pub fn grow(&mut self, size: usize) -> Option<usize> {
+        let address = self.memory.len();
+        let new_length = address.checked_add(size)?;
+        if new_length > MMIO_BASE || self.limit.is_some_and(|limit| new_length > limit) {
+            return None;
+        }
+
+        self.memory.resize(new_length, 0);
+        Some(address)
+    }
+
+    pub fn limit(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Sets the size the heap may not grow past, or removes the limit. Memory already in the heap is kept.
+    pub fn set_limit(&mut self, limit: Option<usize>) {
+        self.limit = limit;
+    }
+
+    /// Releases the block starting at `address`, returning its size, or None if no block starts there
+    pub fn free(&mut self, address: usize) -> Option<usize> {
+        let size = self.allocations.remove(&address)?;
+        self.stats.total_frees += 1;
+        self.stats.bytes_in_use -= size;
+
+        let mut start = address;
+        let mut end = address + size;
+
+        // Merge with the free block directly before this one, if there is one
+        let previous = self.free_blocks.range(..address).next_back().map(|(a, s)| (*a, *s));
+        if let Some((previous_address, previous_size)) = previous {
+            if previous_address + previous_size == start {
+                self.free_blocks.remove(&previous_address);
+                start = previous_address;
+            }
+        }
+
+        // ...and with the one directly after it
+        if let Some(next_size) = self.free_blocks.remove(&end) {
+            end += next_size;
+        }
+
+        self.free_blocks.insert(start, end - start);
+        Some(size)
+    }
+
+    /// Returns `length` bytes starting at `address`, or None if any of them are out of bounds
+    pub fn slice(&self, address: usize, length: usize) -> Option<&[u8]> {
+        let end = address.checked_add(length)?;
+        self.memory.get(address..end)
+    }
+
+    /// Mutable version of `slice`
+    pub fn slice_mut(&mut self, address: usize, length: usize) -> Option<&mut [u8]> {
+        let end = address.checked_add(length)?;
+        self.memory.get_mut(address..end)
+    }
+
+    /// Stores `new` in the word at `address` if it currently holds `expected`. Returns the value the word held
+    /// before, which equals `expected` when the swap happened. The address must be in bounds.
+    ///
+    /// It's atomic only with respect to the VM that owns the heap: no instruction, trap or other green thread of that
+    /// VM can run between the read and the write. It isn't a `std::sync::atomic` operation and is no safer across OS
+    /// threads than any other write, which is fine while a heap belongs to one VM on one thread. This and `fetch_add`
+    /// are the heap's only read-modify-write operations, so they're what has to become truly atomic if a heap is ever
+    /// shared between threads.
+    pub fn compare_and_swap(&mut self, address: usize, expected: i32, new: i32) -> i32 {
+        let word = &mut self.memory[address..address + 4];
+        let current = read_heap_word(word);
+        if current == expected {
+            write_heap_word(word, new);
+        }
+        current
+    }
+
+    /// Adds `amount` to the word at `address`, wrapping on overflow, and returns the value it held before. The address
+    /// must be in bounds. Like `compare_and_swap`, it's atomic only with respect to the single VM thread that owns the
+    /// heap, not across OS threads.
+    pub fn fetch_add(&mut self, address: usize, amount: i32) -> i32 {
+        let word = &mut self.memory[address..address + 4];
+        let current = read_heap_word(word);
+        write_heap_word(word, current.wrapping_add(amount));
+        current
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        &self.memory
+    }
+
+    pub fn len(&self) -> usize {
+        self.memory.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.memory.is_empty()
+    }
+
+    pub fn stats(&self) -> &HeapStats {
+        &self.stats
+    }
+
+    /// Appends everything needed to rebuild the heap with `decode`, including which blocks are allocated, to a VM
+    /// snapshot
+    pub fn encode(&self, out: &mut Vec<u8>) {
+        out.write_u64::<LittleEndian>(self.memory.len() as u64).unwrap();
+        out.extend_from_slice(&self.memory);
+        for blocks in &[&self.allocations, &self.free_blocks] {
+            out.write_u64::<LittleEndian>(blocks.len() as u64).unwrap();
+            for (address, size) in blocks.iter() {
+                out.write_u64::<LittleEndian>(*address as u64).unwrap();
+                out.write_u64::<LittleEndian>(*size as u64).unwrap();
+            }
+        }
+        for counter in &[self.stats.total_allocations, self.stats.total_frees, self.stats.bytes_in_use, self.stats.peak_bytes_in_use] {
+            out.write_u64::<LittleEndian>(*counter as u64).unwrap();
+        }
+        // u64::MAX stands for no limit
+        out.write_u64::<LittleEndian>(self.limit.map_or(u64::MAX, |limit| limit as u64)).unwrap();
+    }
+
+    /// Reads a heap written by `encode`, leaving `input` just after it
+    pub fn decode(input: &mut &[u8]) -> io::Result<Heap> {
+        let read_usize = |input: &mut &[u8]| input.read_u64::<LittleEndian>().map(|value| value as usize);
+
+        let length = read_usize(input)?;
+        if length > input.len() {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "heap is longer than the snapshot"));
+        }
+        let mut memory = vec![0; length];
+        input.read_exact(&mut memory)?;
+
+        let mut maps = vec![];
+        for _ in 0..2 {
+            let mut blocks = BTreeMap::new();
+            for _ in 0..read_usize(input)? {
+                let address = read_usize(input)?;
+                blocks.insert(address, read_usize(input)?);
+            }
+            maps.push(blocks);
+        }
+        let free_blocks = maps.pop().unwrap();
+        let allocations = maps.pop().unwrap();
+
+        let stats = HeapStats {
+            total_allocations: read_usize(input)?,
+            total_frees: read_usize(input)?,
+            bytes_in_use: read_usize(input)?,
+            peak_bytes_in_use: read_usize(input)?,
+        };
+        let limit = match input.read_u64::<LittleEndian>()? {
+            u64::MAX => None,
+            limit => Some(limit as usize),
+        };
+
+        Ok(Heap { memory, allocations, free_blocks, stats, limit })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allocate_grows_heap() {
+        let mut heap = Heap::new();
+        assert_eq!(heap.allocate(16), Some(0));
+        assert_eq!(heap.allocate(8), Some(16));
+        assert_eq!(heap.len(), 24);
+    }
+
+    #[test]
+    fn test_freed_blocks_are_reused() {
+        let mut heap = Heap::new();
+        let first = heap.allocate(16).unwrap();
+        heap.allocate(8);
+        heap.slice_mut(first, 1).unwrap()[0] = 42;
+        assert_eq!(heap.free(first), Some(16));
+        assert_eq!(heap.allocate(4), Some(first));
+        assert_eq!(heap.slice(first, 1), Some(&[0][..]));
+        assert_eq!(heap.allocate(12), Some(4));
+        assert_eq!(heap.len(), 24);
+    }
+
+    #[test]
+    fn test_adjacent_free_blocks_are_merged() {
+        let mut heap = Heap::new();
+        let a = heap.allocate(8).unwrap();
+        let b = heap.allocate(8).unwrap();
+        let c = heap.allocate(8).unwrap();
+        heap.allocate(8);
+        heap.free(a);
+        heap.free(c);
+        heap.free(b);
+        assert_eq!(heap.allocate(24), Some(a));
+        assert_eq!(heap.len(), 32);
+    }
+
+    #[test]
+    fn test_free_unknown_address() {
+        let mut heap = Heap::new();
+        let a = heap.allocate(8).unwrap();
+        assert_eq!(heap.free(a + 1), None);
+        assert_eq!(heap.free(a), Some(8));
+        assert_eq!(heap.free(a), None);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut heap = Heap::new();
+        let a = heap.allocate(8).unwrap();
+        heap.allocate(4);
+        heap.free(a);
+        let stats = heap.stats();
+        assert_eq!(stats.total_allocations, 2);
+        assert_eq!(stats.total_frees, 1);
+        assert_eq!(stats.bytes_in_use, 4);
+        assert_eq!(stats.peak_bytes_in_use, 12);
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let mut heap = Heap::new();
+        let address = heap.allocate(8).unwrap();
+        assert_eq!(heap.compare_and_swap(address + 4, 0, 7), 0);
+        assert_eq!(heap.slice(address + 4, 4).unwrap(), &[7, 0, 0, 0]);
+        assert_eq!(heap.compare_and_swap(address + 4, 0, 9), 7);
+        assert_eq!(heap.slice(address + 4, 4).unwrap(), &[7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_fetch_add() {
+        let mut heap = Heap::new();
+        let address = heap.allocate(4).unwrap();
+        assert_eq!(heap.fetch_add(address, 5), 0);
+        assert_eq!(heap.fetch_add(address, -2), 5);
+        assert_eq!(heap.slice(address, 4).unwrap(), &[3, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_encode_and_decode() {
+        let mut heap = Heap::new();
+        heap.set_limit(Some(64));
+        let first = heap.allocate(8).unwrap();
+        heap.allocate(8).unwrap();
+        heap.free(first);
+        heap.slice_mut(8, 2).unwrap().copy_from_slice(&[1, 2]);
+
+        let mut bytes = vec![];
+        heap.encode(&mut bytes);
+        bytes.push(99);
+        let mut input = &bytes[..];
+        let mut decoded = Heap::decode(&mut input).unwrap();
+        assert_eq!(input, &[99]);
+        assert_eq!(decoded.as_slice(), heap.as_slice());
+        assert_eq!(decoded.stats(), heap.stats());
+        assert_eq!(decoded.allocate(4), Some(0));
+        assert!(decoded.grow(64).is_none());
+
+        assert!(Heap::decode(&mut &bytes[..10]).is_err());
+    }
+
+    #[test]
+    fn test_limit() {
+        let mut heap = Heap::new();
+        heap.set_limit(Some(16));
+        assert_eq!(heap.allocate(12), Some(0));
+        assert_eq!(heap.allocate(8), None);
+        assert_eq!(heap.grow(4), Some(12));
+        assert_eq!(heap.grow(1), None);
+        assert_eq!(heap.len(), 16);
+    }
+}
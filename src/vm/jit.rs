@@ -0,0 +1,339 @@
+use crate::encoding::read_code_u16;
+use crate::instruction::{Opcode, SHIFT_REGISTER};
+use crate::vm::flags::{FLAG_CARRY, FLAG_NEGATIVE, FLAG_OVERFLOW, FLAG_ZERO};
+
+use cranelift_codegen::ir::condcodes::IntCC;
+use cranelift_codegen::ir::{types, AbiParam, InstBuilder, MemFlagsData, Value};
+use cranelift_codegen::settings::{self, Configurable};
+use cranelift_codegen::Context;
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Module};
+use std::mem;
+use std::ops::Range;
+
+/// Times execution has to reach an instruction before the block starting there is compiled, unless told otherwise
+pub const DEFAULT_JIT_THRESHOLD: u32 = 1000;
+
+/// Most instructions compiled into one block
+pub const MAX_BLOCK_INSTRUCTIONS: usize = 256;
+
+/// Native code for a block, called with the integer registers and the flag bits, both of which it may update
+type BlockFunction = unsafe extern "C" fn(*mut i32, *mut u8);
+
+/// A block of instructions compiled to native code
+#[derive(Debug, Clone, Copy)]
+struct CompiledBlock {
+    function: BlockFunction,
+    /// Offset of the instruction after the block
+    end: usize,
+    instructions: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Entry {
+    /// Not compiled yet, with the number of times execution has reached it
+    Cold(u32),
+    /// Compiled, as an index into the compiled blocks
+    Compiled(usize),
+    /// Starts with an instruction the JIT doesn't compile
+    Interpreted,
+}
+
+/// What running a compiled block did
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BlockRun {
+    /// Offset of the instruction after the block, where the program carries on
+    pub end: usize,
+    /// Instructions the block stood in for
+    pub instructions: u64,
+}
+
+/// Compiles hot blocks of bytecode to native code with Cranelift, for programs doing long runs of arithmetic. A
+/// block is a run of LOAD, LUI, ORI, ADD, SUB, MUL, SHL and SHR by an immediate, and NOP, ending before the first
+/// other instruction, which the interpreter runs as usual. Only the narrow encoding is compiled.
+///
+/// Blocks are thrown away when the program changes length or writes over their code, but their native code is only
+/// freed with the JIT.
+pub struct Jit {
+    module: JITModule,
+    context: Context,
+    builder_context: FunctionBuilderContext,
+    threshold: u32,
+    /// What is known about the block starting at each offset in the program
+    entries: Vec<Entry>,
+    blocks: Vec<CompiledBlock>,
+}
+
+impl Jit {
+    /// Sets up a JIT that compiles a block once execution has reached it `threshold` times. Fails if Cranelift
+    /// doesn't support the host.
+    pub fn new(threshold: u32) -> Result<Jit, String> {
+        let mut flags = settings::builder();
+        flags.set("use_colocated_libcalls", "false").map_err(|e| e.to_string())?;
+        flags.set("is_pic", "false").map_err(|e| e.to_string())?;
+        flags.set("opt_level", "speed").map_err(|e| e.to_string())?;
+        let isa = cranelift_native::builder()?.finish(settings::Flags::new(flags)).map_err(|e| e.to_string())?;
+
+        let module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+        Ok(Jit {
+            context: module.make_context(),
+            module,
+            builder_context: FunctionBuilderContext::new(),
+            threshold,
+            entries: vec![],
+            blocks: vec![],
+        })
+    }
+
+    /// Runs the compiled block starting at `pc`, if there is one, on the registers and flag bits. Otherwise counts
+    /// that execution reached `pc`, compiling the block there once it's hot, and returns None for the interpreter
+    /// to run the instruction.
+    pub fn run(&mut self, program: &[u8], pc: usize, registers: &mut [i32; 32], flags: &mut u8) -> Option<BlockRun> {
+        if self.entries.len() != program.len() {
+            self.entries = vec![Entry::Cold(0); program.len()];
+        }
+
+        let index = match self.entries.get_mut(pc)? {
+            Entry::Compiled(index) => *index,
+            Entry::Interpreted => return None,
+            Entry::Cold(count) => {
+                *count += 1;
+                if *count < self.threshold {
+                    return None;
+                }
+                self.compile_at(program, pc)?
+            }
+        };
+
+        let block = self.blocks[index];
+        // The block only reads and writes the 32 registers and the flag bits, as it only holds instructions whose
+        // register operands are all below 32
+        unsafe { (block.function)(registers.as_mut_ptr(), flags) };
+        Some(BlockRun { end: block.end, instructions: block.instructions })
+    }
+
+    /// Forgets every compiled block, such as when the program has been replaced
+    pub fn forget(&mut self) {
+        self.entries.clear();
+    }
+
+    /// Forgets the compiled blocks holding any of the bytes in `written`, after the program wrote over its code
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        let blocks = &self.blocks;
+        for (start, entry) in self.entries.iter_mut().enumerate() {
+            if let Entry::Compiled(index) = *entry {
+                if start < written.end && written.start < blocks[index].end {
+                    *entry = Entry::Cold(0);
+                }
+            }
+        }
+    }
+
+    /// Compiles the block starting at `pc`, returning its index, or marks it to be left to the interpreter
+    fn compile_at(&mut self, program: &[u8], pc: usize) -> Option<usize> {
+        let instructions: Vec<[u8; 4]> = program[pc..]
+            .chunks_exact(4)
+            .take(MAX_BLOCK_INSTRUCTIONS)
+            .map(|bytes| [bytes[0], bytes[1], bytes[2], bytes[3]])
+            .take_while(|instruction| is_compilable(*instruction))
+            .collect();
+
+        let function = if instructions.is_empty() { None } else { self.compile(&instructions).ok() };
+        let function = match function {
+            Some(function) => function,
+            None => {
+                self.entries[pc] = Entry::Interpreted;
+                return None;
+            }
+        };
+
+        let index = self.blocks.len();
+        let count = instructions.len();
+        self.blocks.push(CompiledBlock { function, end: pc + 4 * count, instructions: count as u64 });
+        self.entries[pc] = Entry::Compiled(index);
+        Some(index)
+    }
+
+    fn compile(&mut self, instructions: &[[u8; 4]]) -> Result<BlockFunction, String> {
+        let target = self.module.target_config();
+        let pointer = target.pointer_type();
+        let mut signature = self.module.make_signature();
+        signature.params.push(AbiParam::new(pointer));
+        signature.params.push(AbiParam::new(pointer));
+        let id = self.module.declare_anonymous_function(&signature).map_err(|e| e.to_string())?;
+
+        self.context.func.signature = signature;
+        let mut builder = FunctionBuilder::new(&mut self.context.func, &mut self.builder_context);
+        let entry = builder.create_block();
+        builder.append_block_params_for_function_params(entry);
+        builder.switch_to_block(entry);
+        builder.seal_block(entry);
+        let registers = builder.block_params(entry)[0];
+        let flags_address = builder.block_params(entry)[1];
+
+        let memory = MemFlagsData::trusted();
+        let offset = |register: u8| i32::from(register) * 4;
+        let mut flags = None;
+        for instruction in instructions {
+            let [opcode, first, second, third] = *instruction;
+            let immediate = i64::from(read_code_u16(&[second, third]));
+            let result = match Opcode::from_byte(opcode) {
+                Opcode::LOAD => builder.ins().iconst(types::I32, immediate),
+                Opcode::LUI => builder.ins().iconst(types::I32, i64::from((immediate << 16) as i32)),
+                Opcode::ORI => {
+                    let value = builder.ins().load(types::I32, memory, registers, offset(first));
+                    builder.ins().bor_imm_u(value, immediate)
+                }
+                Opcode::SHL | Opcode::SHR if second >= 32 => builder.ins().iconst(types::I32, 0),
+                Opcode::SHL => {
+                    let value = builder.ins().load(types::I32, memory, registers, offset(first));
+                    builder.ins().ishl_imm_u(value, i64::from(second))
+                }
+                Opcode::SHR => {
+                    let value = builder.ins().load(types::I32, memory, registers, offset(first));
+                    builder.ins().ushr_imm_u(value, i64::from(second))
+                }
+                Opcode::ADD | Opcode::SUB | Opcode::MUL => {
+                    let a = builder.ins().load(types::I32, memory, registers, offset(first));
+                    let b = builder.ins().load(types::I32, memory, registers, offset(second));
+                    let (result, carry, overflow) = match Opcode::from_byte(opcode) {
+                        Opcode::ADD => {
+                            let (result, overflow) = builder.ins().sadd_overflow(a, b);
+                            let (_, carry) = builder.ins().uadd_overflow(a, b);
+                            (result, carry, overflow)
+                        }
+                        Opcode::SUB => {
+                            let (result, overflow) = builder.ins().ssub_overflow(a, b);
+                            let borrow = builder.ins().icmp(IntCC::UnsignedLessThan, a, b);
+                            (result, borrow, overflow)
+                        }
+                        _ => {
+                            let (result, overflow) = builder.ins().smul_overflow(a, b);
+                            (result, overflow, overflow)
+                        }
+                    };
+                    flags = Some(flag_bits(&mut builder, result, carry, overflow));
+                    builder.ins().store(memory, result, registers, offset(third));
+                    continue;
+                }
+                _ => continue,
+            };
+            builder.ins().store(memory, result, registers, offset(first));
+        }
+
+        // Only the last instruction to set the flags decides what they end up as
+        if let Some(flags) = flags {
+            builder.ins().store(memory, flags, flags_address, 0);
+        }
+        builder.ins().return_(&[]);
+        builder.finalize(target);
+
+        self.module.define_function(id, &mut self.context).map_err(|e| e.to_string())?;
+        self.module.clear_context(&mut self.context);
+        self.module.finalize_definitions().map_err(|e| e.to_string())?;
+
+        let code = self.module.get_finalized_function(id);
+        Ok(unsafe { mem::transmute::<*const u8, BlockFunction>(code) })
+    }
+}
+
+/// Whether the JIT compiles the instruction. Register operands must name one of the 32 registers, so the native
+/// code never reaches outside of them.
+fn is_compilable(instruction: [u8; 4]) -> bool {
+    let [opcode, first, second, third] = instruction;
+    match Opcode::from_byte(opcode) {
+        Opcode::LOAD | Opcode::LUI | Opcode::ORI => first < 32,
+        Opcode::SHL | Opcode::SHR => first < 32 && third != SHIFT_REGISTER,
+        Opcode::ADD | Opcode::SUB | Opcode::MUL => first < 32 && second < 32 && third < 32,
+        Opcode::NOP => true,
+        _ => false,
+    }
+}
+
+/// The flag bits for an integer result, the same way `Flags::from_result` works them out
+fn flag_bits(builder: &mut FunctionBuilder, result: Value, carry: Value, overflow: Value) -> Value {
+    let zero = builder.ins().icmp_imm_s(IntCC::Equal, result, 0);
+    let negative = builder.ins().icmp_imm_s(IntCC::SignedLessThan, result, 0);
+    let mut bits = builder.ins().imul_imm_u(zero, i64::from(FLAG_ZERO));
+    for (flag, value) in [(FLAG_NEGATIVE, negative), (FLAG_CARRY, carry), (FLAG_OVERFLOW, overflow)] {
+        let bit = builder.ins().imul_imm_u(value, i64::from(flag));
+        bits = builder.ins().bor(bits, bit);
+    }
+    bits
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::random::Xorshift;
+    use crate::vm::VM;
+
+    /// A straight-line run of the instructions the JIT compiles, with operands picked by `random`
+    fn random_block(random: &mut Xorshift, length: usize) -> Vec<u8> {
+        let opcodes = [Opcode::LOAD, Opcode::LUI, Opcode::ORI, Opcode::ADD, Opcode::SUB, Opcode::MUL, Opcode::SHL];
+        let mut block = vec![];
+        for _ in 0..length {
+            let opcode = opcodes[random.next_u32() as usize % opcodes.len()];
+            let register = |random: &mut Xorshift| (random.next_u32() % 4) as u8;
+            match opcode {
+                Opcode::SHL => block.extend([opcode.into(), register(random), (random.next_u32() % 40) as u8, 0]),
+                Opcode::ADD | Opcode::SUB | Opcode::MUL => {
+                    block.extend([opcode.into(), register(random), register(random), register(random)])
+                }
+                _ => block.extend([opcode.into(), register(random), random.next_u32() as u8, random.next_u32() as u8]),
+            }
+        }
+        block
+    }
+
+    /// Runs `program` with the JIT compiling blocks after they've been reached `threshold` times, or interpreted
+    fn run(program: &[u8], threshold: Option<u32>) -> VM {
+        let mut vm = VM::new();
+        if let Some(threshold) = threshold {
+            vm.set_jit(Some(threshold)).unwrap();
+        }
+        vm.program = program.to_vec();
+        vm.run().unwrap();
+        vm
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let mut random = Xorshift::new(7);
+        for _ in 0..50 {
+            // Runs a random block 20 times with CLOOP and LOOP, then halts
+            let mut program = vec![Opcode::CLOOP.into(), 0, 20, 0];
+            program.extend(random_block(&mut random, 12));
+            program.extend([Opcode::LOOP.into(), 0, 4, 0, Opcode::HLT.into(), 0, 0, 0]);
+
+            let interpreted = run(&program, None);
+            let compiled = run(&program, Some(2));
+            assert_eq!(compiled.registers, interpreted.registers);
+            assert_eq!(compiled.flags(), interpreted.flags());
+            assert_eq!(compiled.pc(), interpreted.pc());
+            assert_eq!(compiled.instructions_executed, interpreted.instructions_executed);
+            assert!(!compiled.jit.unwrap().blocks.is_empty());
+        }
+    }
+
+    #[test]
+    fn test_overwritten_block() {
+        // The block at 4 adds $1 to $0, then WCODE turns its ADD into a SUB the next time around
+        let mut program = vec![Opcode::CLOOP.into(), 0, 6, 0];
+        program.extend([Opcode::ADD.into(), 0, 1, 0]);
+        program.extend([Opcode::WCODE.into(), 2, 3, 0]);
+        program.extend([Opcode::LOOP.into(), 0, 4, 0, Opcode::HLT.into(), 0, 0, 0]);
+
+        let mut vm = VM::new();
+        vm.set_jit(Some(2)).unwrap();
+        vm.program = program;
+        vm.set_writable_code(Some(4..8));
+        vm.registers[1] = 10;
+        vm.registers[2] = 0x0200_0100;
+        vm.registers[3] = 4;
+        vm.run().unwrap();
+        // One ADD, then five SUBs
+        assert_eq!(vm.registers[0], -40);
+    }
+}
@@ -0,0 +1,138 @@
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::collections::BTreeMap;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// First bytes of a key-value store file
+const STORE_PREFIX: [u8; 4] = *b"IRKV";
+/// Version of the store format, bumped whenever the layout changes
+const STORE_VERSION: u8 = 1;
+
+/// Keys and values the key-value syscalls work on, which programs can use to keep state from one run to the next.
+/// A store opened from a file is written back to it after every change.
+#[derive(Debug, Default)]
+pub struct KeyValueStore {
+    entries: BTreeMap<Vec<u8>, Vec<u8>>,
+    /// File the store is kept in, if it outlives the VM
+    path: Option<PathBuf>,
+}
+
+impl KeyValueStore {
+    /// Creates an empty store that only lasts as long as the VM
+    pub fn in_memory() -> KeyValueStore {
+        KeyValueStore::default()
+    }
+
+    /// Opens the store kept in a file, starting an empty one if the file doesn't exist yet
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<KeyValueStore> {
+        let path = path.as_ref().to_path_buf();
+        let entries = match std::fs::read(&path) {
+            Ok(bytes) => decode(&bytes)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+
+        Ok(KeyValueStore { entries, path: Some(path) })
+    }
+
+    pub fn get(&self, key: &[u8]) -> Option<&[u8]> {
+        self.entries.get(key).map(|value| value.as_slice())
+    }
+
+    /// Stores a value, replacing any the key already had
+    pub fn put(&mut self, key: &[u8], value: &[u8]) -> io::Result<()> {
+        self.entries.insert(key.to_vec(), value.to_vec());
+        self.persist()
+    }
+
+    /// Removes a key, returning true if it was there
+    pub fn delete(&mut self, key: &[u8]) -> io::Result<bool> {
+        let deleted = self.entries.remove(key).is_some();
+        if deleted {
+            self.persist()?;
+        }
+        Ok(deleted)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    fn persist(&self) -> io::Result<()> {
+        match &self.path {
+            Some(path) => std::fs::write(path, encode(&self.entries)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Encodes the entries in key order. Lengths are little-endian and come before the bytes they describe.
+fn encode(entries: &BTreeMap<Vec<u8>, Vec<u8>>) -> Vec<u8> {
+    let mut out = STORE_PREFIX.to_vec();
+    out.push(STORE_VERSION);
+    out.write_u64::<LittleEndian>(entries.len() as u64).unwrap();
+    for (key, value) in entries {
+        for bytes in &[key, value] {
+            out.write_u64::<LittleEndian>(bytes.len() as u64).unwrap();
+            out.extend_from_slice(bytes);
+        }
+    }
+    out
+}
+
+fn decode(bytes: &[u8]) -> io::Result<BTreeMap<Vec<u8>, Vec<u8>>> {
+    if bytes.len() < 5 || bytes[0..4] != STORE_PREFIX || bytes[4] != STORE_VERSION {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not an iridium key-value store"));
+    }
+    let mut input = &bytes[5..];
+
+    let mut entries = BTreeMap::new();
+    for _ in 0..input.read_u64::<LittleEndian>()? {
+        let key = read_bytes(&mut input)?;
+        let value = read_bytes(&mut input)?;
+        entries.insert(key, value);
+    }
+    Ok(entries)
+}
+
+fn read_bytes(input: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let length = input.read_u64::<LittleEndian>()? as usize;
+    if length > input.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "key-value store is truncated"));
+    }
+
+    let mut bytes = vec![0; length];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_persisted_store() {
+        let path = std::env::temp_dir().join("iridium_kv_test_persisted_store.kv");
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = KeyValueStore::open(&path).unwrap();
+        assert!(store.is_empty());
+        store.put(b"count", b"1").unwrap();
+        store.put(b"name", b"iridium").unwrap();
+        store.put(b"count", b"2").unwrap();
+        assert!(store.delete(b"name").unwrap());
+        assert!(!store.delete(b"name").unwrap());
+
+        let store = KeyValueStore::open(&path).unwrap();
+        assert_eq!(store.len(), 1);
+        assert_eq!(store.get(b"count"), Some(&b"2"[..]));
+
+        std::fs::write(&path, b"nope").unwrap();
+        assert!(KeyValueStore::open(&path).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,89 @@
+use crate::assembler::symbols::{SymbolTable, SymbolType};
+use crate::assembler::target::Target;
+use crate::assembler::{code_start, metadata_range, program_target, read_metadata, writable_region, PIE_HEADER_LENGTH};
+
+use std::ops::Range;
+
+/// What the header of a loaded program says about it
+#[derive(Debug, Clone, PartialEq)]
+pub struct ProgramHeader {
+    /// The VM configuration the program was assembled for
+    pub target: Target,
+    /// Offsets of the read-only data, which includes the metadata
+    pub read_only: Range<usize>,
+    /// Offsets of the strings given to `.author`, `.version` and `.description`, if there are any
+    pub metadata: Option<Range<usize>>,
+    /// Offsets of the instructions, from the first one to the end of the program
+    pub code: Range<usize>,
+    /// Offsets WCODE may write to, if the program declared them with `.writable`
+    pub writable: Option<Range<usize>>,
+    /// The metadata as names and values, in the order they were declared
+    pub fields: Vec<(String, String)>,
+}
+
+impl ProgramHeader {
+    /// Reads the header of an assembled program, or returns None if it doesn't start with a valid one
+    pub fn read(program: &[u8]) -> Option<ProgramHeader> {
+        let start = code_start(program)?;
+        Some(ProgramHeader {
+            target: program_target(program)?,
+            read_only: PIE_HEADER_LENGTH..start,
+            metadata: metadata_range(program),
+            code: start..program.len(),
+            writable: writable_region(program),
+            fields: read_metadata(program),
+        })
+    }
+}
+
+/// Describes the program a VM has loaded, returned by `VM::loaded_program`
+#[derive(Debug, Clone)]
+pub struct LoadedProgram<'a> {
+    /// Bytes in the program, including its header
+    pub length: usize,
+    /// The program's header, or None for instructions added without one, such as those typed into the REPL
+    pub header: Option<ProgramHeader>,
+    /// The symbols the program was assembled with, if they were attached with `VM::attach_symbols`
+    pub symbols: Option<&'a SymbolTable>,
+}
+
+impl<'a> LoadedProgram<'a> {
+    /// Whether code addresses can be named, which needs symbols with at least one label among them
+    pub fn has_debug_info(&self) -> bool {
+        self.symbols
+            .is_some_and(|symbols| symbols.symbols.iter().any(|symbol| *symbol.symbol_type() == SymbolType::Label))
+    }
+
+    /// Formats the description as a report, one section or symbol per line
+    pub fn to_lines(&self) -> Vec<String> {
+        let range = |range: &Option<Range<usize>>| match range {
+            Some(range) => format!("{}..{}", range.start, range.end),
+            None => "none".to_string(),
+        };
+
+        let mut lines = vec![format!("{} bytes", self.length)];
+        match &self.header {
+            Some(header) => {
+                lines.push(format!("target: {}", header.target));
+                lines.push(format!("read-only: {}..{}", header.read_only.start, header.read_only.end));
+                lines.push(format!("metadata: {}", range(&header.metadata)));
+                lines.push(format!("code: {}..{}", header.code.start, header.code.end));
+                lines.push(format!("writable: {}", range(&header.writable)));
+                for (name, value) in &header.fields {
+                    lines.push(format!("{}: {}", name, value));
+                }
+            }
+            None => lines.push("no header".to_string()),
+        }
+
+        lines.push(format!("debug info: {}", if self.has_debug_info() { "yes" } else { "no" }));
+        if let Some(symbols) = self.symbols {
+            lines.push("symbols:".to_string());
+            for symbol in &symbols.symbols {
+                let offset = symbol.offset().map_or("?".to_string(), |offset| offset.to_string());
+                lines.push(format!("{:>10}  {:?} {}", offset, symbol.symbol_type(), symbol.name()));
+            }
+        }
+        lines
+    }
+}
@@ -0,0 +1,94 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+
+/// Messages waiting to be received, by mailbox id
+type Mailboxes = HashMap<i32, VecDeque<i32>>;
+
+/// Mailboxes for a group of VMs that message each other with SEND and RECV. Each VM gets one under an id of its own
+/// with `VM::join_post_office`, and clones of a post office share the same mailboxes, so the VMs can run on
+/// different threads.
+#[derive(Debug, Clone, Default)]
+pub struct PostOffice {
+    shared: Arc<(Mutex<Mailboxes>, Condvar)>,
+}
+
+impl PostOffice {
+    pub fn new() -> PostOffice {
+        PostOffice::default()
+    }
+
+    /// Opens an empty mailbox under `id`, returning false if there is one already
+    pub fn open(&self, id: i32) -> bool {
+        let mut mailboxes = self.shared.0.lock().unwrap();
+        if mailboxes.contains_key(&id) {
+            return false;
+        }
+        mailboxes.insert(id, VecDeque::new());
+        true
+    }
+
+    /// Removes a mailbox along with any messages still in it
+    pub fn close(&self, id: i32) {
+        self.shared.0.lock().unwrap().remove(&id);
+    }
+
+    /// Adds a message to the back of a mailbox, returning false if there is no mailbox with that id
+    pub fn send(&self, to: i32, message: i32) -> bool {
+        let (mailboxes, arrived) = &*self.shared;
+        match mailboxes.lock().unwrap().get_mut(&to) {
+            Some(mailbox) => {
+                mailbox.push_back(message);
+                arrived.notify_all();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Takes the oldest message from a mailbox without waiting, or None if it is empty
+    pub fn try_receive(&self, id: i32) -> Option<i32> {
+        self.shared.0.lock().unwrap().get_mut(&id).and_then(|mailbox| mailbox.pop_front())
+    }
+
+    /// Takes the oldest message from a mailbox, waiting up to `timeout` for one to arrive if it is empty
+    pub fn receive_timeout(&self, id: i32, timeout: Duration) -> Option<i32> {
+        let (mailboxes, arrived) = &*self.shared;
+        let mailboxes = mailboxes.lock().unwrap();
+        let (mut mailboxes, _) = arrived
+            .wait_timeout_while(mailboxes, timeout, |mailboxes| mailboxes.get(&id).is_some_and(|mailbox| mailbox.is_empty()))
+            .unwrap();
+        mailboxes.get_mut(&id).and_then(|mailbox| mailbox.pop_front())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn test_send_and_receive() {
+        let office = PostOffice::new();
+        assert!(office.open(1));
+        assert!(!office.open(1));
+        assert!(!office.send(2, 7));
+
+        assert!(office.send(1, 7));
+        assert!(office.send(1, 8));
+        assert_eq!(office.try_receive(1), Some(7));
+        assert_eq!(office.try_receive(1), Some(8));
+        assert_eq!(office.try_receive(1), None);
+
+        let sender = office.clone();
+        let handle = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(10));
+            sender.send(1, 9);
+        });
+        assert_eq!(office.receive_timeout(1, Duration::from_secs(5)), Some(9));
+        handle.join().unwrap();
+
+        office.close(1);
+        assert!(!office.send(1, 10));
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::BTreeSet;
+use std::thread;
+use std::time::Duration;
+
+/// A syscall on its way through the middleware. Middleware may rewrite the number or the registers, which the
+/// syscall then runs with.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyscallCall {
+    pub number: u16,
+    /// Offset of the SYSCALL instruction
+    pub pc: usize,
+    pub registers: [i32; 32],
+}
+
+/// What a middleware decided to do with a syscall before it runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyscallVerdict {
+    /// Pass the syscall on to the next middleware, and then to its handler
+    Allow,
+    /// Carry on without running the syscall, leaving the registers as the middleware set them, such as -1 in $0 to
+    /// make it look like it failed
+    Skip,
+    /// Stop the program with `VMError::SyscallDenied`
+    Deny,
+}
+
+/// Code an embedder puts around the VM's syscalls, for sandboxing, auditing or testing how a program copes with slow
+/// or failing ones. Middleware added with `VM::add_syscall_middleware` sees each syscall in the order it was added
+/// before the syscall runs, and in the reverse order after it has run.
+pub trait SyscallMiddleware {
+    /// Called before the syscall runs. Any middleware added after this one only sees the syscall if this allows it.
+    fn before(&mut self, _call: &mut SyscallCall) -> SyscallVerdict {
+        SyscallVerdict::Allow
+    }
+
+    /// Called once the syscall has run, been skipped or been denied, with the registers it left behind and whether
+    /// it stopped the program. Only middleware whose `before` was called sees this.
+    fn after(&mut self, _call: &SyscallCall, _registers: &[i32; 32], _stop: bool) {}
+}
+
+/// Lets through only the syscalls it has been told to allow, or all but the ones it has been told to deny
+pub struct SyscallFilter {
+    numbers: BTreeSet<u16>,
+    allowing: bool,
+    verdict: SyscallVerdict,
+}
+
+impl SyscallFilter {
+    /// Denies every syscall but these
+    pub fn allowing(numbers: &[u16]) -> SyscallFilter {
+        SyscallFilter { numbers: numbers.iter().copied().collect(), allowing: true, verdict: SyscallVerdict::Deny }
+    }
+
+    /// Denies these syscalls and allows the rest
+    pub fn denying(numbers: &[u16]) -> SyscallFilter {
+        SyscallFilter { numbers: numbers.iter().copied().collect(), allowing: false, verdict: SyscallVerdict::Deny }
+    }
+
+    /// Makes the syscalls that aren't allowed fail with -1 in $0 instead of stopping the program
+    pub fn failing(mut self) -> SyscallFilter {
+        self.verdict = SyscallVerdict::Skip;
+        self
+    }
+}
+
+impl SyscallMiddleware for SyscallFilter {
+    fn before(&mut self, call: &mut SyscallCall) -> SyscallVerdict {
+        if self.numbers.contains(&call.number) == self.allowing {
+            return SyscallVerdict::Allow;
+        }
+        if self.verdict == SyscallVerdict::Skip {
+            call.registers[0] = -1;
+        }
+        self.verdict
+    }
+}
+
+/// Waits before every syscall, or the ones given, as if the host were slow to answer them
+pub struct SyscallLatency {
+    delay: Duration,
+    numbers: Option<BTreeSet<u16>>,
+}
+
+impl SyscallLatency {
+    pub fn new(delay: Duration) -> SyscallLatency {
+        SyscallLatency { delay, numbers: None }
+    }
+
+    /// Only delays these syscalls
+    pub fn only(mut self, numbers: &[u16]) -> SyscallLatency {
+        self.numbers = Some(numbers.iter().copied().collect());
+        self
+    }
+}
+
+impl SyscallMiddleware for SyscallLatency {
+    fn before(&mut self, call: &mut SyscallCall) -> SyscallVerdict {
+        if self.numbers.as_ref().is_none_or(|numbers| numbers.contains(&call.number)) {
+            thread::sleep(self.delay);
+        }
+        SyscallVerdict::Allow
+    }
+}
+
+/// Describes each syscall once it has run, with its first four argument registers and the two registers syscalls
+/// return results in, such as `syscall 3 at 68: $0=0 $1=7 $2=0 $3=0 -> $0=1700000000 $1=7`. Each line is handed to a
+/// function, such as one that writes it to a log file.
+pub struct SyscallLog {
+    write: Box<dyn FnMut(String)>,
+}
+
+impl SyscallLog {
+    pub fn new<F>(write: F) -> SyscallLog
+    where
+        F: FnMut(String) + 'static,
+    {
+        SyscallLog { write: Box::new(write) }
+    }
+}
+
+impl SyscallMiddleware for SyscallLog {
+    fn after(&mut self, call: &SyscallCall, registers: &[i32; 32], stop: bool) {
+        let mut line = format!(
+            "syscall {} at {}: $0={} $1={} $2={} $3={} -> $0={} $1={}",
+            call.number, call.pc, call.registers[0], call.registers[1], call.registers[2], call.registers[3],
+            registers[0], registers[1]
+        );
+        if stop {
+            line.push_str(" (stopped)");
+        }
+        (self.write)(line);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::Assembler;
+    use crate::vm::errors::VMError;
+    use crate::vm::syscalls::{SYS_EXIT, SYS_TIME};
+    use crate::vm::VM;
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// Writes down when it sees each syscall, and rewrites the exit code if told to
+    struct Tracer {
+        name: &'static str,
+        seen: Rc<RefCell<Vec<String>>>,
+        exit_code: Option<i32>,
+    }
+
+    impl SyscallMiddleware for Tracer {
+        fn before(&mut self, call: &mut SyscallCall) -> SyscallVerdict {
+            self.seen.borrow_mut().push(format!("before {}", self.name));
+            if let (SYS_EXIT, Some(code)) = (call.number, self.exit_code) {
+                call.registers[1] = code;
+            }
+            SyscallVerdict::Allow
+        }
+
+        fn after(&mut self, _call: &SyscallCall, _registers: &[i32; 32], _stop: bool) {
+            self.seen.borrow_mut().push(format!("after {}", self.name));
+        }
+    }
+
+    fn vm_for(source: &str) -> VM {
+        let mut vm = VM::new();
+        vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        vm
+    }
+
+    #[test]
+    fn test_middleware_order_and_rewrite() {
+        let seen = Rc::new(RefCell::new(vec![]));
+        let lines = Rc::new(RefCell::new(vec![]));
+        let mut vm = vm_for(".data\n.code\nload $1 #3\nsyscall #0");
+        vm.add_syscall_middleware(Tracer { name: "outer", seen: seen.clone(), exit_code: None });
+        vm.add_syscall_middleware(Tracer { name: "inner", seen: seen.clone(), exit_code: Some(7) });
+        let log = lines.clone();
+        vm.add_syscall_middleware(SyscallLog::new(move |line| log.borrow_mut().push(line)));
+
+        assert_eq!(vm.run().unwrap().exit_code, 7);
+        assert_eq!(*seen.borrow(), vec!["before outer", "before inner", "after inner", "after outer"]);
+        assert_eq!(*lines.borrow(), vec!["syscall 0 at 68: $0=0 $1=7 $2=0 $3=0 -> $0=0 $1=7 (stopped)"]);
+    }
+
+    #[test]
+    fn test_syscall_filter() {
+        let mut vm = vm_for(".data\n.code\nsyscall #3\nhlt");
+        vm.add_syscall_middleware(SyscallFilter::denying(&[SYS_TIME]));
+        assert_eq!(vm.run(), Err(VMError::SyscallDenied { pc: 64, number: SYS_TIME }));
+
+        let seen = Rc::new(RefCell::new(vec![]));
+        let mut vm = vm_for(".data\n.code\nsyscall #3\nhlt");
+        vm.add_syscall_middleware(SyscallFilter::allowing(&[SYS_EXIT]).failing());
+        vm.add_syscall_middleware(Tracer { name: "inner", seen: seen.clone(), exit_code: None });
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], -1);
+        assert!(seen.borrow().is_empty());
+    }
+}
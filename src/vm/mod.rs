@@ -0,0 +1,3685 @@
+use crate::assembler::symbols::SymbolTable;
+use crate::assembler::target::{HeapModel, DEFAULT_REGISTER_WIDTH};
+use crate::assembler::{
+    code_start, is_wide_encoding, program_target, writable_region, PIE_HEADER_LENGTH, WIDE_IMMEDIATE_LENGTH,
+};
+use crate::disassembler::disassemble_instruction;
+use crate::encoding::{read_code_f64, read_code_u16, read_heap_word, write_code_i32, write_heap_word};
+use crate::formatting::NumberFormat;
+use crate::instruction::{Opcode, HLT_WITH_CODE, RAND_BOUNDED, RECV_WAIT, SHIFT_REGISTER};
+use crate::vm::allocations::AllocationTracker;
+use crate::vm::breakpoints::Breakpoint;
+use crate::vm::cancel::CancelHandle;
+use crate::vm::canaries::Canaries;
+use crate::vm::channels::OutputRecord;
+use crate::vm::config::{Dispatch, VMBuilder, VMConfig};
+use crate::vm::devices::{Console, Device, DeviceBus, Timer, CONSOLE_ADDRESS, MMIO_BASE, TIMER_ADDRESS};
+use crate::vm::errors::VMError;
+use crate::vm::events::VMEvent;
+use crate::vm::files::FileTable;
+use crate::vm::flags::{Flags, FLAG_CARRY, FLAG_OVERFLOW, FLAG_ZERO};
+use crate::vm::heap::{Heap, HeapStats};
+use crate::vm::kv::KeyValueStore;
+use crate::vm::loaded::{LoadedProgram, ProgramHeader};
+use crate::vm::mailbox::PostOffice;
+use crate::vm::middleware::{SyscallCall, SyscallMiddleware, SyscallVerdict};
+use crate::vm::output::{OutputBuffer, DEFAULT_OUTPUT_BUFFER};
+use crate::vm::pause::PauseHandle;
+use crate::vm::predecode::{DecodeCache, DecodedInstruction};
+use crate::vm::random::Xorshift;
+use crate::vm::record::{RecordedInput, Recorder, Recording, RECORDED_SYSCALLS};
+use crate::vm::run_result::{HaltedBy, RunResult};
+use crate::vm::scheduler::{Context, Scheduler};
+use crate::vm::snapshot::VMState;
+use crate::vm::sockets::{NetworkPolicy, SocketTable};
+use crate::vm::stats::{HotSpot, Profile, RunStats, HOTTEST_PC_COUNT};
+use crate::vm::stdio::ChannelInput;
+use crate::vm::step::{RegisterChange, StepResult};
+use crate::vm::syscalls::{SyscallHandler, SyscallTable};
+use crate::vm::threaded::ThreadedCode;
+use crate::vm::trace::{TraceEvent, TraceHook};
+use crate::vm::traps::{Trap, TrapFrame, VectorTable};
+use crate::vm::watchdog::Watchdog;
+use crate::vm::watchpoints::{written_registers, writes_heap, Watchpoint, Watchpoints};
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, BufRead, Write};
+use std::ops::Range;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::time::{Duration, Instant};
+
+pub mod affinity;
+pub mod allocations;
+pub mod audit;
+pub mod breakpoints;
+pub mod canaries;
+pub mod cancel;
+pub mod channels;
+pub mod config;
+pub mod devices;
+pub mod errors;
+pub mod events;
+pub mod files;
+pub mod flags;
+pub mod heap;
+#[cfg(feature = "jit")]
+pub mod jit;
+pub mod kv;
+pub mod loaded;
+pub mod mailbox;
+pub mod middleware;
+pub mod output;
+pub mod pause;
+pub mod predecode;
+pub mod random;
+pub mod record;
+pub mod run_result;
+pub mod scheduler;
+pub mod snapshot;
+pub mod sockets;
+pub mod stats;
+pub mod stdio;
+pub mod step;
+pub mod syscalls;
+pub mod threaded;
+pub mod trace;
+pub mod traps;
+pub mod watchdog;
+pub mod watchpoints;
+
+/// Instructions `run_for` executes between looking at the clock
+pub const DEADLINE_CHECK_INTERVAL: u32 = 1024;
+
+/// Longest a RECV waiting for a message blocks the host thread before the VM checks for cancellation and its timer
+pub const RECEIVE_WAIT_SLICE: Duration = Duration::from_millis(10);
+
+/// Instructions start at multiples of this many bytes from the start of the code, or twice it in the wide encoding
+pub const INSTRUCTION_ALIGNMENT: usize = 4;
+
+/// Default limit on how deeply CALLs can be nested
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 1024;
+
+/// A function provided by the program embedding the VM, called by the HCALL opcode with the VM's registers
+pub type HostFunction = Box<dyn FnMut(&mut [i32; 32])>;
+
+pub struct VM {
+    /// Array that simulates having hardware registers
+    pub registers: [i32; 32],
+    /// Second bank of registers for floating point values
+    pub float_registers: [f64; 32],
+    /// Program counter that tracks which byte is being executed
+    pc: usize,
+    /// The bytecode of the program being run
+    pub program: Vec<u8>,
+    /// Contains the remainder of modulo division ops
+    remainder: usize,
+    /// Condition flags from the last arithmetic or comparison operation
+    flags: Flags,
+    /// Represents our heap memory
+    heap: Heap,
+    /// Contains the read-only section data
+    ro_data: Vec<u8>,
+    /// Return addresses of the CALLs that haven't RET'd yet
+    call_stack: Vec<usize>,
+    /// How many return addresses `call_stack` may hold before a CALL is refused
+    max_call_depth: usize,
+    /// Handlers for the SYSCALL opcode
+    syscalls: SyscallTable,
+    /// What syscalls go through on their way to the table, in the order it sees them
+    syscall_middleware: Vec<Box<dyn SyscallMiddleware>>,
+    /// Functions registered by the embedder for the HCALL opcode
+    host_functions: HashMap<u16, HostFunction>,
+    /// Handler addresses for traps, set by the SETTRAP opcode
+    vectors: VectorTable,
+    /// Addresses to resume at, and the flags to restore, when the trap handlers that are running IRET
+    trap_stack: Vec<TrapFrame>,
+    /// Iterations left in the loop started by CLOOP, counted down by LOOP
+    loop_counter: usize,
+    /// Number of instructions between timer traps, if the timer is on
+    timer_interval: Option<u32>,
+    /// Instructions left until the next timer trap
+    timer_remaining: u32,
+    /// Whether the program uses the wide encoding, where each instruction is prefixed with a 32-bit immediate
+    wide: bool,
+    /// The 32-bit immediate of the instruction being executed, when the program uses the wide encoding
+    wide_immediate: i32,
+    /// Operand bytes of the instruction being executed, which `next_8_bits` and `next_16_bits` hand out in turn
+    operands: [u8; 3],
+    /// How many of the operand bytes have been handed out
+    operands_read: usize,
+    /// The part of the program WCODE may write to. Everywhere else, the program can't change its own code.
+    writable_code: Option<Range<usize>>,
+    /// Source of the numbers produced by RAND
+    random: Xorshift,
+    /// Counters for what the program has done, if statistics are turned on
+    stats: Option<RunStats>,
+    /// Host files opened by the file syscalls
+    files: FileTable,
+    /// UDP sockets opened by the socket syscalls
+    sockets: SocketTable,
+    /// Where the input syscalls read from, or None for stdin
+    input: Option<Box<dyn BufRead>>,
+    /// Numbers of the syscalls the program has made
+    syscalls_used: BTreeSet<u16>,
+    /// Addresses in the device range the program has used LW or SW on
+    devices_used: BTreeSet<usize>,
+    /// Set when the program exits through the exit syscall
+    exit_code: Option<i32>,
+    /// Offset of the instruction being executed, which errors are reported at
+    instruction_start: usize,
+    /// Why the last instruction stopped the program, if it failed
+    error: Option<VMError>,
+    /// Lets other threads stop the program between instructions
+    cancel: CancelHandle,
+    /// Lets other threads hold the program between instructions until it's resumed
+    pause: PauseHandle,
+    /// Watches for the program spinning in a small loop, if one has been set
+    watchdog: Option<Watchdog>,
+    /// Where lifecycle events are sent, if anything has subscribed to them
+    events: Option<Sender<VMEvent>>,
+    /// Where records emitted on output channels are sent, if anything has subscribed to them
+    output: Option<Sender<OutputRecord>>,
+    /// What PRTS and the print syscall have printed but hasn't been written to stdout yet
+    stdout_buffer: OutputBuffer,
+    /// Where the buffered output is sent instead of stdout, if the host connected a channel for it
+    stdout_channel: Option<Sender<Vec<u8>>>,
+    /// Called before each instruction, if a tracer has been set
+    trace_hook: Option<TraceHook>,
+    /// Records the program's nondeterministic inputs, or feeds it recorded ones
+    recorder: Recorder,
+    /// Instructions executed since the current run started
+    instructions_executed: u64,
+    /// Instructions executed since the VM was created, which RDCYCLE reads
+    cycles: u64,
+    /// Addresses the program stops at before running the instruction there
+    breakpoints: BTreeMap<usize, Breakpoint>,
+    /// The breakpoint the program last stopped at, so resuming runs the instruction there instead of stopping again
+    paused_at: Option<usize>,
+    /// Registers and heap words the program stops after changing
+    watchpoints: Watchpoints,
+    /// What stopped the program, if it has stopped without failing
+    halted_by: HaltedBy,
+    /// Whether jump targets are checked when the jump is made, rather than only running off the end being caught
+    strict_jumps: bool,
+    /// Canaries after the arrays allocated by calls that haven't returned, if stack canaries are on
+    canaries: Option<Canaries>,
+    /// Devices that LW and SW reach at addresses from `MMIO_BASE` up
+    devices: DeviceBus,
+    /// Store for the key-value syscalls, if the host has given the program one
+    kv_store: Option<KeyValueStore>,
+    /// Green threads started by SPAWN that are waiting for their turn
+    scheduler: Scheduler,
+    /// The post office this VM's mailbox is in, with the mailbox's id, if the host has given it one
+    mailbox: Option<(PostOffice, i32)>,
+    /// How the VM was sized and which optional parts it has
+    config: VMConfig,
+    /// The symbols the program was assembled with, if the host attached them
+    symbols: Option<SymbolTable>,
+    /// Heap statistics for each ALOC, if heap tracking is on
+    allocations: Option<AllocationTracker>,
+    /// The program's instructions decoded ahead of time, while a run is going with predecoding on. Anything that
+    /// changes the program's length drops it, and WCODE decodes the words it writes again.
+    decode_cache: Option<DecodeCache>,
+    /// The program threaded into a handler for each instruction, with threaded dispatch, once it has been threaded
+    /// since the program last changed
+    threaded_code: Option<ThreadedCode>,
+    /// Compiles hot blocks to native code, if the JIT has been turned on
+    #[cfg(feature = "jit")]
+    jit: Option<jit::Jit>,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl VM {
+    /// Starts setting up a VM that isn't configured the default way
+    pub fn builder() -> VMBuilder {
+        VMBuilder::new()
+    }
+
+    pub fn new() -> VM {
+        VM {
+            registers: [0; 32],
+            float_registers: [0.0; 32],
+            program: vec![],
+            pc: 0,
+            remainder: 0,
+            flags: Flags::new(),
+            heap: Heap::new(),
+            ro_data: vec![],
+            call_stack: vec![],
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            syscalls: SyscallTable::new(),
+            syscall_middleware: vec![],
+            host_functions: HashMap::new(),
+            vectors: VectorTable::new(),
+            trap_stack: vec![],
+            loop_counter: 0,
+            timer_interval: None,
+            timer_remaining: 0,
+            wide: false,
+            wide_immediate: 0,
+            operands: [0; 3],
+            operands_read: 0,
+            writable_code: None,
+            random: Xorshift::from_time(),
+            stats: None,
+            files: FileTable::new(),
+            sockets: SocketTable::new(),
+            input: None,
+            syscalls_used: BTreeSet::new(),
+            devices_used: BTreeSet::new(),
+            exit_code: None,
+            instruction_start: 0,
+            error: None,
+            cancel: CancelHandle::new(),
+            pause: PauseHandle::new(),
+            watchdog: None,
+            events: None,
+            output: None,
+            stdout_buffer: OutputBuffer::new(DEFAULT_OUTPUT_BUFFER),
+            stdout_channel: None,
+            trace_hook: None,
+            recorder: Recorder::Off,
+            instructions_executed: 0,
+            cycles: 0,
+            breakpoints: BTreeMap::new(),
+            paused_at: None,
+            watchpoints: Watchpoints::new(),
+            halted_by: HaltedBy::EndOfProgram,
+            strict_jumps: false,
+            canaries: None,
+            devices: DeviceBus::new(),
+            kv_store: None,
+            scheduler: Scheduler::new(),
+            mailbox: None,
+            config: VMConfig::default(),
+            symbols: None,
+            allocations: None,
+            decode_cache: None,
+            threaded_code: None,
+            #[cfg(feature = "jit")]
+            jit: None,
+        }
+    }
+
+    pub fn get_test_vm() -> VM {
+        let mut test_vm = VM::new();
+        test_vm.registers[0] = 5;
+        test_vm.registers[1] = 10;
+        test_vm.float_registers[0] = 5.0;
+        test_vm.float_registers[1] = 10.0;
+        test_vm
+    }
+
+    /// Runs the program until it halts, exits or runs off its end, returning how it finished. Returns why it stopped
+    /// early instead if an instruction failed without a trap handler to deal with it.
+    pub fn run(&mut self) -> Result<RunResult, VMError> {
+        if self.prepare() {
+            return self.finish();
+        }
+
+        let mut is_done = false;
+
+        while !is_done {
+            #[cfg(feature = "jit")]
+            if self.run_compiled_block() {
+                continue;
+            }
+            if self.run_threaded() {
+                continue;
+            }
+            is_done = self.execute_instruction();
+        }
+
+        self.finish()
+    }
+
+    /// Runs the program like `run`, but stops with `VMError::BudgetExhausted` once `budget` instructions have been
+    /// executed, so untrusted programs can't loop forever. Calling it again carries on from where it stopped.
+    pub fn run_with_budget(&mut self, budget: u64) -> Result<RunResult, VMError> {
+        if self.prepare() {
+            return self.finish();
+        }
+
+        for _ in 0..budget {
+            if self.execute_instruction() {
+                return self.finish();
+            }
+        }
+
+        self.fail(VMError::BudgetExhausted { pc: self.pc, budget });
+        self.finish()
+    }
+
+    /// Runs the program like `run`, but stops with `VMError::TimedOut` once it has been running for `limit`, so
+    /// embedders can bound how long they wait whatever the program does. The clock is only read every
+    /// `DEADLINE_CHECK_INTERVAL` instructions to keep it cheap, so the limit can be overrun by that many
+    /// instructions. Calling it again carries on from where it stopped.
+    pub fn run_for(&mut self, limit: Duration) -> Result<RunResult, VMError> {
+        let started = Instant::now();
+        if self.prepare() {
+            return self.finish();
+        }
+
+        loop {
+            for _ in 0..DEADLINE_CHECK_INTERVAL {
+                if self.execute_instruction() {
+                    return self.finish();
+                }
+            }
+
+            if started.elapsed() >= limit {
+                self.fail(VMError::TimedOut { pc: self.pc, limit });
+                return self.finish();
+            }
+        }
+    }
+
+    /// Captures the state of the program, so it can be saved and carried on from later with `restore`
+    pub fn snapshot(&self) -> VMState {
+        VMState {
+            registers: self.registers,
+            float_registers: self.float_registers,
+            pc: self.pc,
+            remainder: self.remainder,
+            flags: self.flags,
+            loop_counter: self.loop_counter,
+            wide: self.wide,
+            writable_code: self.writable_code.clone(),
+            program: self.program.clone(),
+            ro_data: self.ro_data.clone(),
+            heap: self.heap.clone(),
+            call_stack: self.call_stack.clone(),
+            config: self.config.clone(),
+        }
+    }
+
+    /// Replaces the program and its state with a snapshot. Whatever the host has set up, such as host functions
+    /// and limits, is kept, apart from the heap limit, which comes from the snapshot. Snapshots only hold the running
+    /// green thread, so any others are dropped. Fails, leaving the VM as it was, if the snapshot was taken on a VM
+    /// configured in a way the program can't carry on without, such as with float support this VM doesn't have.
+    pub fn restore(&mut self, state: VMState) -> Result<(), String> {
+        state.config.check_compatible(&self.config)?;
+        self.registers = state.registers;
+        self.float_registers = state.float_registers;
+        self.pc = state.pc;
+        self.remainder = state.remainder;
+        self.flags = state.flags;
+        self.loop_counter = state.loop_counter;
+        self.wide = state.wide;
+        self.writable_code = state.writable_code;
+        self.program = state.program;
+        self.ro_data = state.ro_data;
+        self.heap = state.heap;
+        self.config.heap_limit = self.heap.limit();
+        self.call_stack = state.call_stack;
+        self.decode_cache = None;
+        self.threaded_code = None;
+        #[cfg(feature = "jit")]
+        if let Some(jit) = &mut self.jit {
+            jit.forget();
+        }
+        if self.canaries.is_some() {
+            self.canaries = Some(Canaries::new());
+        }
+        if self.allocations.is_some() {
+            self.allocations = Some(AllocationTracker::new());
+        }
+        self.trap_stack.clear();
+        self.scheduler.reset();
+        self.exit_code = None;
+        self.error = None;
+        Ok(())
+    }
+
+    /// Starts recording the program's nondeterministic inputs, replacing any recording or replay in progress
+    pub fn start_recording(&mut self) {
+        self.recorder = Recorder::Recording(Recording::new());
+    }
+
+    /// Stops recording and returns what was recorded, or None if the VM wasn't recording
+    pub fn take_recording(&mut self) -> Option<Recording> {
+        match std::mem::take(&mut self.recorder) {
+            Recorder::Recording(recording) => Some(recording),
+            other => {
+                self.recorder = other;
+                None
+            }
+        }
+    }
+
+    /// Feeds the program the inputs of a recording instead of asking the host for them, so it runs the way it did
+    /// when it was recorded. Recorded file syscalls don't touch the host's files. The run fails with
+    /// `VMError::ReplayDiverged` if the program asks for an input the recording doesn't have next.
+    pub fn replay(&mut self, recording: Recording) {
+        self.recorder = Recorder::Replaying(recording, 0);
+    }
+
+    /// Returns a handle that stops the VM before its next instruction when cancelled, such as from a thread watching
+    /// for Ctrl-C. The run stops with `VMError::Cancelled`, and the cancellation is used up by it.
+    pub fn cancel_handle(&self) -> CancelHandle {
+        self.cancel.clone()
+    }
+
+    /// Stops the VM before its next instruction, with `VMError::Paused` reporting where it will carry on from. Until
+    /// `resume` is called, running it again stops straight away, so the host can take a snapshot or attach a debugger
+    /// without the program moving on.
+    pub fn pause(&self) {
+        self.pause.pause();
+    }
+
+    /// Lifts a pause, so the next run carries on from where the program was paused
+    pub fn resume(&self) {
+        self.pause.resume();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.pause.is_paused()
+    }
+
+    /// Returns a handle for pausing and resuming the VM from another thread while it runs
+    pub fn pause_handle(&self) -> PauseHandle {
+        self.pause.clone()
+    }
+
+    /// Watches the program for spinning in a small loop for too long, calling back to the host when it does, or
+    /// stops watching if given None. The callback decides whether the run stops with `VMError::Stalled`.
+    pub fn set_watchdog(&mut self, watchdog: Option<Watchdog>) {
+        self.watchdog = watchdog;
+    }
+
+    /// Assembled programs start with a header, raw bytecode (such as from the REPL) does not. Returns true if the
+    /// header says the program was built for a configuration this VM doesn't have, so it can't be run.
+    fn prepare(&mut self) -> bool {
+        self.instructions_executed = 0;
+        self.halted_by = HaltedBy::EndOfProgram;
+        self.watchpoints.refresh(&self.registers, &self.heap);
+        self.emit(VMEvent::Started);
+        if self.enter_program() {
+            return true;
+        }
+        // Decoded afresh for each run, as the host may have written to `program` since the last one
+        if self.config.predecode {
+            let start = code_start(&self.program).unwrap_or(0);
+            self.decode_cache = Some(DecodeCache::build(&self.program, start, self.wide));
+        }
+        false
+    }
+
+    /// Moves past the header of a program that hasn't started yet, returning true if the VM can't run it
+    fn enter_program(&mut self) -> bool {
+        if self.pc == 0 && self.verify_header() {
+            if let Err(reason) = self.process_header() {
+                return self.fail(VMError::IncompatibleTarget { pc: 0, reason });
+            }
+        }
+        false
+    }
+
+    /// Executes a single instruction, returning why it failed if it did
+    pub fn run_once(&mut self) -> Result<(), VMError> {
+        if self.execute_instruction() {
+            self.finish().map(|_| ())
+        } else {
+            self.flush_output();
+            Ok(())
+        }
+    }
+
+    /// Executes a single instruction and describes what it did, for debuggers showing a program a step at a time.
+    /// Only the registers the instruction can write are compared, so a step costs little more than `run_once`.
+    pub fn step(&mut self) -> StepResult {
+        if self.enter_program() {
+            let stopped = Some(self.finish());
+            return StepResult { pc: 0, next_pc: 0, instruction: None, registers: vec![], stopped };
+        }
+
+        let pc = self.pc;
+        let prefix = if self.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+        let bytes = self.program.get(pc + prefix..).unwrap_or(&[]);
+        let instruction = disassemble_instruction(bytes, NumberFormat::Decimal).map(|(text, _)| text);
+        let written = if bytes.len() >= 4 { written_registers(bytes) } else { 0..0 };
+
+        let before = self.registers;
+        let stop = self.execute_instruction();
+        self.flush_output();
+        let registers = written
+            .filter(|number| before[*number] != self.registers[*number])
+            .map(|number| RegisterChange { number, old: before[number], new: self.registers[number] })
+            .collect();
+        let stopped = if stop { Some(self.finish()) } else { None };
+
+        StepResult { pc, next_pc: self.pc, instruction, registers, stopped }
+    }
+
+    /// Reports how the program stopped to subscribers, and to the caller if it failed
+    fn finish(&mut self) -> Result<RunResult, VMError> {
+        self.flush_output();
+        self.decode_cache = None;
+        let result = match self.error.take() {
+            Some(error) => Err(error),
+            None => Ok(RunResult {
+                exit_code: self.exit_code.unwrap_or(0),
+                instructions_executed: self.instructions_executed,
+                halted_by: self.halted_by,
+            }),
+        };
+
+        let event = match &result {
+            Ok(result) => VMEvent::Halted { code: result.exit_code },
+            Err(VMError::Breakpoint { pc, .. }) => VMEvent::Breakpoint { pc: *pc },
+            Err(error) if error.is_resumable() => VMEvent::Stopped { reason: error.clone() },
+            Err(error) => VMEvent::Crashed { error: error.clone() },
+        };
+        self.emit(event);
+
+        result
+    }
+
+    /// Returns a channel that receives the VM's lifecycle events from now on. Only the latest subscriber gets them.
+    pub fn subscribe(&mut self) -> Receiver<VMEvent> {
+        let (sender, receiver) = channel();
+        self.events = Some(sender);
+        receiver
+    }
+
+    /// Returns a channel that receives the records the program emits on its output channels from now on. Only the
+    /// latest subscriber gets them. Without one, they are written to stderr a line at a time.
+    pub fn subscribe_output(&mut self) -> Receiver<OutputRecord> {
+        let (sender, receiver) = channel();
+        self.output = Some(sender);
+        receiver
+    }
+
+    /// Hands a record from the emit syscall to the subscriber, or writes it to stderr if there isn't one
+    pub fn emit_record(&mut self, record: OutputRecord) {
+        match &self.output {
+            Some(sender) => {
+                if let Err(unsent) = sender.send(record) {
+                    self.output = None;
+                    eprintln!("{}", unsent.0);
+                }
+            }
+            None => eprintln!("{}", record),
+        }
+    }
+
+    fn emit(&mut self, event: VMEvent) {
+        // A subscriber that has gone away just stops getting events
+        if let Some(sender) = &self.events {
+            if sender.send(event).is_err() {
+                self.events = None;
+            }
+        }
+    }
+
+    /// Executes a single instruction and returns true if the program should stop. If it stopped because the
+    /// instruction failed, `run` and `run_once` report why.
+    pub fn execute_instruction(&mut self) -> bool {
+        if self.cancel.take() {
+            return self.fail(VMError::Cancelled { pc: self.pc });
+        }
+        if self.pause.is_paused() {
+            return self.fail(VMError::Paused { pc: self.pc });
+        }
+        let pc = self.pc;
+        let resuming = self.paused_at.take() == Some(pc);
+        if !resuming && self.breakpoints.contains_key(&pc) {
+            return self.stop_at_breakpoint(pc);
+        }
+        if let Some(range) = self.watchdog.as_mut().and_then(|watchdog| watchdog.observe(pc)) {
+            return self.fail(VMError::Stalled { pc, range });
+        }
+
+        let start = self.pc;
+        let is_done = self.execute_opcode();
+
+        if let Some(stats) = self.stats.as_mut() {
+            let prefix = if self.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+            if let Some(byte) = self.program.get(start + prefix) {
+                stats.record(Opcode::from(*byte), start, self.pc, prefix + 4);
+            }
+        }
+
+        if is_done {
+            return true;
+        }
+        self.tick_timer();
+        self.check_watchpoints(start)
+    }
+
+    fn execute_opcode(&mut self) -> bool {
+        // If our program counter has exceeded the length
+        // of the program itself, something has gone awry
+        if self.pc > self.program.len() {
+            let error = VMError::OutOfBoundsJump { pc: self.instruction_start, target: self.pc };
+            return self.fail(error);
+        }
+        if self.pc == self.program.len() {
+            self.halted_by = HaltedBy::EndOfProgram;
+            return true;
+        }
+
+        let start = self.pc;
+        self.instruction_start = start;
+        let instruction = match self.decode_cache.as_ref().and_then(|cache| cache.get(start)) {
+            Some(instruction) => *instruction,
+            None => {
+                if self.wide && self.pc + WIDE_IMMEDIATE_LENGTH >= self.program.len() {
+                    self.halted_by = HaltedBy::EndOfProgram;
+                    return true;
+                }
+                DecodedInstruction::read(&self.program, start, self.wide)
+            }
+        };
+        if self.wide {
+            self.wide_immediate = instruction.wide_immediate;
+            self.pc += WIDE_IMMEDIATE_LENGTH;
+        }
+        self.operands = instruction.operands;
+        self.operands_read = 0;
+
+        self.instructions_executed += 1;
+        self.cycles += 1;
+        if self.trace_hook.is_some() {
+            self.trace(start);
+        }
+
+        let opcode = instruction.opcode;
+        self.pc += 1;
+        if !self.config.float_support && opcode.uses_floats() {
+            self.skip_operands();
+            if opcode == Opcode::LOADF64 {
+                self.pc = (self.pc + 8).min(self.program.len());
+            }
+            let error = self.illegal_opcode();
+            return self.raise(Trap::IllegalOpcode, error);
+        }
+
+        match opcode {
+            Opcode::LOAD => {
+                let register = self.next_8_bits() as usize;
+                self.registers[register] = self.next_immediate();
+            }
+            Opcode::LUI => {
+                let register = self.next_8_bits() as usize;
+                let upper = u32::from(self.next_16_bits());
+                self.registers[register] = (upper << 16) as i32;
+            }
+            Opcode::ORI => {
+                let register = self.next_8_bits() as usize;
+                let lower = u32::from(self.next_16_bits());
+                self.registers[register] = (self.registers[register] as u32 | lower) as i32;
+            }
+            Opcode::HLT => {
+                // hlt $register also sets the exit code
+                let register = self.next_8_bits() as usize;
+                self.next_8_bits();
+                if self.next_8_bits() == HLT_WITH_CODE {
+                    self.exit_code = Some(self.registers[register]);
+                }
+                self.flush_output();
+                self.halted_by = HaltedBy::Hlt;
+                return true;
+            }
+            Opcode::IGL => {
+                self.skip_operands();
+                let error = self.illegal_opcode();
+                return self.raise(Trap::IllegalOpcode, error);
+            }
+            Opcode::ADD => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                let (result, overflow) = register1.overflowing_add(register2);
+                let (_, carry) = (register1 as u32).overflowing_add(register2 as u32);
+                self.flags = Flags::from_result(result, carry, overflow);
+                self.registers[self.next_8_bits() as usize] = result;
+            }
+            Opcode::SUB => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                self.flags = VM::compare(register1, register2);
+                self.registers[self.next_8_bits() as usize] = register1.wrapping_sub(register2);
+            }
+            Opcode::MUL => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                let (result, overflow) = register1.overflowing_mul(register2);
+                self.flags = Flags::from_result(result, overflow, overflow);
+                self.registers[self.next_8_bits() as usize] = result;
+            }
+            Opcode::DIV => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                let target = self.next_8_bits() as usize;
+
+                if register2 == 0 {
+                    return self.raise(Trap::DivideByZero, VMError::DivisionByZero { pc: start });
+                }
+
+                let (result, overflow) = register1.overflowing_div(register2);
+                self.flags = Flags::from_result(result, false, overflow);
+                self.registers[target] = result;
+                self.remainder = register1.wrapping_rem(register2) as usize;
+            }
+            Opcode::SHL => {
+                let register = self.next_8_bits() as usize;
+                let amount = self.next_shift_amount();
+                let value = self.registers[register] as u32;
+                self.registers[register] = value.checked_shl(amount).unwrap_or(0) as i32;
+            }
+            Opcode::SHR => {
+                let register = self.next_8_bits() as usize;
+                let amount = self.next_shift_amount();
+                let value = self.registers[register] as u32;
+                self.registers[register] = value.checked_shr(amount).unwrap_or(0) as i32;
+            }
+            Opcode::LOADF64 => {
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                self.float_registers[register] = self.next_f64();
+            }
+            Opcode::ADDF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.float_registers[self.next_8_bits() as usize] = register1 + register2;
+            }
+            Opcode::SUBF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.float_registers[self.next_8_bits() as usize] = register1 - register2;
+            }
+            Opcode::MULF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.float_registers[self.next_8_bits() as usize] = register1 * register2;
+            }
+            Opcode::DIVF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.float_registers[self.next_8_bits() as usize] = register1 / register2;
+            }
+            Opcode::EQF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition((register1 - register2).abs() < f64::EPSILON);
+                self.next_8_bits();
+            }
+            Opcode::NEQF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition((register1 - register2).abs() >= f64::EPSILON);
+                self.next_8_bits();
+            }
+            Opcode::GTF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition(register1 > register2);
+                self.next_8_bits();
+            }
+            Opcode::GTEF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition(register1 >= register2);
+                self.next_8_bits();
+            }
+            Opcode::LTF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition(register1 < register2);
+                self.next_8_bits();
+            }
+            Opcode::LTEF64 => {
+                let register1 = self.float_registers[self.next_8_bits() as usize];
+                let register2 = self.float_registers[self.next_8_bits() as usize];
+                self.flags = Flags::from_condition(register1 <= register2);
+                self.next_8_bits();
+            }
+            Opcode::LW => {
+                let target = self.next_8_bits() as usize;
+                let address = self.next_address();
+                if address >= MMIO_BASE as i64 {
+                    match self.read_device(address as usize) {
+                        Some(Ok(value)) => self.registers[target] = value,
+                        Some(Err(message)) => return self.memory_fault(message),
+                        None => return self.fail(VMError::ReplayDiverged { pc: self.instruction_start }),
+                    }
+                    return false;
+                }
+
+                let address = match self.word_address(address) {
+                    Ok(address) => address,
+                    Err(message) => return self.memory_fault(message),
+                };
+                let word = self.heap.slice(address, 4).unwrap();
+                self.registers[target] = read_heap_word(word);
+            }
+            Opcode::SW => {
+                let value = self.registers[self.next_8_bits() as usize];
+                let address = self.next_address();
+                if address >= MMIO_BASE as i64 {
+                    self.devices_used.insert(address as usize);
+                    // Devices such as the console write straight away, so what was printed before goes first
+                    self.flush_output();
+                    if let Err(message) = self.devices.write(address as usize, value) {
+                        return self.memory_fault(message);
+                    }
+                    return false;
+                }
+
+                let address = match self.word_address(address) {
+                    Ok(address) => address,
+                    Err(message) => return self.memory_fault(message),
+                };
+                let word = self.heap.slice_mut(address, 4).unwrap();
+                write_heap_word(word, value);
+                self.note_write(address);
+            }
+            Opcode::CAS => {
+                // cas $address $expected $new stores $new if the word holds $expected. Either way $expected is left
+                // holding what the word held, and the zero flag says whether the swap happened.
+                let address = self.registers[self.next_8_bits() as usize];
+                let expected = self.next_8_bits() as usize;
+                let new = self.registers[self.next_8_bits() as usize];
+                let address = match self.word_address(i64::from(address)) {
+                    Ok(address) => address,
+                    Err(message) => return self.memory_fault(message),
+                };
+
+                let previous = self.heap.compare_and_swap(address, self.registers[expected], new);
+                self.note_write(address);
+                self.flags = Flags::from_condition(previous == self.registers[expected]);
+                self.registers[expected] = previous;
+            }
+            Opcode::XADD => {
+                // xadd $target $address $amount adds $amount to the word and puts what it held before in $target
+                let target = self.next_8_bits() as usize;
+                let address = self.registers[self.next_8_bits() as usize];
+                let amount = self.registers[self.next_8_bits() as usize];
+                let address = match self.word_address(i64::from(address)) {
+                    Ok(address) => address,
+                    Err(message) => return self.memory_fault(message),
+                };
+
+                self.registers[target] = self.heap.fetch_add(address, amount);
+                self.note_write(address);
+            }
+            Opcode::RAND => {
+                // rand $target fills the register with any i32, rand $target $bound with one in 0..$bound. Bounds
+                // that aren't positive give 0.
+                let target = self.next_8_bits() as usize;
+                let bound = self.registers[self.next_8_bits() as usize];
+                let bounded = self.next_8_bits() == RAND_BOUNDED;
+
+                let value = match (bounded, bound) {
+                    (false, _) => self.next_random(None),
+                    (true, bound) if bound > 0 => self.next_random(Some(bound as u32)),
+                    (true, _) => Some(0),
+                };
+                match value {
+                    Some(value) => self.registers[target] = value as i32,
+                    None => return self.fail(VMError::ReplayDiverged { pc: start }),
+                }
+            }
+            opcode @ (Opcode::VADD | Opcode::VMUL) => {
+                // vadd $target $source #count works on $target..$target+count and $source..$source+count, wrapping
+                // on overflow without touching the flags
+                let target = self.next_8_bits() as usize;
+                let source = self.next_8_bits() as usize;
+                let count = self.next_8_bits() as usize;
+                if target.max(source) + count > self.registers.len() {
+                    let reason = format!("Vector of {} registers from ${} and ${} runs past $31", count, target, source);
+                    return self.raise(Trap::IllegalOpcode, VMError::InvalidOperands { pc: start, reason });
+                }
+
+                // Working from a copy lets the ranges overlap, and the plain zipped loops are left for the compiler
+                // to vectorize
+                let sources = self.registers;
+                let targets = &mut self.registers[target..target + count];
+                let sources = &sources[source..source + count];
+                if opcode == Opcode::VADD {
+                    for (target, source) in targets.iter_mut().zip(sources) {
+                        *target = target.wrapping_add(*source);
+                    }
+                } else {
+                    for (target, source) in targets.iter_mut().zip(sources) {
+                        *target = target.wrapping_mul(*source);
+                    }
+                }
+            }
+            Opcode::WCODE => {
+                // wcode $value $address writes the value's bytes, most significant first, over the program so the
+                // word reads the same way as the instruction it encodes
+                let value = self.registers[self.next_8_bits() as usize];
+                let address = self.registers[self.next_8_bits() as usize];
+                self.next_8_bits();
+
+                let address = match self.writable_code_address(i64::from(address)) {
+                    Ok(address) => address,
+                    Err(message) => return self.memory_fault(message),
+                };
+                write_code_i32(&mut self.program[address..address + 4], value);
+                if let Some(cache) = &mut self.decode_cache {
+                    cache.refresh(&self.program, address..address + 4);
+                }
+                if let Some(code) = &mut self.threaded_code {
+                    code.refresh(&self.program, address..address + 4);
+                }
+                #[cfg(feature = "jit")]
+                if let Some(jit) = &mut self.jit {
+                    jit.invalidate(address..address + 4);
+                }
+            }
+            Opcode::SYSCALL => {
+                let number = self.next_16_bits();
+                self.next_8_bits();
+                self.syscalls_used.insert(number);
+
+                if self.syscall_middleware.is_empty() {
+                    return self.syscall(number);
+                }
+                return self.syscall_through_middleware(number);
+            }
+            Opcode::HCALL => {
+                let number = self.next_16_bits();
+                self.next_8_bits();
+
+                match self.host_functions.get_mut(&number) {
+                    Some(function) => function(&mut self.registers),
+                    None => return self.fail(VMError::UnknownHostFunction { pc: start, number }),
+                }
+            }
+            Opcode::CLOOP => {
+                self.loop_counter = self.next_immediate() as usize;
+                self.next_8_bits();
+            }
+            Opcode::LOOP => {
+                // Counts down and jumps back while there are iterations left, so a body ending in LOOP runs the
+                // number of times given to CLOOP
+                let target = self.next_immediate() as usize;
+                self.next_8_bits();
+
+                if self.loop_counter > 0 {
+                    self.loop_counter -= 1;
+                    if self.loop_counter > 0 {
+                        return self.jump(target);
+                    }
+                }
+            }
+            Opcode::CALL => {
+                let target = self.next_immediate() as usize;
+                self.next_8_bits();
+
+                if self.call_stack.len() >= self.max_call_depth {
+                    return self.fail(VMError::CallDepthExceeded { pc: start, depth: self.max_call_depth });
+                }
+                if let Some(error) = self.bad_jump(target) {
+                    return self.raise(Trap::BadJump, error);
+                }
+
+                self.call_stack.push(self.pc);
+                self.pc = target;
+            }
+            Opcode::RET => {
+                if let Some(canaries) = &mut self.canaries {
+                    if let Err(smashed) = canaries.check_return(&self.heap, self.call_stack.len()) {
+                        return self.fail(smashed.error(start));
+                    }
+                }
+
+                // A green thread finishes by returning from the code it was spawned at
+                match self.call_stack.pop() {
+                    Some(return_address) => self.pc = return_address,
+                    None => match self.scheduler.finish() {
+                        Some(next) => self.load_context(next),
+                        None => return self.fail(VMError::ReturnWithoutCall { pc: start }),
+                    },
+                }
+            }
+            Opcode::SPAWN => {
+                // spawn $id @worker starts a green thread at the label with a copy of this thread's registers, so
+                // arguments can be passed in them, and puts its id in $id, or -1 if there are too many threads
+                let register = self.next_8_bits() as usize;
+                let target = self.next_immediate() as usize;
+                if let Some(error) = self.bad_jump(target) {
+                    return self.raise(Trap::BadJump, error);
+                }
+
+                match self.scheduler.next_id() {
+                    Some(id) => {
+                        self.scheduler.spawn(Context {
+                            id,
+                            registers: self.registers,
+                            float_registers: self.float_registers,
+                            pc: target,
+                            remainder: 0,
+                            flags: Flags::new(),
+                            loop_counter: 0,
+                            call_stack: vec![],
+                            trap_stack: vec![],
+                        });
+                        self.registers[register] = id;
+                    }
+                    None => self.registers[register] = -1,
+                }
+            }
+            Opcode::YIELD => {
+                self.skip_operands();
+                self.yield_context();
+            }
+            Opcode::SEND => {
+                // send $to $message puts the message in the mailbox of the VM with id $to. The zero flag says whether
+                // it was delivered, which it isn't if there is no such VM.
+                let to = self.registers[self.next_8_bits() as usize];
+                let message = self.registers[self.next_8_bits() as usize];
+                self.next_8_bits();
+
+                match self.send_message(to, message) {
+                    Some(delivered) => self.flags = Flags::from_condition(delivered),
+                    None => return self.fail(VMError::ReplayDiverged { pc: start }),
+                }
+            }
+            Opcode::RECV => {
+                // recv $target takes the oldest message from this VM's mailbox, waiting for one if it is empty, and
+                // recv $target #0 only checks for one. The zero flag says whether a message was taken.
+                let target = self.next_8_bits() as usize;
+                self.next_8_bits();
+                let wait = self.next_8_bits() == RECV_WAIT;
+
+                match self.receive_message(wait) {
+                    Ok(Some(message)) => {
+                        self.registers[target] = message;
+                        self.flags = Flags::from_condition(true);
+                    }
+                    Ok(None) if wait => {
+                        // Come back to this RECV once the other green threads have had a turn
+                        self.pc = start;
+                        self.yield_context();
+                    }
+                    Ok(None) => self.flags = Flags::from_condition(false),
+                    Err(error) => return self.fail(error),
+                }
+            }
+            Opcode::JMP => {
+                let target = self.registers[self.next_8_bits() as usize];
+                return self.jump(target as usize);
+            }
+            Opcode::JMPR => {
+                // Jumps to a base plus an offset, such as the start of a jump table plus the entry to take
+                let base = self.registers[self.next_8_bits() as usize];
+                let offset = self.registers[self.next_8_bits() as usize];
+                return self.jump(base.wrapping_add(offset) as usize);
+            }
+            Opcode::BR => {
+                let offset = self.next_signed_immediate();
+                return self.jump((start as i64 + i64::from(offset)) as usize);
+            }
+            Opcode::JMPF => {
+                let value = self.registers[self.next_8_bits() as usize];
+                return self.jump(self.pc.wrapping_add(value as usize));
+            }
+            Opcode::EQ => {
+                let register1 = self.registers[self.next_8_bits() as usize];
+                let register2 = self.registers[self.next_8_bits() as usize];
+                self.flags = VM::compare(register1, register2);
+                self.next_8_bits();
+            }
+            Opcode::JMPE | Opcode::JZ => return self.jump_if(FLAG_ZERO, true),
+            Opcode::JNZ => return self.jump_if(FLAG_ZERO, false),
+            Opcode::JC => return self.jump_if(FLAG_CARRY, true),
+            Opcode::JO => return self.jump_if(FLAG_OVERFLOW, true),
+            Opcode::JNO => return self.jump_if(FLAG_OVERFLOW, false),
+            Opcode::ALOC => {
+                let bytes = self.registers[self.next_8_bits() as usize];
+                let target = self.next_8_bits() as usize;
+                self.next_8_bits();
+
+                if bytes < 0 {
+                    let message = format!("Cannot allocate a negative number of bytes: {}", bytes);
+                    return self.memory_fault(message);
+                }
+
+                // Arrays allocated inside a call get a canary after them when stack canaries are on
+                let depth = self.call_stack.len();
+                let guarded = self.canaries.is_some() && depth > 0;
+                let padding = if guarded { Canaries::padding() } else { 0 };
+                match self.heap.allocate(bytes as usize + padding) {
+                    Some(address) => {
+                        if let (true, Some(canaries)) = (guarded, &mut self.canaries) {
+                            canaries.place(&mut self.heap, address, bytes as usize, depth);
+                        }
+                        if let Some(allocations) = &mut self.allocations {
+                            allocations.allocated(start, address, bytes as usize);
+                        }
+                        self.registers[target] = address as i32;
+                    }
+                    None => {
+                        let message = format!("Not enough heap left to allocate {} bytes", bytes);
+                        return self.memory_fault(message);
+                    }
+                }
+            }
+            Opcode::RDCYCLE => {
+                // Counts the instructions before this one, so two reads around a loop differ by what the loop ran
+                let register = self.next_8_bits() as usize;
+                self.next_16_bits();
+                self.registers[register] = (self.cycles - 1) as i32;
+            }
+            Opcode::FREE => {
+                let address = self.registers[self.next_8_bits() as usize];
+                self.next_16_bits();
+
+                if let (true, Some(canaries)) = (address >= 0, &mut self.canaries) {
+                    if let Err(smashed) = canaries.check_free(&self.heap, address as usize) {
+                        return self.fail(smashed.error(start));
+                    }
+                }
+                if address < 0 || self.heap.free(address as usize).is_none() {
+                    let message = format!("Attempted to free an address that was not allocated: {}", address);
+                    return self.memory_fault(message);
+                }
+                if let Some(allocations) = &mut self.allocations {
+                    allocations.freed(address as usize);
+                }
+            }
+            Opcode::PRTS => {
+                let starting_offset = self.next_immediate() as usize;
+                self.next_8_bits();
+                let slice = self.ro_data.as_slice();
+                let length = slice.get(starting_offset..).and_then(|rest| rest.iter().position(|byte| *byte == 0));
+
+                let ending_offset = match length {
+                    Some(length) => starting_offset + length,
+                    None => {
+                        let message = format!("No string found in read-only data at offset {}", starting_offset);
+                        return self.memory_fault(message);
+                    }
+                };
+
+                let result = std::str::from_utf8(&slice[starting_offset..ending_offset]);
+
+                match result {
+                    Ok(s) => {
+                        let s = s.to_string();
+                        self.write_output(s.as_bytes());
+                    }
+                    Err(e) => {
+                        let message = format!("Error decoding string for prts instruction: {:#?}\n", e);
+                        self.write_output(message.as_bytes());
+                    }
+                }
+            }
+            Opcode::NOP => {
+                self.next_8_bits();
+                self.next_16_bits();
+            }
+            Opcode::FLUSH => {
+                self.skip_operands();
+                self.flush_output();
+            }
+            Opcode::SETTRAP => {
+                let number = self.next_8_bits() as usize;
+                let handler = self.next_immediate() as usize;
+
+                if !self.vectors.set(number, handler) {
+                    return self.fail(VMError::UnknownTrap { pc: start, number });
+                }
+            }
+            Opcode::IRET => {
+                self.next_8_bits();
+                self.next_16_bits();
+
+                match self.trap_stack.pop() {
+                    Some(frame) => {
+                        self.pc = frame.return_address;
+                        self.flags = frame.flags;
+                    }
+                    None => return self.fail(VMError::ReturnOutsideTrap { pc: start }),
+                }
+            }
+            _ => {
+                self.skip_operands();
+                let error = self.illegal_opcode();
+                return self.raise(Trap::IllegalOpcode, error);
+            }
+        }
+
+        false
+    }
+
+    /// Produces a number for RAND, below `bound` if there is one. Returns None if a replay has no number to give.
+    fn next_random(&mut self, bound: Option<u32>) -> Option<u32> {
+        if self.recorder.is_replaying() {
+            return match self.recorder.next() {
+                Some(RecordedInput::Random(value)) => Some(value),
+                _ => None,
+            };
+        }
+
+        let value = match bound {
+            Some(bound) => self.random.below(bound),
+            None => self.random.next_u32(),
+        };
+        self.recorder.record(RecordedInput::Random(value));
+        Some(value)
+    }
+
+    /// Reads a word from a device for LW, recording it or taking it from a replay without touching the device.
+    /// Returns None if a replay has no word to give.
+    fn read_device(&mut self, address: usize) -> Option<Result<i32, String>> {
+        self.devices_used.insert(address);
+        if self.recorder.is_replaying() {
+            return match self.recorder.next() {
+                Some(RecordedInput::DeviceRead(value)) => Some(Ok(value)),
+                _ => None,
+            };
+        }
+
+        let value = self.devices.read(address);
+        if let Ok(value) = value {
+            self.recorder.record(RecordedInput::DeviceRead(value));
+        }
+        Some(value)
+    }
+
+    /// Runs the handler for a syscall, returning true if the program should stop
+    fn syscall(&mut self, number: u16) -> bool {
+        let stop = match self.syscalls.get(number) {
+            Some(handler) if RECORDED_SYSCALLS.contains(&number) => self.recorded_syscall(number, handler),
+            Some(handler) => handler(self),
+            None => return self.fail(VMError::UnknownSyscall { pc: self.instruction_start, number }),
+        };
+        if stop {
+            self.halted_by = HaltedBy::Syscall { number };
+        }
+        stop
+    }
+
+    /// Passes a syscall through the middleware before running it, and back out through the middleware that saw it
+    /// afterwards
+    fn syscall_through_middleware(&mut self, number: u16) -> bool {
+        let mut middleware = std::mem::take(&mut self.syscall_middleware);
+        let mut call = SyscallCall { number, pc: self.instruction_start, registers: self.registers };
+        let mut verdict = SyscallVerdict::Allow;
+        let mut entered = 0;
+        while verdict == SyscallVerdict::Allow && entered < middleware.len() {
+            verdict = middleware[entered].before(&mut call);
+            entered += 1;
+        }
+
+        self.registers = call.registers;
+        let stop = match verdict {
+            SyscallVerdict::Allow => self.syscall(call.number),
+            SyscallVerdict::Skip => false,
+            SyscallVerdict::Deny => self.fail(VMError::SyscallDenied { pc: call.pc, number: call.number }),
+        };
+        for layer in middleware[..entered].iter_mut().rev() {
+            layer.after(&call, &self.registers, stop);
+        }
+
+        // Keeps any middleware a handler added, after what was already there
+        middleware.append(&mut self.syscall_middleware);
+        self.syscall_middleware = middleware;
+        stop
+    }
+
+    /// Runs a syscall whose results come from outside the VM, recording them or taking them from a replay
+    fn recorded_syscall(&mut self, number: u16, handler: SyscallHandler) -> bool {
+        if self.recorder.is_replaying() {
+            return match self.recorder.next() {
+                Some(RecordedInput::Syscall { number: recorded, registers, heap, stop }) if recorded == number => {
+                    self.registers = *registers;
+                    if let Some(heap) = heap {
+                        self.heap = heap;
+                    }
+                    stop
+                }
+                _ => self.fail(VMError::ReplayDiverged { pc: self.instruction_start }),
+            };
+        }
+
+        let stop = handler(self);
+        if self.recorder.is_recording() {
+            let input = RecordedInput::of_syscall(number, self.registers, &self.heap, stop);
+            self.recorder.record(input);
+        }
+        stop
+    }
+
+    /// Lets the next green thread waiting to run have a turn, if there is one
+    fn yield_context(&mut self) {
+        let running = self.save_context();
+        let next = self.scheduler.switch(running);
+        self.load_context(next);
+    }
+
+    /// Delivers a message for SEND, returning whether it arrived, or takes that from a replay without sending it.
+    /// Returns None if a replay has no answer to give.
+    fn send_message(&mut self, to: i32, message: i32) -> Option<bool> {
+        if self.recorder.is_replaying() {
+            return match self.recorder.next() {
+                Some(RecordedInput::Delivered(delivered)) => Some(delivered),
+                _ => None,
+            };
+        }
+
+        let delivered = self.mailbox.as_ref().is_some_and(|(office, _)| office.send(to, message));
+        self.recorder.record(RecordedInput::Delivered(delivered));
+        Some(delivered)
+    }
+
+    /// Takes a message for RECV, or from a replay. When waiting and there are no other green threads to run, it
+    /// blocks for up to `RECEIVE_WAIT_SLICE` before giving up, so a VM waiting on its own doesn't keep the host busy.
+    fn receive_message(&mut self, wait: bool) -> Result<Option<i32>, VMError> {
+        let pc = self.instruction_start;
+        if self.recorder.is_replaying() {
+            return match self.recorder.next() {
+                Some(RecordedInput::Received(message)) => Ok(message),
+                _ => Err(VMError::ReplayDiverged { pc }),
+            };
+        }
+
+        let (office, id) = self.mailbox.clone().ok_or(VMError::NoMailbox { pc })?;
+        let message = if wait && self.scheduler.context_count() == 1 {
+            office.receive_timeout(id, RECEIVE_WAIT_SLICE)
+        } else {
+            office.try_receive(id)
+        };
+
+        // Coming up empty while waiting is only a retry, so replays just need the message that finally arrived
+        if message.is_some() || !wait {
+            self.recorder.record(RecordedInput::Received(message));
+        }
+        Ok(message)
+    }
+
+    /// Takes the running green thread's state out of the VM so another one can be loaded
+    fn save_context(&mut self) -> Context {
+        Context {
+            id: self.scheduler.current(),
+            registers: self.registers,
+            float_registers: self.float_registers,
+            pc: self.pc,
+            remainder: self.remainder,
+            flags: self.flags,
+            loop_counter: self.loop_counter,
+            call_stack: std::mem::take(&mut self.call_stack),
+            trap_stack: std::mem::take(&mut self.trap_stack),
+        }
+    }
+
+    fn load_context(&mut self, context: Context) {
+        self.registers = context.registers;
+        self.float_registers = context.float_registers;
+        self.pc = context.pc;
+        self.remainder = context.remainder;
+        self.flags = context.flags;
+        self.loop_counter = context.loop_counter;
+        self.call_stack = context.call_stack;
+        self.trap_stack = context.trap_stack;
+    }
+
+    /// Transfers control to the handler for a trap, which resumes at the instruction after the faulting one when it
+    /// IRETs. The flags are restored by IRET too, so a trap arriving between a compare and a jump doesn't change
+    /// where the jump goes. Without a handler, the VM stops with the error.
+    fn raise(&mut self, trap: Trap, error: VMError) -> bool {
+        if self.enter_trap(trap) {
+            false
+        } else {
+            self.fail(error)
+        }
+    }
+
+    /// Jumps to the handler for a trap, returning false if there is no handler or traps are nested too deeply
+    fn enter_trap(&mut self, trap: Trap) -> bool {
+        match self.vectors.get(trap) {
+            Some(handler) if self.trap_stack.len() < self.max_call_depth => {
+                let fault_address = if trap == Trap::Timer { self.pc } else { self.instruction_start };
+                self.trap_stack.push(TrapFrame { return_address: self.pc, fault_address, flags: self.flags });
+                self.pc = handler;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn memory_fault(&mut self, reason: String) -> bool {
+        let error = VMError::MemoryFault { pc: self.instruction_start, reason };
+        self.raise(Trap::MemoryFault, error)
+    }
+
+    fn illegal_opcode(&self) -> VMError {
+        let prefix = if self.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+        let byte = self.program.get(self.instruction_start + prefix).copied().unwrap_or(0);
+        VMError::IllegalOpcode { pc: self.instruction_start, byte }
+    }
+
+    /// Records why the program is stopping and returns true to stop the VM
+    fn fail(&mut self, error: VMError) -> bool {
+        self.error = Some(error);
+        true
+    }
+
+    /// Raises the timer trap every `interval` instructions, or turns the timer off if `interval` is None. The count
+    /// restarts from the time this is called.
+    pub fn set_timer(&mut self, interval: Option<u32>) {
+        self.timer_interval = interval;
+        self.timer_remaining = interval.unwrap_or(0);
+    }
+
+    /// Counts down to the next timer trap. The timer is ignored while a trap handler is running, or when there is
+    /// no handler for it, rather than stopping the VM.
+    fn tick_timer(&mut self) {
+        let interval = match self.timer_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        self.timer_remaining = self.timer_remaining.saturating_sub(1);
+        if self.timer_remaining > 0 {
+            return;
+        }
+
+        self.timer_remaining = interval;
+        if self.trap_stack.is_empty() {
+            self.enter_trap(Trap::Timer);
+        }
+    }
+
+    /// Flags for comparing two integers, which are the flags of subtracting the second from the first
+    fn compare(register1: i32, register2: i32) -> Flags {
+        let (result, overflow) = register1.overflowing_sub(register2);
+        let borrow = (register1 as u32) < (register2 as u32);
+        Flags::from_result(result, borrow, overflow)
+    }
+
+    /// Conditional jump to the address in the operand register, taken when `flag` is in the state given by `set`
+    fn jump_if(&mut self, flag: u8, set: bool) -> bool {
+        let target = self.registers[self.next_8_bits() as usize];
+        self.next_16_bits();
+
+        if self.flags.is_set(flag) == set {
+            return self.jump(target as usize);
+        }
+        false
+    }
+
+    /// Moves the PC to a jump target, returning true if the VM should stop. With strict jumps on, a target that isn't
+    /// the start of an instruction raises the bad jump trap instead, and a handler resumes after the jump.
+    fn jump(&mut self, target: usize) -> bool {
+        if let Some(error) = self.bad_jump(target) {
+            self.pc = self.instruction_start + self.instruction_alignment();
+            return self.raise(Trap::BadJump, error);
+        }
+
+        self.pc = target;
+        false
+    }
+
+    /// Returns why a jump target is bad, if strict jumps are on and it is
+    fn bad_jump(&self, target: usize) -> Option<VMError> {
+        if !self.strict_jumps {
+            return None;
+        }
+
+        let pc = self.instruction_start;
+        let code = code_start(&self.program).unwrap_or(0);
+        if target < code || target > self.program.len() {
+            return Some(VMError::OutOfBoundsJump { pc, target });
+        }
+
+        if !(target - code).is_multiple_of(self.instruction_alignment()) {
+            return Some(VMError::MisalignedJump { pc, target });
+        }
+
+        None
+    }
+
+    fn instruction_alignment(&self) -> usize {
+        if self.wide { INSTRUCTION_ALIGNMENT + WIDE_IMMEDIATE_LENGTH } else { INSTRUCTION_ALIGNMENT }
+    }
+
+    /// Holds back up to `bytes` of what PRTS and the print syscall print before writing it to stdout, or writes each
+    /// print straight away with 0. The buffer is also written out by FLUSH, before the program reads input or writes
+    /// to a device, and whenever a run stops. Anything already buffered is written out first.
+    pub fn set_output_buffer(&mut self, bytes: usize) {
+        self.flush_output();
+        self.config.output_buffer = bytes;
+        self.stdout_buffer = OutputBuffer::new(bytes);
+    }
+
+    /// Prints program output through the output buffer, writing the buffer out once it's full
+    pub fn write_output(&mut self, bytes: &[u8]) {
+        if self.stdout_buffer.write(bytes) {
+            self.flush_output();
+        }
+    }
+
+    /// Writes out the program output held in the buffer, in one write so other VMs' output doesn't end up in the
+    /// middle of it. It goes to the output channel if there is one, or to stdout if not or the receiver has gone.
+    pub fn flush_output(&mut self) {
+        let mut bytes = self.stdout_buffer.take();
+        if bytes.is_empty() {
+            return;
+        }
+        if let Some(sender) = &self.stdout_channel {
+            match sender.send(bytes) {
+                Ok(()) => return,
+                Err(unsent) => {
+                    self.stdout_channel = None;
+                    bytes = unsent.0;
+                }
+            }
+        }
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(&bytes).ok();
+        stdout.flush().ok();
+    }
+
+    /// Program output that is buffered and hasn't been written out yet
+    pub fn pending_output(&self) -> &[u8] {
+        self.stdout_buffer.pending()
+    }
+
+    /// Turns checking of jump targets on or off. Off by default, where only jumps past the end of the program are
+    /// caught, once the VM gets there. On, every jump has to land on the start of an instruction in the code.
+    pub fn set_strict_jumps(&mut self, strict: bool) {
+        self.strict_jumps = strict;
+    }
+
+    /// Turns stack canaries on or off, a debugging mode for catching writes past the end of arrays. Off by default.
+    /// On, each array allocated inside a call is followed by a canary that RET and FREE check, stopping the program
+    /// with the offset of the instruction that overwrote it.
+    pub fn set_stack_canaries(&mut self, on: bool) {
+        self.canaries = if on { Some(Canaries::new()) } else { None };
+    }
+
+    /// Turns predecoding on or off. On, each run decodes the program's instructions once, when it starts, rather than
+    /// every time they run. Stepping through a program a single instruction at a time decodes each one as it goes.
+    pub fn set_predecode(&mut self, on: bool) {
+        self.config.predecode = on;
+        self.decode_cache = None;
+    }
+
+    /// Turns the JIT on, compiling blocks of arithmetic to native code once execution has reached them `threshold`
+    /// times, or off with None. Only `run` uses compiled blocks, and only while nothing is watching the program
+    /// instruction by instruction: no breakpoints, watchpoints, watchdog, trace hook, statistics or timer. Fails if
+    /// Cranelift doesn't support the host.
+    #[cfg(feature = "jit")]
+    pub fn set_jit(&mut self, threshold: Option<u32>) -> Result<(), String> {
+        self.jit = match threshold {
+            Some(threshold) => Some(jit::Jit::new(threshold)?),
+            None => None,
+        };
+        Ok(())
+    }
+
+    /// Runs the compiled block at the PC, if the JIT is on, can be used and has one there, returning true if it did
+    #[cfg(feature = "jit")]
+    fn run_compiled_block(&mut self) -> bool {
+        let observed = self.is_observed() || self.pause.is_paused();
+        let jit = match &mut self.jit {
+            Some(jit) if !observed && !self.wide => jit,
+            _ => return false,
+        };
+
+        let mut flags = self.flags.bits();
+        match jit.run(&self.program, self.pc, &mut self.registers, &mut flags) {
+            Some(run) => {
+                self.flags = Flags::from_bits(flags);
+                self.pc = run.end;
+                self.instruction_start = run.end - 4;
+                self.instructions_executed += run.instructions;
+                self.cycles += run.instructions;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Runs instructions through their handlers for as long as it can, with threaded dispatch, returning true if it
+    /// ran any. Stops at the first instruction without a handler, or that its handler leaves to the interpreter, and
+    /// before the next instruction once the VM is cancelled or paused. Like the JIT, it's only used while nothing is
+    /// watching the program instruction by instruction.
+    fn run_threaded(&mut self) -> bool {
+        if self.config.dispatch != Dispatch::Threaded || self.wide || self.is_observed() {
+            return false;
+        }
+        let code = match self.threaded_code.take() {
+            Some(code) if code.describes(&self.program) => code,
+            _ => ThreadedCode::build(&self.program, code_start(&self.program).unwrap_or(0)),
+        };
+
+        let mut ran = 0;
+        while let Some(instruction) = code.get(self.pc) {
+            if self.cancel.is_cancelled() || self.pause.is_paused() {
+                break;
+            }
+            self.instruction_start = self.pc;
+            match (instruction.handler)(self, self.pc, instruction.operands) {
+                Some(next) => self.pc = next,
+                None => break,
+            }
+            ran += 1;
+        }
+
+        self.threaded_code = Some(code);
+        self.instructions_executed += ran;
+        self.cycles += ran;
+        ran > 0
+    }
+
+    /// Whether something watches the program instruction by instruction: breakpoints, watchpoints, a watchdog, a
+    /// trace hook, statistics or a timer. Those need every instruction to go through `execute_instruction`.
+    fn is_observed(&self) -> bool {
+        !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.watchdog.is_some()
+            || self.trace_hook.is_some()
+            || self.stats.is_some()
+            || self.timer_interval.is_some()
+    }
+
+    /// Turns heap tracking on or off. On, heap statistics are kept for each ALOC in the program, and the blocks
+    /// never freed can be listed with `heap_report`. Turning it off throws away what was tracked.
+    pub fn set_heap_tracking(&mut self, on: bool) {
+        self.config.heap_tracking = on;
+        self.allocations = if on { Some(AllocationTracker::new()) } else { None };
+    }
+
+    /// Heap statistics for each ALOC, if heap tracking is on
+    pub fn allocations(&self) -> Option<&AllocationTracker> {
+        self.allocations.as_ref()
+    }
+
+    /// Reports what each ALOC allocated and which blocks were never freed, naming them by the labels in the symbols
+    /// attached with `attach_symbols`. Returns None if heap tracking is off.
+    pub fn heap_report(&self) -> Option<Vec<String>> {
+        self.allocations.as_ref().map(|allocations| allocations.report(self.symbols.as_ref()))
+    }
+
+    /// Lets the stack canaries blame a store on the instruction being executed if it overwrote one
+    fn note_write(&mut self, address: usize) {
+        if let Some(canaries) = &mut self.canaries {
+            canaries.note_write(&self.heap, address, 4, self.instruction_start);
+        }
+    }
+
+    /// How the VM was sized and which optional parts it has
+    pub fn config(&self) -> &VMConfig {
+        &self.config
+    }
+
+    pub fn flags(&self) -> Flags {
+        self.flags
+    }
+
+    /// Moves past the operand bytes of an instruction that can't be decoded, so a trap handler resumes at the next
+    /// instruction word
+    fn skip_operands(&mut self) {
+        self.pc = (self.pc + 3).min(self.program.len());
+    }
+
+    fn trace(&mut self, start: usize) {
+        let mut operands = [0; 3];
+        if let Some(bytes) = self.program.get(self.pc + 1..self.pc + 4) {
+            operands.copy_from_slice(bytes);
+        }
+        let event = TraceEvent {
+            pc: start,
+            opcode: Opcode::from(self.program[self.pc]),
+            operands,
+            wide_immediate: if self.wide { Some(self.wide_immediate) } else { None },
+            registers: &self.registers,
+        };
+        if let Some(hook) = &mut self.trace_hook {
+            hook(&event);
+        }
+    }
+
+    /// Hands out the next operand byte of the instruction being executed
+    fn next_8_bits(&mut self) -> u8 {
+        let result = self.operands[self.operands_read];
+        self.operands_read += 1;
+        self.pc += 1;
+        result
+    }
+
+    /// Reads the 16-bit immediate of the instruction as an unsigned number. In the wide encoding, the instruction's
+    /// 32-bit immediate is returned instead.
+    fn next_immediate(&mut self) -> i32 {
+        let narrow = self.next_16_bits();
+        if self.wide { self.wide_immediate } else { i32::from(narrow) }
+    }
+
+    /// Signed version of `next_immediate`, for offsets
+    fn next_signed_immediate(&mut self) -> i32 {
+        let narrow = self.next_16_bits() as i16;
+        if self.wide { self.wide_immediate } else { i32::from(narrow) }
+    }
+
+    /// Hands out the next two operand bytes of the instruction being executed as a 16-bit immediate
+    fn next_16_bits(&mut self) -> u16 {
+        let result = read_code_u16(&self.operands[self.operands_read..]);
+        self.operands_read += 2;
+        self.pc += 2;
+        result
+    }
+
+    /// Reads a float immediate, which is stored in the 8 bytes following its instruction
+    fn next_f64(&mut self) -> f64 {
+        let result = read_code_f64(&self.program[self.pc..self.pc + 8]);
+        self.pc += 8;
+        result
+    }
+
+    /// Reads the base register and offset of a memory access and adds them up
+    fn next_address(&mut self) -> i64 {
+        let base = i64::from(self.registers[self.next_8_bits() as usize]);
+        let offset = i64::from(self.next_8_bits());
+        base + offset
+    }
+
+    /// Checks that a word written by WCODE at `address` lies entirely inside the writable region
+    fn writable_code_address(&self, address: i64) -> Result<usize, String> {
+        match &self.writable_code {
+            Some(region) if address >= region.start as i64 && address + 4 <= region.end as i64 => {
+                if address as usize + 4 > self.program.len() {
+                    return Err(format!("Code write out of bounds at address {}", address));
+                }
+                Ok(address as usize)
+            }
+            _ => Err(format!("Code write outside of the writable region at address {}", address)),
+        }
+    }
+
+    /// Checks that a word at `address` is aligned and inside the heap, returning the address as an index into it or
+    /// a description of the problem if the address is misaligned or the word would run past the end of the heap
+    fn word_address(&self, address: i64) -> Result<usize, String> {
+        if address % 4 != 0 {
+            return Err(format!("Misaligned memory access at address {}", address));
+        }
+
+        if address < 0 || address as usize + 4 > self.heap.len() {
+            return Err(format!("Memory access out of bounds at address {}", address));
+        }
+
+        Ok(address as usize)
+    }
+
+    /// Reads the amount operand of a shift, which is either an immediate or the number of a register holding it
+    fn next_shift_amount(&mut self) -> u32 {
+        let amount = self.next_8_bits();
+
+        if self.next_8_bits() == SHIFT_REGISTER {
+            self.registers[amount as usize] as u32
+        } else {
+            u32::from(amount)
+        }
+    }
+
+    /// Returns the table of handlers used by the SYSCALL opcode, so embedders can add their own
+    pub fn syscalls_mut(&mut self) -> &mut SyscallTable {
+        &mut self.syscalls
+    }
+
+    /// Puts middleware around every syscall, inside any middleware already added, so it sees syscalls after them
+    /// and their results before them
+    pub fn add_syscall_middleware<M>(&mut self, middleware: M)
+    where
+        M: SyscallMiddleware + 'static,
+    {
+        self.syscall_middleware.push(Box::new(middleware));
+    }
+
+    pub fn clear_syscall_middleware(&mut self) {
+        self.syscall_middleware.clear();
+    }
+
+    /// Makes the program stop with `VMError::Breakpoint` whenever it is about to run the instruction at `pc`. Running
+    /// the VM again carries on from there.
+    pub fn set_breakpoint(&mut self, pc: usize) {
+        self.add_breakpoint(Breakpoint::new(pc));
+    }
+
+    /// Removes a breakpoint, returning false if there wasn't one at `pc`
+    pub fn clear_breakpoint(&mut self, pc: usize) -> bool {
+        self.remove_breakpoint(pc).is_some()
+    }
+
+    /// Adds a breakpoint, such as a temporary one, replacing any already at its address
+    pub fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.insert(breakpoint.address, breakpoint);
+    }
+
+    /// Removes the breakpoint at `address`, returning it with how many times it was hit
+    pub fn remove_breakpoint(&mut self, address: usize) -> Option<Breakpoint> {
+        self.breakpoints.remove(&address)
+    }
+
+    /// The breakpoints, lowest address first
+    pub fn breakpoints(&self) -> impl Iterator<Item = &Breakpoint> {
+        self.breakpoints.values()
+    }
+
+    fn stop_at_breakpoint(&mut self, pc: usize) -> bool {
+        let breakpoint = self.breakpoints.get_mut(&pc).unwrap();
+        breakpoint.hits += 1;
+        let (hits, temporary) = (breakpoint.hits, breakpoint.temporary);
+        if temporary {
+            self.breakpoints.remove(&pc);
+        }
+
+        self.paused_at = Some(pc);
+        self.fail(VMError::Breakpoint { pc, hits, temporary })
+    }
+
+    /// Starts watching a register or heap word, so the program stops after any instruction that changes it. Returns
+    /// false if it was already being watched, or is a register that doesn't exist.
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        if let Watchpoint::Register(number) = watchpoint {
+            if number >= self.registers.len() {
+                return false;
+            }
+        }
+        let added = self.watchpoints.add(watchpoint, 0);
+        self.watchpoints.refresh(&self.registers, &self.heap);
+        added
+    }
+
+    /// Stops watching a register or heap word, returning false if it wasn't being watched
+    pub fn remove_watchpoint(&mut self, watchpoint: Watchpoint) -> bool {
+        self.watchpoints.remove(watchpoint)
+    }
+
+    /// The watchpoints, in the order they were added
+    pub fn watchpoints(&self) -> impl Iterator<Item = Watchpoint> + '_ {
+        self.watchpoints.iter()
+    }
+
+    /// Stops the program if the instruction that started at `start` changed something being watched. Only the
+    /// registers the instruction can write are looked at, and the heap only after instructions that can write it.
+    fn check_watchpoints(&mut self, start: usize) -> bool {
+        if self.watchpoints.is_empty() {
+            return false;
+        }
+
+        let prefix = if self.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+        let instruction = match self.program.get(start + prefix..start + prefix + 4) {
+            Some(instruction) => instruction,
+            None => return false,
+        };
+        let written = written_registers(instruction);
+        let heap_written = writes_heap(Opcode::from(instruction[0]));
+        match self.watchpoints.check(written, heap_written, &self.registers, &self.heap) {
+            Some(hit) => {
+                let (watchpoint, old, new) = (hit.watchpoint, hit.old, hit.new);
+                self.fail(VMError::Watchpoint { pc: self.pc, changed_by: start, watchpoint, old, new })
+            }
+            None => false,
+        }
+    }
+
+    /// Calls `hook` with every instruction from now on, just before it's executed, replacing any hook already set
+    pub fn set_trace_hook<F>(&mut self, hook: F)
+    where
+        F: FnMut(&TraceEvent) + 'static,
+    {
+        self.trace_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_trace_hook(&mut self) {
+        self.trace_hook = None;
+    }
+
+    /// Registers a function that bytecode can call with `hcall #number`, replacing any function already registered
+    /// under that number. The function gets the VM's registers, so it takes arguments from them and returns results
+    /// in them.
+    pub fn register_host_fn<F>(&mut self, number: u16, function: F)
+    where
+        F: FnMut(&mut [i32; 32]) + 'static,
+    {
+        self.host_functions.insert(number, Box::new(function));
+    }
+
+    /// Sets the part of the program WCODE may write to. Assembled programs declare it with `.writable` and have it
+    /// set when they're loaded.
+    pub fn set_writable_code(&mut self, region: Option<Range<usize>>) {
+        self.writable_code = region;
+    }
+
+    /// Makes the input syscalls read from `input` instead of stdin, such as when the VM is embedded somewhere
+    /// without a console
+    pub fn set_input(&mut self, input: Box<dyn BufRead>) {
+        self.input = Some(input);
+    }
+
+    /// Makes the input syscalls read what the host sends on a channel, a chunk per message, so a host can feed the
+    /// program from another thread or task as it goes. Reads wait for the host to send more, and the input ends when
+    /// it drops every sender.
+    pub fn set_input_channel(&mut self, receiver: Receiver<Vec<u8>>) {
+        self.set_input(Box::new(ChannelInput::new(receiver)));
+    }
+
+    /// Sends what PRTS and the print syscall print to the host on a channel instead of stdout, or sends it to stdout
+    /// again with None. Each message is what was in the output buffer when it was written out, so set the buffer to 0
+    /// bytes to get every print as its own message.
+    pub fn set_output_channel(&mut self, sender: Option<Sender<Vec<u8>>>) {
+        self.flush_output();
+        self.stdout_channel = sender;
+    }
+
+    /// Reads a line, including its line ending, for the input syscalls. Returns the number of bytes read, which is
+    /// 0 at the end of the input.
+    pub fn read_input_line(&mut self, line: &mut String) -> io::Result<usize> {
+        // A prompt printed before reading has to be seen before the program waits for the answer
+        self.flush_output();
+        match self.input.as_mut() {
+            Some(input) => input.read_line(line),
+            None => io::stdin().read_line(line),
+        }
+    }
+
+    /// Gives the program a key-value store, or takes it away if given None. Without one the key-value syscalls fail.
+    pub fn set_kv_store(&mut self, store: Option<KeyValueStore>) {
+        self.kv_store = store;
+    }
+
+    pub fn kv_store_mut(&mut self) -> Option<&mut KeyValueStore> {
+        self.kv_store.as_mut()
+    }
+
+    /// Returns the files the program has open, so embedders can turn filesystem access off
+    pub fn files_mut(&mut self) -> &mut FileTable {
+        &mut self.files
+    }
+
+    pub fn sockets_mut(&mut self) -> &mut SocketTable {
+        &mut self.sockets
+    }
+
+    /// Sets which addresses the socket syscalls may use. Until this is called they may use none.
+    pub fn set_network_policy(&mut self, policy: NetworkPolicy) {
+        self.sockets.set_policy(policy);
+    }
+
+    /// Allocates a zeroed heap block of `size` bytes for a syscall and returns its address, or None if the heap is
+    /// full
+    pub fn allocate(&mut self, size: usize) -> Option<usize> {
+        self.heap.allocate(size)
+    }
+
+    /// Adds `size` bytes to the end of the heap for the sbrk syscall. Returns the address of the first new byte, or
+    /// None if the heap is full.
+    pub fn grow_heap(&mut self, size: usize) -> Option<usize> {
+        self.heap.grow(size)
+    }
+
+    /// Sets the most bytes the heap may hold, or None to let it grow without limit. Allocations and growth past the
+    /// limit fail.
+    pub fn set_max_heap_size(&mut self, size: Option<usize>) {
+        self.heap.set_limit(size);
+        self.config.heap_limit = size;
+    }
+
+    /// Sets whether the program uses the wide encoding. Assembled programs say so in their header and have it set
+    /// when they're loaded.
+    pub fn set_wide_encoding(&mut self, wide: bool) {
+        self.wide = wide;
+    }
+
+    /// Maps a device to `length` bytes at `base`, so LW and SW there reach it instead of the heap. `base` has to be
+    /// at or above `MMIO_BASE`, and the range can't overlap another device.
+    pub fn map_device(&mut self, base: usize, length: usize, device: Box<dyn Device>) -> Result<(), String> {
+        self.devices.map(base, length, device)
+    }
+
+    /// Maps the console at `CONSOLE_ADDRESS` and the timer at `TIMER_ADDRESS`
+    pub fn map_standard_devices(&mut self) {
+        self.map_device(CONSOLE_ADDRESS, 4, Box::new(Console)).unwrap();
+        self.map_device(TIMER_ADDRESS, 4, Box::new(Timer::new())).unwrap();
+    }
+
+    pub fn is_wide(&self) -> bool {
+        self.wide
+    }
+
+    /// Seeds the generator used by RAND, so a program produces the same numbers each time it is run
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = Xorshift::new(seed);
+    }
+
+    /// Starts collecting statistics about the instructions the program executes
+    pub fn enable_stats(&mut self) {
+        self.stats = Some(RunStats::new());
+    }
+
+    /// Returns the statistics collected since `enable_stats` was called, or None if it wasn't
+    pub fn stats(&self) -> Option<&RunStats> {
+        self.stats.as_ref()
+    }
+
+    /// Reports how many times each opcode has run and which instructions ran most, from the statistics collected
+    /// since `enable_stats` was called. Returns None if it wasn't.
+    pub fn profile(&self) -> Option<Profile> {
+        let stats = self.stats.as_ref()?;
+        let mut opcodes: Vec<(String, u64)> = stats.opcodes.iter().map(|(name, count)| (name.clone(), *count)).collect();
+        opcodes.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+
+        let prefix = if self.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+        let hot_spots = stats
+            .hottest_pcs(HOTTEST_PC_COUNT)
+            .into_iter()
+            .map(|(pc, count)| HotSpot {
+                pc,
+                count,
+                instruction: self
+                    .program
+                    .get(pc + prefix..)
+                    .and_then(|bytes| disassemble_instruction(bytes, NumberFormat::Decimal))
+                    .map(|(text, _)| text),
+            })
+            .collect();
+
+        Some(Profile { instructions: stats.instructions, opcodes, hot_spots })
+    }
+
+    /// Returns the numbers of the syscalls the program has made so far
+    pub fn syscalls_used(&self) -> &BTreeSet<u16> {
+        &self.syscalls_used
+    }
+
+    /// Returns the addresses in the device range the program has read or written so far
+    pub fn devices_used(&self) -> &BTreeSet<usize> {
+        &self.devices_used
+    }
+
+    /// Returns the exit code the program passed to the exit syscall, if it used it
+    pub fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+
+    /// Gives the VM a mailbox under `id` in a post office, so it can message the other VMs there with SEND and RECV.
+    /// Any mailbox it had before is closed.
+    pub fn join_post_office(&mut self, office: PostOffice, id: i32) -> Result<(), String> {
+        if !office.open(id) {
+            return Err(format!("There is already a mailbox with id {}", id));
+        }
+        if let Some((previous, previous_id)) = self.mailbox.replace((office, id)) {
+            previous.close(previous_id);
+        }
+        Ok(())
+    }
+
+    /// Sets how many green threads, counting the main one, can exist at once. SPAWN gives -1 beyond that.
+    pub fn set_max_contexts(&mut self, max: usize) {
+        self.scheduler.set_max_contexts(max);
+    }
+
+    /// Id of the green thread that is running, which is 0 for the one the program started in
+    pub fn current_context(&self) -> i32 {
+        self.scheduler.current()
+    }
+
+    /// Sets how deeply CALLs can be nested before the VM stops
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Returns the return addresses of the CALLs currently in progress, innermost last
+    pub fn call_stack(&self) -> &[usize] {
+        &self.call_stack
+    }
+
+    /// Returns the heap memory of the VM
+    pub fn heap(&self) -> &[u8] {
+        self.heap.as_slice()
+    }
+
+    /// Returns `length` bytes of heap memory starting at `address`, or None if any of them are out of bounds
+    pub fn heap_slice(&self, address: usize, length: usize) -> Option<&[u8]> {
+        self.heap.slice(address, length)
+    }
+
+    /// Mutable version of `heap_slice`
+    pub fn heap_slice_mut(&mut self, address: usize, length: usize) -> Option<&mut [u8]> {
+        self.heap.slice_mut(address, length)
+    }
+
+    /// Returns allocation counters for the heap
+    pub fn heap_stats(&self) -> &HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn add_byte(&mut self, b: u8) {
+        self.program.push(b);
+        self.decode_cache = None;
+        self.threaded_code = None;
+    }
+
+    /// Describes the loaded program: its header, where its sections are and the symbols attached to it
+    pub fn loaded_program(&self) -> LoadedProgram<'_> {
+        LoadedProgram {
+            length: self.program.len(),
+            header: ProgramHeader::read(&self.program),
+            symbols: self.symbols.as_ref(),
+        }
+    }
+
+    /// Keeps the symbols the program was assembled with, so `loaded_program` can report them. Programs don't carry
+    /// their symbols, so the host has to attach them if it has them.
+    pub fn attach_symbols(&mut self, symbols: Option<SymbolTable>) {
+        self.symbols = symbols;
+    }
+
+    /// Adds an arbitrary byte to the VM's program
+    pub fn add_bytes(&mut self, mut b: Vec<u8>) {
+        self.program.append(&mut b);
+        self.decode_cache = None;
+        self.threaded_code = None;
+    }
+
+    /// Processes the header of bytecode the VM wants to execute
+    fn verify_header(&self) -> bool {
+        code_start(&self.program).is_some()
+    }
+
+    /// Checks the program's target against the VM, then loads the read-only section that follows the header and
+    /// points the PC at the first instruction. A fixed heap target caps the heap at its size.
+    fn process_header(&mut self) -> Result<(), String> {
+        if let Some(target) = program_target(&self.program) {
+            if target.register_width != DEFAULT_REGISTER_WIDTH {
+                return Err(format!(
+                    "the program was built for {}, which has {}-bit registers, but this VM's are {}-bit",
+                    target, target.register_width, DEFAULT_REGISTER_WIDTH
+                ));
+            }
+            if let HeapModel::Fixed(size) = target.heap {
+                let size = size as usize;
+                match self.heap.limit() {
+                    Some(limit) if limit < size => {
+                        return Err(format!(
+                            "the program was built for {}, which has a {} byte heap, but this VM's heap is limited to {}",
+                            target, size, limit
+                        ));
+                    }
+                    _ => self.heap.set_limit(Some(size)),
+                }
+            }
+        }
+
+        if let Some(start) = code_start(&self.program) {
+            self.ro_data = self.program[PIE_HEADER_LENGTH..start].to_vec();
+            self.writable_code = writable_region(&self.program);
+            self.wide = is_wide_encoding(&self.program);
+            self.pc = start;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::assembler::{Assembler, PIE_HEADER_PREFIX};
+    use crate::vm::flags::FLAG_NEGATIVE;
+
+    #[test]
+    fn test_create_vm() {
+        let test_vm = VM::new();
+        assert_eq!(test_vm.registers[0], 0)
+    }
+
+    #[test]
+    fn test_opcode_hlt() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![5, 0, 0, 0];
+        test_vm.program = test_bytes;
+        let result = test_vm.run().unwrap();
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(result, RunResult { exit_code: 0, instructions_executed: 1, halted_by: HaltedBy::Hlt });
+    }
+
+    #[test]
+    fn test_opcode_hlt_with_exit_code() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![1, 0, 1, 0, 5, 0, 0, HLT_WITH_CODE];
+        let result = test_vm.run().unwrap();
+        assert_eq!(result.exit_code, 15);
+        assert_eq!(result.instructions_executed, 2);
+        assert_eq!(test_vm.exit_code(), Some(15));
+    }
+
+    #[test]
+    fn test_run_result() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![51, 0, 0, 0];
+        let result = test_vm.run().unwrap();
+        assert_eq!(result, RunResult { exit_code: 3, instructions_executed: 1, halted_by: HaltedBy::Syscall { number: 0 } });
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![1, 0, 1, 0];
+        assert_eq!(test_vm.run().unwrap().halted_by, HaltedBy::EndOfProgram);
+    }
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut test_vm = VM::new();
+        let test_bytes = vec![200, 0, 0, 0];
+        test_vm.program = test_bytes;
+        assert_eq!(test_vm.run(), Err(VMError::IllegalOpcode { pc: 0, byte: 200 }));
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_load_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        // Immediates are big-endian, so 500 is 1 then 244
+        test_vm.program = vec![0, 0, 1, 244];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 500);
+    }
+
+    #[test]
+    fn test_loaded_program() {
+        let mut test_vm = VM::new();
+        assert_eq!(test_vm.loaded_program().header, None);
+
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.author 'Ada'\n.code\nstart: load $0 #1\njmp @start").unwrap();
+        let length = program.len();
+        test_vm.add_bytes(program);
+        test_vm.attach_symbols(Some(asm.symbols.clone()));
+
+        let loaded = test_vm.loaded_program();
+        let header = loaded.header.clone().unwrap();
+        assert_eq!(loaded.length, length);
+        assert_eq!(header.read_only, 64..header.code.start);
+        assert_eq!(header.metadata, Some(header.read_only.clone()));
+        assert_eq!(header.code.end, length);
+        assert_eq!(header.fields, vec![("author".to_string(), "Ada".to_string())]);
+        assert!(loaded.has_debug_info());
+        assert!(loaded.to_lines().contains(&format!("{:>10}  Label start", header.code.start)));
+
+        test_vm.attach_symbols(None);
+        assert!(!test_vm.loaded_program().has_debug_info());
+    }
+
+    #[test]
+    fn test_assembled_immediates_round_trip() {
+        let source = ".data\n.code\nload $0 #500\nload $1 #70000\nhlt";
+        let program = Assembler::new().assemble(source).unwrap();
+        let start = code_start(&program).unwrap();
+        let (instruction, _) = disassemble_instruction(&program[start..], NumberFormat::Decimal).unwrap();
+        assert_eq!(instruction, "load $0 #500");
+
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(program);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 500);
+        assert_eq!(test_vm.registers[1], 70000);
+    }
+
+    #[test]
+    fn test_lui_and_ori_opcodes() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![39, 0, 0x12, 0x34, 60, 0, 0x56, 0x78];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 0x1234_0000);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 0x1234_5678);
+    }
+
+    #[test]
+    fn test_li_loads_negative_numbers() {
+        let mut asm = Assembler::new();
+        let program = asm.assemble(".data\n.code\nli $2 #-100000\nhlt").unwrap();
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(program);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], -100000);
+    }
+
+    #[test]
+    fn test_jmp_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 1;
+        test_vm.program = vec![6, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 1);
+    }
+
+    #[test]
+    fn test_jmpr_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 8;
+        test_vm.registers[1] = 4;
+        test_vm.program = vec![61, 0, 1, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 12);
+    }
+
+    #[test]
+    fn test_br_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![16, 0, 0, 0, 62, 255, 252, 0];
+        test_vm.pc = 4;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 0);
+
+        test_vm.program = vec![62, 0, 8, 0];
+        test_vm.pc = 0;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_cloop_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![40, 0, 10, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.loop_counter, 10);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_loop_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.loop_counter = 2;
+        test_vm.program = vec![41, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.loop_counter, 1);
+        assert_eq!(test_vm.pc, 0);
+
+        // The last iteration falls through instead of jumping
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.loop_counter, 0);
+        assert_eq!(test_vm.pc, 4);
+
+        test_vm.pc = 0;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.loop_counter, 0);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut test_vm = VM::get_test_vm();
+        assert!(test_vm.stats().is_none());
+        test_vm.enable_stats();
+        test_vm.program = vec![1, 0, 1, 2, 5, 0, 0, 0];
+        test_vm.run().unwrap();
+        let stats = test_vm.stats().unwrap();
+        assert_eq!(stats.instructions, 2);
+        assert_eq!(stats.opcodes["add"], 1);
+        assert_eq!(stats.hottest_pcs(1), vec![(0, 1)]);
+
+        let profile = test_vm.profile().unwrap();
+        assert_eq!(profile.opcodes, vec![("add".to_string(), 1), ("hlt".to_string(), 1)]);
+        assert_eq!(profile.hot_spots[0], HotSpot { pc: 0, count: 1, instruction: Some("add $0 $1 $2".to_string()) });
+    }
+
+    #[test]
+    fn test_rand_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.seed_random(1);
+        test_vm.registers[1] = 6;
+        test_vm.program = vec![65, 2, 0, 0, 65, 3, 1, 1];
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], Xorshift::new(1).next_u32() as i32);
+        assert!(test_vm.registers[3] >= 0 && test_vm.registers[3] < 6);
+
+        test_vm.registers[1] = -1;
+        test_vm.pc = 4;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[3], 0);
+    }
+
+    #[test]
+    fn test_wcode_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[2] = 0x0002_0100;
+        test_vm.registers[3] = 4;
+        test_vm.set_writable_code(Some(4..8));
+        test_vm.program = vec![66, 2, 3, 0, 16, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.program[4..8], [0, 2, 1, 0]);
+
+        // Writes that reach outside the region fault and leave the program alone
+        test_vm.registers[3] = 2;
+        test_vm.pc = 0;
+        assert!(test_vm.execute_instruction());
+        assert_eq!(test_vm.program, vec![66, 2, 3, 0, 0, 2, 1, 0]);
+    }
+
+    #[test]
+    fn test_predecode() {
+        // The WCODE overwrites the HLT after it with a LOAD, which must run instead of the decoded HLT
+        let mut test_vm = VM::builder().predecode(true).build();
+        test_vm.registers[2] = 0x0003_0007;
+        test_vm.registers[3] = 4;
+        test_vm.set_writable_code(Some(4..8));
+        test_vm.add_bytes(vec![66, 2, 3, 0, 5, 0, 0, 0]);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 7);
+
+        // A write by the host that leaves the program's length alone is decoded by the next run
+        test_vm.program[0..4].copy_from_slice(&[0, 4, 0, 9]);
+        test_vm.pc = 0;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[4], 9);
+
+        // Added instructions are decoded too
+        test_vm.add_bytes(vec![1, 3, 4, 5]);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[5], 16);
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_predecode(true);
+        test_vm.set_wide_encoding(true);
+        test_vm.add_bytes(vec![0, 1, 134, 160, 0, 2, 134, 160]);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], 100_000);
+    }
+
+    #[test]
+    fn test_wcode_without_region() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![66, 0, 1, 0];
+        assert!(test_vm.execute_instruction());
+        assert_eq!(test_vm.program, vec![66, 0, 1, 0]);
+    }
+
+    #[test]
+    fn test_wide_encoding() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_wide_encoding(true);
+        test_vm.program = vec![0, 1, 134, 160, 0, 2, 134, 160, 255, 255, 255, 248, 62, 255, 248, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 100_000);
+        assert_eq!(test_vm.pc, 8);
+
+        // Relative jumps are measured from the start of the immediate, where the instruction starts
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 0);
+    }
+
+    #[test]
+    fn test_program_target() {
+        use crate::assembler::target::Target;
+
+        let source = ".data\n.code\nload $0 #1\nhlt";
+        let mut asm = Assembler::new();
+        asm.set_target(Target::parse("iridium64").unwrap());
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(asm.assemble(source).unwrap());
+        assert!(matches!(test_vm.run(), Err(VMError::IncompatibleTarget { .. })));
+        assert_eq!(test_vm.registers[0], 0);
+
+        // A fixed heap caps the VM's heap, but can't be bigger than a limit the VM already has
+        asm.set_target(Target::parse("iridium32-heap64").unwrap());
+        let program = asm.assemble(source).unwrap();
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(program.clone());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.heap.limit(), Some(64));
+        let mut test_vm = VM::new();
+        test_vm.set_max_heap_size(Some(32));
+        test_vm.add_bytes(program);
+        assert!(matches!(test_vm.run(), Err(VMError::IncompatibleTarget { .. })));
+    }
+
+    #[test]
+    fn test_rdcycle_opcode() {
+        let source = ".data\n.code\nload $2 #3\nrdcycle $0\nadd $2 $2 $2\nadd $2 $2 $2\nrdcycle $1\nhlt";
+        let mut test_vm = VM::get_test_vm();
+        test_vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 1);
+        assert_eq!(test_vm.registers[1] - test_vm.registers[0], 3);
+        assert!(Assembler::new().assemble(".data\n.code\nrdcycle\nhlt").is_err());
+    }
+
+    #[test]
+    fn test_jmpf_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![7, 0, 0, 0, 6, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_eq_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 10;
+        test_vm.registers[1] = 10;
+        test_vm.program = vec![9, 0, 1, 0, 9, 0, 1, 0];
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.registers[1] = 20;
+        test_vm.run_once().unwrap();
+        assert!(!test_vm.flags.is_set(FLAG_ZERO));
+    }
+
+    #[test]
+    fn test_jeq_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.flags = Flags::from_bits(FLAG_ZERO);
+        test_vm.program = vec![15, 0, 0, 0, 17, 0, 0, 0, 17, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 7);
+    }
+
+    #[test]
+    fn test_arithmetic_flags() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[2] = i32::MAX;
+        test_vm.registers[3] = -1;
+        test_vm.program = vec![2, 0, 0, 4, 1, 2, 1, 4, 1, 3, 3, 4, 3, 2, 1, 4];
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_OVERFLOW));
+        assert!(test_vm.flags.is_set(FLAG_NEGATIVE));
+        assert_eq!(test_vm.registers[4], i32::MIN + 9);
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_CARRY));
+        assert!(!test_vm.flags.is_set(FLAG_OVERFLOW));
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_OVERFLOW));
+    }
+
+    #[test]
+    fn test_eq_sets_carry_when_below() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![9, 0, 1, 0];
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_CARRY));
+        assert!(test_vm.flags.is_set(FLAG_NEGATIVE));
+        assert!(!test_vm.flags.is_set(FLAG_ZERO));
+    }
+
+    #[test]
+    fn test_conditional_jumps() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 100;
+        test_vm.flags = Flags::from_bits(FLAG_CARRY);
+        test_vm.program = vec![55, 0, 0, 0, 56, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 100);
+
+        test_vm.pc = 0;
+        test_vm.program = vec![57, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 100);
+
+        test_vm.pc = 0;
+        test_vm.program = vec![58, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_overflow_jumps() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(".data\n.code\nload $5 @overflowed\nload $6 @fine\nmul $0 $1 $2\njno $6\noverflowed: load $3 #1\nhlt\nfine: load $3 #2\nhlt\n")
+            .unwrap();
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.add_bytes(program.clone());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 2);
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = i32::MAX;
+        test_vm.add_bytes(program);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 1);
+        assert_eq!(test_vm.registers[2], i32::MAX.wrapping_mul(10));
+    }
+
+    #[test]
+    fn test_jeq_opcode_not_taken() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 7;
+        test_vm.program = vec![15, 0, 0, 0, 17, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_aloc_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 1024;
+        test_vm.program = vec![17, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap.len(), 1024);
+    }
+
+    #[test]
+    fn test_aloc_opcode_returns_base_address() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 16;
+        test_vm.program = vec![17, 0, 1, 0, 17, 0, 2, 0];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[1], 0);
+        assert_eq!(test_vm.registers[2], 16);
+        assert_eq!(test_vm.heap.len(), 32);
+        assert_eq!(test_vm.pc, 8);
+    }
+
+    #[test]
+    fn test_jump_past_end_of_program() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 100;
+        test_vm.program = vec![6, 0, 0, 0];
+        assert_eq!(test_vm.run(), Err(VMError::OutOfBoundsJump { pc: 0, target: 100 }));
+
+        // Jumping to the very end is how some programs finish
+        test_vm.registers[0] = 4;
+        test_vm.pc = 0;
+        assert!(test_vm.run().is_ok());
+    }
+
+    #[test]
+    fn test_memory_mapped_device() {
+        use crate::vm::devices::Framebuffer;
+
+        // sw $0 $2, then lw $3 $2
+        let framebuffer = Framebuffer::new(4, 1);
+        let pixels = framebuffer.pixels();
+        let mut test_vm = VM::get_test_vm();
+        test_vm.map_device(MMIO_BASE, framebuffer.byte_length(), Box::new(framebuffer)).unwrap();
+        test_vm.registers[2] = MMIO_BASE as i32;
+        test_vm.program = vec![50, 0, 2, 4, 49, 3, 2, 4];
+        test_vm.run().unwrap();
+        assert_eq!(*pixels.lock().unwrap(), vec![0, 5, 0, 0]);
+        assert_eq!(test_vm.registers[3], 5);
+        assert!(test_vm.heap().is_empty());
+
+        test_vm.registers[2] = MMIO_BASE as i32 + 64;
+        test_vm.pc = 0;
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { pc: 0, .. })));
+    }
+
+    #[test]
+    fn test_strict_jumps() {
+        // jmp $0, where $0 is in the middle of the second instruction
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 6;
+        test_vm.program = vec![6, 0, 0, 0, 0, 0, 0, 0];
+        test_vm.set_strict_jumps(true);
+        assert_eq!(test_vm.run(), Err(VMError::MisalignedJump { pc: 0, target: 6 }));
+
+        test_vm.registers[0] = 100;
+        test_vm.pc = 0;
+        assert_eq!(test_vm.run(), Err(VMError::OutOfBoundsJump { pc: 0, target: 100 }));
+
+        // A handler for the bad jump trap that loads 9 into $3, installed before the jump
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 2;
+        test_vm.program = vec![53, 4, 0, 12, 6, 0, 0, 0, 5, 0, 0, 0, 0, 3, 0, 9, 54, 0, 0, 0];
+        test_vm.set_strict_jumps(true);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 9);
+        assert_eq!(test_vm.pc, 12);
+    }
+
+    #[test]
+    fn test_run_with_budget() {
+        // An infinite loop: jmp $0 with $0 = 0
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 0;
+        test_vm.program = vec![6, 0, 0, 0];
+        assert_eq!(test_vm.run_with_budget(10), Err(VMError::BudgetExhausted { pc: 0, budget: 10 }));
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2, 5, 0, 0, 0];
+        assert_eq!(test_vm.run_with_budget(1), Err(VMError::BudgetExhausted { pc: 4, budget: 1 }));
+        assert!(test_vm.run_with_budget(1).is_ok());
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_run_for() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 0;
+        test_vm.program = vec![6, 0, 0, 0];
+        let limit = Duration::from_millis(10);
+        assert_eq!(test_vm.run_for(limit), Err(VMError::TimedOut { pc: 0, limit }));
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![1, 0, 1, 2, 5, 0, 0, 0];
+        assert!(test_vm.run_for(Duration::from_secs(60)).is_ok());
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_cancel_handle() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 0;
+        test_vm.program = vec![6, 0, 0, 0];
+        let handle = test_vm.cancel_handle();
+
+        let canceller = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.cancel();
+        });
+        assert_eq!(test_vm.run(), Err(VMError::Cancelled { pc: 0 }));
+        canceller.join().unwrap();
+
+        // The cancellation doesn't carry over to the next run
+        test_vm.program = vec![5, 0, 0, 0];
+        assert!(test_vm.run().is_ok());
+    }
+
+    #[test]
+    fn test_output_buffering() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![Opcode::LOAD.into(), 1, 0, 42, Opcode::SYSCALL.into(), 0, 1, 0];
+        test_vm.program.extend([Opcode::FLUSH.into(), 0, 0, 0, Opcode::SYSCALL.into(), 0, 1, 0]);
+        test_vm.execute_instruction();
+        test_vm.execute_instruction();
+        assert_eq!(test_vm.pending_output(), b"42\n");
+        test_vm.execute_instruction();
+        assert!(test_vm.pending_output().is_empty());
+
+        // A buffer smaller than the line is written out as soon as it's printed
+        test_vm.set_output_buffer(2);
+        test_vm.execute_instruction();
+        assert!(test_vm.pending_output().is_empty());
+        assert_eq!(Assembler::new().assemble(".data\n.code\nflush\nhlt").unwrap()[64], Opcode::FLUSH.into());
+    }
+
+    #[test]
+    fn test_stdio_channels() {
+        let (input, input_receiver) = channel();
+        let (output_sender, output) = channel();
+
+        // Reads numbers and prints each one doubled, until the input ends and reading gives 0
+        let vm = std::thread::spawn(move || {
+            let source = ".data\n.code\nload $2 @top\ntop: syscall #2\nadd $0 $0 $1\nsyscall #1\njnz $2\nhlt";
+            let mut test_vm = VM::new();
+            test_vm.add_bytes(Assembler::new().assemble(source).unwrap());
+            test_vm.set_input_channel(input_receiver);
+            test_vm.set_output_channel(Some(output_sender));
+            test_vm.set_output_buffer(0);
+            test_vm.run().unwrap();
+        });
+
+        input.send(b"21\n".to_vec()).unwrap();
+        assert_eq!(output.recv().unwrap(), b"42\n");
+        input.send(b"5\n".to_vec()).unwrap();
+        assert_eq!(output.recv().unwrap(), b"10\n");
+        drop(input);
+        vm.join().unwrap();
+        assert_eq!(output.recv().unwrap(), b"0\n");
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 4;
+        // Counts up in $2 forever, jumping back to the ADD at 4
+        test_vm.program = vec![0, 2, 0, 0, 1, 2, 1, 2, 6, 0, 0, 0];
+        let handle = test_vm.pause_handle();
+
+        let pauser = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(10));
+            handle.pause();
+        });
+        let pc = match test_vm.run() {
+            Err(VMError::Paused { pc }) => pc,
+            other => panic!("expected a pause, got {:?}", other),
+        };
+        pauser.join().unwrap();
+        assert_eq!(pc, test_vm.pc());
+        assert!(test_vm.is_paused());
+
+        // Nothing runs until the VM is resumed
+        let count = test_vm.registers[2];
+        assert_eq!(test_vm.run(), Err(VMError::Paused { pc }));
+        assert_eq!(test_vm.registers[2], count);
+
+        test_vm.resume();
+        assert!(test_vm.run_with_budget(3).is_err());
+        assert!(test_vm.registers[2] > count);
+    }
+
+    #[test]
+    fn test_watchdog_stops_spinning_program() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 0;
+        test_vm.program = vec![6, 0, 0, 0];
+        let watchdog = Watchdog::new(16, Duration::from_millis(5), Box::new(|_, _| true));
+        test_vm.set_watchdog(Some(watchdog));
+        assert_eq!(test_vm.run(), Err(VMError::Stalled { pc: 0, range: 0..1 }));
+    }
+
+    #[test]
+    fn test_lifecycle_events() {
+        let mut test_vm = VM::get_test_vm();
+        let events = test_vm.subscribe();
+        test_vm.program = vec![1, 0, 1, 2, 5, 0, 0, 0];
+        test_vm.run_with_budget(1).unwrap_err();
+        test_vm.run().unwrap();
+        test_vm.program.extend_from_slice(&[200, 0, 0, 0]);
+        test_vm.pc = 8;
+        test_vm.run().unwrap_err();
+
+        let received: Vec<VMEvent> = events.try_iter().collect();
+        assert_eq!(
+            received,
+            vec![
+                VMEvent::Started,
+                VMEvent::Stopped { reason: VMError::BudgetExhausted { pc: 4, budget: 1 } },
+                VMEvent::Started,
+                VMEvent::Halted { code: 0 },
+                VMEvent::Started,
+                VMEvent::Crashed { error: VMError::IllegalOpcode { pc: 8, byte: 200 } },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        let mut test_vm = VM::get_test_vm();
+        let events = test_vm.subscribe();
+        test_vm.program = vec![0, 4, 0, 0, 1, 0, 1, 2, 5, 0, 0, 0];
+        test_vm.set_breakpoint(4);
+        test_vm.set_breakpoint(8);
+
+        assert_eq!(test_vm.run(), Err(VMError::Breakpoint { pc: 4, hits: 1, temporary: false }));
+        assert_eq!(test_vm.registers[2], 0);
+        assert_eq!(test_vm.run(), Err(VMError::Breakpoint { pc: 8, hits: 1, temporary: false }));
+        assert_eq!(test_vm.registers[2], 15);
+        assert!(test_vm.clear_breakpoint(8));
+        assert!(!test_vm.clear_breakpoint(8));
+        assert_eq!(test_vm.breakpoints().map(|breakpoint| breakpoint.address).collect::<Vec<_>>(), vec![4]);
+        test_vm.run().unwrap();
+
+        let received: Vec<VMEvent> = events.try_iter().collect();
+        assert_eq!(received[1], VMEvent::Breakpoint { pc: 4 });
+        assert_eq!(received[3], VMEvent::Breakpoint { pc: 8 });
+        assert_eq!(received[5], VMEvent::Halted { code: 0 });
+    }
+
+    #[test]
+    fn test_temporary_breakpoint() {
+        let mut test_vm = VM::get_test_vm();
+        // Jumps back to the start until $0 has been added to $2 twice
+        test_vm.program = vec![1, 0, 2, 2, 9, 2, 6, 0, 15, 4, 0, 0, 6, 5, 0, 0, 5, 0, 0, 0];
+        test_vm.registers[4] = 16;
+        test_vm.registers[5] = 0;
+        test_vm.registers[6] = 10;
+        test_vm.add_breakpoint(Breakpoint::temporary(0));
+        test_vm.set_breakpoint(16);
+
+        assert_eq!(test_vm.run(), Err(VMError::Breakpoint { pc: 0, hits: 1, temporary: true }));
+        assert_eq!(test_vm.breakpoints().count(), 1);
+        assert_eq!(test_vm.run(), Err(VMError::Breakpoint { pc: 16, hits: 1, temporary: false }));
+        assert_eq!(test_vm.registers[2], 10);
+        assert_eq!(test_vm.remove_breakpoint(16).map(|breakpoint| breakpoint.hits), Some(1));
+        assert!(test_vm.remove_breakpoint(16).is_none());
+    }
+
+    #[test]
+    fn test_step() {
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(Assembler::new().assemble(".data\n.code\nload $0 #7\nadd $0 $0 $1\nhlt").unwrap());
+
+        let step = test_vm.step();
+        assert_eq!((step.pc, step.next_pc), (64, 68));
+        assert_eq!(step.registers, vec![RegisterChange { number: 0, old: 0, new: 7 }]);
+        assert_eq!(test_vm.step().to_string(), "68: add $0 $0 $1 => 72, $1: 0 => 14");
+
+        let step = test_vm.step();
+        assert_eq!(step.instruction.as_deref(), Some("hlt"));
+        assert!(step.registers.is_empty());
+        assert_eq!(step.stopped.map(|result| result.unwrap().halted_by), Some(HaltedBy::Hlt));
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![0, 2, 0, 5, 1, 0, 1, 3, 5, 0, 0, 0];
+        assert!(test_vm.add_watchpoint(Watchpoint::Register(3)));
+        assert!(!test_vm.add_watchpoint(Watchpoint::Register(32)));
+
+        let watchpoint = Watchpoint::Register(3);
+        assert_eq!(test_vm.run(), Err(VMError::Watchpoint { pc: 8, changed_by: 4, watchpoint, old: 0, new: 15 }));
+        test_vm.run().unwrap();
+
+        let source = ".data\n.code\nload $0 #4\naloc $0 $1\nload $2 #9\nsw $2 $1 #0\nsw $2 $1 #0\nhlt";
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(Assembler::new().assemble(source).unwrap());
+        test_vm.add_watchpoint(Watchpoint::Heap(0));
+        let error = test_vm.run().unwrap_err();
+        let watchpoint = Watchpoint::Heap(0);
+        assert_eq!(error, VMError::Watchpoint { pc: 80, changed_by: 76, watchpoint, old: 0, new: 9 });
+        assert_eq!(error.to_string(), "The instruction at 76 changed heap word 0 from 0 to 9");
+        assert!(test_vm.remove_watchpoint(Watchpoint::Heap(0)));
+        assert_eq!(test_vm.watchpoints().count(), 0);
+        test_vm.run().unwrap();
+    }
+
+    #[test]
+    fn test_stack_canaries() {
+        // fill allocates an 8 byte array and stores a word at $3 bytes into it
+        let source = ".data\n.code\nload $0 #8\ncall @fill\nhlt\nfill: aloc $0 $1\nload $2 #99\nadd $1 $3 $1\n\
+                      overflow: sw $2 $1 #0\nret";
+        let mut asm = Assembler::new();
+        let program = asm.assemble(source).unwrap();
+        let overflow = asm.symbols.symbol_value("overflow").unwrap() as usize;
+
+        let mut test_vm = VM::new();
+        test_vm.set_stack_canaries(true);
+        test_vm.add_bytes(program.clone());
+        test_vm.registers[3] = 4;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.heap.len(), 12);
+
+        let mut test_vm = VM::new();
+        test_vm.set_stack_canaries(true);
+        test_vm.add_bytes(program);
+        test_vm.registers[3] = 8;
+        let error = test_vm.run().unwrap_err();
+        assert_eq!(error, VMError::StackSmashed { pc: overflow + 4, array: 0, overwritten_by: Some(overflow) });
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        // Adds $1 to $0 and stores it on the heap, twice
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(4);
+        test_vm.registers[2] = 0;
+        test_vm.program = vec![1, 0, 1, 0, 50, 0, 2, 0, 1, 0, 1, 0, 50, 0, 2, 0];
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+
+        let bytes = test_vm.snapshot().to_bytes();
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 25);
+
+        let mut restored = VM::new();
+        restored.restore(VMState::from_bytes(&bytes).unwrap()).unwrap();
+        assert_eq!(restored.pc(), 8);
+        assert_eq!(restored.heap_slice(0, 4), Some(&[15, 0, 0, 0][..]));
+        restored.run().unwrap();
+        assert_eq!(restored.registers, test_vm.registers);
+        assert_eq!(restored.heap(), test_vm.heap());
+
+        assert!(VMState::from_bytes(&bytes[..bytes.len() - 1]).is_err());
+        assert!(VMState::from_bytes(b"nope").is_err());
+    }
+
+    #[test]
+    fn test_builder() {
+        let mut test_vm = VM::builder().heap_kb(1).float_support(false).build();
+        let config = VMConfig { heap_limit: Some(1024), float_support: false, ..VMConfig::default() };
+        assert_eq!(test_vm.config(), &config);
+        test_vm.add_bytes(Assembler::new().assemble(".data\n.code\nload $0 #2048\naloc $0 $1\nhlt").unwrap());
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { .. })));
+
+        // Float opcodes are illegal without float support, and a snapshot that may need them can't be restored
+        test_vm.program = vec![Opcode::LOADF64.into(), 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        test_vm.pc = 0;
+        assert_eq!(test_vm.run(), Err(VMError::IllegalOpcode { pc: 0, byte: Opcode::LOADF64.into() }));
+        let snapshot = VM::new().snapshot();
+        assert!(test_vm.restore(VMState::from_bytes(&snapshot.to_bytes()).unwrap()).is_err());
+        assert!(VM::new().restore(test_vm.snapshot()).is_ok());
+    }
+
+    #[test]
+    fn test_heap_tracking() {
+        let source = ".data\n.code\nload $0 #8\nmake: aloc $0 $1\naloc $0 $2\nfree $1\nhlt";
+        let mut asm = Assembler::new();
+        let mut test_vm = VM::builder().heap_tracking(true).build();
+        test_vm.add_bytes(asm.assemble(source).unwrap());
+        test_vm.attach_symbols(Some(asm.symbols.clone()));
+        test_vm.run().unwrap();
+
+        let make = asm.symbols.symbol_value("make").unwrap() as usize;
+        let sites: Vec<(usize, usize)> = test_vm.allocations().unwrap().sites().map(|s| (s.pc, s.frees)).collect();
+        assert_eq!(sites, vec![(make, 1), (make + 4, 0)]);
+        let report = test_vm.heap_report().unwrap();
+        assert_eq!(report.last().unwrap(), &format!("  8 bytes at 8, allocated at {} (make + 4)", make + 4));
+        assert_eq!(VM::new().heap_report(), None);
+    }
+
+    #[test]
+    fn test_aloc_opcode_negative_size() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = -1;
+        test_vm.program = vec![17, 0, 1, 0];
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { pc: 0, .. })));
+        assert!(test_vm.heap.is_empty());
+    }
+
+    #[test]
+    fn test_free_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 16;
+        test_vm.program = vec![17, 0, 1, 0, 48, 1, 0, 0, 17, 0, 2, 0];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], test_vm.registers[1]);
+        assert_eq!(test_vm.heap.len(), 16);
+        assert_eq!(test_vm.heap_stats().total_frees, 1);
+        assert_eq!(test_vm.heap_stats().bytes_in_use, 16);
+    }
+
+    #[test]
+    fn test_free_opcode_unallocated_address() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = 3;
+        test_vm.program = vec![48, 0, 0, 0, 5, 0, 0, 0];
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { pc: 0, .. })));
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_heap_slice_bounds() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(8);
+        assert!(test_vm.heap_slice(4, 4).is_some());
+        assert!(test_vm.heap_slice(5, 4).is_none());
+        assert!(test_vm.heap_slice(usize::MAX, 2).is_none());
+        test_vm.heap_slice_mut(0, 2).unwrap().copy_from_slice(&[1, 2]);
+        assert_eq!(test_vm.heap()[0..3], [1, 2, 0]);
+    }
+
+    #[test]
+    fn test_shl_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![33, 0, 4, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 80);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_shl_opcode_register_amount() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![33, 0, 1, 1];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 40);
+    }
+
+    #[test]
+    fn test_shr_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[0] = -16;
+        test_vm.registers[1] = 28;
+        test_vm.program = vec![34, 0, 1, 1, 34, 1, 2, 0];
+        test_vm.run().unwrap();
+        // Shifts are logical, so the sign bit is not carried along
+        assert_eq!(test_vm.registers[0], 15);
+        assert_eq!(test_vm.registers[1], 7);
+    }
+
+    #[test]
+    fn test_loadf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![22, 2, 0, 0, 64, 6, 0, 0, 0, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.float_registers[2], 2.75);
+        assert_eq!(test_vm.pc, 12);
+    }
+
+    #[test]
+    fn test_addf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![23, 0, 1, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.float_registers[2], 15.0);
+    }
+
+    #[test]
+    fn test_subf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![24, 1, 0, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.float_registers[2], 5.0);
+    }
+
+    #[test]
+    fn test_mulf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![25, 0, 1, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.float_registers[2], 50.0);
+    }
+
+    #[test]
+    fn test_divf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![26, 1, 0, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.float_registers[2], 2.0);
+    }
+
+    #[test]
+    fn test_eqf64_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.float_registers[1] = 5.0;
+        test_vm.program = vec![27, 0, 1, 0, 28, 0, 1, 0];
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.run_once().unwrap();
+        assert!(!test_vm.flags.is_set(FLAG_ZERO));
+    }
+
+    #[test]
+    fn test_float_comparison_opcodes() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![29, 0, 1, 0, 30, 1, 0, 0, 31, 0, 1, 0, 32, 1, 1, 0];
+        test_vm.run_once().unwrap();
+        assert!(!test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+        test_vm.run_once().unwrap();
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+    }
+
+    #[test]
+    fn test_call_and_ret_opcodes() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![46, 0, 8, 0, 5, 0, 0, 0, 1, 0, 1, 2, 47, 0, 0, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 8);
+        assert_eq!(test_vm.call_stack(), &[4]);
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.pc, 4);
+        assert!(test_vm.call_stack().is_empty());
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_nested_calls() {
+        let source = ".data\n.code\nload $0 #1\nload $1 #2\ncall @double\nhlt\n\
+                      double: mul $0 $1 $0\ncall @triple\nret\n\
+                      triple: add $0 $0 $2\nadd $0 $2 $0\nret\n";
+        let mut asm = Assembler::new();
+        let mut test_vm = VM::new();
+        test_vm.add_bytes(asm.assemble(source).unwrap());
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 6);
+        assert!(test_vm.call_stack().is_empty());
+    }
+
+    #[test]
+    fn test_max_call_depth() {
+        // A function that calls itself forever
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_max_call_depth(3);
+        test_vm.program = vec![46, 0, 0, 0];
+        assert_eq!(test_vm.run(), Err(VMError::CallDepthExceeded { pc: 0, depth: 3 }));
+        assert_eq!(test_vm.call_stack().len(), 3);
+    }
+
+    #[test]
+    fn test_spawn_and_yield_opcodes() {
+        let mut asm = Assembler::new();
+        let source = ".data\n.code\nload $5 #4\naloc $5 $6\nspawn $1 @worker\nspawn $2 @worker\nyield\nlw $0 $6\nhlt $0\n\
+                      worker: lw $7 $6\nload $8 #1\nadd $7 $8 $7\nsw $7 $6\nret";
+        let mut vm = VM::new();
+        vm.add_bytes(asm.assemble(source).unwrap());
+
+        // Each worker runs once the main thread yields, and hands back to it when it returns
+        let result = vm.run().unwrap();
+        assert_eq!(result.exit_code, 2);
+        assert_eq!((vm.registers[1], vm.registers[2]), (1, 2));
+        assert_eq!(vm.current_context(), 0);
+
+        let mut vm = VM::new();
+        vm.set_max_contexts(1);
+        vm.add_bytes(asm.assemble(".data\n.code\nspawn $0 @end\nyield\nend: hlt").unwrap());
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0], -1);
+    }
+
+    #[test]
+    fn test_send_and_recv_opcodes() {
+        let office = PostOffice::new();
+        let mut asm = Assembler::new();
+        let mut ping = VM::new();
+        ping.join_post_office(office.clone(), 1).unwrap();
+        ping.add_bytes(asm.assemble(".data\n.code\nload $0 #2\nload $1 #41\nsend $0 $1\nrecv $2\nhlt $2").unwrap());
+        let program = asm.assemble(".data\n.code\nrecv $0\nload $1 #1\nadd $0 $1 $0\nsend $1 $0\nhlt").unwrap();
+
+        // Each VM runs on a thread of its own, and pong waits for ping's message before answering it
+        let pong_office = office.clone();
+        let (ready, is_ready) = channel();
+        let pong = std::thread::spawn(move || {
+            let mut pong = VM::new();
+            pong.join_post_office(pong_office, 2).unwrap();
+            pong.add_bytes(program);
+            ready.send(()).unwrap();
+            pong.run().unwrap();
+        });
+        is_ready.recv().unwrap();
+        assert_eq!(ping.run().unwrap().exit_code, 42);
+        pong.join().unwrap();
+        assert!(VM::new().join_post_office(office, 2).is_err());
+
+        let mut vm = VM::new();
+        vm.join_post_office(PostOffice::new(), 1).unwrap();
+        vm.program = vec![72, 0, 0, 0, 71, 1, 0, 0];
+        vm.registers[1] = 2;
+        vm.run().unwrap();
+        assert!(!vm.flags().is_set(FLAG_ZERO));
+
+        let mut vm = VM::new();
+        vm.program = vec![72, 0, 0, 1];
+        assert_eq!(vm.run(), Err(VMError::NoMailbox { pc: 0 }));
+    }
+
+    #[test]
+    fn test_ret_with_empty_call_stack() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![47, 0, 0, 0, 1, 0, 1, 2];
+        assert_eq!(test_vm.run(), Err(VMError::ReturnWithoutCall { pc: 0 }));
+        assert_eq!(test_vm.pc, 1);
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_sw_and_lw_opcodes() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(16);
+        test_vm.registers[2] = 4;
+        test_vm.program = vec![50, 1, 2, 4, 49, 3, 2, 4];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.heap_slice(8, 4), Some(&[10, 0, 0, 0][..]));
+        assert_eq!(test_vm.registers[3], 10);
+    }
+
+    #[test]
+    fn test_cas_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(8);
+        test_vm.registers[2] = 4;
+        test_vm.registers[3] = 0;
+        test_vm.registers[4] = 1;
+        test_vm.program = vec![63, 2, 3, 4, 63, 2, 3, 4];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap_slice(4, 4), Some(&[1, 0, 0, 0][..]));
+        assert_eq!(test_vm.registers[3], 0);
+        assert!(test_vm.flags.is_set(FLAG_ZERO));
+
+        // The word now holds 1, so the second swap fails and reports what it found
+        test_vm.registers[4] = 2;
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap_slice(4, 4), Some(&[1, 0, 0, 0][..]));
+        assert_eq!(test_vm.registers[3], 1);
+        assert!(!test_vm.flags.is_set(FLAG_ZERO));
+    }
+
+    #[test]
+    fn test_xadd_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(8);
+        test_vm.registers[2] = 4;
+        test_vm.program = vec![64, 3, 2, 1, 64, 3, 2, 1];
+        test_vm.run_once().unwrap();
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.heap_slice(4, 4), Some(&[20, 0, 0, 0][..]));
+        assert_eq!(test_vm.registers[3], 10);
+    }
+
+    #[test]
+    fn test_xadd_misaligned_address() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(8);
+        test_vm.registers[2] = 2;
+        test_vm.program = vec![64, 3, 2, 1];
+        assert!(test_vm.execute_instruction());
+        assert_eq!(test_vm.heap(), &[0; 8]);
+    }
+
+    #[test]
+    fn test_vector_opcodes() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[..8].copy_from_slice(&[1, 2, 3, 4, 10, 20, 30, i32::MAX]);
+        test_vm.program = vec![67, 0, 4, 4, 68, 0, 4, 2];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[..4], [11, 22, 33, i32::MIN + 3]);
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[..4], [110, 440, 33, i32::MIN + 3]);
+    }
+
+    #[test]
+    fn test_vector_past_last_register() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![67, 0, 30, 4];
+        assert!(test_vm.execute_instruction());
+        assert_eq!(test_vm.registers[0], 5);
+    }
+
+    #[test]
+    fn test_aloc_past_heap_limit() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_max_heap_size(Some(8));
+        test_vm.program = vec![17, 1, 2, 0];
+        assert!(test_vm.execute_instruction());
+        assert!(test_vm.heap().is_empty());
+
+        test_vm.registers[1] = 8;
+        test_vm.pc = 0;
+        assert!(!test_vm.execute_instruction());
+        assert_eq!(test_vm.heap().len(), 8);
+    }
+
+    #[test]
+    fn test_lw_misaligned_address() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(16);
+        test_vm.registers[2] = 2;
+        test_vm.program = vec![49, 3, 2, 0, 1, 0, 1, 2];
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { pc: 0, .. })));
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.registers[2], 2);
+    }
+
+    #[test]
+    fn test_sw_out_of_bounds() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.heap.allocate(8);
+        test_vm.registers[2] = 8;
+        test_vm.program = vec![50, 1, 2, 0];
+        assert!(matches!(test_vm.run(), Err(VMError::MemoryFault { pc: 0, .. })));
+        assert_eq!(test_vm.heap_slice(4, 4), Some(&[0, 0, 0, 0][..]));
+        test_vm.registers[2] = -4;
+        test_vm.pc = 0;
+        assert!(test_vm.run().is_err());
+        assert_eq!(test_vm.heap(), &[0; 8]);
+    }
+
+    #[test]
+    fn test_syscall_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[1] = 3;
+        test_vm.program = vec![51, 0, 0, 0, 1, 0, 1, 2];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.exit_code(), Some(3));
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_syscall_custom_handler() {
+        fn answer(vm: &mut VM) -> bool {
+            vm.registers[0] = 42;
+            false
+        }
+
+        let mut test_vm = VM::get_test_vm();
+        test_vm.syscalls_mut().register(500, answer);
+        test_vm.program = vec![51, 1, 244, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[0], 42);
+    }
+
+    #[test]
+    fn test_record_and_replay() {
+        // rand $2, then readline
+        let program = vec![65, 2, 0, 0, 51, 0, 5, 0];
+        let mut recorded = VM::new();
+        recorded.set_input(Box::new(&b"hello\n"[..]));
+        recorded.program = program.clone();
+        recorded.start_recording();
+        recorded.run().unwrap();
+        let recording = recorded.take_recording().unwrap();
+        assert_eq!(recording.inputs.len(), 2);
+        assert!(recorded.take_recording().is_none());
+
+        let mut replayed = VM::new();
+        replayed.set_input(Box::new(&b"goodbye\n"[..]));
+        replayed.program = program.clone();
+        replayed.replay(Recording::from_bytes(&recording.to_bytes()).unwrap());
+        replayed.run().unwrap();
+        assert_eq!(replayed.registers, recorded.registers);
+        assert_eq!(replayed.heap_slice(0, 5), Some(&b"hello"[..]));
+
+        let mut diverged = VM::new();
+        diverged.program = program;
+        diverged.replay(Recording::new());
+        assert_eq!(diverged.run(), Err(VMError::ReplayDiverged { pc: 0 }));
+    }
+
+    #[test]
+    fn test_record_and_replay_device_reads() {
+        use crate::vm::devices::Framebuffer;
+
+        // lw $3 $2, reading the second pixel
+        let program = vec![49, 3, 2, 4];
+        let framebuffer = Framebuffer::new(4, 1);
+        framebuffer.pixels().lock().unwrap()[1] = 9;
+        let mut recorded = VM::new();
+        recorded.map_device(MMIO_BASE, framebuffer.byte_length(), Box::new(framebuffer)).unwrap();
+        recorded.registers[2] = MMIO_BASE as i32;
+        recorded.program = program.clone();
+        recorded.start_recording();
+        recorded.run().unwrap();
+        assert_eq!(recorded.devices_used().iter().copied().collect::<Vec<_>>(), vec![MMIO_BASE + 4]);
+        let recording = recorded.take_recording().unwrap();
+        assert!(matches!(recording.inputs[..], [RecordedInput::DeviceRead(9)]));
+
+        // The word comes from the recording, so the device doesn't need to be there
+        let mut replayed = VM::new();
+        replayed.registers[2] = MMIO_BASE as i32;
+        replayed.program = program.clone();
+        replayed.replay(recording);
+        replayed.run().unwrap();
+        assert_eq!(replayed.registers[3], 9);
+
+        let mut diverged = VM::new();
+        diverged.registers[2] = MMIO_BASE as i32;
+        diverged.program = program;
+        diverged.replay(Recording::new());
+        assert_eq!(diverged.run(), Err(VMError::ReplayDiverged { pc: 0 }));
+    }
+
+    #[test]
+    fn test_unknown_syscall() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![51, 1, 244, 0, 1, 0, 1, 2];
+        assert_eq!(test_vm.run(), Err(VMError::UnknownSyscall { pc: 0, number: 500 }));
+        assert_eq!(test_vm.registers[2], 0);
+        assert_eq!(test_vm.exit_code(), None);
+    }
+
+    #[test]
+    fn test_hcall_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.register_host_fn(3, |registers| registers[2] = registers[0] + registers[1]);
+        test_vm.program = vec![52, 0, 3, 0];
+        test_vm.run_once().unwrap();
+        assert_eq!(test_vm.registers[2], 15);
+        assert_eq!(test_vm.pc, 4);
+    }
+
+    #[test]
+    fn test_trace_hook() {
+        use std::cell::RefCell;
+        use std::rc::Rc;
+
+        let traced = Rc::new(RefCell::new(vec![]));
+        let log = traced.clone();
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_trace_hook(move |event| log.borrow_mut().push((event.pc, event.opcode, event.operands, event.registers[2])));
+        test_vm.program = vec![1, 0, 1, 2, 0, 3, 0, 9, 5, 0, 0, 0];
+        test_vm.run().unwrap();
+        assert_eq!(
+            *traced.borrow(),
+            vec![(0, Opcode::ADD, [0, 1, 2], 0), (4, Opcode::LOAD, [3, 0, 9], 15), (8, Opcode::HLT, [0, 0, 0], 15)]
+        );
+
+        test_vm.clear_trace_hook();
+        test_vm.pc = 0;
+        test_vm.run().unwrap();
+        assert_eq!(traced.borrow().len(), 3);
+    }
+
+    #[test]
+    fn test_hcall_closure_state() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut test_vm = VM::get_test_vm();
+        test_vm.register_host_fn(1, move |_| counter.set(counter.get() + 1));
+        test_vm.program = vec![52, 0, 1, 0, 52, 0, 1, 0];
+        test_vm.run().unwrap();
+        assert_eq!(calls.get(), 2);
+    }
+
+    #[test]
+    fn test_hcall_unregistered_function() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![52, 0, 9, 0, 1, 0, 1, 2];
+        assert_eq!(test_vm.run(), Err(VMError::UnknownHostFunction { pc: 0, number: 9 }));
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_nop_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![16, 0, 0, 0, 1, 0, 1, 2];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_divide_by_zero_without_handler() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![4, 0, 2, 3, 1, 0, 1, 2];
+        assert_eq!(test_vm.run(), Err(VMError::DivisionByZero { pc: 0 }));
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_divide_by_zero_trap() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(
+                ".data\n.code\nsettrap #0 @handler\ndiv $0 $1 $2\nload $4 #1\nhlt\nhandler: load $3 #7\niret\n",
+            )
+            .unwrap();
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[1] = 0;
+        test_vm.add_bytes(program);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 7);
+        assert_eq!(test_vm.registers[4], 1);
+        assert!(test_vm.trap_stack.is_empty());
+    }
+
+    #[test]
+    fn test_illegal_opcode_trap() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![53, 1, 0, 12, 100, 0, 0, 0, 5, 0, 0, 0, 0, 3, 0, 9, 54, 0, 0, 0];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 9);
+        assert_eq!(test_vm.pc, 12);
+    }
+
+    #[test]
+    fn test_illegal_opcode_handler_reads_instruction() {
+        // The handler looks up the inc $5 it was raised by and emulates it, then the program carries on after it
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![
+            53, 1, 0, 16, 18, 5, 0, 0, 0, 6, 0, 1, 5, 0, 0, 0, 51, 0, 20, 0, 1, 5, 7, 5, 54, 0, 0, 0,
+        ];
+        test_vm.registers[5] = 41;
+        test_vm.registers[7] = 1;
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[0], 4);
+        assert_eq!(test_vm.registers[1], 0x1205_0000);
+        assert_eq!(test_vm.registers[5], 42);
+        assert_eq!(test_vm.registers[6], 1);
+    }
+
+    #[test]
+    fn test_memory_fault_trap() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![53, 2, 0, 12, 49, 0, 1, 0, 5, 0, 0, 0, 0, 3, 0, 9, 54, 0, 0, 0];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 9);
+    }
+
+    #[test]
+    fn test_iret_outside_handler() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![54, 0, 0, 0, 1, 0, 1, 2];
+        assert_eq!(test_vm.run(), Err(VMError::ReturnOutsideTrap { pc: 0 }));
+        assert_eq!(test_vm.pc, 4);
+        assert_eq!(test_vm.registers[2], 0);
+    }
+
+    #[test]
+    fn test_timer_trap() {
+        let mut asm = Assembler::new();
+        let program = asm
+            .assemble(
+                ".data\n.code\nsettrap #3 @tick\nload $1 #3\nsyscall #4\nload $0 #0\nload $2 #1\nload $3 #12\nload $6 @done\nload $4 @spin\nspin: add $0 $2 $0\neq $0 $3\njmpe $6\njmp $4\ndone: hlt\ntick: add $5 $2 $5\niret\n",
+            )
+            .unwrap();
+        let mut test_vm = VM::get_test_vm();
+        test_vm.registers[5] = 0;
+        test_vm.add_bytes(program);
+        test_vm.run().unwrap();
+        assert!(test_vm.registers[5] > 0);
+        assert_eq!(test_vm.registers[0], 12);
+    }
+
+    #[test]
+    fn test_timer_without_handler() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.set_timer(Some(1));
+        test_vm.program = vec![1, 0, 1, 2, 1, 0, 1, 3];
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[3], 15);
+    }
+
+    fn prepend_header(mut b: Vec<u8>) -> Vec<u8> {
+        let mut prepension = vec![];
+        for byte in PIE_HEADER_PREFIX.iter() {
+            prepension.push(*byte);
+        }
+        while prepension.len() < PIE_HEADER_LENGTH {
+            prepension.push(0);
+        }
+        prepension.append(&mut b);
+        prepension
+    }
+
+    #[test]
+    fn test_mul_opcode() {
+        let mut test_vm = VM::get_test_vm();
+        test_vm.program = vec![3, 0, 1, 2];
+        test_vm.program = prepend_header(test_vm.program);
+        test_vm.run().unwrap();
+        assert_eq!(test_vm.registers[2], 50);
+    }
+}
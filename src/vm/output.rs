@@ -0,0 +1,56 @@
+/// Bytes of program output held back before they are written out, unless told otherwise
+pub const DEFAULT_OUTPUT_BUFFER: usize = 8 * 1024;
+
+/// What PRTS and the print syscall have printed but the VM hasn't written to stdout yet. Holding output back until
+/// there's a buffer's worth, the program flushes it or the run stops keeps print-heavy programs from making a write
+/// for every instruction, and keeps each VM's lines whole when several print at once.
+#[derive(Debug, Default, Clone)]
+pub struct OutputBuffer {
+    bytes: Vec<u8>,
+    /// Bytes held before the buffer counts as full, where 0 means every write is written out straight away
+    capacity: usize,
+}
+
+impl OutputBuffer {
+    pub fn new(capacity: usize) -> OutputBuffer {
+        OutputBuffer { bytes: Vec::with_capacity(capacity), capacity }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Adds bytes to the buffer, returning true if it's now full and should be flushed
+    pub fn write(&mut self, bytes: &[u8]) -> bool {
+        self.bytes.extend_from_slice(bytes);
+        self.bytes.len() >= self.capacity
+    }
+
+    /// The bytes waiting to be written out
+    pub fn pending(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    /// Empties the buffer, returning what was in it
+    pub fn take(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.bytes)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_buffer() {
+        let mut buffer = OutputBuffer::new(8);
+        assert!(!buffer.write(b"hello"));
+        assert_eq!(buffer.pending(), b"hello");
+        assert!(buffer.write(b" world"));
+        assert_eq!(buffer.take(), b"hello world");
+        assert!(buffer.pending().is_empty());
+
+        // Unbuffered, every write fills it
+        assert!(OutputBuffer::new(0).write(b"!"));
+    }
+}
@@ -0,0 +1,46 @@
+use crate::vm::cancel::SharedFlag;
+
+/// Pauses a running VM from another thread. Clones share the same flag, so any of them can pause or resume the VM it
+/// came from.
+#[derive(Debug, Clone, Default)]
+pub struct PauseHandle {
+    paused: SharedFlag,
+}
+
+impl PauseHandle {
+    pub fn new() -> PauseHandle {
+        PauseHandle::default()
+    }
+
+    /// Asks the VM to stop before its next instruction, and to keep stopping there until it's resumed
+    pub fn pause(&self) {
+        self.paused.set();
+    }
+
+    /// Lets the VM run again the next time it's asked to
+    pub fn resume(&self) {
+        self.paused.clear();
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.paused.is_set()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pause_handle() {
+        let handle = PauseHandle::new();
+        let clone = handle.clone();
+        assert!(!handle.is_paused());
+        clone.pause();
+        assert!(handle.is_paused());
+        // Unlike a cancellation, a pause lasts until it's lifted
+        assert!(handle.is_paused());
+        handle.resume();
+        assert!(!clone.is_paused());
+    }
+}
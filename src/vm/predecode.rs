@@ -0,0 +1,123 @@
+use crate::assembler::WIDE_IMMEDIATE_LENGTH;
+use crate::encoding::read_code_i32;
+use crate::instruction::Opcode;
+
+use std::ops::Range;
+
+/// An instruction with its opcode, operands and wide immediate pulled out of the program's bytes, which is what the
+/// interpreter runs
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DecodedInstruction {
+    pub opcode: Opcode,
+    pub operands: [u8; 3],
+    /// The 32-bit immediate in front of the instruction in the wide encoding, or 0 in the usual one
+    pub wide_immediate: i32,
+}
+
+impl DecodedInstruction {
+    /// Decodes the instruction at the start of `bytes`, which must hold a whole one
+    pub fn decode(bytes: &[u8], wide: bool) -> DecodedInstruction {
+        let (wide_immediate, bytes) = if wide {
+            (read_code_i32(bytes), &bytes[WIDE_IMMEDIATE_LENGTH..])
+        } else {
+            (0, bytes)
+        };
+        DecodedInstruction {
+            opcode: Opcode::from_byte(bytes[0]),
+            operands: [bytes[1], bytes[2], bytes[3]],
+            wide_immediate,
+        }
+    }
+
+    /// Decodes the instruction at `pc` in `program`, reading operand bytes past the end of the program as 0. In the
+    /// wide encoding, the 32-bit immediate must be there in full.
+    pub fn read(program: &[u8], pc: usize, wide: bool) -> DecodedInstruction {
+        let (wide_immediate, pc) = if wide {
+            (read_code_i32(&program[pc..]), pc + WIDE_IMMEDIATE_LENGTH)
+        } else {
+            (0, pc)
+        };
+        let operand = |index: usize| program.get(pc + index).copied().unwrap_or(0);
+        DecodedInstruction {
+            opcode: Opcode::from_byte(program[pc]),
+            operands: [operand(1), operand(2), operand(3)],
+            wide_immediate,
+        }
+    }
+}
+
+/// Every whole instruction in a program's code, decoded once and looked up by offset. Only offsets a whole number of
+/// instructions into the code are cached, so a jump into the middle of one decodes it from the bytes as usual.
+#[derive(Debug, Clone)]
+pub struct DecodeCache {
+    start: usize,
+    width: usize,
+    /// The width is a power of two, so offsets are divided by it with a shift
+    width_shift: u32,
+    wide: bool,
+    instructions: Vec<DecodedInstruction>,
+}
+
+impl DecodeCache {
+    /// Decodes the instructions from `start`, the offset of the first one, to the end of `program`
+    pub fn build(program: &[u8], start: usize, wide: bool) -> DecodeCache {
+        let width = if wide { WIDE_IMMEDIATE_LENGTH + 4 } else { 4 };
+        let instructions = program
+            .get(start..)
+            .unwrap_or(&[])
+            .chunks_exact(width)
+            .map(|bytes| DecodedInstruction::decode(bytes, wide))
+            .collect();
+        DecodeCache { start, width, width_shift: width.trailing_zeros(), wide, instructions }
+    }
+
+    /// The decoded instruction starting at `pc`, if there is one in the cache
+    pub fn get(&self, pc: usize) -> Option<&DecodedInstruction> {
+        let offset = pc.checked_sub(self.start)?;
+        if offset & (self.width - 1) != 0 {
+            return None;
+        }
+        self.instructions.get(offset >> self.width_shift)
+    }
+
+    /// Decodes the instructions overlapping `written` again, after the program wrote over its own code
+    pub fn refresh(&mut self, program: &[u8], written: Range<usize>) {
+        let first = written.start.saturating_sub(self.start) / self.width;
+        let last = written.end.saturating_sub(self.start).div_ceil(self.width);
+        for index in first..last.min(self.instructions.len()) {
+            let address = self.start + index * self.width;
+            self.instructions[index] = DecodedInstruction::decode(&program[address..address + self.width], self.wide);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decode_cache() {
+        let mut program = vec![0, 0, 1, 244, 5, 0, 0, 0, 1];
+        let mut cache = DecodeCache::build(&program, 0, false);
+        assert_eq!(cache.get(0).unwrap().opcode, Opcode::LOAD);
+        assert_eq!(cache.get(0).unwrap().operands, [0, 1, 244]);
+        assert_eq!(cache.get(4).unwrap().opcode, Opcode::HLT);
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(8), None);
+
+        program[4] = 16;
+        cache.refresh(&program, 4..8);
+        assert_eq!(cache.get(4).unwrap().opcode, Opcode::NOP);
+        assert_eq!(DecodedInstruction::read(&program, 0, false), *cache.get(0).unwrap());
+        // The last instruction is cut short, so its missing operand reads as 0
+        assert_eq!(DecodedInstruction::read(&program, 8, false).operands, [0, 0, 0]);
+    }
+
+    #[test]
+    fn test_decode_cache_wide() {
+        let program = [0, 1, 0, 0, 0, 2, 0, 0];
+        let cache = DecodeCache::build(&program, 0, true);
+        let load = DecodedInstruction { opcode: Opcode::LOAD, operands: [2, 0, 0], wide_immediate: 65536 };
+        assert_eq!(cache.get(0), Some(&load));
+    }
+}
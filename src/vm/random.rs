@@ -0,0 +1,64 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Used in place of a zero seed, which would leave xorshift stuck producing zeroes forever
+const ZERO_SEED_REPLACEMENT: u64 = 0x9E37_79B9_7F4A_7C15;
+
+/// The xorshift64* generator behind the RAND opcode. It's fast and small, not cryptographically secure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Xorshift {
+    state: u64,
+}
+
+impl Xorshift {
+    /// Creates a generator that always produces the same sequence for the same seed
+    pub fn new(seed: u64) -> Xorshift {
+        Xorshift {
+            state: if seed == 0 { ZERO_SEED_REPLACEMENT } else { seed },
+        }
+    }
+
+    /// Creates a generator seeded from the current time, so each run gets different numbers
+    pub fn from_time() -> Xorshift {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_nanos() as u64).unwrap_or(0);
+        Xorshift::new(nanos)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 32) as u32
+    }
+
+    /// Returns a number in 0..bound. The small bias from using a remainder doesn't matter for what RAND is used for.
+    pub fn below(&mut self, bound: u32) -> u32 {
+        self.next_u32() % bound
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut first = Xorshift::new(42);
+        let mut second = Xorshift::new(42);
+        let first: Vec<u32> = (0..5).map(|_| first.next_u32()).collect();
+        let second: Vec<u32> = (0..5).map(|_| second.next_u32()).collect();
+        assert_eq!(first, second);
+        assert_ne!(first[0], first[1]);
+    }
+
+    #[test]
+    fn test_zero_seed() {
+        let mut generator = Xorshift::new(0);
+        assert_ne!(generator.next_u32(), generator.next_u32());
+    }
+
+    #[test]
+    fn test_below() {
+        let mut generator = Xorshift::new(7);
+        assert!((0..100).all(|_| generator.below(6) < 6));
+    }
+}
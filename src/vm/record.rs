@@ -0,0 +1,254 @@
+use crate::vm::heap::Heap;
+use crate::vm::syscalls::{
+    SYS_CLOSE, SYS_FREAD, SYS_FWRITE, SYS_KV_DELETE, SYS_KV_GET, SYS_KV_PUT, SYS_OPEN, SYS_READ, SYS_READLINE, SYS_TIME,
+    SYS_UDP_BIND, SYS_UDP_CLOSE, SYS_UDP_JOIN, SYS_UDP_RECV, SYS_UDP_SEND,
+};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io;
+use std::path::Path;
+
+/// First bytes of a recording file
+const RECORDING_PREFIX: [u8; 4] = *b"IRRC";
+/// Version of the recording format, bumped whenever the layout changes
+const RECORDING_VERSION: u8 = 1;
+
+/// Syscalls whose results are recorded and replayed instead of being carried out again. On top of the
+/// nondeterministic ones, this covers writing and closing files, since they were only opened while recording,
+/// storing keys, so replays leave the key-value store alone, and every socket syscall, so replays stay off the network.
+pub const RECORDED_SYSCALLS: [u16; 15] = [
+    SYS_READ, SYS_TIME, SYS_READLINE, SYS_OPEN, SYS_FREAD, SYS_FWRITE, SYS_CLOSE, SYS_KV_PUT, SYS_KV_GET, SYS_KV_DELETE,
+    SYS_UDP_BIND, SYS_UDP_SEND, SYS_UDP_RECV, SYS_UDP_JOIN, SYS_UDP_CLOSE,
+];
+
+/// Recorded syscalls that can write to the heap, so the heap they leave behind is recorded with their registers
+const HEAP_WRITING_SYSCALLS: [u16; 4] = [SYS_READLINE, SYS_FREAD, SYS_KV_GET, SYS_UDP_RECV];
+
+const TAG_RANDOM: u8 = 0;
+const TAG_SYSCALL: u8 = 1;
+const TAG_RECEIVED: u8 = 2;
+const TAG_DELIVERED: u8 = 3;
+const TAG_DEVICE_READ: u8 = 4;
+
+/// One value the program got from outside the VM
+#[derive(Debug, Clone)]
+pub enum RecordedInput {
+    /// A number produced by RAND, after any bound was applied
+    Random(u32),
+    /// The registers, and heap if it could have changed, a syscall left behind, and whether it stopped the VM
+    Syscall {
+        number: u16,
+        registers: Box<[i32; 32]>,
+        heap: Option<Heap>,
+        stop: bool,
+    },
+    /// The message RECV took, or None if it only checked for one and there wasn't any
+    Received(Option<i32>),
+    /// Whether a message sent with SEND was delivered
+    Delivered(bool),
+    /// The word LW read from a device
+    DeviceRead(i32),
+}
+
+impl RecordedInput {
+    pub(crate) fn of_syscall(number: u16, registers: [i32; 32], heap: &Heap, stop: bool) -> RecordedInput {
+        let heap = if HEAP_WRITING_SYSCALLS.contains(&number) { Some(heap.clone()) } else { None };
+        RecordedInput::Syscall { number, registers: Box::new(registers), heap, stop }
+    }
+}
+
+/// Every nondeterministic input of a run, in the order the program received them. Replaying it with `VM::replay`
+/// makes the program run exactly as it did while it was recorded.
+#[derive(Debug, Clone, Default)]
+pub struct Recording {
+    pub inputs: Vec<RecordedInput>,
+}
+
+impl Recording {
+    pub fn new() -> Recording {
+        Recording::default()
+    }
+
+    /// Encodes the recording in the recording file format. Integers are little-endian, and each input starts with a
+    /// tag byte saying what kind it is.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = RECORDING_PREFIX.to_vec();
+        out.push(RECORDING_VERSION);
+        out.write_u64::<LittleEndian>(self.inputs.len() as u64).unwrap();
+
+        for input in &self.inputs {
+            match input {
+                RecordedInput::Random(value) => {
+                    out.push(TAG_RANDOM);
+                    out.write_u32::<LittleEndian>(*value).unwrap();
+                }
+                RecordedInput::Syscall { number, registers, heap, stop } => {
+                    out.push(TAG_SYSCALL);
+                    out.write_u16::<LittleEndian>(*number).unwrap();
+                    for register in registers.iter() {
+                        out.write_i32::<LittleEndian>(*register).unwrap();
+                    }
+                    out.push(*stop as u8);
+                    match heap {
+                        Some(heap) => {
+                            out.push(1);
+                            heap.encode(&mut out);
+                        }
+                        None => out.push(0),
+                    }
+                }
+                RecordedInput::Received(message) => {
+                    out.push(TAG_RECEIVED);
+                    out.push(message.is_some() as u8);
+                    out.write_i32::<LittleEndian>(message.unwrap_or(0)).unwrap();
+                }
+                RecordedInput::Delivered(delivered) => {
+                    out.push(TAG_DELIVERED);
+                    out.push(*delivered as u8);
+                }
+                RecordedInput::DeviceRead(value) => {
+                    out.push(TAG_DEVICE_READ);
+                    out.write_i32::<LittleEndian>(*value).unwrap();
+                }
+            }
+        }
+
+        out
+    }
+
+    /// Decodes a recording written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Recording> {
+        if bytes.len() < 5 || bytes[0..4] != RECORDING_PREFIX {
+            return Err(invalid("not an iridium recording"));
+        }
+        if bytes[4] != RECORDING_VERSION {
+            return Err(invalid(&format!(
+                "recording version {} isn't supported, expected {}",
+                bytes[4], RECORDING_VERSION
+            )));
+        }
+        let mut input = &bytes[5..];
+
+        let count = input.read_u64::<LittleEndian>()?;
+        let mut inputs = vec![];
+        for _ in 0..count {
+            let recorded = match input.read_u8()? {
+                TAG_RANDOM => RecordedInput::Random(input.read_u32::<LittleEndian>()?),
+                TAG_SYSCALL => {
+                    let number = input.read_u16::<LittleEndian>()?;
+                    let mut registers = [0; 32];
+                    for register in registers.iter_mut() {
+                        *register = input.read_i32::<LittleEndian>()?;
+                    }
+                    let stop = input.read_u8()? != 0;
+                    let heap = if input.read_u8()? != 0 { Some(Heap::decode(&mut input)?) } else { None };
+                    RecordedInput::Syscall { number, registers: Box::new(registers), heap, stop }
+                }
+                TAG_RECEIVED => {
+                    let received = input.read_u8()? != 0;
+                    let message = input.read_i32::<LittleEndian>()?;
+                    RecordedInput::Received(if received { Some(message) } else { None })
+                }
+                TAG_DELIVERED => RecordedInput::Delivered(input.read_u8()? != 0),
+                TAG_DEVICE_READ => RecordedInput::DeviceRead(input.read_i32::<LittleEndian>()?),
+                tag => return Err(invalid(&format!("unknown input tag {}", tag))),
+            };
+            inputs.push(recorded);
+        }
+
+        Ok(Recording { inputs })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Recording> {
+        Recording::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+/// Whether a VM is recording its inputs, replaying them, or neither
+#[derive(Debug, Default)]
+pub(crate) enum Recorder {
+    #[default]
+    Off,
+    Recording(Recording),
+    /// Replaying a recording, with the index of the next input to hand out
+    Replaying(Recording, usize),
+}
+
+impl Recorder {
+    pub(crate) fn is_replaying(&self) -> bool {
+        matches!(self, Recorder::Replaying(..))
+    }
+
+    pub(crate) fn is_recording(&self) -> bool {
+        matches!(self, Recorder::Recording(_))
+    }
+
+    /// Adds an input to the recording, if there is one
+    pub(crate) fn record(&mut self, input: RecordedInput) {
+        if let Recorder::Recording(recording) = self {
+            recording.inputs.push(input);
+        }
+    }
+
+    /// Hands out the next input of the recording being replayed, or None once it has run out
+    pub(crate) fn next(&mut self) -> Option<RecordedInput> {
+        match self {
+            Recorder::Replaying(recording, next) => {
+                let input = recording.inputs.get(*next).cloned();
+                *next += 1;
+                input
+            }
+            _ => None,
+        }
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_bytes_and_back() {
+        let mut heap = Heap::new();
+        heap.allocate(4).unwrap();
+        let mut registers = [0; 32];
+        registers[0] = 1234;
+
+        let recording = Recording {
+            inputs: vec![
+                RecordedInput::Random(99),
+                RecordedInput::of_syscall(SYS_TIME, registers, &heap, false),
+                RecordedInput::of_syscall(SYS_READLINE, registers, &heap, true),
+                RecordedInput::Received(Some(-5)),
+                RecordedInput::Delivered(true),
+                RecordedInput::DeviceRead(-7),
+            ],
+        };
+        let decoded = Recording::from_bytes(&recording.to_bytes()).unwrap();
+        assert_eq!(decoded.inputs.len(), 6);
+        assert!(matches!(decoded.inputs[5], RecordedInput::DeviceRead(-7)));
+        assert!(matches!(decoded.inputs[3], RecordedInput::Received(Some(-5))));
+        assert!(matches!(decoded.inputs[4], RecordedInput::Delivered(true)));
+        assert!(matches!(decoded.inputs[0], RecordedInput::Random(99)));
+        assert!(matches!(decoded.inputs[1], RecordedInput::Syscall { heap: None, stop: false, .. }));
+        match &decoded.inputs[2] {
+            RecordedInput::Syscall { number, registers, heap: Some(heap), stop } => {
+                assert_eq!(*number, SYS_READLINE);
+                assert_eq!(registers[0], 1234);
+                assert_eq!(heap.stats().total_allocations, 1);
+                assert!(*stop);
+            }
+            other => panic!("unexpected input {:?}", other),
+        }
+
+        assert!(Recording::from_bytes(b"IRSN\x01").is_err());
+    }
+}
@@ -0,0 +1,20 @@
+/// What stopped a program that finished without failing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum HaltedBy {
+    /// A HLT instruction
+    Hlt,
+    /// A syscall that stops the VM, such as exit
+    Syscall { number: u16 },
+    /// Running off the end of the program
+    EndOfProgram,
+}
+
+/// How a run of the VM finished, returned by `VM::run` and the other run methods
+#[derive(Debug, Clone, PartialEq)]
+pub struct RunResult {
+    /// Exit code from the exit syscall or `hlt $register`, or 0 if the program didn't give one
+    pub exit_code: i32,
+    /// Instructions executed by this call, not counting earlier ones the program resumed from
+    pub instructions_executed: u64,
+    pub halted_by: HaltedBy,
+}
@@ -0,0 +1,162 @@
+use crate::vm::flags::Flags;
+use crate::vm::traps::TrapFrame;
+
+use std::collections::VecDeque;
+
+/// Default limit on how many green threads, counting the main one, can exist at once
+pub const DEFAULT_MAX_CONTEXTS: usize = 256;
+
+/// Id of the context a program starts in
+pub const MAIN_CONTEXT: i32 = 0;
+
+/// What a green thread keeps while another one is running. Threads share the program, the heap and everything the
+/// host has set up; each has its own registers, flags, call stack and trap handlers in progress.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Context {
+    pub id: i32,
+    pub registers: [i32; 32],
+    pub float_registers: [f64; 32],
+    pub pc: usize,
+    pub remainder: usize,
+    pub flags: Flags,
+    pub loop_counter: usize,
+    pub call_stack: Vec<usize>,
+    pub trap_stack: Vec<TrapFrame>,
+}
+
+/// Round-robin scheduler for the green threads started by SPAWN. Threads only switch when the running one YIELDs
+/// or finishes, so no locking is needed around anything they share.
+#[derive(Debug)]
+pub struct Scheduler {
+    /// Threads that aren't running, in the order they'll get to run
+    waiting: VecDeque<Context>,
+    /// Id of the thread that is running
+    current: i32,
+    next_id: i32,
+    max_contexts: usize,
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Scheduler {
+    pub fn new() -> Scheduler {
+        Scheduler {
+            waiting: VecDeque::new(),
+            current: MAIN_CONTEXT,
+            next_id: MAIN_CONTEXT + 1,
+            max_contexts: DEFAULT_MAX_CONTEXTS,
+        }
+    }
+
+    /// Returns the id the next spawned thread will get, or None if there are already as many as allowed
+    pub fn next_id(&self) -> Option<i32> {
+        if self.waiting.len() + 1 >= self.max_contexts {
+            None
+        } else {
+            Some(self.next_id)
+        }
+    }
+
+    /// Queues a new thread behind the ones already waiting. Its id should come from `next_id`.
+    pub fn spawn(&mut self, context: Context) {
+        self.next_id = context.id + 1;
+        self.waiting.push_back(context);
+    }
+
+    /// Puts the running thread at the back of the queue and returns the one to run instead, which is the running
+    /// one again if no other thread is waiting
+    pub fn switch(&mut self, running: Context) -> Context {
+        match self.waiting.pop_front() {
+            Some(next) => {
+                self.waiting.push_back(running);
+                self.current = next.id;
+                next
+            }
+            None => running,
+        }
+    }
+
+    /// Drops the running thread and returns the one to run instead. Returns None if the running thread is the main
+    /// one, which only finishes when the whole program does.
+    pub fn finish(&mut self) -> Option<Context> {
+        if self.current == MAIN_CONTEXT {
+            return None;
+        }
+
+        let next = self.waiting.pop_front()?;
+        self.current = next.id;
+        Some(next)
+    }
+
+    /// Drops every thread but the running one, which becomes the main thread
+    pub fn reset(&mut self) {
+        self.waiting.clear();
+        self.current = MAIN_CONTEXT;
+        self.next_id = MAIN_CONTEXT + 1;
+    }
+
+    /// Id of the running thread
+    pub fn current(&self) -> i32 {
+        self.current
+    }
+
+    /// Number of threads, including the running one
+    pub fn context_count(&self) -> usize {
+        self.waiting.len() + 1
+    }
+
+    pub fn set_max_contexts(&mut self, max: usize) {
+        self.max_contexts = max;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn context(id: i32) -> Context {
+        Context {
+            id,
+            registers: [id; 32],
+            float_registers: [0.0; 32],
+            pc: 0,
+            remainder: 0,
+            flags: Flags::new(),
+            loop_counter: 0,
+            call_stack: vec![],
+            trap_stack: vec![],
+        }
+    }
+
+    #[test]
+    fn test_round_robin() {
+        let mut scheduler = Scheduler::new();
+        assert_eq!(scheduler.switch(context(0)).id, 0);
+        assert_eq!(scheduler.finish(), None);
+
+        for _ in 0..2 {
+            let id = scheduler.next_id().unwrap();
+            scheduler.spawn(context(id));
+        }
+        assert_eq!(scheduler.context_count(), 3);
+
+        assert_eq!(scheduler.switch(context(0)).id, 1);
+        assert_eq!(scheduler.current(), 1);
+        assert_eq!(scheduler.switch(context(1)).id, 2);
+        assert_eq!(scheduler.finish().map(|c| c.id), Some(0));
+        assert_eq!(scheduler.switch(context(0)).id, 1);
+        assert_eq!(scheduler.context_count(), 2);
+    }
+
+    #[test]
+    fn test_max_contexts() {
+        let mut scheduler = Scheduler::new();
+        scheduler.set_max_contexts(2);
+        scheduler.spawn(context(scheduler.next_id().unwrap()));
+        assert_eq!(scheduler.next_id(), None);
+    }
+}
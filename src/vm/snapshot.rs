@@ -0,0 +1,161 @@
+use crate::vm::config::VMConfig;
+use crate::vm::flags::Flags;
+use crate::vm::heap::Heap;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read};
+use std::ops::Range;
+use std::path::Path;
+
+/// First bytes of a snapshot file
+const SNAPSHOT_PREFIX: [u8; 4] = *b"IRSN";
+/// Version of the snapshot format, bumped whenever the layout changes
+const SNAPSHOT_VERSION: u8 = 2;
+/// Version before the VM's configuration was recorded, which is read as the default configuration
+const UNCONFIGURED_SNAPSHOT_VERSION: u8 = 1;
+
+/// Everything a program needs to carry on from where a VM was, taken with `VM::snapshot` and put back with
+/// `VM::restore`. Host functions, syscall handlers, trap handlers, open files and statistics belong to the host
+/// rather than the program and aren't included.
+#[derive(Debug, Clone)]
+pub struct VMState {
+    pub registers: [i32; 32],
+    pub float_registers: [f64; 32],
+    pub pc: usize,
+    pub remainder: usize,
+    pub flags: Flags,
+    pub loop_counter: usize,
+    pub wide: bool,
+    pub writable_code: Option<Range<usize>>,
+    pub program: Vec<u8>,
+    pub ro_data: Vec<u8>,
+    pub heap: Heap,
+    pub call_stack: Vec<usize>,
+    /// How the VM the snapshot was taken on was configured
+    pub config: VMConfig,
+}
+
+impl VMState {
+    /// Encodes the state in the snapshot file format. Integers are little-endian, and lengths come before the
+    /// bytes they describe.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = SNAPSHOT_PREFIX.to_vec();
+        out.push(SNAPSHOT_VERSION);
+        self.config.encode(&mut out);
+
+        for register in self.registers.iter() {
+            out.write_i32::<LittleEndian>(*register).unwrap();
+        }
+        for register in self.float_registers.iter() {
+            out.write_f64::<LittleEndian>(*register).unwrap();
+        }
+        for value in &[self.pc, self.remainder, self.loop_counter] {
+            out.write_u64::<LittleEndian>(*value as u64).unwrap();
+        }
+        out.push(self.flags.bits());
+        out.push(self.wide as u8);
+
+        let writable = self.writable_code.clone().unwrap_or(0..0);
+        out.write_u64::<LittleEndian>(writable.start as u64).unwrap();
+        out.write_u64::<LittleEndian>(writable.end as u64).unwrap();
+
+        for bytes in &[&self.program, &self.ro_data] {
+            out.write_u64::<LittleEndian>(bytes.len() as u64).unwrap();
+            out.extend_from_slice(bytes);
+        }
+        self.heap.encode(&mut out);
+
+        out.write_u64::<LittleEndian>(self.call_stack.len() as u64).unwrap();
+        for address in &self.call_stack {
+            out.write_u64::<LittleEndian>(*address as u64).unwrap();
+        }
+
+        out
+    }
+
+    /// Decodes a snapshot written by `to_bytes`
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<VMState> {
+        if bytes.len() < 5 || bytes[0..4] != SNAPSHOT_PREFIX {
+            return Err(invalid("not an iridium snapshot"));
+        }
+        let mut input = &bytes[5..];
+        let config = match bytes[4] {
+            SNAPSHOT_VERSION => VMConfig::decode(&mut input)?,
+            UNCONFIGURED_SNAPSHOT_VERSION => VMConfig::default(),
+            version => {
+                let message = format!("snapshot version {} isn't supported, expected {}", version, SNAPSHOT_VERSION);
+                return Err(invalid(&message));
+            }
+        };
+
+        let mut registers = [0; 32];
+        for register in registers.iter_mut() {
+            *register = input.read_i32::<LittleEndian>()?;
+        }
+        let mut float_registers = [0.0; 32];
+        for register in float_registers.iter_mut() {
+            *register = input.read_f64::<LittleEndian>()?;
+        }
+        let pc = read_usize(&mut input)?;
+        let remainder = read_usize(&mut input)?;
+        let loop_counter = read_usize(&mut input)?;
+        let flags = Flags::from_bits(input.read_u8()?);
+        let wide = input.read_u8()? != 0;
+
+        let writable = read_usize(&mut input)?..read_usize(&mut input)?;
+        let writable_code = if writable.start < writable.end { Some(writable) } else { None };
+
+        let program = read_bytes(&mut input)?;
+        let ro_data = read_bytes(&mut input)?;
+        let heap = Heap::decode(&mut input)?;
+        let config = VMConfig { heap_limit: heap.limit(), ..config };
+
+        let mut call_stack = vec![];
+        for _ in 0..read_usize(&mut input)? {
+            call_stack.push(read_usize(&mut input)?);
+        }
+
+        Ok(VMState {
+            registers,
+            float_registers,
+            pc,
+            remainder,
+            flags,
+            loop_counter,
+            wide,
+            writable_code,
+            program,
+            ro_data,
+            heap,
+            call_stack,
+            config,
+        })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        std::fs::write(path, self.to_bytes())
+    }
+
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<VMState> {
+        VMState::from_bytes(&std::fs::read(path)?)
+    }
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read_usize(input: &mut &[u8]) -> io::Result<usize> {
+    input.read_u64::<LittleEndian>().map(|value| value as usize)
+}
+
+fn read_bytes(input: &mut &[u8]) -> io::Result<Vec<u8>> {
+    let length = read_usize(input)?;
+    if length > input.len() {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "snapshot is truncated"));
+    }
+
+    let mut bytes = vec![0; length];
+    input.read_exact(&mut bytes)?;
+    Ok(bytes)
+}
@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr, SocketAddrV4, UdpSocket};
+
+/// Lowest socket descriptor handed out. Sockets are numbered separately from files.
+const FIRST_SOCKET: i32 = 3;
+
+/// Which addresses UDP sockets may bind, send to and join. Each rule is an address and port, where 0.0.0.0 matches
+/// any host and port 0 any port. Nothing is allowed until a rule is added.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NetworkPolicy {
+    rules: Vec<SocketAddrV4>,
+}
+
+impl NetworkPolicy {
+    /// Creates a policy that allows nothing
+    pub fn new() -> NetworkPolicy {
+        NetworkPolicy::default()
+    }
+
+    pub fn allow(&mut self, rule: SocketAddrV4) {
+        self.rules.push(rule);
+    }
+
+    /// Reads a rule written as `host:port`, such as `239.0.0.1:5000` or `0.0.0.0:9000`
+    pub fn parse_rule(text: &str) -> Result<SocketAddrV4, String> {
+        text.parse().map_err(|_| format!("Expected an IPv4 address and port, such as 127.0.0.1:9000, found {}", text))
+    }
+
+    /// True if datagrams may be sent to `address`
+    pub fn allows(&self, address: SocketAddrV4) -> bool {
+        self.rules.iter().any(|rule| matches_host(rule, *address.ip()) && matches_port(rule, address.port()))
+    }
+
+    /// True if a socket may be bound to `port`. Port 0, which lets the host pick one, is allowed by any rule.
+    pub fn allows_port(&self, port: u16) -> bool {
+        self.rules.iter().any(|rule| port == 0 || matches_port(rule, port))
+    }
+
+    /// True if the multicast group `group` may be joined
+    pub fn allows_host(&self, group: Ipv4Addr) -> bool {
+        self.rules.iter().any(|rule| matches_host(rule, group))
+    }
+}
+
+fn matches_host(rule: &SocketAddrV4, host: Ipv4Addr) -> bool {
+    rule.ip().is_unspecified() || *rule.ip() == host
+}
+
+fn matches_port(rule: &SocketAddrV4, port: u16) -> bool {
+    rule.port() == 0 || rule.port() == port
+}
+
+/// The UDP sockets a program has open, by socket descriptor. Sockets don't block: receiving when nothing has
+/// arrived gives nothing rather than waiting.
+#[derive(Debug)]
+pub struct SocketTable {
+    sockets: HashMap<i32, UdpSocket>,
+    next_socket: i32,
+    policy: NetworkPolicy,
+}
+
+impl Default for SocketTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SocketTable {
+    pub fn new() -> SocketTable {
+        SocketTable {
+            sockets: HashMap::new(),
+            next_socket: FIRST_SOCKET,
+            policy: NetworkPolicy::new(),
+        }
+    }
+
+    /// Replaces the policy. Sockets that are already open stay usable, but what they do from now on is checked
+    /// against the new one.
+    pub fn set_policy(&mut self, policy: NetworkPolicy) {
+        self.policy = policy;
+    }
+
+    /// Opens a socket listening on `port` of every local address and returns its descriptor
+    pub fn bind(&mut self, port: u16) -> Result<i32, String> {
+        if !self.policy.allows_port(port) {
+            return Err(format!("The network policy doesn't allow binding port {}", port));
+        }
+
+        let socket = UdpSocket::bind(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port)).map_err(|e| e.to_string())?;
+        socket.set_nonblocking(true).map_err(|e| e.to_string())?;
+        let descriptor = self.next_socket;
+        self.next_socket += 1;
+        self.sockets.insert(descriptor, socket);
+        Ok(descriptor)
+    }
+
+    /// Sends `data` as one datagram and returns how many bytes that was
+    pub fn send(&mut self, descriptor: i32, data: &[u8], to: SocketAddrV4) -> Result<usize, String> {
+        if !self.policy.allows(to) {
+            return Err(format!("The network policy doesn't allow sending to {}", to));
+        }
+        self.get(descriptor)?.send_to(data, to).map_err(|e| e.to_string())
+    }
+
+    /// Takes the next datagram that has arrived, returning its length and who sent it, or None if there isn't one.
+    /// Datagrams longer than `buffer` are cut short.
+    pub fn receive(&mut self, descriptor: i32, buffer: &mut [u8]) -> Result<Option<(usize, SocketAddrV4)>, String> {
+        match self.get(descriptor)?.recv_from(buffer) {
+            Ok((length, SocketAddr::V4(from))) => Ok(Some((length, from))),
+            Ok((_, from)) => Err(format!("Received a datagram from {}, which isn't IPv4", from)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(None),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Joins a multicast group so the socket receives datagrams sent to it
+    pub fn join(&mut self, descriptor: i32, group: Ipv4Addr) -> Result<(), String> {
+        if !group.is_multicast() {
+            return Err(format!("{} isn't a multicast address", group));
+        }
+        if !self.policy.allows_host(group) {
+            return Err(format!("The network policy doesn't allow joining {}", group));
+        }
+        self.get(descriptor)?.join_multicast_v4(&group, &Ipv4Addr::UNSPECIFIED).map_err(|e| e.to_string())
+    }
+
+    /// Returns the port a socket ended up bound to, which the host picks when binding port 0
+    pub fn local_port(&self, descriptor: i32) -> Result<u16, String> {
+        self.get(descriptor)?.local_addr().map(|address| address.port()).map_err(|e| e.to_string())
+    }
+
+    pub fn close(&mut self, descriptor: i32) -> Result<(), String> {
+        self.sockets.remove(&descriptor).map(|_| ()).ok_or_else(|| format!("Bad socket descriptor: {}", descriptor))
+    }
+
+    fn get(&self, descriptor: i32) -> Result<&UdpSocket, String> {
+        self.sockets.get(&descriptor).ok_or_else(|| format!("Bad socket descriptor: {}", descriptor))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_policy() {
+        let mut policy = NetworkPolicy::new();
+        assert!(!policy.allows_port(0));
+
+        policy.allow(NetworkPolicy::parse_rule("0.0.0.0:9000").unwrap());
+        policy.allow(NetworkPolicy::parse_rule("239.0.0.1:0").unwrap());
+        assert!(policy.allows(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 9000)));
+        assert!(!policy.allows(SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 53)));
+        assert!(policy.allows(SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 1), 53)));
+        assert!(policy.allows_host(Ipv4Addr::new(239, 0, 0, 1)));
+        assert!(!policy.allows(SocketAddrV4::new(Ipv4Addr::new(239, 0, 0, 2), 53)));
+        assert!(NetworkPolicy::parse_rule("localhost").is_err());
+    }
+
+    #[test]
+    fn test_send_and_receive() {
+        let mut policy = NetworkPolicy::new();
+        policy.allow(NetworkPolicy::parse_rule("127.0.0.1:0").unwrap());
+        let mut sockets = SocketTable::new();
+        sockets.set_policy(policy);
+
+        let sender = sockets.bind(0).unwrap();
+        let receiver = sockets.bind(0).unwrap();
+        let mut buffer = [0; 16];
+        assert_eq!(sockets.receive(receiver, &mut buffer), Ok(None));
+
+        let to = SocketAddrV4::new(Ipv4Addr::LOCALHOST, sockets.local_port(receiver).unwrap());
+        assert_eq!(sockets.send(sender, b"beacon", to), Ok(6));
+        assert!(sockets.send(sender, b"beacon", SocketAddrV4::new(Ipv4Addr::new(10, 0, 0, 1), 9000)).is_err());
+
+        let mut received = None;
+        for _ in 0..100 {
+            received = sockets.receive(receiver, &mut buffer).unwrap();
+            if received.is_some() {
+                break;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        let (length, from) = received.unwrap();
+        assert_eq!(&buffer[..length], b"beacon");
+        assert_eq!(from.port(), sockets.local_port(sender).unwrap());
+
+        assert!(sockets.join(receiver, Ipv4Addr::new(239, 0, 0, 1)).is_err());
+        sockets.close(sender).unwrap();
+        assert!(sockets.close(sender).is_err());
+    }
+}
@@ -0,0 +1,225 @@
+use crate::instruction::Opcode;
+
+use std::collections::{BTreeMap, HashMap};
+
+/// How many of the hottest PCs are listed in exported statistics
+pub const HOTTEST_PC_COUNT: usize = 10;
+
+/// How often a conditional branch went each way
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct BranchCounts {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+/// Counters describing what a program did while it ran, collected when a VM has statistics turned on
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct RunStats {
+    /// Instructions executed
+    pub instructions: u64,
+    /// Instructions executed of each opcode
+    pub opcodes: BTreeMap<String, u64>,
+    /// Outcomes of each kind of conditional branch
+    pub branches: BTreeMap<String, BranchCounts>,
+    /// Heap words read by loads and read-modify-writes
+    pub memory_reads: u64,
+    /// Heap words written by stores and read-modify-writes
+    pub memory_writes: u64,
+    /// Times the instruction at each PC was executed
+    pc_counts: HashMap<usize, u64>,
+}
+
+impl RunStats {
+    pub fn new() -> RunStats {
+        RunStats::default()
+    }
+
+    /// Records an instruction that started at `start`, took up `length` bytes and left the PC at `next`
+    pub fn record(&mut self, opcode: Opcode, start: usize, next: usize, length: usize) {
+        let name = format!("{:?}", opcode).to_lowercase();
+        self.instructions += 1;
+        *self.pc_counts.entry(start).or_insert(0) += 1;
+
+        match opcode {
+            Opcode::LW => self.memory_reads += 1,
+            Opcode::SW => self.memory_writes += 1,
+            Opcode::CAS | Opcode::XADD => {
+                self.memory_reads += 1;
+                self.memory_writes += 1;
+            }
+            _ => {}
+        }
+
+        if is_conditional_branch(opcode) {
+            let counts = self.branches.entry(name.clone()).or_default();
+            // Ending up anywhere but the next instruction means the branch was taken
+            if next == start + length {
+                counts.not_taken += 1;
+            } else {
+                counts.taken += 1;
+            }
+        }
+
+        *self.opcodes.entry(name).or_insert(0) += 1;
+    }
+
+    /// Returns the `count` most executed PCs and how many times each ran, most executed first
+    pub fn hottest_pcs(&self, count: usize) -> Vec<(usize, u64)> {
+        let mut pcs: Vec<(usize, u64)> = self.pc_counts.iter().map(|(pc, hits)| (*pc, *hits)).collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pcs.truncate(count);
+        pcs
+    }
+
+    /// Formats the statistics as CSV rows of category, name and value
+    pub fn to_csv(&self) -> String {
+        let mut lines = vec!["category,name,value".to_string(), format!("total,instructions,{}", self.instructions)];
+        for (name, count) in &self.opcodes {
+            lines.push(format!("opcode,{},{}", name, count));
+        }
+        for (name, counts) in &self.branches {
+            lines.push(format!("branch_taken,{},{}", name, counts.taken));
+            lines.push(format!("branch_not_taken,{},{}", name, counts.not_taken));
+        }
+        lines.push(format!("memory,reads,{}", self.memory_reads));
+        lines.push(format!("memory,writes,{}", self.memory_writes));
+        for (pc, hits) in self.hottest_pcs(HOTTEST_PC_COUNT) {
+            lines.push(format!("pc,{},{}", pc, hits));
+        }
+
+        lines.join("\n") + "\n"
+    }
+
+    /// Formats the statistics as a JSON object
+    pub fn to_json(&self) -> String {
+        let opcodes: Vec<String> = self.opcodes.iter().map(|(name, count)| format!("\"{}\":{}", name, count)).collect();
+        let branches: Vec<String> = self
+            .branches
+            .iter()
+            .map(|(name, counts)| format!("\"{}\":{{\"taken\":{},\"not_taken\":{}}}", name, counts.taken, counts.not_taken))
+            .collect();
+        let pcs: Vec<String> = self
+            .hottest_pcs(HOTTEST_PC_COUNT)
+            .iter()
+            .map(|(pc, hits)| format!("{{\"pc\":{},\"count\":{}}}", pc, hits))
+            .collect();
+
+        format!(
+            "{{\"instructions\":{},\"opcodes\":{{{}}},\"branches\":{{{}}},\"memory\":{{\"reads\":{},\"writes\":{}}},\"hottest_pcs\":[{}]}}\n",
+            self.instructions,
+            opcodes.join(","),
+            branches.join(","),
+            self.memory_reads,
+            self.memory_writes,
+            pcs.join(",")
+        )
+    }
+}
+
+/// An instruction the program spent a lot of its time on
+#[derive(Debug, Clone, PartialEq)]
+pub struct HotSpot {
+    pub pc: usize,
+    /// Times the instruction was executed
+    pub count: u64,
+    /// The instruction disassembled, if the program still has a whole one at `pc`
+    pub instruction: Option<String>,
+}
+
+/// Where a program spent its time, from `VM::profile`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub instructions: u64,
+    /// Instructions executed of each opcode, most executed first
+    pub opcodes: Vec<(String, u64)>,
+    /// The most executed instructions, most first
+    pub hot_spots: Vec<HotSpot>,
+}
+
+impl Profile {
+    /// Formats the profile as a report with the share of the run each opcode and hot spot took
+    pub fn to_lines(&self) -> Vec<String> {
+        let share = |count: u64| 100.0 * count as f64 / self.instructions.max(1) as f64;
+
+        let mut lines = vec![format!("{} instructions executed", self.instructions), "opcodes:".to_string()];
+        for (name, count) in &self.opcodes {
+            lines.push(format!("{:>10} {:>6.2}%  {}", count, share(*count), name));
+        }
+        lines.push("hot spots:".to_string());
+        for spot in &self.hot_spots {
+            let instruction = spot.instruction.as_deref().unwrap_or("?");
+            lines.push(format!("{:>10} {:>6.2}%  {:>6}  {}", spot.count, share(spot.count), spot.pc, instruction));
+        }
+        lines
+    }
+}
+
+fn is_conditional_branch(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::JMPE | Opcode::JZ | Opcode::JNZ | Opcode::JC | Opcode::JO | Opcode::JNO | Opcode::LOOP
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> RunStats {
+        let mut stats = RunStats::new();
+        stats.record(Opcode::LOAD, 0, 4, 4);
+        stats.record(Opcode::SW, 4, 8, 4);
+        stats.record(Opcode::JZ, 8, 12, 4);
+        stats.record(Opcode::JZ, 8, 0, 4);
+        stats.record(Opcode::LOAD, 0, 4, 4);
+        stats
+    }
+
+    #[test]
+    fn test_record() {
+        let stats = sample();
+        assert_eq!(stats.instructions, 5);
+        assert_eq!(stats.opcodes["load"], 2);
+        assert_eq!(stats.branches["jz"], BranchCounts { taken: 1, not_taken: 1 });
+        assert_eq!((stats.memory_reads, stats.memory_writes), (0, 1));
+        assert_eq!(stats.hottest_pcs(2), vec![(0, 2), (8, 2)]);
+    }
+
+    #[test]
+    fn test_to_csv() {
+        let csv = sample().to_csv();
+        assert!(csv.starts_with("category,name,value\ntotal,instructions,5\n"));
+        assert!(csv.contains("opcode,load,2\n"));
+        assert!(csv.contains("branch_taken,jz,1\nbranch_not_taken,jz,1\n"));
+        assert!(csv.ends_with("pc,0,2\npc,8,2\npc,4,1\n"));
+    }
+
+    #[test]
+    fn test_profile_lines() {
+        let profile = Profile {
+            instructions: 4,
+            opcodes: vec![("add".to_string(), 3), ("hlt".to_string(), 1)],
+            hot_spots: vec![HotSpot { pc: 64, count: 3, instruction: Some("add $0 $1 $2".to_string()) }],
+        };
+        assert_eq!(
+            profile.to_lines(),
+            vec![
+                "4 instructions executed",
+                "opcodes:",
+                "         3  75.00%  add",
+                "         1  25.00%  hlt",
+                "hot spots:",
+                "         3  75.00%      64  add $0 $1 $2",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_to_json() {
+        let json = sample().to_json();
+        assert!(json.starts_with("{\"instructions\":5,\"opcodes\":{\"jz\":2,\"load\":2,\"sw\":1},"));
+        assert!(json.contains("\"branches\":{\"jz\":{\"taken\":1,\"not_taken\":1}}"));
+        assert!(json.contains("\"memory\":{\"reads\":0,\"writes\":1}"));
+        assert!(json.ends_with("\"hottest_pcs\":[{\"pc\":0,\"count\":2},{\"pc\":8,\"count\":2},{\"pc\":4,\"count\":1}]}\n"));
+    }
+}
@@ -0,0 +1,71 @@
+use std::io::{self, BufRead, Read};
+use std::sync::mpsc::Receiver;
+
+/// Input that arrives on a channel, for hosts that feed a VM from another thread or task. Each message is a chunk of
+/// input, such as a line. Reading waits for the next chunk once the last one is used up, and the input ends once
+/// every sender has been dropped.
+pub struct ChannelInput {
+    receiver: Receiver<Vec<u8>>,
+    chunk: Vec<u8>,
+    /// How much of the chunk has been read
+    position: usize,
+}
+
+impl ChannelInput {
+    pub fn new(receiver: Receiver<Vec<u8>>) -> ChannelInput {
+        ChannelInput { receiver, chunk: vec![], position: 0 }
+    }
+}
+
+impl Read for ChannelInput {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let available = self.fill_buf()?;
+        let count = available.len().min(buf.len());
+        buf[..count].copy_from_slice(&available[..count]);
+        self.consume(count);
+        Ok(count)
+    }
+}
+
+impl BufRead for ChannelInput {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        while self.position >= self.chunk.len() {
+            match self.receiver.recv() {
+                Ok(chunk) => {
+                    self.chunk = chunk;
+                    self.position = 0;
+                }
+                Err(_) => return Ok(&[]),
+            }
+        }
+        Ok(&self.chunk[self.position..])
+    }
+
+    fn consume(&mut self, amount: usize) {
+        self.position = (self.position + amount).min(self.chunk.len());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc::channel;
+
+    #[test]
+    fn test_channel_input() {
+        let (sender, receiver) = channel();
+        let mut input = ChannelInput::new(receiver);
+        sender.send(b"first li".to_vec()).unwrap();
+        sender.send(vec![]).unwrap();
+        sender.send(b"ne\nsecond\n".to_vec()).unwrap();
+        drop(sender);
+
+        let mut line = String::new();
+        input.read_line(&mut line).unwrap();
+        assert_eq!(line, "first line\n");
+        line.clear();
+        input.read_line(&mut line).unwrap();
+        assert_eq!(line, "second\n");
+        assert_eq!(input.read_line(&mut line).unwrap(), 0);
+    }
+}
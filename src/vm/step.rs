@@ -0,0 +1,42 @@
+use crate::vm::errors::VMError;
+use crate::vm::run_result::RunResult;
+
+use std::fmt;
+
+/// A register an instruction changed, with what it held before and after
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterChange {
+    pub number: usize,
+    pub old: i32,
+    pub new: i32,
+}
+
+/// What a single instruction did, returned by `VM::step`. Displayed on one line, such as
+/// `72: add $0 $1 $2 => 76, $2: 0 => 15`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StepResult {
+    /// Offset of the instruction that ran
+    pub pc: usize,
+    /// Where the program carries on from
+    pub next_pc: usize,
+    /// The instruction as assembly, or None if the program had run off its end
+    pub instruction: Option<String>,
+    /// The integer registers the instruction changed, lowest first
+    pub registers: Vec<RegisterChange>,
+    /// How the program stopped, if this instruction stopped it
+    pub stopped: Option<Result<RunResult, VMError>>,
+}
+
+impl fmt::Display for StepResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {} => {}", self.pc, self.instruction.as_deref().unwrap_or("end of program"), self.next_pc)?;
+        for change in &self.registers {
+            write!(f, ", ${}: {} => {}", change.number, change.old, change.new)?;
+        }
+        match &self.stopped {
+            Some(Ok(result)) => write!(f, ", finished with exit code {}", result.exit_code),
+            Some(Err(error)) => write!(f, ", stopped: {}", error),
+            None => Ok(()),
+        }
+    }
+}
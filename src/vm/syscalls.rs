@@ -0,0 +1,653 @@
+use crate::assembler::WIDE_IMMEDIATE_LENGTH;
+use crate::encoding::read_code_i32;
+use crate::vm::channels::OutputRecord;
+use crate::vm::errors::VMError;
+use crate::vm::files::OpenMode;
+use crate::vm::VM;
+
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddrV4};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Stops the VM. The exit code is taken from $1.
+pub const SYS_EXIT: u16 = 0;
+/// Prints the integer in $1 on a line of its own
+pub const SYS_PRINT: u16 = 1;
+/// Reads a line from stdin and stores it in $0 as an integer, or 0 if it isn't one
+pub const SYS_READ: u16 = 2;
+/// Stores the current Unix time, in seconds, in $0
+pub const SYS_TIME: u16 = 3;
+/// Raises the timer trap every $1 instructions, or turns the timer off if $1 is 0
+pub const SYS_SETTIMER: u16 = 4;
+/// Reads a line into a newly allocated heap block, without its line ending. Stores the block's address in $0 and the
+/// line's length in $1, or -1 and 0 at the end of the input. $0 is also -1 if the heap is full.
+pub const SYS_READLINE: u16 = 5;
+/// Opens the file whose path is the $2 bytes at heap address $1, to read if $3 is 0, write if it's 1 or append if
+/// it's 2. Stores the file descriptor in $0, or -1 if the file couldn't be opened.
+pub const SYS_OPEN: u16 = 6;
+/// Reads up to $3 bytes from file descriptor $1 into the heap at address $2. Stores the number of bytes read in $0,
+/// which is 0 at the end of the file, or -1 on failure.
+pub const SYS_FREAD: u16 = 7;
+/// Writes the $3 bytes at heap address $2 to file descriptor $1. Stores the number of bytes written in $0, or -1 on
+/// failure.
+pub const SYS_FWRITE: u16 = 8;
+/// Closes file descriptor $1. Stores 0 in $0, or -1 if it wasn't open.
+pub const SYS_CLOSE: u16 = 9;
+/// Grows the heap by $1 bytes. Stores the address of the first new byte in $0, or -1 if the heap can't grow that far.
+pub const SYS_SBRK: u16 = 10;
+/// Stores the $4 bytes at heap address $3 under the key that is the $2 bytes at heap address $1. Stores 0 in $0, or
+/// -1 if there is no key-value store or it couldn't be written.
+pub const SYS_KV_PUT: u16 = 11;
+/// Looks up the key that is the $2 bytes at heap address $1, copying its value into a newly allocated heap block.
+/// Stores the block's address in $0 and the value's length in $1, or -1 and 0 if the key isn't there. $0 is also -1
+/// if there is no key-value store or the heap is full.
+pub const SYS_KV_GET: u16 = 12;
+/// Removes the key that is the $2 bytes at heap address $1. Stores 1 in $0 if it was there and 0 if it wasn't, or -1
+/// if there is no key-value store or it couldn't be written.
+pub const SYS_KV_DELETE: u16 = 13;
+/// Opens a UDP socket on port $1, or one the host picks if $1 is 0. Stores the socket descriptor in $0, or -1 if the
+/// network policy doesn't allow the port or it couldn't be bound.
+pub const SYS_UDP_BIND: u16 = 14;
+/// Sends the $3 bytes at heap address $2 from socket $1 to the IPv4 address in $4, such as 0x7f000001, and port $5.
+/// Stores the number of bytes sent in $0, or -1 if the network policy doesn't allow the destination or it failed.
+pub const SYS_UDP_SEND: u16 = 15;
+/// Copies the next datagram that has arrived on socket $1 into the $3 bytes at heap address $2, without waiting for
+/// one. Stores its length in $0 and the sender's IPv4 address and port in $4 and $5, or -1 in $0 if nothing has
+/// arrived or it failed.
+pub const SYS_UDP_RECV: u16 = 16;
+/// Joins socket $1 to the multicast group whose IPv4 address is in $2. Stores 0 in $0, or -1 if the network policy
+/// doesn't allow the group or it failed.
+pub const SYS_UDP_JOIN: u16 = 17;
+/// Closes socket $1. Stores 0 in $0, or -1 if it wasn't open.
+pub const SYS_UDP_CLOSE: u16 = 18;
+/// Emits a record with tag $3 and the $5 bytes at heap address $4 as its data on the output channel named by the $2
+/// bytes at heap address $1. Stores 0 in $0, or -1 if the name is empty or isn't UTF-8.
+pub const SYS_EMIT: u16 = 19;
+/// Describes the trap being handled, for a handler that emulates what the program tried to do. Stores the address of
+/// the instruction that raised it in $0 and the instruction's four bytes in $1, most significant first, the way wcode
+/// writes them. In wide programs the instruction's immediate goes in $2. Stores -1 in $0 outside a trap handler.
+pub const SYS_TRAPINFO: u16 = 20;
+
+/// Syscalls whose results depend on the world outside the VM, so two runs of a program that uses them can differ
+pub const NONDETERMINISTIC_SYSCALLS: [u16; 9] =
+    [SYS_READ, SYS_TIME, SYS_READLINE, SYS_OPEN, SYS_FREAD, SYS_KV_GET, SYS_KV_DELETE, SYS_UDP_BIND, SYS_UDP_RECV];
+
+/// A syscall implementation. Returns true if the VM should stop executing, the same as `VM::execute_instruction`.
+pub type SyscallHandler = fn(&mut VM) -> bool;
+
+/// Maps syscall numbers, as used by `syscall #N`, to the functions that carry them out
+#[derive(Clone)]
+pub struct SyscallTable {
+    handlers: HashMap<u16, SyscallHandler>,
+}
+
+impl Default for SyscallTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl SyscallTable {
+    /// Creates a table with the standard syscalls registered
+    pub fn new() -> SyscallTable {
+        let mut table = SyscallTable::empty();
+        table.register(SYS_EXIT, sys_exit);
+        table.register(SYS_PRINT, sys_print);
+        table.register(SYS_READ, sys_read);
+        table.register(SYS_TIME, sys_time);
+        table.register(SYS_SETTIMER, sys_settimer);
+        table.register(SYS_READLINE, sys_readline);
+        table.register(SYS_OPEN, sys_open);
+        table.register(SYS_FREAD, sys_fread);
+        table.register(SYS_FWRITE, sys_fwrite);
+        table.register(SYS_CLOSE, sys_close);
+        table.register(SYS_SBRK, sys_sbrk);
+        table.register(SYS_KV_PUT, sys_kv_put);
+        table.register(SYS_KV_GET, sys_kv_get);
+        table.register(SYS_KV_DELETE, sys_kv_delete);
+        table.register(SYS_UDP_BIND, sys_udp_bind);
+        table.register(SYS_UDP_SEND, sys_udp_send);
+        table.register(SYS_UDP_RECV, sys_udp_recv);
+        table.register(SYS_UDP_JOIN, sys_udp_join);
+        table.register(SYS_UDP_CLOSE, sys_udp_close);
+        table.register(SYS_EMIT, sys_emit);
+        table.register(SYS_TRAPINFO, sys_trapinfo);
+        table
+    }
+
+    /// Creates a table without any syscalls registered
+    pub fn empty() -> SyscallTable {
+        SyscallTable { handlers: HashMap::new() }
+    }
+
+    /// Registers a handler for a syscall number, replacing any handler it already had
+    pub fn register(&mut self, number: u16, handler: SyscallHandler) {
+        self.handlers.insert(number, handler);
+    }
+
+    pub fn get(&self, number: u16) -> Option<SyscallHandler> {
+        self.handlers.get(&number).copied()
+    }
+}
+
+fn sys_exit(vm: &mut VM) -> bool {
+    vm.exit_code = Some(vm.registers[1]);
+    true
+}
+
+fn sys_print(vm: &mut VM) -> bool {
+    let line = format!("{}\n", vm.registers[1]);
+    vm.write_output(line.as_bytes());
+    false
+}
+
+fn sys_read(vm: &mut VM) -> bool {
+    let mut line = String::new();
+    if let Err(e) = vm.read_input_line(&mut line) {
+        return input_failed(vm, e);
+    }
+
+    vm.registers[0] = line.trim().parse::<i32>().unwrap_or(0);
+    false
+}
+
+fn sys_time(vm: &mut VM) -> bool {
+    let seconds = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or(0);
+    vm.registers[0] = seconds as i32;
+    false
+}
+
+fn sys_readline(vm: &mut VM) -> bool {
+    let mut line = String::new();
+    match vm.read_input_line(&mut line) {
+        Ok(0) => {
+            vm.registers[0] = -1;
+            vm.registers[1] = 0;
+        }
+        Ok(_) => {
+            let line = line.trim_end_matches(&['\r', '\n'][..]).as_bytes();
+            match vm.allocate(line.len()) {
+                Some(address) => {
+                    vm.heap_slice_mut(address, line.len()).unwrap().copy_from_slice(line);
+                    vm.registers[0] = address as i32;
+                }
+                None => vm.registers[0] = -1,
+            }
+            vm.registers[1] = line.len() as i32;
+        }
+        Err(e) => return input_failed(vm, e),
+    }
+    false
+}
+
+/// Stops the program because its input couldn't be read
+fn input_failed(vm: &mut VM, error: io::Error) -> bool {
+    let pc = vm.instruction_start;
+    vm.fail(VMError::InputFailed { pc, reason: error.to_string() })
+}
+
+fn sys_open(vm: &mut VM) -> bool {
+    let path = heap_range(vm, vm.registers[1], vm.registers[2])
+        .and_then(|(address, length)| vm.heap_slice(address, length))
+        .map(|bytes| String::from_utf8_lossy(bytes).into_owned());
+    let mode = OpenMode::from_number(vm.registers[3]);
+
+    vm.registers[0] = match (path, mode) {
+        (Some(path), Some(mode)) => vm.files_mut().open(&path, mode).unwrap_or(-1),
+        _ => -1,
+    };
+    false
+}
+
+fn sys_fread(vm: &mut VM) -> bool {
+    let fd = vm.registers[1];
+    vm.registers[0] = match heap_range(vm, vm.registers[2], vm.registers[3]) {
+        Some((address, length)) => {
+            let mut buffer = vec![0; length];
+            match vm.files_mut().read(fd, &mut buffer) {
+                Ok(read) => {
+                    vm.heap_slice_mut(address, read).unwrap().copy_from_slice(&buffer[..read]);
+                    read as i32
+                }
+                Err(_) => -1,
+            }
+        }
+        None => -1,
+    };
+    false
+}
+
+fn sys_fwrite(vm: &mut VM) -> bool {
+    let fd = vm.registers[1];
+    let data = heap_range(vm, vm.registers[2], vm.registers[3]).map(|(address, length)| vm.heap_slice(address, length).unwrap().to_vec());
+
+    vm.registers[0] = match data {
+        Some(data) => vm.files_mut().write(fd, &data).map(|written| written as i32).unwrap_or(-1),
+        None => -1,
+    };
+    false
+}
+
+fn sys_close(vm: &mut VM) -> bool {
+    let fd = vm.registers[1];
+    vm.registers[0] = if vm.files_mut().close(fd).is_ok() { 0 } else { -1 };
+    false
+}
+
+/// Checks that a buffer given to a syscall lies inside the heap, returning its address and length if it does
+fn heap_range(vm: &VM, address: i32, length: i32) -> Option<(usize, usize)> {
+    if address < 0 || length < 0 {
+        return None;
+    }
+
+    let (address, length) = (address as usize, length as usize);
+    vm.heap_slice(address, length).map(|_| (address, length))
+}
+
+fn sys_trapinfo(vm: &mut VM) -> bool {
+    let address = match vm.trap_stack.last() {
+        Some(frame) => frame.fault_address,
+        None => {
+            vm.registers[0] = -1;
+            return false;
+        }
+    };
+
+    let prefix = if vm.wide { WIDE_IMMEDIATE_LENGTH } else { 0 };
+    let program = &vm.program;
+    let word = |at: usize| program.get(at..at + 4).map_or(0, read_code_i32);
+    let (instruction, immediate) = (word(address + prefix), word(address));
+    vm.registers[0] = address as i32;
+    vm.registers[1] = instruction;
+    if vm.wide {
+        vm.registers[2] = immediate;
+    }
+    false
+}
+
+fn sys_sbrk(vm: &mut VM) -> bool {
+    let size = vm.registers[1];
+    vm.registers[0] = match size {
+        size if size >= 0 => vm.grow_heap(size as usize).map_or(-1, |address| address as i32),
+        _ => -1,
+    };
+    false
+}
+
+/// Copies a key or value given to a key-value syscall out of the heap
+fn heap_bytes(vm: &VM, address: i32, length: i32) -> Option<Vec<u8>> {
+    heap_range(vm, address, length).map(|(address, length)| vm.heap_slice(address, length).unwrap().to_vec())
+}
+
+fn sys_kv_put(vm: &mut VM) -> bool {
+    let key = heap_bytes(vm, vm.registers[1], vm.registers[2]);
+    let value = heap_bytes(vm, vm.registers[3], vm.registers[4]);
+
+    vm.registers[0] = match (key, value, vm.kv_store_mut()) {
+        (Some(key), Some(value), Some(store)) => store.put(&key, &value).map_or(-1, |_| 0),
+        _ => -1,
+    };
+    false
+}
+
+fn sys_kv_get(vm: &mut VM) -> bool {
+    let key = heap_bytes(vm, vm.registers[1], vm.registers[2]);
+    let value = match (key, vm.kv_store_mut()) {
+        (Some(key), Some(store)) => store.get(&key).map(|value| value.to_vec()),
+        _ => None,
+    };
+
+    match value {
+        Some(value) => {
+            vm.registers[0] = match vm.allocate(value.len()) {
+                Some(address) => {
+                    vm.heap_slice_mut(address, value.len()).unwrap().copy_from_slice(&value);
+                    address as i32
+                }
+                None => -1,
+            };
+            vm.registers[1] = value.len() as i32;
+        }
+        None => {
+            vm.registers[0] = -1;
+            vm.registers[1] = 0;
+        }
+    }
+    false
+}
+
+fn sys_kv_delete(vm: &mut VM) -> bool {
+    let key = heap_bytes(vm, vm.registers[1], vm.registers[2]);
+
+    vm.registers[0] = match (key, vm.kv_store_mut()) {
+        (Some(key), Some(store)) => store.delete(&key).map_or(-1, |deleted| deleted as i32),
+        _ => -1,
+    };
+    false
+}
+
+fn sys_udp_bind(vm: &mut VM) -> bool {
+    let port = vm.registers[1];
+    vm.registers[0] = match u16::try_from(port) {
+        Ok(port) => vm.sockets_mut().bind(port).unwrap_or(-1),
+        Err(_) => -1,
+    };
+    false
+}
+
+fn sys_udp_send(vm: &mut VM) -> bool {
+    let socket = vm.registers[1];
+    let data = heap_bytes(vm, vm.registers[2], vm.registers[3]);
+    let to = u16::try_from(vm.registers[5]).ok().map(|port| SocketAddrV4::new(Ipv4Addr::from(vm.registers[4] as u32), port));
+
+    vm.registers[0] = match (data, to) {
+        (Some(data), Some(to)) => vm.sockets_mut().send(socket, &data, to).map_or(-1, |sent| sent as i32),
+        _ => -1,
+    };
+    false
+}
+
+fn sys_udp_recv(vm: &mut VM) -> bool {
+    let socket = vm.registers[1];
+    let (address, length) = match heap_range(vm, vm.registers[2], vm.registers[3]) {
+        Some(range) => range,
+        None => {
+            vm.registers[0] = -1;
+            return false;
+        }
+    };
+
+    let mut buffer = vec![0; length];
+    match vm.sockets_mut().receive(socket, &mut buffer) {
+        Ok(Some((received, from))) => {
+            let received = received.min(length);
+            vm.heap_slice_mut(address, received).unwrap().copy_from_slice(&buffer[..received]);
+            vm.registers[0] = received as i32;
+            vm.registers[4] = u32::from(*from.ip()) as i32;
+            vm.registers[5] = i32::from(from.port());
+        }
+        _ => vm.registers[0] = -1,
+    }
+    false
+}
+
+fn sys_udp_join(vm: &mut VM) -> bool {
+    let socket = vm.registers[1];
+    let group = Ipv4Addr::from(vm.registers[2] as u32);
+    vm.registers[0] = if vm.sockets_mut().join(socket, group).is_ok() { 0 } else { -1 };
+    false
+}
+
+fn sys_udp_close(vm: &mut VM) -> bool {
+    let socket = vm.registers[1];
+    vm.registers[0] = if vm.sockets_mut().close(socket).is_ok() { 0 } else { -1 };
+    false
+}
+
+fn sys_emit(vm: &mut VM) -> bool {
+    let channel = heap_bytes(vm, vm.registers[1], vm.registers[2]).and_then(|name| String::from_utf8(name).ok());
+    let data = heap_bytes(vm, vm.registers[4], vm.registers[5]);
+
+    vm.registers[0] = match (channel, data) {
+        (Some(channel), Some(data)) if !channel.is_empty() => {
+            let tag = vm.registers[3];
+            vm.emit_record(OutputRecord { channel, tag, data });
+            0
+        }
+        _ => -1,
+    };
+    false
+}
+
+fn sys_settimer(vm: &mut VM) -> bool {
+    let interval = vm.registers[1];
+    vm.set_timer(if interval > 0 { Some(interval as u32) } else { None });
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::kv::KeyValueStore;
+    use crate::vm::sockets::NetworkPolicy;
+
+    #[test]
+    fn test_standard_table() {
+        let table = SyscallTable::new();
+        assert!(table.get(SYS_EXIT).is_some());
+        assert!(table.get(SYS_PRINT).is_some());
+        assert!(table.get(SYS_READ).is_some());
+        assert!(table.get(SYS_TIME).is_some());
+        assert!(table.get(SYS_SETTIMER).is_some());
+        assert!(table.get(SYS_READLINE).is_some());
+        assert!(table.get(SYS_OPEN).is_some());
+        assert!(table.get(SYS_CLOSE).is_some());
+        assert!(table.get(1000).is_none());
+        assert!(SyscallTable::empty().get(SYS_EXIT).is_none());
+    }
+
+    #[test]
+    fn test_register_handler() {
+        fn double(vm: &mut VM) -> bool {
+            vm.registers[0] *= 2;
+            false
+        }
+
+        let mut table = SyscallTable::empty();
+        table.register(7, double);
+        let mut vm = VM::get_test_vm();
+        assert!(!(table.get(7).unwrap())(&mut vm));
+        assert_eq!(vm.registers[0], 10);
+    }
+
+    #[test]
+    fn test_sys_readline() {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(&b"hello\r\n\nlast"[..]));
+
+        assert!(!sys_readline(&mut vm));
+        assert_eq!(vm.registers[1], 5);
+        assert_eq!(vm.heap_slice(vm.registers[0] as usize, 5), Some(&b"hello"[..]));
+
+        assert!(!sys_readline(&mut vm));
+        assert_eq!(vm.registers[1], 0);
+
+        assert!(!sys_readline(&mut vm));
+        assert_eq!(vm.heap_slice(vm.registers[0] as usize, 4), Some(&b"last"[..]));
+
+        assert!(!sys_readline(&mut vm));
+        assert_eq!((vm.registers[0], vm.registers[1]), (-1, 0));
+    }
+
+    #[test]
+    fn test_sys_read_from_input() {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(&b"42\nnope\n"[..]));
+        assert!(!sys_read(&mut vm));
+        assert_eq!(vm.registers[0], 42);
+        assert!(!sys_read(&mut vm));
+        assert_eq!(vm.registers[0], 0);
+    }
+
+    #[test]
+    fn test_input_failure_stops_the_vm() {
+        let mut vm = VM::new();
+        vm.set_input(Box::new(&b"\xff\n"[..]));
+        assert!(sys_read(&mut vm));
+        assert!(matches!(vm.error, Some(VMError::InputFailed { pc: 0, .. })));
+
+        let mut vm = VM::new();
+        vm.set_input(Box::new(&b"\xfe\n"[..]));
+        assert!(sys_readline(&mut vm));
+        let error = vm.error.unwrap().to_string();
+        assert!(error.starts_with("Unable to read input at 0: "), "{}", error);
+    }
+
+    #[test]
+    fn test_file_syscalls() {
+        let path = std::env::temp_dir().join("iridium_syscalls_test_file_syscalls.txt");
+        let path = path.to_str().unwrap().as_bytes().to_vec();
+        let mut vm = VM::new();
+        let name = vm.allocate(path.len()).unwrap();
+        vm.heap_slice_mut(name, path.len()).unwrap().copy_from_slice(&path);
+        let buffer = vm.allocate(8).unwrap();
+        vm.heap_slice_mut(buffer, 3).unwrap().copy_from_slice(b"abc");
+
+        vm.registers[1..4].copy_from_slice(&[name as i32, path.len() as i32, 1]);
+        sys_open(&mut vm);
+        let fd = vm.registers[0];
+        assert!(fd >= 0);
+        vm.registers[1..4].copy_from_slice(&[fd, buffer as i32, 3]);
+        sys_fwrite(&mut vm);
+        assert_eq!(vm.registers[0], 3);
+        sys_close(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+
+        vm.registers[1..4].copy_from_slice(&[name as i32, path.len() as i32, 0]);
+        sys_open(&mut vm);
+        let fd = vm.registers[0];
+        vm.registers[1..4].copy_from_slice(&[fd, buffer as i32 + 4, 4]);
+        sys_fread(&mut vm);
+        assert_eq!(vm.registers[0], 3);
+        assert_eq!(vm.heap_slice(buffer + 4, 3), Some(&b"abc"[..]));
+        sys_close(&mut vm);
+
+        // Closing twice and buffers outside the heap fail without stopping the VM
+        assert!(!sys_close(&mut vm));
+        assert_eq!(vm.registers[0], -1);
+        vm.registers[1..4].copy_from_slice(&[fd, buffer as i32, 100]);
+        sys_fwrite(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+
+        std::fs::remove_file(String::from_utf8(path).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_open_without_filesystem_access() {
+        let mut vm = VM::new();
+        vm.files_mut().set_enabled(false);
+        let name = vm.allocate(10).unwrap();
+        vm.heap_slice_mut(name, 10).unwrap().copy_from_slice(b"Cargo.toml");
+        vm.registers[1..4].copy_from_slice(&[name as i32, 10, 0]);
+        sys_open(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+    }
+
+    #[test]
+    fn test_udp_syscalls() {
+        let mut vm = VM::new();
+        vm.registers[1] = 0;
+        sys_udp_bind(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+
+        let mut policy = NetworkPolicy::new();
+        policy.allow(NetworkPolicy::parse_rule("127.0.0.1:0").unwrap());
+        vm.set_network_policy(policy);
+        sys_udp_bind(&mut vm);
+        let socket = vm.registers[0];
+        assert!(socket >= 0);
+
+        let buffer = vm.allocate(8).unwrap() as i32;
+        vm.registers[1..4].copy_from_slice(&[socket, buffer, 8]);
+        sys_udp_recv(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+
+        vm.registers[1..6].copy_from_slice(&[socket, buffer, 8, 0x0a00_0001, 53]);
+        sys_udp_send(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+
+        vm.registers[1] = socket;
+        sys_udp_close(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+        sys_udp_close(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+    }
+
+    #[test]
+    fn test_sys_emit() {
+        let mut vm = VM::new();
+        let records = vm.subscribe_output();
+        let name = vm.allocate(4).unwrap();
+        vm.heap_slice_mut(name, 4).unwrap().copy_from_slice(b"data");
+        let data = vm.allocate(2).unwrap();
+        vm.heap_slice_mut(data, 2).unwrap().copy_from_slice(&[1, 2]);
+
+        vm.registers[1..6].copy_from_slice(&[name as i32, 4, 7, data as i32, 2]);
+        sys_emit(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+        assert_eq!(
+            records.try_recv(),
+            Ok(OutputRecord { channel: "data".to_string(), tag: 7, data: vec![1, 2] })
+        );
+
+        vm.registers[2] = 0;
+        sys_emit(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+        assert!(records.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_kv_syscalls() {
+        let mut vm = VM::new();
+        let key = vm.allocate(3).unwrap();
+        vm.heap_slice_mut(key, 3).unwrap().copy_from_slice(b"hit");
+        let value = vm.allocate(4).unwrap();
+        vm.heap_slice_mut(value, 4).unwrap().copy_from_slice(b"1234");
+
+        // Without a store, every call fails
+        vm.registers[1..5].copy_from_slice(&[key as i32, 3, value as i32, 4]);
+        sys_kv_put(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+
+        vm.set_kv_store(Some(KeyValueStore::in_memory()));
+        sys_kv_put(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+        sys_kv_get(&mut vm);
+        assert_eq!(vm.registers[1], 4);
+        assert_eq!(vm.heap_slice(vm.registers[0] as usize, 4), Some(&b"1234"[..]));
+
+        vm.registers[1..3].copy_from_slice(&[key as i32, 3]);
+        sys_kv_delete(&mut vm);
+        assert_eq!(vm.registers[0], 1);
+        sys_kv_delete(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+        sys_kv_get(&mut vm);
+        assert_eq!((vm.registers[0], vm.registers[1]), (-1, 0));
+    }
+
+    #[test]
+    fn test_sys_sbrk() {
+        let mut vm = VM::new();
+        vm.set_max_heap_size(Some(16));
+        vm.registers[1] = 12;
+        sys_sbrk(&mut vm);
+        assert_eq!(vm.registers[0], 0);
+        sys_sbrk(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+        vm.registers[1] = 4;
+        sys_sbrk(&mut vm);
+        assert_eq!(vm.registers[0], 12);
+        assert_eq!(vm.heap().len(), 16);
+        vm.registers[1] = -4;
+        sys_sbrk(&mut vm);
+        assert_eq!(vm.registers[0], -1);
+    }
+
+    #[test]
+    fn test_sys_trapinfo_outside_handler() {
+        let mut vm = VM::new();
+        vm.registers[1] = 7;
+        assert!(!sys_trapinfo(&mut vm));
+        assert_eq!(vm.registers[0], -1);
+        assert_eq!(vm.registers[1], 7);
+    }
+
+    #[test]
+    fn test_sys_time() {
+        let mut vm = VM::new();
+        assert!(!sys_time(&mut vm));
+        assert!(vm.registers[0] > 0);
+    }
+}
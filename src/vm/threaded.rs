@@ -0,0 +1,324 @@
+use crate::encoding::read_code_u16;
+use crate::instruction::{Opcode, SHIFT_REGISTER};
+use crate::vm::config::REGISTER_COUNT;
+use crate::vm::flags::{Flags, FLAG_CARRY, FLAG_OVERFLOW, FLAG_ZERO};
+use crate::vm::VM;
+
+use std::ops::Range;
+
+/// Runs the instruction at `pc` with its operand bytes and returns the offset of the next one to run. Returns None
+/// without changing anything when the instruction needs more than the handler does, such as raising a trap, so the
+/// interpreter runs it instead.
+pub type Handler = fn(&mut VM, usize, [u8; 3]) -> Option<usize>;
+
+/// An instruction threaded ahead of time: the handler that runs it, with the operands to give it
+#[derive(Debug, Clone, Copy)]
+pub struct ThreadedInstruction {
+    pub handler: Handler,
+    pub operands: [u8; 3],
+}
+
+/// The program's code as a handler for each instruction, for threaded dispatch. Running an instruction calls its
+/// handler straight away instead of matching on its opcode. Only the narrow encoding is threaded, and instructions
+/// without a handler, which is any but the common arithmetic, comparisons, jumps and loops, are left to the
+/// interpreter.
+#[derive(Debug, Clone)]
+pub struct ThreadedCode {
+    start: usize,
+    /// Length of the program when the code was threaded, which it no longer describes once that changes
+    program_length: usize,
+    instructions: Vec<Option<ThreadedInstruction>>,
+}
+
+impl ThreadedCode {
+    /// Threads the instructions from `start`, the offset of the first one, to the end of `program`
+    pub fn build(program: &[u8], start: usize) -> ThreadedCode {
+        let instructions = program.get(start..).unwrap_or(&[]).chunks_exact(4).map(thread).collect();
+        ThreadedCode { start, program_length: program.len(), instructions }
+    }
+
+    /// Whether the code was threaded from a program of this length
+    pub fn describes(&self, program: &[u8]) -> bool {
+        self.program_length == program.len()
+    }
+
+    /// The threaded instruction starting at `pc`, if it has a handler
+    pub fn get(&self, pc: usize) -> Option<ThreadedInstruction> {
+        let offset = pc.checked_sub(self.start)?;
+        if offset % 4 != 0 {
+            return None;
+        }
+        self.instructions.get(offset / 4).copied().flatten()
+    }
+
+    /// Threads the instructions overlapping `written` again, after the program wrote over its own code
+    pub fn refresh(&mut self, program: &[u8], written: Range<usize>) {
+        let first = written.start.saturating_sub(self.start) / 4;
+        let last = written.end.saturating_sub(self.start).div_ceil(4);
+        for index in first..last.min(self.instructions.len()) {
+            let address = self.start + index * 4;
+            self.instructions[index] = thread(&program[address..address + 4]);
+        }
+    }
+}
+
+/// Picks the handler for an instruction, if it has one. Register operands have to name one of the registers, so the
+/// handlers can index them without checking.
+fn thread(bytes: &[u8]) -> Option<ThreadedInstruction> {
+    let operands = [bytes[1], bytes[2], bytes[3]];
+    let registers = |count: usize| operands[..count].iter().all(|register| usize::from(*register) < REGISTER_COUNT);
+    let handler: Handler = match Opcode::from_byte(bytes[0]) {
+        Opcode::LOAD if registers(1) => load,
+        Opcode::LUI if registers(1) => lui,
+        Opcode::ORI if registers(1) => ori,
+        Opcode::ADD if registers(3) => add,
+        Opcode::SUB if registers(3) => sub,
+        Opcode::MUL if registers(3) => mul,
+        Opcode::DIV if registers(3) => div,
+        Opcode::SHL | Opcode::SHR if operands[2] == SHIFT_REGISTER && !registers(2) => return None,
+        Opcode::SHL if registers(1) => shl,
+        Opcode::SHR if registers(1) => shr,
+        Opcode::EQ if registers(2) => eq,
+        Opcode::JMP if registers(1) => jmp,
+        Opcode::JMPF if registers(1) => jmpf,
+        Opcode::BR => br,
+        Opcode::JMPE | Opcode::JZ if registers(1) => jz,
+        Opcode::JNZ if registers(1) => jnz,
+        Opcode::JC if registers(1) => jc,
+        Opcode::JO if registers(1) => jo,
+        Opcode::JNO if registers(1) => jno,
+        Opcode::CLOOP => cloop,
+        Opcode::LOOP => repeat,
+        Opcode::NOP => nop,
+        _ => return None,
+    };
+    Some(ThreadedInstruction { handler, operands })
+}
+
+fn load(vm: &mut VM, pc: usize, [register, high, low]: [u8; 3]) -> Option<usize> {
+    vm.registers[usize::from(register)] = i32::from(read_code_u16(&[high, low]));
+    Some(pc + 4)
+}
+
+fn lui(vm: &mut VM, pc: usize, [register, high, low]: [u8; 3]) -> Option<usize> {
+    vm.registers[usize::from(register)] = (u32::from(read_code_u16(&[high, low])) << 16) as i32;
+    Some(pc + 4)
+}
+
+fn ori(vm: &mut VM, pc: usize, [register, high, low]: [u8; 3]) -> Option<usize> {
+    let register = usize::from(register);
+    vm.registers[register] = (vm.registers[register] as u32 | u32::from(read_code_u16(&[high, low]))) as i32;
+    Some(pc + 4)
+}
+
+fn add(vm: &mut VM, pc: usize, [first, second, target]: [u8; 3]) -> Option<usize> {
+    let (register1, register2) = (vm.registers[usize::from(first)], vm.registers[usize::from(second)]);
+    let (result, overflow) = register1.overflowing_add(register2);
+    let (_, carry) = (register1 as u32).overflowing_add(register2 as u32);
+    vm.flags = Flags::from_result(result, carry, overflow);
+    vm.registers[usize::from(target)] = result;
+    Some(pc + 4)
+}
+
+fn sub(vm: &mut VM, pc: usize, [first, second, target]: [u8; 3]) -> Option<usize> {
+    let (register1, register2) = (vm.registers[usize::from(first)], vm.registers[usize::from(second)]);
+    vm.flags = VM::compare(register1, register2);
+    vm.registers[usize::from(target)] = register1.wrapping_sub(register2);
+    Some(pc + 4)
+}
+
+fn mul(vm: &mut VM, pc: usize, [first, second, target]: [u8; 3]) -> Option<usize> {
+    let (register1, register2) = (vm.registers[usize::from(first)], vm.registers[usize::from(second)]);
+    let (result, overflow) = register1.overflowing_mul(register2);
+    vm.flags = Flags::from_result(result, overflow, overflow);
+    vm.registers[usize::from(target)] = result;
+    Some(pc + 4)
+}
+
+fn div(vm: &mut VM, pc: usize, [first, second, target]: [u8; 3]) -> Option<usize> {
+    let (register1, register2) = (vm.registers[usize::from(first)], vm.registers[usize::from(second)]);
+    // Dividing by zero raises a trap, which is the interpreter's job
+    if register2 == 0 {
+        return None;
+    }
+
+    let (result, overflow) = register1.overflowing_div(register2);
+    vm.flags = Flags::from_result(result, false, overflow);
+    vm.registers[usize::from(target)] = result;
+    vm.remainder = register1.wrapping_rem(register2) as usize;
+    Some(pc + 4)
+}
+
+fn shift_amount(vm: &VM, amount: u8, mode: u8) -> u32 {
+    if mode == SHIFT_REGISTER { vm.registers[usize::from(amount)] as u32 } else { u32::from(amount) }
+}
+
+fn shl(vm: &mut VM, pc: usize, [register, amount, mode]: [u8; 3]) -> Option<usize> {
+    let amount = shift_amount(vm, amount, mode);
+    let register = usize::from(register);
+    vm.registers[register] = (vm.registers[register] as u32).checked_shl(amount).unwrap_or(0) as i32;
+    Some(pc + 4)
+}
+
+fn shr(vm: &mut VM, pc: usize, [register, amount, mode]: [u8; 3]) -> Option<usize> {
+    let amount = shift_amount(vm, amount, mode);
+    let register = usize::from(register);
+    vm.registers[register] = (vm.registers[register] as u32).checked_shr(amount).unwrap_or(0) as i32;
+    Some(pc + 4)
+}
+
+fn eq(vm: &mut VM, pc: usize, [first, second, _]: [u8; 3]) -> Option<usize> {
+    vm.flags = VM::compare(vm.registers[usize::from(first)], vm.registers[usize::from(second)]);
+    Some(pc + 4)
+}
+
+/// Jumps to `target`, unless strict jumps are on and it's a bad target, which raises a trap the interpreter has to
+fn jump(vm: &VM, target: usize) -> Option<usize> {
+    match vm.bad_jump(target) {
+        Some(_) => None,
+        None => Some(target),
+    }
+}
+
+fn jmp(vm: &mut VM, _: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump(vm, vm.registers[usize::from(register)] as usize)
+}
+
+fn jmpf(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    // Like the interpreter, jumps forward from just after the register operand
+    jump(vm, (pc + 2).wrapping_add(vm.registers[usize::from(register)] as usize))
+}
+
+fn br(vm: &mut VM, pc: usize, [_, high, low]: [u8; 3]) -> Option<usize> {
+    jump(vm, (pc as i64 + i64::from(read_code_u16(&[high, low]) as i16)) as usize)
+}
+
+fn jump_if(vm: &VM, pc: usize, register: u8, flag: u8, set: bool) -> Option<usize> {
+    if vm.flags.is_set(flag) == set {
+        return jump(vm, vm.registers[usize::from(register)] as usize);
+    }
+    Some(pc + 4)
+}
+
+fn jz(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump_if(vm, pc, register, FLAG_ZERO, true)
+}
+
+fn jnz(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump_if(vm, pc, register, FLAG_ZERO, false)
+}
+
+fn jc(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump_if(vm, pc, register, FLAG_CARRY, true)
+}
+
+fn jo(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump_if(vm, pc, register, FLAG_OVERFLOW, true)
+}
+
+fn jno(vm: &mut VM, pc: usize, [register, _, _]: [u8; 3]) -> Option<usize> {
+    jump_if(vm, pc, register, FLAG_OVERFLOW, false)
+}
+
+fn cloop(vm: &mut VM, pc: usize, [high, low, _]: [u8; 3]) -> Option<usize> {
+    vm.loop_counter = usize::from(read_code_u16(&[high, low]));
+    Some(pc + 4)
+}
+
+/// LOOP, which is a keyword in Rust
+fn repeat(vm: &mut VM, pc: usize, [high, low, _]: [u8; 3]) -> Option<usize> {
+    let next = match vm.loop_counter {
+        0 | 1 => pc + 4,
+        _ => jump(vm, usize::from(read_code_u16(&[high, low])))?,
+    };
+    vm.loop_counter = vm.loop_counter.saturating_sub(1);
+    Some(next)
+}
+
+fn nop(_: &mut VM, pc: usize, _: [u8; 3]) -> Option<usize> {
+    Some(pc + 4)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::config::Dispatch;
+    use crate::vm::random::Xorshift;
+
+    /// A block of the instructions with handlers, with operands picked by `random`, that doesn't jump
+    fn random_block(random: &mut Xorshift, length: usize) -> Vec<u8> {
+        let opcodes = [
+            Opcode::LOAD,
+            Opcode::LUI,
+            Opcode::ORI,
+            Opcode::ADD,
+            Opcode::SUB,
+            Opcode::MUL,
+            Opcode::DIV,
+            Opcode::SHL,
+            Opcode::SHR,
+            Opcode::EQ,
+            Opcode::NOP,
+        ];
+        let mut block = vec![];
+        for _ in 0..length {
+            let opcode = opcodes[random.next_u32() as usize % opcodes.len()];
+            let register = |random: &mut Xorshift| (random.next_u32() % 4) as u8;
+            let byte = |random: &mut Xorshift| random.next_u32() as u8;
+            match opcode {
+                Opcode::SHL | Opcode::SHR => {
+                    block.extend([opcode.into(), register(random), (random.next_u32() % 40) as u8, 0])
+                }
+                Opcode::LOAD | Opcode::LUI | Opcode::ORI => {
+                    block.extend([opcode.into(), register(random), byte(random), byte(random)])
+                }
+                _ => block.extend([opcode.into(), register(random), register(random), register(random)]),
+            }
+        }
+        block
+    }
+
+    fn run(program: &[u8], dispatch: Dispatch) -> VM {
+        let mut vm = VM::builder().dispatch(dispatch).build();
+        vm.program = program.to_vec();
+        vm.registers[..4].copy_from_slice(&[7, -3, 0, 1 << 30]);
+        let _ = vm.run();
+        vm
+    }
+
+    #[test]
+    fn test_matches_interpreter() {
+        let mut random = Xorshift::new(11);
+        for _ in 0..50 {
+            // Runs a random block 20 times, then halts. DIV by a zero register stops the run the same way in both.
+            let mut program = vec![Opcode::CLOOP.into(), 0, 20, 0];
+            program.extend(random_block(&mut random, 12));
+            program.extend([Opcode::LOOP.into(), 0, 4, 0, Opcode::HLT.into(), 0, 0, 0]);
+
+            let interpreted = run(&program, Dispatch::Match);
+            let threaded = run(&program, Dispatch::Threaded);
+            assert_eq!(threaded.registers, interpreted.registers);
+            assert_eq!(threaded.flags(), interpreted.flags());
+            assert_eq!(threaded.pc(), interpreted.pc());
+            assert_eq!(threaded.instructions_executed, interpreted.instructions_executed);
+            assert_eq!(threaded.error, interpreted.error);
+            assert!(threaded.threaded_code.is_some());
+        }
+    }
+
+    #[test]
+    fn test_threaded_code() {
+        let mut program = vec![Opcode::LOAD.into(), 0, 1, 244, Opcode::PRTS.into(), 0, 0, 0];
+        let mut code = ThreadedCode::build(&program, 0);
+        assert_eq!(code.get(0).unwrap().operands, [0, 1, 244]);
+        assert!(code.get(2).is_none());
+        assert!(code.get(4).is_none());
+
+        program[4] = Opcode::NOP.into();
+        code.refresh(&program, 4..8);
+        assert!(code.get(4).is_some());
+        assert!(code.describes(&program));
+        program.push(0);
+        assert!(!code.describes(&program));
+    }
+}
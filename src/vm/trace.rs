@@ -0,0 +1,18 @@
+use crate::instruction::Opcode;
+
+/// An instruction the VM is about to execute, handed to the hook set with `VM::set_trace_hook`
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceEvent<'a> {
+    /// Where the instruction starts, counting any wide immediate in front of it
+    pub pc: usize,
+    pub opcode: Opcode,
+    /// The three bytes after the opcode, before they're decoded. What they mean depends on the opcode.
+    pub operands: [u8; 3],
+    /// The 32-bit immediate in front of the instruction, in programs using the wide encoding
+    pub wide_immediate: Option<i32>,
+    /// The registers as they are before the instruction runs
+    pub registers: &'a [i32; 32],
+}
+
+/// A function called with every instruction before it's executed, for building tracers and coverage tools
+pub type TraceHook = Box<dyn FnMut(&TraceEvent)>;
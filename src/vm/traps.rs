@@ -0,0 +1,80 @@
+use crate::vm::flags::Flags;
+
+/// Faults that transfer control to a handler from the vector table, when one is installed. The discriminant is the
+/// trap's number, as used by `settrap #number @handler`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Trap {
+    /// Integer division by zero
+    DivideByZero = 0,
+    /// An opcode the VM doesn't recognize or implement
+    IllegalOpcode = 1,
+    /// A heap or read-only data access that was out of bounds or misaligned, or a bad ALOC or FREE
+    MemoryFault = 2,
+    /// The timer set with the settimer syscall has run out
+    Timer = 3,
+    /// A jump to somewhere that isn't the start of an instruction, when strict jumps are on
+    BadJump = 4,
+}
+
+/// Number of entries in the vector table
+pub const TRAP_COUNT: usize = 5;
+
+impl Trap {
+    pub fn number(self) -> usize {
+        self as usize
+    }
+}
+
+/// What entering a trap handler saved, so IRET can go back to where the program was
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TrapFrame {
+    /// Where the program carries on after the handler returns, which is just past the instruction that raised the
+    /// trap
+    pub return_address: usize,
+    /// Start of the instruction that raised the trap, or for the timer, the one that would have run next. An illegal
+    /// opcode handler can decode it from here to emulate the instruction.
+    pub fault_address: usize,
+    pub flags: Flags,
+}
+
+/// Handler addresses for each trap, indexed by trap number
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct VectorTable {
+    handlers: [Option<usize>; TRAP_COUNT],
+}
+
+impl VectorTable {
+    pub fn new() -> VectorTable {
+        VectorTable::default()
+    }
+
+    /// Installs the handler for a trap number. Returns false if there is no such trap.
+    pub fn set(&mut self, number: usize, handler: usize) -> bool {
+        match self.handlers.get_mut(number) {
+            Some(entry) => {
+                *entry = Some(handler);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn get(&self, trap: Trap) -> Option<usize> {
+        self.handlers[trap.number()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_vector_table() {
+        let mut vectors = VectorTable::new();
+        assert!(vectors.get(Trap::DivideByZero).is_none());
+        assert!(vectors.set(Trap::DivideByZero.number(), 64));
+        assert_eq!(vectors.get(Trap::DivideByZero), Some(64));
+        assert!(vectors.get(Trap::MemoryFault).is_none());
+        assert!(!vectors.set(TRAP_COUNT, 64));
+    }
+}
@@ -0,0 +1,103 @@
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+/// Instructions between looks at the clock, so watching a program costs little more than a comparison per
+/// instruction. A program has to stay in the same loop for at least this many instructions to be noticed.
+const CHECK_INTERVAL: u32 = 1024;
+
+/// Called with the range of program counters a program has been stuck in and for how long. Returns true to stop the
+/// VM, or false to let it carry on and start watching again.
+pub type WatchdogCallback = Box<dyn FnMut(Range<usize>, Duration) -> bool>;
+
+/// Notices when a program has spent too long going round a small loop, which is usually a bug rather than work. A
+/// program is stuck while all of the instructions it runs lie within `span` bytes of each other.
+pub struct Watchdog {
+    span: usize,
+    timeout: Duration,
+    callback: WatchdogCallback,
+    /// Lowest and highest program counters seen since the program was last seen to make progress
+    low: usize,
+    high: usize,
+    since: Instant,
+    until_check: u32,
+}
+
+impl Watchdog {
+    pub fn new(span: usize, timeout: Duration, callback: WatchdogCallback) -> Watchdog {
+        Watchdog {
+            span,
+            timeout,
+            callback,
+            low: 0,
+            high: 0,
+            since: Instant::now(),
+            until_check: CHECK_INTERVAL,
+        }
+    }
+
+    /// Records that the instruction at `pc` is about to run. Returns the range the program is stuck in if the
+    /// callback asked for the VM to stop.
+    pub fn observe(&mut self, pc: usize) -> Option<Range<usize>> {
+        let low = self.low.min(pc);
+        let high = self.high.max(pc);
+        if high - low > self.span {
+            self.restart(pc);
+            return None;
+        }
+        self.low = low;
+        self.high = high;
+
+        self.until_check -= 1;
+        if self.until_check > 0 {
+            return None;
+        }
+        self.until_check = CHECK_INTERVAL;
+
+        let stuck_for = self.since.elapsed();
+        if stuck_for < self.timeout {
+            return None;
+        }
+
+        let range = self.low..self.high + 1;
+        self.restart(pc);
+        if (self.callback)(range.clone(), stuck_for) {
+            Some(range)
+        } else {
+            None
+        }
+    }
+
+    fn restart(&mut self, pc: usize) {
+        self.low = pc;
+        self.high = pc;
+        self.since = Instant::now();
+        self.until_check = CHECK_INTERVAL;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[test]
+    fn test_watchdog_fires_in_small_loop() {
+        let calls = Rc::new(Cell::new(0));
+        let counter = calls.clone();
+        let mut watchdog = Watchdog::new(8, Duration::from_millis(0), Box::new(move |range, _| {
+            counter.set(counter.get() + 1);
+            range == (0..5)
+        }));
+
+        let stopped = (0..CHECK_INTERVAL).filter_map(|step| watchdog.observe((step % 2 * 4) as usize)).next();
+        assert_eq!(stopped, Some(0..5));
+        assert_eq!(calls.get(), 1);
+    }
+
+    #[test]
+    fn test_watchdog_ignores_progress() {
+        let mut watchdog = Watchdog::new(8, Duration::from_millis(0), Box::new(|_, _| true));
+        assert!((0..CHECK_INTERVAL * 2).all(|step| watchdog.observe(step as usize * 4).is_none()));
+    }
+}
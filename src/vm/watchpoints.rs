@@ -0,0 +1,183 @@
+use crate::encoding::read_heap_word;
+use crate::instruction::Opcode;
+use crate::vm::heap::Heap;
+
+use std::fmt;
+use std::ops::Range;
+
+/// Something whose value the program stops after changing, added with `VM::add_watchpoint`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Watchpoint {
+    /// An integer register, by number
+    Register(usize),
+    /// The word at a heap address. Bytes past the end of the heap count as zeros, so a word can be watched before
+    /// it has been allocated.
+    Heap(usize),
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Watchpoint::Register(number) => write!(f, "${}", number),
+            Watchpoint::Heap(address) => write!(f, "heap word {}", address),
+        }
+    }
+}
+
+/// A watchpoint whose value changed, with what it held before and after
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchpointHit {
+    pub watchpoint: Watchpoint,
+    pub old: i32,
+    pub new: i32,
+}
+
+/// The watchpoints set on a VM, each with the value it was last seen holding
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    watched: Vec<(Watchpoint, i32)>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.watched.is_empty()
+    }
+
+    /// Starts watching something that currently holds `value`. Returns false if it was already being watched.
+    pub fn add(&mut self, watchpoint: Watchpoint, value: i32) -> bool {
+        if self.watched.iter().any(|(watched, _)| *watched == watchpoint) {
+            return false;
+        }
+        self.watched.push((watchpoint, value));
+        true
+    }
+
+    /// Stops watching something, returning false if it wasn't being watched
+    pub fn remove(&mut self, watchpoint: Watchpoint) -> bool {
+        let count = self.watched.len();
+        self.watched.retain(|(watched, _)| *watched != watchpoint);
+        self.watched.len() != count
+    }
+
+    /// The watchpoints, in the order they were added
+    pub fn iter(&self) -> impl Iterator<Item = Watchpoint> + '_ {
+        self.watched.iter().map(|(watchpoint, _)| *watchpoint)
+    }
+
+    /// Takes the current values as the ones changes are measured from, such as after the host changed a register
+    pub fn refresh(&mut self, registers: &[i32; 32], heap: &Heap) {
+        for (watchpoint, value) in &mut self.watched {
+            *value = current_value(*watchpoint, registers, heap);
+        }
+    }
+
+    /// Looks for changes to the watched registers in `written`, and to the watched heap words if `heap_written` is
+    /// set. Every change is taken as the new value to watch from, and the first one found is returned.
+    pub fn check(
+        &mut self,
+        written: Range<usize>,
+        heap_written: bool,
+        registers: &[i32; 32],
+        heap: &Heap,
+    ) -> Option<WatchpointHit> {
+        let mut hit = None;
+        for (watchpoint, value) in &mut self.watched {
+            let affected = match *watchpoint {
+                Watchpoint::Register(number) => written.contains(&number),
+                Watchpoint::Heap(_) => heap_written,
+            };
+            if !affected {
+                continue;
+            }
+
+            let new = current_value(*watchpoint, registers, heap);
+            if new != *value {
+                let old = std::mem::replace(value, new);
+                hit = hit.or(Some(WatchpointHit { watchpoint: *watchpoint, old, new }));
+            }
+        }
+        hit
+    }
+}
+
+fn current_value(watchpoint: Watchpoint, registers: &[i32; 32], heap: &Heap) -> i32 {
+    match watchpoint {
+        Watchpoint::Register(number) => registers[number],
+        Watchpoint::Heap(address) => {
+            let mut word = [0; 4];
+            for (offset, byte) in word.iter_mut().enumerate() {
+                *byte = heap.as_slice().get(address + offset).copied().unwrap_or(0);
+            }
+            read_heap_word(&word)
+        }
+    }
+}
+
+/// The integer registers the 4 byte instruction may write, going by its opcode and operands. Instructions that hand
+/// the registers to code outside the program, like SYSCALL and HCALL, or that can switch green threads, like YIELD,
+/// may write all of them.
+pub fn written_registers(instruction: &[u8]) -> Range<usize> {
+    let register = |index: usize| {
+        let number = instruction[index] as usize;
+        number..(number + 1).min(32)
+    };
+
+    match Opcode::from(instruction[0]) {
+        Opcode::LOAD | Opcode::LUI | Opcode::ORI | Opcode::SHL | Opcode::SHR | Opcode::LW | Opcode::XADD => register(1),
+        Opcode::RAND | Opcode::SPAWN | Opcode::RDCYCLE => register(1),
+        Opcode::ALOC | Opcode::CAS => register(2),
+        Opcode::ADD | Opcode::SUB | Opcode::MUL | Opcode::DIV => register(3),
+        Opcode::VADD | Opcode::VMUL => {
+            let target = instruction[1] as usize;
+            target.min(32)..(target + instruction[3] as usize).min(32)
+        }
+        Opcode::SYSCALL | Opcode::HCALL | Opcode::RET | Opcode::YIELD | Opcode::RECV => 0..32,
+        _ => 0..0,
+    }
+}
+
+/// Whether the instruction may change the heap
+pub fn writes_heap(opcode: Opcode) -> bool {
+    matches!(opcode, Opcode::SW | Opcode::CAS | Opcode::XADD | Opcode::ALOC | Opcode::SYSCALL)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check() {
+        let mut registers = [0; 32];
+        let mut heap = Heap::new();
+        heap.allocate(8);
+        let mut watchpoints = Watchpoints::new();
+        assert!(watchpoints.add(Watchpoint::Register(2), 0));
+        assert!(watchpoints.add(Watchpoint::Heap(4), 0));
+        assert!(!watchpoints.add(Watchpoint::Register(2), 0));
+
+        registers[2] = 7;
+        assert_eq!(watchpoints.check(0..2, false, &registers, &heap), None);
+        let hit = WatchpointHit { watchpoint: Watchpoint::Register(2), old: 0, new: 7 };
+        assert_eq!(watchpoints.check(2..3, false, &registers, &heap), Some(hit));
+        assert_eq!(watchpoints.check(0..32, false, &registers, &heap), None);
+
+        heap.slice_mut(4, 4).unwrap().copy_from_slice(&[1, 1, 0, 0]);
+        let hit = WatchpointHit { watchpoint: Watchpoint::Heap(4), old: 0, new: 257 };
+        assert_eq!(watchpoints.check(0..0, true, &registers, &heap), Some(hit));
+        assert!(watchpoints.remove(Watchpoint::Heap(4)));
+        assert_eq!(watchpoints.iter().collect::<Vec<_>>(), vec![Watchpoint::Register(2)]);
+    }
+
+    #[test]
+    fn test_written_registers() {
+        assert_eq!(written_registers(&[0, 4, 0, 9]), 4..5);
+        assert_eq!(written_registers(&[1, 0, 1, 2]), 2..3);
+        assert_eq!(written_registers(&[Opcode::VADD.into(), 30, 0, 4]), 30..32);
+        assert_eq!(written_registers(&[Opcode::SYSCALL.into(), 0, 1, 0]), 0..32);
+        assert_eq!(written_registers(&[Opcode::SW.into(), 1, 2, 0]), 0..0);
+    }
+}
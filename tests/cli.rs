@@ -0,0 +1,62 @@
+use std::env;
+use std::fs;
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Runs the iridium binary with `args`, giving it `input` on stdin
+fn iridium(args: &[&str], input: &str) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_iridium"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input.as_bytes()).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/// A path in a directory of its own for the test called `name`, which is created empty
+fn scratch_dir(name: &str) -> PathBuf {
+    let dir = env::temp_dir().join(format!("iridium-cli-{}-{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn test_output_writes_the_assembled_program() {
+    let dir = scratch_dir("output");
+    let source = dir.join("program.iasm");
+    fs::write(&source, ".data\n.code\nload $0 #5\nhlt\n").unwrap();
+    let binary = dir.join("json");
+
+    let output = iridium(&["--output", binary.to_str().unwrap(), source.to_str().unwrap()], "");
+    assert!(output.status.success());
+    assert!(fs::read(&binary).unwrap().starts_with(&[45, 50, 49, 45]));
+
+    // Without an input file there's nothing to write, rather than a REPL to start
+    let output = iridium(&["-o", binary.to_str().unwrap()], ".quit\n");
+    assert!(!output.status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_repl_format() {
+    let output = iridium(&["--repl-format", "json"], "load $0 #5\n.quit\n");
+    assert!(output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let replies: Vec<&str> = stdout.lines().collect();
+    assert_eq!(replies.len(), 2);
+    assert!(replies[0].starts_with(r#"{"command":"load $0 #5","ok":true"#));
+
+    assert!(!iridium(&["--repl-format", "xml"], ".quit\n").status.success());
+
+    // The REPL only starts without an input file, so a format with one is a mistake
+    let dir = scratch_dir("repl-format");
+    let source = dir.join("program.iasm");
+    fs::write(&source, ".data\n.code\nhlt\n").unwrap();
+    assert!(!iridium(&["--repl-format", "json", source.to_str().unwrap()], "").status.success());
+    fs::remove_dir_all(&dir).unwrap();
+}